@@ -0,0 +1,275 @@
+//! `#[derive(Filterable)]` for `wirefilter-engine`, which is re-exported from
+//! it with the `derive` feature.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, TokenStream as TokenStream2};
+use syn::{
+    Data, DeriveInput, Error, Fields, GenericArgument, Lit, Meta, NestedMeta, PathArguments,
+    Result, Type,
+};
+
+// How values of a Rust type are converted to values of a scheme type.
+enum Kind {
+    Ip,
+    Bytes,
+    Int,
+    Bool,
+    Cidr,
+    Endpoint,
+    Float,
+    Timestamp,
+    Mac,
+}
+
+impl Kind {
+    fn of(ty: &Type) -> Option<Self> {
+        if is_bytes(ty) {
+            return Some(Kind::Bytes);
+        }
+
+        Some(match last_ident(ty)?.to_string().as_str() {
+            "IpAddr" => Kind::Ip,
+            "u8" | "u16" | "i8" | "i16" | "i32" => Kind::Int,
+            "bool" => Kind::Bool,
+            "IpCidr" => Kind::Cidr,
+            "Endpoint" => Kind::Endpoint,
+            "f32" | "f64" => Kind::Float,
+            "Timestamp" => Kind::Timestamp,
+            "Mac" => Kind::Mac,
+            _ => return None,
+        })
+    }
+
+    fn scheme_type(&self) -> TokenStream2 {
+        match self {
+            Kind::Ip => quote!(::wirefilter::Type::Ip),
+            Kind::Bytes => quote!(::wirefilter::Type::Bytes),
+            Kind::Int => quote!(::wirefilter::Type::Int),
+            Kind::Bool => quote!(::wirefilter::Type::Bool),
+            Kind::Cidr => quote!(::wirefilter::Type::Cidr),
+            Kind::Endpoint => quote!(::wirefilter::Type::Endpoint),
+            Kind::Float => quote!(::wirefilter::Type::Float),
+            Kind::Timestamp => quote!(::wirefilter::Type::Timestamp),
+            Kind::Mac => quote!(::wirefilter::Type::Mac),
+        }
+    }
+
+    // Converts a place expression of the value into an `LhsValue`.
+    fn value(&self, value: &TokenStream2) -> TokenStream2 {
+        match self {
+            Kind::Bytes => quote! {
+                ::wirefilter::LhsValue::Bytes(::std::convert::AsRef::<[u8]>::as_ref(&#value))
+            },
+            Kind::Int => quote!(::wirefilter::LhsValue::Int(::std::convert::From::from(#value))),
+            Kind::Float => {
+                quote!(::wirefilter::LhsValue::Float(::std::convert::From::from(#value)))
+            }
+            _ => quote! {
+                ::wirefilter::LhsValue::from(::std::clone::Clone::clone(&#value))
+            },
+        }
+    }
+}
+
+// Returns the name of the type without a path, like `IpAddr` of
+// `std::net::IpAddr`.
+fn last_ident(ty: &Type) -> Option<&Ident> {
+    match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.segments.iter().last().map(|s| &s.ident),
+        _ => None,
+    }
+}
+
+// Returns `T` of `Option<T>`, `Vec<T>` and alike.
+fn inner_type(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(ty) => ty.path.segments.iter().last()?,
+        _ => return None,
+    };
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_named(ty: &Type, name: &str) -> bool {
+    last_ident(ty).map_or(false, |ident| ident == name)
+}
+
+fn is_u8_slice(ty: &Type) -> bool {
+    match ty {
+        Type::Slice(ty) => is_named(&ty.elem, "u8"),
+        _ => false,
+    }
+}
+
+// Types that can be borrowed as `[u8]`: `String`, `&str`, `&[u8]`, `Vec<u8>`
+// and `Box<[u8]>`.
+fn is_bytes(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(ty) => is_named(&ty.elem, "str") || is_u8_slice(&ty.elem),
+        _ if is_named(ty, "String") => true,
+        _ if is_named(ty, "Vec") => inner_type(ty).map_or(false, |ty| is_named(ty, "u8")),
+        _ if is_named(ty, "Box") => inner_type(ty).map_or(false, is_u8_slice),
+        _ => false,
+    }
+}
+
+// Options from `#[filter(name = "...")]` and `#[filter(skip)]`.
+struct FieldOptions {
+    name: Option<String>,
+    skip: bool,
+}
+
+fn field_options(attrs: &[syn::Attribute]) -> Result<FieldOptions> {
+    let mut options = FieldOptions {
+        name: None,
+        skip: false,
+    };
+
+    for attr in attrs {
+        if !attr.path.is_ident("filter") {
+            continue;
+        }
+
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[filter(...)]")),
+        };
+
+        for item in list.nested {
+            match item {
+                NestedMeta::Meta(Meta::Word(ref word)) if word == "skip" => options.skip = true,
+                NestedMeta::Meta(Meta::NameValue(ref pair)) if pair.ident == "name" => {
+                    match &pair.lit {
+                        Lit::Str(name) => options.name = Some(name.value()),
+                        lit => return Err(Error::new_spanned(lit, "expected a string")),
+                    }
+                }
+                item => {
+                    return Err(Error::new_spanned(
+                        item,
+                        "expected `name = \"...\"` or `skip`",
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+fn derive(input: &DeriveInput) -> Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "Filterable can only be derived for structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Filterable can only be derived for structs",
+            ));
+        }
+    };
+
+    let mut add_fields = Vec::new();
+    let mut set_values = Vec::new();
+
+    for field in fields {
+        let options = field_options(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().unwrap();
+        let name = options.name.unwrap_or_else(|| ident.to_string());
+
+        let (ty, optional) = match inner_type(&field.ty) {
+            Some(inner) if is_named(&field.ty, "Option") => (inner, true),
+            _ => (&field.ty, false),
+        };
+        let kind = Kind::of(ty).ok_or_else(|| {
+            Error::new_spanned(
+                &field.ty,
+                "unsupported field type, use #[filter(skip)] to leave it out",
+            )
+        })?;
+
+        let scheme_type = kind.scheme_type();
+        add_fields.push(quote! {
+            scheme.add_field(#name.to_owned(), #scheme_type)?;
+        });
+
+        set_values.push(if optional {
+            let value = kind.value(&quote!(*value));
+            quote! {
+                if let Some(value) = &self.#ident {
+                    ctx.set_field_value(#name, #value)?;
+                }
+            }
+        } else {
+            let value = kind.value(&quote!(self.#ident));
+            quote! {
+                ctx.set_field_value(#name, #value)?;
+            }
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::wirefilter::Filterable for #ident #ty_generics #where_clause {
+            fn add_fields(
+                scheme: &mut ::wirefilter::Scheme,
+            ) -> ::std::result::Result<(), ::wirefilter::FieldError> {
+                #(#add_fields)*
+                Ok(())
+            }
+
+            fn fill_context<'e>(
+                &'e self,
+                ctx: &mut ::wirefilter::ExecutionContext<'e>,
+            ) -> ::std::result::Result<(), ::wirefilter::SetFieldValueError> {
+                #(#set_values)*
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Implements `wirefilter::Filterable` for a struct with named fields.
+///
+/// Each field becomes a scheme field named after it, unless renamed with
+/// `#[filter(name = "ip.src")]` or left out with `#[filter(skip)]`. Field
+/// types map to scheme types as follows, and `Option`s of them are only set
+/// when they are `Some`:
+///  - `IpAddr` to `ip`;
+///  - `String`, `&str`, `Vec<u8>` and `Box<[u8]>` to `bytes`;
+///  - `u8`, `u16`, `i8`, `i16` and `i32` to `int`;
+///  - `bool` to `bool`;
+///  - `IpCidr` to `cidr`, `Endpoint` to `endpoint`, `Timestamp` to
+///    `timestamp` and `Mac` to `mac`;
+///  - `f32` and `f64` to `float`.
+#[proc_macro_derive(Filterable, attributes(filter))]
+pub fn derive_filterable(input: TokenStream) -> TokenStream {
+    syn::parse::<DeriveInput>(input)
+        .and_then(|input| derive(&input))
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
@@ -11,8 +11,11 @@ extern crate wirefilter;
 use criterion::{
     criterion_group, criterion_main, Bencher, Benchmark, Criterion, ParameterizedBenchmark,
 };
-use std::{fmt::Debug, net::IpAddr};
-use wirefilter::{ExecutionContext, GetType, LhsValue, Scheme};
+use std::{
+    fmt::Debug,
+    net::{IpAddr, Ipv4Addr},
+};
+use wirefilter::{ExecutionContext, GetType, LhsValue, Scheme, Type};
 
 struct FieldBench<'a, T: 'static> {
     field: &'static str,
@@ -84,6 +87,25 @@ impl<'a, T: 'static + Copy + Debug + Into<LhsValue<'static>>> FieldBench<'a, T>
                     values.iter().cloned(),
                 ),
             );
+
+            c.bench(
+                "program execution",
+                ParameterizedBenchmark::new(
+                    name,
+                    move |b: &mut Bencher, value: &T| {
+                        let mut scheme = Scheme::default();
+                        scheme.add_field(field.to_owned(), ty).unwrap();
+
+                        let program = scheme.parse(filter).unwrap().compile_program();
+
+                        let mut exec_ctx = ExecutionContext::new(&scheme);
+                        exec_ctx.set_field_value(field, *value).unwrap();
+
+                        b.iter(|| program.execute(&exec_ctx));
+                    },
+                    values.iter().cloned(),
+                ),
+            );
         }
     }
 }
@@ -107,6 +129,27 @@ fn bench_ip_comparisons(c: &mut Criterion) {
     }.run(c)
 }
 
+fn bench_large_ip_list(c: &mut Criterion) {
+    let filter = format!(
+        "ip.addr in {{ {} }}",
+        (0..50_000u32)
+            .map(|i| Ipv4Addr::from(0x0A00_0000 + i).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    c.bench(
+        "parsing",
+        Benchmark::new("ip.addr in { 50000 addresses }", move |b: &mut Bencher| {
+            let mut scheme = Scheme::default();
+            scheme.add_field("ip.addr".to_owned(), Type::Ip).unwrap();
+
+            b.iter(|| scheme.parse(&filter).unwrap());
+        })
+        .sample_size(10),
+    );
+}
+
 fn bench_int_comparisons(c: &mut Criterion) {
     FieldBench {
         field: "tcp.port",
@@ -151,6 +194,7 @@ criterion_group! {
     config = Criterion::default();
     targets =
         bench_ip_comparisons,
+        bench_large_ip_list,
         bench_int_comparisons,
         bench_string_comparisons,
         bench_string_matches,
@@ -0,0 +1,108 @@
+use super::field_expr::ComparisonOp;
+use std::collections::HashSet;
+
+/// A summary of what a filter uses, as returned by
+/// [`Scheme::analyze`](::Scheme::analyze), e.g. to check filters against a
+/// policy like "no regexes" before accepting them.
+#[derive(Debug, PartialEq, Default)]
+pub struct FilterAnalysis<'s> {
+    pub(crate) used_fields: HashSet<&'s str>,
+    pub(crate) used_operators: HashSet<ComparisonOp>,
+}
+
+impl<'s> FilterAnalysis<'s> {
+    /// Returns names of all fields the filter refers to.
+    pub fn used_fields(&self) -> &HashSet<&'s str> {
+        &self.used_fields
+    }
+
+    /// Returns all comparison operators the filter uses.
+    ///
+    /// Boolean fields used on their own don't have an operator, so they're
+    /// only reported in [`used_fields`](FilterAnalysis::used_fields).
+    pub fn used_operators(&self) -> &HashSet<ComparisonOp> {
+        &self.used_operators
+    }
+}
+
+#[test]
+fn test_analyze() {
+    use super::field_expr::{BytesOp, OrderingOp};
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        http.ua: Bytes,
+        ssl: Bool,
+    };
+
+    let analysis = scheme
+        .analyze(r#"http.host == "example.org" && (http.ua matches "bot" || not http.ua ~ "^curl") && ssl"#)
+        .unwrap();
+
+    assert_eq!(
+        analysis.used_fields(),
+        &["http.host", "http.ua", "ssl"].iter().cloned().collect()
+    );
+    assert_eq!(
+        analysis.used_operators(),
+        &[
+            ComparisonOp::Ordering(OrderingOp::Equal),
+            ComparisonOp::Bytes(BytesOp::Matches),
+        ]
+        .iter()
+        .cloned()
+        .collect()
+    );
+
+    assert!(scheme.analyze("http.path == 1").is_err());
+}
+
+#[test]
+fn test_literals() {
+    use types::{LhsValue, Type};
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        http.ua: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+    };
+
+    let filter = scheme
+        .parse(r#"http.host == "example.org" && tcp.port == 443 && http.ua contains "curl""#)
+        .unwrap();
+    assert_eq!(
+        filter.literals(Type::Bytes),
+        vec![LhsValue::Bytes(b"example.org"), LhsValue::Bytes(b"curl"),]
+    );
+    assert_eq!(filter.literals(Type::Int), vec![LhsValue::Int(443)]);
+    assert!(filter.literals(Type::Ip).is_empty());
+
+    let filter = scheme
+        .parse(r#"http.host in { "a" "b" } || http.ua ~ "^bot" || tcp.port in { 80 8000..8080 }"#)
+        .unwrap();
+    assert_eq!(
+        filter.literals(Type::Bytes),
+        vec![
+            LhsValue::Bytes(b"a"),
+            LhsValue::Bytes(b"b"),
+            LhsValue::Bytes(b"^bot"),
+        ]
+    );
+    assert_eq!(
+        filter.literals(Type::Int),
+        vec![LhsValue::Int(80), LhsValue::Int(8000), LhsValue::Int(8080)]
+    );
+
+    let filter = scheme
+        .parse("ip.src == 10.0.0.0/8 || ip.src == 192.0.2.1")
+        .unwrap();
+    assert_eq!(
+        filter.literals(Type::Cidr),
+        vec![LhsValue::Cidr("10.0.0.0/8".parse().unwrap())]
+    );
+    assert_eq!(
+        filter.literals(Type::Ip),
+        vec![LhsValue::Ip([192, 0, 2, 1].into())]
+    );
+}
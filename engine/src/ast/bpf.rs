@@ -0,0 +1,789 @@
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{ComparisonOp, ComparisonRhs, FieldExpr, IntOp, OrderingOp},
+    simple_expr::{SimpleExpr, UnaryOp},
+    FilterAst,
+};
+use failure::Fail;
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    ops::RangeInclusive,
+};
+use types::{GetType, LhsValue, Type};
+
+// Opcodes of classic BPF, from `linux/filter.h`.
+const LD_W_ABS: u16 = 0x20;
+const LD_H_ABS: u16 = 0x28;
+const LD_B_ABS: u16 = 0x30;
+const LD_H_IND: u16 = 0x48;
+const LD_B_IND: u16 = 0x50;
+const LD_IMM: u16 = 0x00;
+const LD_MEM: u16 = 0x60;
+const LDX_B_MSH: u16 = 0xb1;
+const ST: u16 = 0x02;
+const ALU_ADD_K: u16 = 0x04;
+const ALU_AND_K: u16 = 0x54;
+const ALU_RSH_K: u16 = 0x74;
+const JMP_JA: u16 = 0x05;
+const JMP_JEQ_K: u16 = 0x15;
+const JMP_JGT_K: u16 = 0x25;
+const JMP_JGE_K: u16 = 0x35;
+const JMP_JSET_K: u16 = 0x45;
+const RET_K: u16 = 0x06;
+
+// Limits of the kernel: the number of instructions, and of scratch memory
+// slots used to count matched items of `xor`.
+const MAX_INSTRUCTIONS: usize = 4096;
+const MEMORY_SLOTS: usize = 16;
+
+// Offsets in an Ethernet frame with an IPv4 packet.
+const ETHER_TYPE: u32 = 12;
+const ETHER_TYPE_IPV4: u32 = 0x0800;
+const IPV4_HEADER: u32 = 14;
+const IPV4_FRAGMENT: u32 = IPV4_HEADER + 6;
+const IPV4_PROTO: u32 = IPV4_HEADER + 9;
+const IPV4_SRC: u32 = IPV4_HEADER + 12;
+const IPV4_DST: u32 = IPV4_HEADER + 16;
+
+/// A classic BPF instruction, with the same layout as `struct sock_filter`
+/// in Linux, so that a slice of them can be attached to a socket with
+/// `SO_ATTACH_FILTER`.
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BpfInstruction {
+    /// The opcode.
+    pub code: u16,
+    /// How many instructions to skip if a conditional jump is taken.
+    pub jt: u8,
+    /// How many instructions to skip if a conditional jump is not taken.
+    pub jf: u8,
+    /// The constant operand, like an offset or a value to compare with.
+    pub k: u32,
+}
+
+/// Formats the instruction like `tcpdump -dd` does, e.g.
+/// `{ 0x28, 0, 0, 0x0000000c }`.
+impl Display for BpfInstruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{ 0x{:02x}, {}, {}, 0x{:08x} }}",
+            self.code, self.jt, self.jf, self.k
+        )
+    }
+}
+
+/// An error that occurs when compiling a filter with
+/// [`FilterAst::compile_bpf`].
+#[derive(Debug, PartialEq)]
+pub enum BpfCompileError {
+    /// Some comparisons can't be done on packet headers. Each of them is
+    /// described together with the reason, like
+    /// ``"`http.host == "a"`: not a packet field"``.
+    Unsupported(Vec<String>),
+
+    /// The program would have more than 4096 instructions, or jumps longer
+    /// than 255 instructions.
+    TooLarge,
+}
+
+impl Display for BpfCompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BpfCompileError::Unsupported(constructs) => {
+                write!(f, "cannot compile to BPF: {}", constructs.join("; "))
+            }
+            BpfCompileError::TooLarge => write!(f, "the filter is too large for BPF"),
+        }
+    }
+}
+
+impl Fail for BpfCompileError {}
+
+#[derive(Clone, Copy)]
+enum PacketField {
+    IpSrc,
+    IpDst,
+    IpProto,
+    TcpFlags,
+    Port { proto: u32, offset: u32 },
+}
+
+impl PacketField {
+    fn new(name: &str, ty: Type) -> Option<Self> {
+        const TCP: u32 = 6;
+        const UDP: u32 = 17;
+
+        let (field, expected) = match name {
+            "ip.src" => (PacketField::IpSrc, Type::Ip),
+            "ip.dst" => (PacketField::IpDst, Type::Ip),
+            "ip.proto" => (PacketField::IpProto, Type::Int),
+            "tcp.flags" => (PacketField::TcpFlags, Type::Int),
+            "tcp.srcport" => (
+                PacketField::Port {
+                    proto: TCP,
+                    offset: 0,
+                },
+                Type::Int,
+            ),
+            "tcp.dstport" => (
+                PacketField::Port {
+                    proto: TCP,
+                    offset: 2,
+                },
+                Type::Int,
+            ),
+            "udp.srcport" => (
+                PacketField::Port {
+                    proto: UDP,
+                    offset: 0,
+                },
+                Type::Int,
+            ),
+            "udp.dstport" => (
+                PacketField::Port {
+                    proto: UDP,
+                    offset: 2,
+                },
+                Type::Int,
+            ),
+            _ => return None,
+        };
+
+        if ty == expected {
+            Some(field)
+        } else {
+            None
+        }
+    }
+}
+
+// What to check about the loaded value once it's in the accumulator.
+enum Test {
+    Ordering(OrderingOp, u32),
+    Ranges(Vec<(u32, u32)>, bool),
+    NonZero,
+    Constant(bool),
+}
+
+type Label = usize;
+
+// An instruction with jumps to labels, which are resolved into relative
+// offsets only once all of the code is generated.
+enum Op {
+    Plain(u16, u32),
+    Jump(u16, u32, Label, Label),
+    Goto(Label),
+}
+
+struct Compiler {
+    ops: Vec<Op>,
+    labels: Vec<usize>,
+    xor_depth: usize,
+    unsupported: Vec<String>,
+}
+
+// Packet values are at most 32 bits and never negative, and integer
+// operators are only compiled if they keep them like that.
+fn to_u32(value: &LhsValue<'_>) -> Result<Option<u32>, &'static str> {
+    match value {
+        LhsValue::Int(value) if *value >= 0 => Ok(Some(*value as u32)),
+        LhsValue::Int(_) => Ok(None),
+        LhsValue::Ip(IpAddr::V4(addr)) => Ok(Some(u32::from(*addr))),
+        LhsValue::Ip(IpAddr::V6(_)) => Err("IPv6 addresses are not supported"),
+        _ => Err("unsupported value"),
+    }
+}
+
+fn to_range(range: &RangeInclusive<LhsValue<'_>>) -> Result<Option<(u32, u32)>, &'static str> {
+    Ok(match (to_u32(range.start())?, to_u32(range.end())?) {
+        (Some(start), Some(end)) => Some((start, end)),
+        // Only the non-negative part of the range can match.
+        (None, Some(end)) => Some((0, end)),
+        (_, None) => None,
+    })
+}
+
+fn ordering_test(op: OrderingOp, value: &LhsValue<'_>) -> Result<Test, &'static str> {
+    Ok(match to_u32(value)? {
+        Some(value) => Test::Ordering(op, value),
+        None => Test::Constant(match op {
+            OrderingOp::NotEqual | OrderingOp::GreaterThan | OrderingOp::GreaterThanEqual => true,
+            OrderingOp::Equal | OrderingOp::LessThan | OrderingOp::LessThanEqual => false,
+        }),
+    })
+}
+
+impl Compiler {
+    fn label(&mut self) -> Label {
+        self.labels.push(usize::max_value());
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: Label) {
+        self.labels[label] = self.ops.len();
+    }
+
+    fn plain(&mut self, code: u16, k: u32) {
+        self.ops.push(Op::Plain(code, k));
+    }
+
+    fn jump(&mut self, code: u16, k: u32, on_true: Label, on_false: Label) {
+        self.ops.push(Op::Jump(code, k, on_true, on_false));
+    }
+
+    fn goto(&mut self, label: Label) {
+        self.ops.push(Op::Goto(label));
+    }
+
+    // Continues only if the jump would be taken, and goes to `on_false`
+    // otherwise.
+    fn require(&mut self, code: u16, k: u32, on_false: Label) {
+        let next = self.label();
+        self.jump(code, k, next, on_false);
+        self.place(next);
+    }
+
+    fn combined(&mut self, expr: &CombinedExpr<'_>, on_true: Label, on_false: Label) {
+        let (op, items) = match expr {
+            CombinedExpr::Simple(expr) => return self.simple(expr, on_true, on_false),
+            CombinedExpr::Combining { op, items } => (*op, items),
+        };
+
+        let (last, rest) = items.split_last().unwrap();
+
+        match op {
+            CombiningOp::And => {
+                for item in rest {
+                    let next = self.label();
+                    self.combined(item, next, on_false);
+                    self.place(next);
+                }
+                self.combined(last, on_true, on_false);
+            }
+            CombiningOp::Or => {
+                for item in rest {
+                    let next = self.label();
+                    self.combined(item, on_true, next);
+                    self.place(next);
+                }
+                self.combined(last, on_true, on_false);
+            }
+            CombiningOp::Xor => {
+                // Matched items are counted in scratch memory, one slot for
+                // each level of nested `xor`.
+                let slot = self.xor_depth as u32;
+                if self.xor_depth == MEMORY_SLOTS {
+                    self.unsupported
+                        .push(format!("more than {} nested xor", MEMORY_SLOTS));
+                    return;
+                }
+                self.xor_depth += 1;
+
+                self.plain(LD_IMM, 0);
+                self.plain(ST, slot);
+                for item in items {
+                    let (matched, next) = (self.label(), self.label());
+                    self.combined(item, matched, next);
+                    self.place(matched);
+                    self.plain(LD_MEM, slot);
+                    self.plain(ALU_ADD_K, 1);
+                    self.plain(ST, slot);
+                    self.place(next);
+                }
+                self.plain(LD_MEM, slot);
+                self.jump(JMP_JSET_K, 1, on_true, on_false);
+
+                self.xor_depth -= 1;
+            }
+        }
+    }
+
+    fn simple(&mut self, expr: &SimpleExpr<'_>, on_true: Label, on_false: Label) {
+        match expr {
+            SimpleExpr::Field(expr) => {
+                if let Err(reason) = self.comparison(expr, on_true, on_false) {
+                    self.unsupported.push(format!("`{}`: {}", expr, reason));
+                }
+            }
+            SimpleExpr::Parenthesized(expr) => self.combined(expr, on_true, on_false),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => self.simple(arg, on_false, on_true),
+            // Fields that are not in the scheme are never set.
+            SimpleExpr::Missing { .. } => self.goto(on_false),
+        }
+    }
+
+    fn comparison(
+        &mut self,
+        expr: &FieldExpr<'_>,
+        on_true: Label,
+        on_false: Label,
+    ) -> Result<(), &'static str> {
+        if expr.call().is_some() {
+            return Err("function calls are not supported");
+        }
+
+        let field = match expr.subscript() {
+            Some(_) => None,
+            None => PacketField::new(expr.field(), expr.fields()[0].get_type()),
+        };
+        let field = field.ok_or("not a packet field")?;
+
+        let rhs = expr.rhs();
+        let (alu, test) = match (expr.op(), &rhs) {
+            (Some(ComparisonOp::Ordering(op)), ComparisonRhs::Literal(value)) => {
+                (None, ordering_test(op, value)?)
+            }
+            (Some(ComparisonOp::Ordering(op)), ComparisonRhs::Cidr(range)) => {
+                let ranges = to_range(range)?.into_iter().collect();
+                (None, Test::Ranges(ranges, op == OrderingOp::NotEqual))
+            }
+            (Some(ComparisonOp::In), ComparisonRhs::List(ranges)) => {
+                let mut values = Vec::new();
+                for range in ranges {
+                    values.extend(to_range(range)?);
+                }
+                (None, Test::Ranges(values, false))
+            }
+            (Some(ComparisonOp::Int(op)), ComparisonRhs::Literal(LhsValue::Int(rhs))) => {
+                let alu = match op {
+                    // Masks are applied to the bits of non-negative values,
+                    // which are the same for both signed and unsigned ones.
+                    IntOp::BitwiseAnd => (ALU_AND_K, *rhs as u32),
+                    IntOp::ShiftRight => (ALU_RSH_K, *rhs as u32),
+                    IntOp::ShiftLeft => return Err("`<<` is not supported"),
+                };
+                let test = match expr.int_result_comparison() {
+                    Some((op, value)) => ordering_test(op, &LhsValue::Int(value))?,
+                    None => Test::NonZero,
+                };
+                (Some(alu), test)
+            }
+            (_, ComparisonRhs::Constant(_))
+            | (_, ComparisonRhs::NamedList(_))
+            | (_, ComparisonRhs::Pattern(_)) => {
+                return Err("values supplied at execution time are not supported");
+            }
+            _ => return Err("unsupported operator"),
+        };
+
+        self.load(field, on_false);
+        if let Some((code, k)) = alu {
+            self.plain(code, k);
+        }
+        self.test(test, on_true, on_false);
+        Ok(())
+    }
+
+    // Loads the field into the accumulator, or goes to `on_false` if the
+    // packet doesn't have it, as comparisons of unset fields never match.
+    fn load(&mut self, field: PacketField, on_false: Label) {
+        self.plain(LD_H_ABS, ETHER_TYPE);
+        self.require(JMP_JEQ_K, ETHER_TYPE_IPV4, on_false);
+
+        let (proto, code, offset) = match field {
+            PacketField::IpSrc => return self.plain(LD_W_ABS, IPV4_SRC),
+            PacketField::IpDst => return self.plain(LD_W_ABS, IPV4_DST),
+            PacketField::IpProto => return self.plain(LD_B_ABS, IPV4_PROTO),
+            PacketField::TcpFlags => (6, LD_B_IND, 13),
+            PacketField::Port { proto, offset } => (proto, LD_H_IND, offset),
+        };
+
+        self.plain(LD_B_ABS, IPV4_PROTO);
+        self.require(JMP_JEQ_K, proto, on_false);
+        // Only the first fragment has the transport header.
+        self.plain(LD_H_ABS, IPV4_FRAGMENT);
+        let next = self.label();
+        self.jump(JMP_JSET_K, 0x1fff, on_false, next);
+        self.place(next);
+        // The IPv4 header length is in the low 4 bits, in 32-bit words.
+        self.plain(LDX_B_MSH, IPV4_HEADER);
+        self.plain(code, IPV4_HEADER + offset);
+    }
+
+    fn test(&mut self, test: Test, on_true: Label, on_false: Label) {
+        match test {
+            Test::Ordering(op, k) => match op {
+                OrderingOp::Equal => self.jump(JMP_JEQ_K, k, on_true, on_false),
+                OrderingOp::NotEqual => self.jump(JMP_JEQ_K, k, on_false, on_true),
+                OrderingOp::GreaterThan => self.jump(JMP_JGT_K, k, on_true, on_false),
+                OrderingOp::GreaterThanEqual => self.jump(JMP_JGE_K, k, on_true, on_false),
+                OrderingOp::LessThan => self.jump(JMP_JGE_K, k, on_false, on_true),
+                OrderingOp::LessThanEqual => self.jump(JMP_JGT_K, k, on_false, on_true),
+            },
+            Test::Ranges(ranges, negated) => {
+                let (on_true, on_false) = if negated {
+                    (on_false, on_true)
+                } else {
+                    (on_true, on_false)
+                };
+
+                if ranges.is_empty() {
+                    return self.goto(on_false);
+                }
+
+                for (i, &(start, end)) in ranges.iter().enumerate() {
+                    let next = if i + 1 == ranges.len() {
+                        on_false
+                    } else {
+                        self.label()
+                    };
+
+                    if start == end {
+                        self.jump(JMP_JEQ_K, start, on_true, next);
+                    } else {
+                        if start > 0 {
+                            self.require(JMP_JGE_K, start, next);
+                        }
+                        self.jump(JMP_JGT_K, end, next, on_true);
+                    }
+
+                    if next != on_false {
+                        self.place(next);
+                    }
+                }
+            }
+            Test::NonZero => self.jump(JMP_JEQ_K, 0, on_false, on_true),
+            Test::Constant(value) => self.goto(if value { on_true } else { on_false }),
+        }
+    }
+
+    fn assemble(self) -> Result<Vec<BpfInstruction>, BpfCompileError> {
+        if !self.unsupported.is_empty() {
+            return Err(BpfCompileError::Unsupported(self.unsupported));
+        }
+
+        if self.ops.len() > MAX_INSTRUCTIONS {
+            return Err(BpfCompileError::TooLarge);
+        }
+
+        let labels = self.labels;
+        let offset = |pc: usize, label: Label| labels[label] - (pc + 1);
+        let short = |offset: usize| {
+            if offset <= 255 {
+                Ok(offset as u8)
+            } else {
+                Err(BpfCompileError::TooLarge)
+            }
+        };
+
+        self.ops
+            .iter()
+            .enumerate()
+            .map(|(pc, op)| {
+                Ok(match *op {
+                    Op::Plain(code, k) => BpfInstruction {
+                        code,
+                        jt: 0,
+                        jf: 0,
+                        k,
+                    },
+                    Op::Jump(code, k, on_true, on_false) => BpfInstruction {
+                        code,
+                        jt: short(offset(pc, on_true))?,
+                        jf: short(offset(pc, on_false))?,
+                        k,
+                    },
+                    Op::Goto(label) => BpfInstruction {
+                        code: JMP_JA,
+                        jt: 0,
+                        jf: 0,
+                        k: offset(pc, label) as u32,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn compile(ast: &FilterAst<'_>) -> Result<Vec<BpfInstruction>, BpfCompileError> {
+    let mut compiler = Compiler {
+        ops: Vec::new(),
+        labels: Vec::new(),
+        xor_depth: 0,
+        unsupported: Vec::new(),
+    };
+
+    let (accept, reject) = (compiler.label(), compiler.label());
+    compiler.combined(&ast.op, accept, reject);
+    compiler.place(accept);
+    compiler.plain(RET_K, u32::max_value());
+    compiler.place(reject);
+    compiler.plain(RET_K, 0);
+
+    compiler.assemble()
+}
+
+#[test]
+fn test_compile_bpf() {
+    use execution_context::ExecutionContext;
+    use scheme::Scheme;
+
+    let scheme = Scheme! {
+        ip.src: Ip,
+        ip.dst: Ip,
+        ip.proto: Int,
+        tcp.srcport: Int,
+        tcp.dstport: Int,
+        tcp.flags: Int,
+        udp.srcport: Int,
+        udp.dstport: Int,
+        http.host: Bytes,
+    };
+
+    assert_eq!(
+        scheme
+            .parse("ip.src == 10.0.0.1")
+            .unwrap()
+            .compile_bpf()
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        [
+            "{ 0x28, 0, 0, 0x0000000c }",
+            "{ 0x15, 0, 3, 0x00000800 }",
+            "{ 0x20, 0, 0, 0x0000001a }",
+            "{ 0x15, 0, 1, 0x0a000001 }",
+            "{ 0x06, 0, 0, 0xffffffff }",
+            "{ 0x06, 0, 0, 0x00000000 }",
+        ]
+    );
+
+    // A classic BPF interpreter, with only the instructions that are used.
+    fn run(program: &[BpfInstruction], packet: &[u8]) -> bool {
+        let (mut a, mut x, mut mem) = (0u32, 0u32, [0u32; MEMORY_SLOTS]);
+        let mut pc = 0;
+        let load = |offset: u32, len: usize| {
+            let bytes = packet.get(offset as usize..offset as usize + len)?;
+            Some(bytes.iter().fold(0, |a, &b| a << 8 | u32::from(b)))
+        };
+
+        loop {
+            let BpfInstruction { code, jt, jf, k } = program[pc];
+            pc += 1;
+            let cond = match code {
+                LD_W_ABS => load(k, 4),
+                LD_H_ABS => load(k, 2),
+                LD_B_ABS => load(k, 1),
+                LD_H_IND => load(x + k, 2),
+                LD_B_IND => load(x + k, 1),
+                LD_IMM => Some(k),
+                LD_MEM => Some(mem[k as usize]),
+                LDX_B_MSH => {
+                    match load(k, 1) {
+                        Some(value) => x = (value & 0xf) * 4,
+                        None => return false,
+                    }
+                    continue;
+                }
+                ST => {
+                    mem[k as usize] = a;
+                    continue;
+                }
+                ALU_ADD_K => Some(a + k),
+                ALU_AND_K => Some(a & k),
+                ALU_RSH_K => Some(a >> k),
+                JMP_JA => {
+                    pc += k as usize;
+                    continue;
+                }
+                JMP_JEQ_K | JMP_JGT_K | JMP_JGE_K | JMP_JSET_K => {
+                    let taken = match code {
+                        JMP_JEQ_K => a == k,
+                        JMP_JGT_K => a > k,
+                        JMP_JGE_K => a >= k,
+                        _ => a & k != 0,
+                    };
+                    pc += if taken { jt } else { jf } as usize;
+                    continue;
+                }
+                RET_K => return k != 0,
+                _ => unreachable!(),
+            };
+            // Loads out of bounds reject the packet.
+            a = match cond {
+                Some(value) => value,
+                None => return false,
+            };
+        }
+    }
+
+    struct Packet {
+        ipv4: bool,
+        src: [u8; 4],
+        dst: [u8; 4],
+        proto: u8,
+        options: usize,
+        fragment: u16,
+        ports: (u16, u16),
+        flags: u8,
+    }
+
+    impl Packet {
+        fn bytes(&self) -> Vec<u8> {
+            let mut bytes = vec![0; 12];
+            bytes.extend_from_slice(if self.ipv4 { &[8, 0] } else { &[0x86, 0xdd] });
+            bytes.push(0x45 + self.options as u8);
+            bytes.extend_from_slice(&[0, 0, 0, 0, 0]);
+            bytes.push((self.fragment >> 8) as u8);
+            bytes.push(self.fragment as u8);
+            bytes.extend_from_slice(&[64, self.proto, 0, 0]);
+            bytes.extend_from_slice(&self.src);
+            bytes.extend_from_slice(&self.dst);
+            bytes.extend(vec![0; self.options * 4]);
+            for port in &[self.ports.0, self.ports.1] {
+                bytes.push((*port >> 8) as u8);
+                bytes.push(*port as u8);
+            }
+            bytes.extend_from_slice(&[0; 9]);
+            bytes.push(self.flags);
+            bytes.extend_from_slice(&[0; 6]);
+            bytes
+        }
+
+        fn context<'s>(&self, scheme: &'s Scheme) -> ExecutionContext<'s> {
+            let mut ctx = ExecutionContext::new(scheme);
+            if !self.ipv4 {
+                return ctx;
+            }
+            ctx.set_field_value("ip.src", IpAddr::from(self.src))
+                .unwrap();
+            ctx.set_field_value("ip.dst", IpAddr::from(self.dst))
+                .unwrap();
+            ctx.set_field_value("ip.proto", i32::from(self.proto))
+                .unwrap();
+            if self.fragment & 0x1fff != 0 {
+                return ctx;
+            }
+            let transport = match self.proto {
+                6 => "tcp",
+                17 => "udp",
+                _ => return ctx,
+            };
+            ctx.set_field_value(&format!("{}.srcport", transport), i32::from(self.ports.0))
+                .unwrap();
+            ctx.set_field_value(&format!("{}.dstport", transport), i32::from(self.ports.1))
+                .unwrap();
+            if self.proto == 6 {
+                ctx.set_field_value("tcp.flags", i32::from(self.flags))
+                    .unwrap();
+            }
+            ctx
+        }
+    }
+
+    let base = Packet {
+        ipv4: true,
+        src: [10, 0, 0, 1],
+        dst: [192, 168, 1, 2],
+        proto: 6,
+        options: 0,
+        fragment: 0,
+        ports: (40000, 443),
+        flags: 0x12,
+    };
+    let packets = [
+        Packet { ..base },
+        Packet {
+            options: 2,
+            flags: 0x02,
+            ..base
+        },
+        Packet {
+            proto: 17,
+            ports: (53, 1024),
+            ..base
+        },
+        Packet { proto: 1, ..base },
+        Packet {
+            fragment: 0x2000,
+            ..base
+        },
+        Packet {
+            fragment: 0x0010,
+            ..base
+        },
+        Packet {
+            src: [8, 8, 8, 8],
+            dst: [10, 1, 2, 3],
+            ports: (443, 22),
+            ..base
+        },
+        Packet {
+            ipv4: false,
+            ..base
+        },
+    ];
+
+    for filter in &[
+        "ip.src == 10.0.0.1",
+        "ip.src != 10.0.0.1",
+        "ip.dst in { 10.0.0.0/8 192.168.0.0/16 }",
+        "ip.dst == 192.168.0.0/16 && not ip.src == 10.0.0.0/24",
+        "ip.src != 10.0.0.0/8",
+        "ip.src > 9.255.255.255 and ip.src <= 10.0.0.1",
+        "ip.dst between 10.0.0.0..10.255.255.255",
+        "ip.proto == 6 || ip.proto == 17",
+        "tcp.dstport == 443",
+        "tcp.dstport != 443",
+        "udp.srcport == 53 or udp.dstport == 53",
+        "tcp.srcport in { 22 80 1000..50000 }",
+        "tcp.srcport in { -10..22 }",
+        "tcp.srcport < 1024 || tcp.dstport >= 1024",
+        "tcp.srcport > -1",
+        "tcp.srcport == -1",
+        "tcp.flags & 0x02",
+        "tcp.flags & 0x12 == 0x12",
+        "tcp.flags & -1 == 0x12",
+        "tcp.srcport >> 8 == 156",
+        "not tcp.flags & 0x10",
+        "tcp.dstport == 443 ^^ udp.srcport == 53 ^^ ip.src == 8.8.8.8",
+        "(ip.proto == 6 ^^ ip.proto == 17) ^^ not (ip.src == 10.0.0.1 ^^ tcp.dstport == 22)",
+    ] {
+        let ast = scheme.parse(filter).unwrap();
+        let program = ast.compile_bpf().unwrap();
+        let compiled = ast.compile();
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(
+                run(&program, &packet.bytes()),
+                compiled.execute(&packet.context(&scheme)).unwrap(),
+                "{} on packet {}",
+                filter,
+                i
+            );
+        }
+    }
+
+    assert_eq!(
+        scheme
+            .parse(r#"ip.src == ::1 or http.host == "a" or tcp.srcport << 1 == 2"#)
+            .unwrap()
+            .compile_bpf(),
+        Err(BpfCompileError::Unsupported(vec![
+            "`ip.src == ::1`: IPv6 addresses are not supported".to_owned(),
+            r#"`http.host == "a"`: not a packet field"#.to_owned(),
+            "`tcp.srcport << 1 == 2`: `<<` is not supported".to_owned(),
+        ]))
+    );
+    assert_eq!(
+        BpfCompileError::Unsupported(vec!["a".to_owned(), "b".to_owned()]).to_string(),
+        "cannot compile to BPF: a; b"
+    );
+
+    let ports = (0..300)
+        .map(|port| (port * 2).to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    assert_eq!(
+        scheme
+            .parse(&format!("tcp.dstport in {{ {} }}", ports))
+            .unwrap()
+            .compile_bpf(),
+        Err(BpfCompileError::TooLarge)
+    );
+}
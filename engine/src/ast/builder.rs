@@ -0,0 +1,573 @@
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{BytesOp, ComparisonOp, FieldExpr, FieldOp, IntOp, OrderingOp},
+    simple_expr::{SimpleExpr, UnaryOp},
+    FilterAst,
+};
+use cidr::IpCidr;
+use failure::Fail;
+use lex::LexErrorKind;
+use rhs_types::{Float, IpRange, Regex};
+use scheme::Scheme;
+use std::{
+    ops::Not,
+    str::{self, FromStr},
+};
+use types::{GetType, LhsValue, RhsValue, RhsValues, Type};
+
+/// An error that occurs when a [`FilterBuilder`] doesn't type-check against
+/// a [`Scheme`](struct@Scheme).
+///
+/// These are the same errors that parser would report for an equivalent
+/// filter string, along with the name of the field they occurred on.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "field {}: {}", field, kind)]
+pub struct BuildError {
+    field: String,
+    #[cause]
+    kind: LexErrorKind,
+}
+
+impl BuildError {
+    pub(crate) fn new(field: &str, kind: LexErrorKind) -> Self {
+        BuildError {
+            field: field.to_owned(),
+            kind,
+        }
+    }
+
+    /// Returns the name of the field the error occurred on.
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Rhs(RhsValue),
+    // Values that have no RHS representation, like booleans, which can't be
+    // compared against, but still should produce a proper type error.
+    Unrepresentable(Type),
+}
+
+impl<'v> From<LhsValue<'v>> for Value {
+    fn from(value: LhsValue<'v>) -> Self {
+        Value::Rhs(match value {
+            LhsValue::Ip(addr) => RhsValue::Ip(addr),
+            // Valid UTF-8 is stored as a string, which is what the parser does
+            // for quoted literals.
+            LhsValue::Bytes(bytes) => RhsValue::Bytes(match str::from_utf8(bytes) {
+                Ok(s) => s.to_owned().into(),
+                Err(_) => bytes.to_vec().into(),
+            }),
+            LhsValue::Int(value) => RhsValue::Int(value),
+            LhsValue::Bool(_) => return Value::Unrepresentable(Type::Bool),
+            LhsValue::Cidr(cidr) => RhsValue::Cidr(cidr),
+            LhsValue::Endpoint(endpoint) => RhsValue::Endpoint(endpoint),
+            // NaN can't be written as a literal either.
+            LhsValue::Float(value) => match Float::new(value) {
+                Some(value) => RhsValue::Float(value),
+                None => return Value::Unrepresentable(Type::Float),
+            },
+            LhsValue::Timestamp(timestamp) => RhsValue::Timestamp(timestamp),
+            LhsValue::Mac(mac) => RhsValue::Mac(mac),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    IsTrue,
+    Ordering(OrderingOp, Value),
+    BitwiseAnd(i32),
+    Contains(Value),
+    Matches(String),
+    InCidrs(Vec<String>),
+}
+
+impl Op {
+    fn comparison_op(&self) -> Option<ComparisonOp> {
+        Some(match self {
+            Op::IsTrue => return None,
+            Op::Ordering(op, _) => ComparisonOp::Ordering(*op),
+            Op::BitwiseAnd(_) => ComparisonOp::Int(IntOp::BitwiseAnd),
+            Op::Contains(_) => ComparisonOp::Bytes(BytesOp::Contains),
+            Op::Matches(_) => ComparisonOp::Bytes(BytesOp::Matches),
+            Op::InCidrs(_) => ComparisonOp::In,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Field { name: String, op: Op },
+    Not(Box<Node>),
+    Combining { op: CombiningOp, items: Vec<Node> },
+}
+
+/// Entry point for building a comparison on a field with a given name.
+pub fn field(name: &str) -> FieldBuilder {
+    FieldBuilder {
+        name: name.to_owned(),
+    }
+}
+
+/// A field reference that is waiting for an operation to be applied.
+///
+/// Created by the [`field`] function.
+#[derive(Debug, Clone)]
+pub struct FieldBuilder {
+    name: String,
+}
+
+macro_rules! ordering_ops {
+    ($($(# $attrs:tt)* $name:ident => $op:ident,)*) => {
+        $(
+            $(# $attrs)*
+            pub fn $name<'v>(self, value: impl Into<LhsValue<'v>>) -> FilterBuilder {
+                self.op(Op::Ordering(OrderingOp::$op, value.into().into()))
+            }
+        )*
+    };
+}
+
+impl FieldBuilder {
+    fn op(self, op: Op) -> FilterBuilder {
+        FilterBuilder {
+            node: Node::Field {
+                name: self.name,
+                op,
+            },
+        }
+    }
+
+    ordering_ops! {
+        /// Builds `field == value`.
+        eq => Equal,
+        /// Builds `field != value`.
+        ne => NotEqual,
+        /// Builds `field >= value`.
+        ge => GreaterThanEqual,
+        /// Builds `field <= value`.
+        le => LessThanEqual,
+        /// Builds `field > value`.
+        gt => GreaterThan,
+        /// Builds `field < value`.
+        lt => LessThan,
+    }
+
+    /// Builds a check of a boolean field on its own.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn is_true(self) -> FilterBuilder {
+        self.op(Op::IsTrue)
+    }
+
+    /// Builds `field & mask`.
+    pub fn bitwise_and(self, mask: i32) -> FilterBuilder {
+        self.op(Op::BitwiseAnd(mask))
+    }
+
+    /// Builds `field contains value`.
+    pub fn contains<'v>(self, value: impl Into<LhsValue<'v>>) -> FilterBuilder {
+        self.op(Op::Contains(value.into().into()))
+    }
+
+    /// Builds `field matches regex`.
+    pub fn matches(self, regex: &str) -> FilterBuilder {
+        self.op(Op::Matches(regex.to_owned()))
+    }
+
+    /// Builds `field in { cidr }`.
+    pub fn in_cidr(self, cidr: &str) -> FilterBuilder {
+        self.in_cidrs(&[cidr])
+    }
+
+    /// Builds `field in { cidr ... }` with networks in the given order.
+    pub fn in_cidrs<S: AsRef<str>>(self, cidrs: &[S]) -> FilterBuilder {
+        self.op(Op::InCidrs(
+            cidrs.iter().map(|cidr| cidr.as_ref().to_owned()).collect(),
+        ))
+    }
+}
+
+/// A filter expression that can be combined with others and then built into
+/// a [`FilterAst`] for a particular [`Scheme`](struct@Scheme).
+///
+/// Produced AST is the same as the one parser would produce for the
+/// equivalent filter string with the minimal required parentheses.
+#[derive(Debug, Clone)]
+pub struct FilterBuilder {
+    node: Node,
+}
+
+impl FilterBuilder {
+    fn combine(self, op: CombiningOp, other: FilterBuilder) -> Self {
+        FilterBuilder {
+            node: match self.node {
+                Node::Combining {
+                    op: lhs_op,
+                    mut items,
+                } => {
+                    if lhs_op == op {
+                        items.push(other.node);
+                        Node::Combining { op, items }
+                    } else {
+                        Node::Combining {
+                            op,
+                            items: vec![Node::Combining { op: lhs_op, items }, other.node],
+                        }
+                    }
+                }
+                lhs => Node::Combining {
+                    op,
+                    items: vec![lhs, other.node],
+                },
+            },
+        }
+    }
+
+    /// Builds `self and other`.
+    pub fn and(self, other: FilterBuilder) -> Self {
+        self.combine(CombiningOp::And, other)
+    }
+
+    /// Builds `self or other`.
+    pub fn or(self, other: FilterBuilder) -> Self {
+        self.combine(CombiningOp::Or, other)
+    }
+
+    /// Builds `self xor other`.
+    pub fn xor(self, other: FilterBuilder) -> Self {
+        self.combine(CombiningOp::Xor, other)
+    }
+
+    /// Resolves fields and type-checks the expression against a given scheme.
+    ///
+    /// Returns only the first error, see [`build_all`](FilterBuilder::build_all)
+    /// for getting all of them.
+    pub fn build(self, scheme: &Scheme) -> Result<FilterAst<'_>, BuildError> {
+        self.build_all(scheme)
+            .map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Resolves fields and type-checks the expression against a given scheme,
+    /// reporting every error in the whole expression, in order.
+    pub fn build_all(self, scheme: &Scheme) -> Result<FilterAst<'_>, Vec<BuildError>> {
+        let mut errors = Vec::new();
+        match build_combined(self.node, scheme, &mut errors) {
+            Some(op) => Ok(FilterAst {
+                scheme,
+                op,
+                warnings: Vec::new(),
+                comments: Vec::new(),
+                span: None,
+            }),
+            None => Err(errors),
+        }
+    }
+}
+
+impl Not for FilterBuilder {
+    type Output = Self;
+
+    /// Builds `not self`.
+    fn not(self) -> Self {
+        FilterBuilder {
+            node: Node::Not(Box::new(self.node)),
+        }
+    }
+}
+
+// Errors are collected into `errors` instead of returning early, so that
+// all subtrees get checked, and `None` is returned if there were any.
+fn build_combined<'s>(
+    node: Node,
+    scheme: &'s Scheme,
+    errors: &mut Vec<BuildError>,
+) -> Option<CombinedExpr<'s>> {
+    Some(match node {
+        Node::Combining { op, items } => CombinedExpr::Combining {
+            op,
+            items: items
+                .into_iter()
+                .map(|item| match item {
+                    // Items binding tighter than the parent don't need
+                    // parentheses, just like in the parsed filter.
+                    Node::Combining { op: item_op, .. }
+                        if item_op.precedence() > op.precedence() =>
+                    {
+                        build_combined(item, scheme, errors)
+                    }
+                    item => build_simple(item, scheme, errors).map(CombinedExpr::Simple),
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .collect::<Option<_>>()?,
+        },
+        node => CombinedExpr::Simple(build_simple(node, scheme, errors)?),
+    })
+}
+
+fn build_simple<'s>(
+    node: Node,
+    scheme: &'s Scheme,
+    errors: &mut Vec<BuildError>,
+) -> Option<SimpleExpr<'s>> {
+    Some(match node {
+        Node::Field { name, op } => match build_field(&name, op, scheme) {
+            Ok(expr) => SimpleExpr::Field(expr),
+            Err(err) => {
+                errors.push(err);
+                return None;
+            }
+        },
+        Node::Not(arg) => SimpleExpr::Unary {
+            op: UnaryOp::Not,
+            arg: Box::new(build_simple(*arg, scheme, errors)?),
+        },
+        node => SimpleExpr::Parenthesized(Box::new(build_combined(node, scheme, errors)?)),
+    })
+}
+
+fn build_field<'s>(name: &str, op: Op, scheme: &'s Scheme) -> Result<FieldExpr<'s>, BuildError> {
+    let error = |kind| BuildError::new(name, kind);
+
+    let field = scheme
+        .get_field_index(name)
+        .map_err(|err| error(LexErrorKind::UnknownField(err)))?;
+    let field_type = field.get_type();
+
+    let unsupported = |op: &Op| {
+        error(match op.comparison_op() {
+            Some(op) => LexErrorKind::UnsupportedOp { field_type, op },
+            // Parser expects an operator after any non-boolean field.
+            None => LexErrorKind::ExpectedName("ComparisonOp"),
+        })
+    };
+
+    let check_value = |value: Value| {
+        let actual = match value {
+            Value::Rhs(rhs) => {
+                if rhs.get_type() == field_type {
+                    return Ok(rhs);
+                }
+                rhs.get_type()
+            }
+            Value::Unrepresentable(ty) => ty,
+        };
+        Err(error(LexErrorKind::TypeMismatch {
+            expected: field_type,
+            actual,
+        }))
+    };
+
+    let op = match (field_type, op) {
+        (Type::Bool, Op::IsTrue) => FieldOp::IsTrue,
+        (Type::Bool, op) | (_, op @ Op::IsTrue) => return Err(unsupported(&op)),
+        (_, Op::Ordering(op, rhs)) => FieldOp::Ordering {
+            op,
+            rhs: check_value(rhs)?,
+        },
+        (Type::Int, Op::BitwiseAnd(rhs)) => FieldOp::Int {
+            op: IntOp::BitwiseAnd,
+            rhs,
+        },
+        (Type::Bytes, Op::Contains(rhs)) => match check_value(rhs)? {
+            RhsValue::Bytes(bytes) => FieldOp::Contains(bytes),
+            _ => unreachable!(),
+        },
+        (Type::Bytes, Op::Matches(regex)) => FieldOp::Matches(
+            Regex::from_str(&regex).map_err(|err| error(LexErrorKind::ParseRegex(err)))?,
+        ),
+        (Type::Ip, Op::InCidrs(cidrs)) => FieldOp::OneOf(RhsValues::Ip(
+            parse_cidrs(&cidrs)
+                .map_err(error)?
+                .into_iter()
+                .map(IpRange::Cidr)
+                .collect(),
+        )),
+        (Type::Cidr, Op::InCidrs(cidrs)) => {
+            FieldOp::OneOf(RhsValues::Cidr(parse_cidrs(&cidrs).map_err(error)?))
+        }
+        (_, op) => return Err(unsupported(&op)),
+    };
+
+    Ok(FieldExpr::new(field, op))
+}
+
+fn parse_cidrs(cidrs: &[String]) -> Result<Vec<IpCidr>, LexErrorKind> {
+    cidrs
+        .iter()
+        .map(|cidr| IpCidr::from_str(cidr).map_err(LexErrorKind::ParseNetwork))
+        .collect()
+}
+
+#[test]
+fn test_builder_matches_parser() {
+    let scheme = &Scheme! {
+        tcp.port: Int,
+        ip.src: Ip,
+        http.host: Bytes,
+        ssl: Bool,
+    };
+
+    let built = field("tcp.port")
+        .eq(443)
+        .and(field("ip.src").in_cidr("10.0.0.0/8"))
+        .build(scheme)
+        .unwrap();
+
+    assert_eq!(
+        built,
+        scheme
+            .parse("tcp.port == 443 and ip.src in { 10.0.0.0/8 }")
+            .unwrap()
+    );
+
+    let built = field("ip.src")
+        .in_cidrs(&["10.0.0.0/8", "192.168.0.0/16", "fd00::/8"])
+        .or(!field("tcp.port").eq(80))
+        .build(scheme)
+        .unwrap();
+
+    assert_eq!(
+        built,
+        scheme
+            .parse("ip.src in { 10.0.0.0/8 192.168.0.0/16 fd00::/8 } or not tcp.port == 80")
+            .unwrap()
+    );
+
+    let built = field("ssl")
+        .is_true()
+        .or(field("http.host").contains("abc"))
+        .and(!field("tcp.port").bitwise_and(1))
+        .and(field("http.host").matches("^a.*$"))
+        .or(field("tcp.port")
+            .ge(1024)
+            .xor(field("tcp.port").lt(2048).and(field("ssl").is_true())))
+        .build(scheme)
+        .unwrap();
+
+    assert_eq!(
+        built,
+        scheme
+            .parse(
+                r#"(ssl or http.host contains "abc") and not tcp.port & 1 and http.host matches "^a.*$" or tcp.port >= 1024 xor tcp.port < 2048 and ssl"#
+            )
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_builder_errors() {
+    let scheme = &Scheme! {
+        tcp.port: Int,
+        ip.src: Ip,
+        ssl: Bool,
+    };
+
+    let error = |field: &str, kind| BuildError {
+        field: field.to_owned(),
+        kind,
+    };
+
+    assert_eq!(
+        field("tcp.port").eq("443").build(scheme),
+        Err(error(
+            "tcp.port",
+            LexErrorKind::TypeMismatch {
+                expected: Type::Int,
+                actual: Type::Bytes,
+            }
+        ))
+    );
+
+    assert_eq!(
+        field("ssl").eq(true).build(scheme),
+        Err(error(
+            "ssl",
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Bool,
+                op: ComparisonOp::Ordering(OrderingOp::Equal),
+            }
+        ))
+    );
+
+    assert_eq!(
+        field("ip.src").contains("abc").build(scheme),
+        Err(error(
+            "ip.src",
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Ip,
+                op: ComparisonOp::Bytes(BytesOp::Contains),
+            }
+        ))
+    );
+
+    assert_eq!(
+        field("tcp.port").is_true().build(scheme),
+        Err(error(
+            "tcp.port",
+            LexErrorKind::ExpectedName("ComparisonOp")
+        ))
+    );
+
+    assert_eq!(
+        field("tcp.dst").eq(443).build(scheme),
+        Err(error(
+            "tcp.dst",
+            LexErrorKind::UnknownField(::scheme::UnknownFieldError)
+        ))
+    );
+
+    assert!(field("ip.src")
+        .in_cidr("10.0.0.0/33")
+        .build(scheme)
+        .is_err());
+
+    assert!(field("ip.src")
+        .in_cidrs(&["10.0.0.0/8", "10.0.0.0/33"])
+        .build(scheme)
+        .is_err());
+}
+
+#[test]
+fn test_builder_all_errors() {
+    let scheme = &Scheme! {
+        tcp.port: Int,
+        ip.src: Ip,
+        ssl: Bool,
+    };
+
+    let builder = field("ssl")
+        .is_true()
+        .and(!field("tcp.port").contains("abc"))
+        .or(field("ssl").is_true().and(field("ip.src").eq(443)));
+
+    let errors = builder.clone().build_all(scheme).unwrap_err();
+
+    assert_eq!(
+        errors
+            .iter()
+            .map(|err| (err.field(), err.to_string()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "tcp.port",
+                "field tcp.port: cannot use operator contains on a field of type int".to_owned()
+            ),
+            (
+                "ip.src",
+                "field ip.src: expected value of type ip, but got int".to_owned()
+            ),
+        ]
+    );
+
+    // `build` reports just the first one.
+    assert_eq!(
+        builder.build(scheme).unwrap_err(),
+        errors.into_iter().next().unwrap()
+    );
+
+    assert!(field("ssl").is_true().build_all(scheme).is_ok());
+}
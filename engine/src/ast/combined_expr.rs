@@ -1,14 +1,47 @@
-use super::{simple_expr::SimpleExpr, CompiledExpr, Expr};
+use super::{
+    field_expr::{ContainsLhs, FieldExpr},
+    precedence::{AND_PRECEDENCE, OR_PRECEDENCE, XOR_PRECEDENCE},
+    profile::{comparisons_span, Profiler},
+    simple_expr::SimpleExpr,
+    ternary::Ternary,
+    trace::TraceEntry,
+    write_tree_line, CompiledExpr, Expr,
+};
+use execution_context::ExecutionContext;
 use lex::{skip_space, Lex, LexResult, LexWith};
-use scheme::{Field, Scheme};
+use parser::Parser;
+use program::Instruction;
+use rhs_types::Bytes;
+use scheme::Field;
 use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
 
-lex_enum!(#[derive(PartialOrd, Ord)] CombiningOp {
+lex_enum!(CombiningOp {
     "or" | "||" => Or,
     "xor" | "^^" => Xor,
     "and" | "&&" => And,
 });
 
+impl CombiningOp {
+    pub(crate) fn precedence(self) -> u8 {
+        match self {
+            CombiningOp::Or => OR_PRECEDENCE,
+            CombiningOp::Xor => XOR_PRECEDENCE,
+            CombiningOp::And => AND_PRECEDENCE,
+        }
+    }
+}
+
+impl Display for CombiningOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CombiningOp::Or => "||",
+            CombiningOp::Xor => "^^",
+            CombiningOp::And => "&&",
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 #[serde(untagged)]
 pub enum CombinedExpr<'s> {
@@ -19,6 +52,23 @@ pub enum CombinedExpr<'s> {
     },
 }
 
+impl<'s> Display for CombinedExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CombinedExpr::Simple(op) => op.fmt(f),
+            CombinedExpr::Combining { op, items } => {
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " {} ", op)?;
+                    }
+                    item.fmt(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 impl<'s> CombinedExpr<'s> {
     fn lex_combining_op(input: &str) -> (Option<CombiningOp>, &str) {
         match CombiningOp::lex(skip_space(input)) {
@@ -29,24 +79,25 @@ impl<'s> CombinedExpr<'s> {
 
     fn lex_more_with_precedence<'i>(
         self,
-        scheme: &'s Scheme,
-        min_prec: Option<CombiningOp>,
+        parser: &Parser<'s>,
+        min_prec: Option<u8>,
         mut lookahead: (Option<CombiningOp>, &'i str),
     ) -> LexResult<'i, Self> {
         let mut lhs = self;
 
         while let Some(op) = lookahead.0 {
-            let mut rhs = SimpleExpr::lex_with(lookahead.1, scheme)
+            let mut rhs = SimpleExpr::lex_with(lookahead.1, parser)
                 .map(|(op, input)| (CombinedExpr::Simple(op), input))?;
 
             loop {
                 lookahead = Self::lex_combining_op(rhs.1);
-                if lookahead.0 <= Some(op) {
+                let lookahead_prec = lookahead.0.map(CombiningOp::precedence);
+                if lookahead_prec <= Some(op.precedence()) {
                     break;
                 }
                 rhs = rhs
                     .0
-                    .lex_more_with_precedence(scheme, lookahead.0, lookahead)?;
+                    .lex_more_with_precedence(parser, lookahead_prec, lookahead)?;
             }
 
             match lhs {
@@ -64,7 +115,7 @@ impl<'s> CombinedExpr<'s> {
                 }
             }
 
-            if lookahead.0 < min_prec {
+            if lookahead.0.map(CombiningOp::precedence) < min_prec {
                 // pretend we haven't seen an operator if its precedence is
                 // outside of our limits
                 lookahead = (None, rhs.1);
@@ -73,13 +124,263 @@ impl<'s> CombinedExpr<'s> {
 
         Ok((lhs, lookahead.1))
     }
+
+    /// Combines two expressions with a given operator, in the same shape as
+    /// the parser would produce for `self op other`, parenthesizing operands
+    /// only where their precedence requires it.
+    pub(crate) fn combine(self, op: CombiningOp, other: Self) -> Self {
+        let mut items = Vec::new();
+
+        for operand in vec![self, other] {
+            match operand {
+                CombinedExpr::Combining {
+                    op: operand_op,
+                    items: operand_items,
+                } => {
+                    if operand_op == op {
+                        items.extend(operand_items);
+                    } else {
+                        let operand = CombinedExpr::Combining {
+                            op: operand_op,
+                            items: operand_items,
+                        };
+                        items.push(if operand_op.precedence() > op.precedence() {
+                            operand
+                        } else {
+                            CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(operand)))
+                        });
+                    }
+                }
+                operand => items.push(operand),
+            }
+        }
+
+        CombinedExpr::Combining { op, items }
+    }
+
+    /// Returns the logical negation, with `not` pushed down to the
+    /// comparisons by De Morgan's laws.
+    pub(crate) fn negate(self) -> Self {
+        // Chains are only ever nested through parentheses by the parser, so
+        // the flipped items keep them to preserve the same shape.
+        fn negate_item(item: CombinedExpr<'_>) -> CombinedExpr<'_> {
+            match item.negate() {
+                CombinedExpr::Simple(item) => CombinedExpr::Simple(item),
+                item => CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(item))),
+            }
+        }
+
+        match self {
+            CombinedExpr::Simple(op) => CombinedExpr::Simple(op.negate()),
+            CombinedExpr::Combining { op, items } => {
+                let items = match op {
+                    // `not (a ^ b)` is `not a ^ b`.
+                    CombiningOp::Xor => {
+                        let mut items = items.into_iter();
+                        let first = items.next().map(negate_item);
+                        first.into_iter().chain(items).collect()
+                    }
+                    _ => items.into_iter().map(negate_item).collect(),
+                };
+
+                let op = match op {
+                    CombiningOp::And => CombiningOp::Or,
+                    CombiningOp::Or => CombiningOp::And,
+                    CombiningOp::Xor => CombiningOp::Xor,
+                };
+
+                CombinedExpr::Combining { op, items }
+            }
+        }
+    }
+
+    /// Returns an equivalent expression that's cheaper to execute.
+    ///
+    /// Nested chains of the same operator are flattened, parentheses are
+    /// kept only where precedence requires them, and items of `and`
+    /// and `or` are deduplicated and stably sorted by cost, so regexes run
+    /// after cheaper comparisons. Items that never match, like ones of
+    /// fields missing from the scheme or `in {}`, fold the chain: a single
+    /// one decides an `and`, and they are dropped from `or` and `xor`, and
+    /// the other way around for their negations.
+    pub(crate) fn optimize(self) -> Self {
+        let (op, items) = match self {
+            CombinedExpr::Simple(op) => {
+                return match op.optimize() {
+                    SimpleExpr::Parenthesized(op) => *op,
+                    op => CombinedExpr::Simple(op),
+                };
+            }
+            CombinedExpr::Combining { op, items } => (op, items),
+        };
+
+        let mut flattened = Vec::with_capacity(items.len());
+        for item in items {
+            match item.optimize().into_chain(op) {
+                Ok(items) => flattened.extend(items),
+                Err(CombinedExpr::Combining { op: item_op, items }) => {
+                    let item = CombinedExpr::Combining { op: item_op, items };
+                    flattened.push(if item_op.precedence() > op.precedence() {
+                        item
+                    } else {
+                        CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(item)))
+                    });
+                }
+                Err(item) => flattened.push(item),
+            }
+        }
+
+        // The value that decides the whole chain, if there's one.
+        let absorbing = match op {
+            CombiningOp::And => Some(false),
+            CombiningOp::Or => Some(true),
+            CombiningOp::Xor => None,
+        };
+        let identity = absorbing.map_or(false, |absorbing| !absorbing);
+
+        let mut items: Vec<Self> = Vec::with_capacity(flattened.len());
+        let mut dropped = None;
+        for item in flattened {
+            let constant = item.constant();
+            if constant.is_some() && constant == absorbing {
+                return item;
+            }
+            if constant == Some(identity) {
+                if dropped.is_none() {
+                    dropped = Some(item);
+                }
+            } else if op == CombiningOp::Xor || !items.contains(&item) {
+                items.push(item);
+            }
+        }
+
+        if op != CombiningOp::Xor {
+            items.sort_by_key(|item| item.cost());
+        }
+
+        match items.len() {
+            // Every item was dropped, so the chain is equal to any of them.
+            0 => dropped.expect("chains are never empty"),
+            1 => items.pop().unwrap(),
+            _ => CombinedExpr::Combining { op, items },
+        }
+    }
+
+    // Returns items of a chain of the given operator, or the expression
+    // itself otherwise.
+    fn into_chain(self, op: CombiningOp) -> Result<Vec<Self>, Self> {
+        match self {
+            CombinedExpr::Combining { op: self_op, items } => {
+                if self_op == op {
+                    Ok(items)
+                } else {
+                    Err(CombinedExpr::Combining { op: self_op, items })
+                }
+            }
+            item => Err(item),
+        }
+    }
+
+    // Returns the result of an expression that doesn't depend on the values.
+    pub(crate) fn constant(&self) -> Option<bool> {
+        match self {
+            CombinedExpr::Simple(op) => op.constant(),
+            CombinedExpr::Combining { .. } => None,
+        }
+    }
+
+    // Compiles items of a disjunction, fusing `contains` checks on the same
+    // field into a single multi-pattern search.
+    fn compile_or_items(items: Vec<Self>) -> Vec<CompiledExpr<'s>> {
+        Self::fuse_contains(items)
+            .into_iter()
+            .map(|item| match item {
+                OrItem::Expr(item) => item.compile(),
+                OrItem::Fused(lhs, needles) => FieldExpr::compile_contains_any(lhs, needles),
+            })
+            .collect()
+    }
+
+    // Fuses `contains` checks of a disjunction into one multi-pattern search
+    // per field that has more than one of them. The search takes the place
+    // of the first fused check, so that the rest of the items are still
+    // evaluated in the same order and only if needed.
+    fn fuse_contains(items: Vec<Self>) -> Vec<OrItem<'s>> {
+        fn contains_lhs<'s>(item: &CombinedExpr<'s>) -> Option<ContainsLhs<'s>> {
+            match item {
+                CombinedExpr::Simple(SimpleExpr::Field(expr)) => expr.contains_lhs(),
+                _ => None,
+            }
+        }
+
+        // Each distinct compared value with the number of its checks, and
+        // the index of the value for every item that's a check.
+        let mut counts: Vec<(ContainsLhs<'s>, usize)> = Vec::new();
+        let groups = items
+            .iter()
+            .map(|item| {
+                let lhs = contains_lhs(item)?;
+                Some(match counts.iter().position(|entry| entry.0 == lhs) {
+                    Some(group) => {
+                        counts[group].1 += 1;
+                        group
+                    }
+                    None => {
+                        counts.push((lhs, 1));
+                        counts.len() - 1
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // The position of each group's search among the fused items.
+        let mut positions = vec![None; counts.len()];
+        let mut fused = Vec::with_capacity(items.len());
+
+        for (item, group) in items.into_iter().zip(groups) {
+            let group = match group {
+                Some(group) if counts[group].1 > 1 => group,
+                _ => {
+                    fused.push(OrItem::Expr(item));
+                    continue;
+                }
+            };
+
+            let bytes = match item {
+                CombinedExpr::Simple(SimpleExpr::Field(expr)) => match expr.into_contains() {
+                    Ok((_, bytes)) => bytes,
+                    Err(_) => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+
+            match positions[group] {
+                Some(position) => match &mut fused[position] {
+                    OrItem::Fused(_, needles) => needles.push(bytes),
+                    OrItem::Expr(_) => unreachable!(),
+                },
+                None => {
+                    positions[group] = Some(fused.len());
+                    fused.push(OrItem::Fused(counts[group].0.clone(), vec![bytes]));
+                }
+            }
+        }
+
+        fused
+    }
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for CombinedExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
-        let (lhs, input) = SimpleExpr::lex_with(input, scheme)?;
+// An item of a disjunction after fusing `contains` checks.
+enum OrItem<'s> {
+    Expr(CombinedExpr<'s>),
+    Fused(ContainsLhs<'s>, Vec<Bytes>),
+}
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for CombinedExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let (lhs, input) = SimpleExpr::lex_with(input, parser)?;
         let lookahead = Self::lex_combining_op(input);
-        CombinedExpr::Simple(lhs).lex_more_with_precedence(scheme, None, lookahead)
+        CombinedExpr::Simple(lhs).lex_more_with_precedence(parser, None, lookahead)
     }
 }
 
@@ -91,15 +392,129 @@ impl<'s> Expr<'s> for CombinedExpr<'s> {
         }
     }
 
+    fn collect_comparisons<'a>(&'a self, comparisons: &mut Vec<&'a FieldExpr<'s>>) {
+        match self {
+            CombinedExpr::Simple(op) => op.collect_comparisons(comparisons),
+            CombinedExpr::Combining { items, .. } => {
+                for item in items {
+                    item.collect_comparisons(comparisons);
+                }
+            }
+        }
+    }
+
+    fn cost(&self) -> u64 {
+        match self {
+            CombinedExpr::Simple(op) => op.cost(),
+            CombinedExpr::Combining { items, .. } => items.iter().map(|op| op.cost()).sum(),
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        match self {
+            CombinedExpr::Simple(op) => op.node_count(),
+            CombinedExpr::Combining { items, .. } => {
+                1 + items.iter().map(|op| op.node_count()).sum::<usize>()
+            }
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            CombinedExpr::Simple(op) => op.depth(),
+            CombinedExpr::Combining { items, .. } => {
+                1 + items.iter().map(|op| op.depth()).max().unwrap_or(0)
+            }
+        }
+    }
+
+    fn write_tree(&self, input: &str, indent: usize, out: &mut String) {
+        match self {
+            CombinedExpr::Simple(op) => op.write_tree(input, indent, out),
+            CombinedExpr::Combining { op, items } => {
+                write_tree_line(
+                    out,
+                    indent,
+                    match op {
+                        CombiningOp::And => "and",
+                        CombiningOp::Or => "or",
+                        CombiningOp::Xor => "xor",
+                    },
+                );
+                for item in items {
+                    item.write_tree(input, indent + 1, out);
+                }
+            }
+        }
+    }
+
+    fn simplify_ranges(&mut self) {
+        match self {
+            CombinedExpr::Simple(op) => op.simplify_ranges(),
+            CombinedExpr::Combining { items, .. } => {
+                for item in items.iter_mut() {
+                    item.simplify_ranges();
+                }
+            }
+        }
+    }
+
+    fn offset_spans(&mut self, offset: usize) {
+        match self {
+            CombinedExpr::Simple(op) => op.offset_spans(offset),
+            CombinedExpr::Combining { items, .. } => {
+                for item in items.iter_mut() {
+                    item.offset_spans(offset);
+                }
+            }
+        }
+    }
+
+    fn write_fingerprint(&self, out: &mut String) {
+        match self {
+            CombinedExpr::Simple(op) => op.write_fingerprint(out),
+            CombinedExpr::Combining { op, items } => {
+                let mut items = items
+                    .iter()
+                    .map(|item| {
+                        let mut out = String::new();
+                        item.write_fingerprint(&mut out);
+                        out
+                    })
+                    .collect::<Vec<_>>();
+                items.sort();
+
+                out.push_str(&format!("{:?}(", op));
+                out.push_str(&items.join(","));
+                out.push(')');
+            }
+        }
+    }
+
+    fn canonicalize(&mut self) {
+        match self {
+            CombinedExpr::Simple(op) => op.canonicalize(),
+            CombinedExpr::Combining { items, .. } => {
+                for item in items.iter_mut() {
+                    item.canonicalize();
+                }
+                // Debug output reflects the whole node structure, which makes
+                // it a convenient total order without requiring `Ord` on
+                // every value type.
+                items.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+            }
+        }
+    }
+
     fn compile(self) -> CompiledExpr<'s> {
         match self {
             CombinedExpr::Simple(op) => op.compile(),
             CombinedExpr::Combining { op, items } => {
-                let items = items
-                    .into_iter()
-                    .map(|item| item.compile())
-                    .collect::<Vec<_>>()
-                    .into_boxed_slice();
+                let items = match op {
+                    CombiningOp::Or => Self::compile_or_items(items),
+                    _ => items.into_iter().map(|item| item.compile()).collect(),
+                }
+                .into_boxed_slice();
 
                 match op {
                     CombiningOp::And => {
@@ -117,6 +532,223 @@ impl<'s> Expr<'s> for CombinedExpr<'s> {
             }
         }
     }
+
+    fn compile_profiled(self, profiler: &mut Profiler) -> CompiledExpr<'s> {
+        let span = comparisons_span(&self);
+        let (op, items) = match self {
+            CombinedExpr::Simple(op) => return op.compile_profiled(profiler),
+            CombinedExpr::Combining { op, items } => (op, items),
+        };
+
+        let label = match op {
+            CombiningOp::And => "and",
+            CombiningOp::Or => "or",
+            CombiningOp::Xor => "xor",
+        };
+        let (counters, items) = profiler.node(label.to_owned(), span, |profiler| {
+            items
+                .into_iter()
+                .map(|item| item.compile_profiled(profiler))
+                .collect::<Vec<_>>()
+        });
+
+        // An `and` or an `or` is short-circuited if it's decided before the
+        // last item.
+        CompiledExpr::new(move |ctx| {
+            counters.time(|| match op {
+                CombiningOp::And => match items.iter().position(|item| !item.execute(ctx)) {
+                    Some(index) => (false, index + 1 < items.len()),
+                    None => (true, false),
+                },
+                CombiningOp::Or => match items.iter().position(|item| item.execute(ctx)) {
+                    Some(index) => (true, index + 1 < items.len()),
+                    None => (false, false),
+                },
+                CombiningOp::Xor => (
+                    items
+                        .iter()
+                        .fold(false, |acc, item| acc ^ item.execute(ctx)),
+                    false,
+                ),
+            })
+        })
+    }
+
+    fn lower(self, program: &mut Vec<Instruction<'s>>) {
+        match self {
+            CombinedExpr::Simple(op) => op.lower(program),
+            CombinedExpr::Combining {
+                op: CombiningOp::Xor,
+                items,
+            } => {
+                for (i, item) in items.into_iter().enumerate() {
+                    if i > 0 {
+                        program.push(Instruction::Push);
+                    }
+                    item.lower(program);
+                    if i > 0 {
+                        program.push(Instruction::Xor);
+                    }
+                }
+            }
+            CombinedExpr::Combining { op, items } => {
+                let items = match op {
+                    CombiningOp::Or => Self::fuse_contains(items),
+                    _ => items.into_iter().map(OrItem::Expr).collect(),
+                };
+
+                // Each item but the last one is followed by a jump to the end
+                // of the chain, which is patched once the end is known.
+                //
+                // Items that are a single comparison are fused with the jump.
+                // Others may end with a comparison too, but their own jumps
+                // land after it, so they need a separate jump to land on.
+                let mut jumps = Vec::with_capacity(items.len());
+                {
+                    let mut remaining = items.len();
+                    let mut push_jump = |program: &mut Vec<Instruction<'s>>, start: usize| {
+                        remaining -= 1;
+                        if remaining == 0 {
+                            return;
+                        }
+
+                        let last = if program.len() == start + 1 {
+                            program.pop()
+                        } else {
+                            None
+                        };
+                        let jump = match (op, last) {
+                            (CombiningOp::Or, Some(Instruction::Test(expr))) => {
+                                Instruction::TestJumpIfTrue(expr, 0)
+                            }
+                            (_, Some(Instruction::Test(expr))) => {
+                                Instruction::TestJumpIfFalse(expr, 0)
+                            }
+                            (op, last) => {
+                                program.extend(last);
+                                match op {
+                                    CombiningOp::Or => Instruction::JumpIfTrue(0),
+                                    _ => Instruction::JumpIfFalse(0),
+                                }
+                            }
+                        };
+                        jumps.push(program.len());
+                        program.push(jump);
+                    };
+
+                    for item in items {
+                        let start = program.len();
+                        match item {
+                            OrItem::Expr(item) => item.lower(program),
+                            OrItem::Fused(lhs, needles) => program.push(Instruction::Test(
+                                FieldExpr::compile_contains_any(lhs, needles),
+                            )),
+                        }
+                        push_jump(program, start);
+                    }
+                }
+
+                let end = program.len();
+                for jump in jumps {
+                    match &mut program[jump] {
+                        Instruction::JumpIfTrue(target)
+                        | Instruction::JumpIfFalse(target)
+                        | Instruction::TestJumpIfTrue(_, target)
+                        | Instruction::TestJumpIfFalse(_, target) => *target = end,
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute_traced<'a, 'e>(
+        &'a self,
+        ctx: &ExecutionContext<'e>,
+        reached: bool,
+        trace: &mut Vec<TraceEntry<'a, 's, 'e>>,
+    ) -> bool {
+        match self {
+            CombinedExpr::Simple(op) => op.execute_traced(ctx, reached, trace),
+            CombinedExpr::Combining { op, items } => {
+                // Collect all results first, so that nothing is short-circuited,
+                // but keep track of what regular execution would have skipped.
+                let mut reached = reached;
+                let mut results = Vec::with_capacity(items.len());
+                for item in items {
+                    let result = item.execute_traced(ctx, reached, trace);
+                    match op {
+                        CombiningOp::And if !result => reached = false,
+                        CombiningOp::Or if result => reached = false,
+                        _ => {}
+                    }
+                    results.push(result);
+                }
+
+                match op {
+                    CombiningOp::And => results.iter().all(|&res| res),
+                    CombiningOp::Or => results.iter().any(|&res| res),
+                    CombiningOp::Xor => results.iter().fold(false, |acc, &res| acc ^ res),
+                }
+            }
+        }
+    }
+
+    fn execute_matching<'a>(
+        &'a self,
+        ctx: &ExecutionContext<'_>,
+        leaves: &mut Vec<&'a FieldExpr<'s>>,
+    ) -> bool {
+        match self {
+            CombinedExpr::Simple(op) => op.execute_matching(ctx, leaves),
+            CombinedExpr::Combining { op, items } => {
+                // Items that don't match never add leaves, so only the ones
+                // of a match that turns out to be useless need to be dropped.
+                let len = leaves.len();
+
+                let result = match op {
+                    CombiningOp::And => items.iter().all(|item| item.execute_matching(ctx, leaves)),
+                    CombiningOp::Or => items.iter().any(|item| item.execute_matching(ctx, leaves)),
+                    CombiningOp::Xor => items
+                        .iter()
+                        .fold(false, |acc, item| acc ^ item.execute_matching(ctx, leaves)),
+                };
+
+                if !result {
+                    leaves.truncate(len);
+                }
+
+                result
+            }
+        }
+    }
+
+    fn execute_ternary(&self, ctx: &ExecutionContext<'_>) -> Ternary {
+        match self {
+            CombinedExpr::Simple(op) => op.execute_ternary(ctx),
+            CombinedExpr::Combining { op, items } => {
+                let results = items.iter().map(|item| item.execute_ternary(ctx));
+
+                match op {
+                    CombiningOp::And => results.fold(Ternary::True, Ternary::and),
+                    CombiningOp::Or => results.fold(Ternary::False, Ternary::or),
+                    CombiningOp::Xor => results.fold(Ternary::False, Ternary::xor),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_precedence_table() {
+    use super::precedence::precedence;
+
+    for &op in &["or", "||", "xor", "^^", "and", "&&"] {
+        assert_eq!(
+            precedence(op),
+            Some(CombiningOp::lex(op).unwrap().0.precedence())
+        );
+    }
 }
 
 #[test]
@@ -129,29 +761,30 @@ fn test() {
         t: Bool,
         f: Bool,
     };
+    let parser = &Parser::new(scheme);
 
     let ctx = &mut ExecutionContext::new(scheme);
 
     let t_expr = CombinedExpr::Simple(SimpleExpr::Field(
-        complete(FieldExpr::lex_with("t", scheme)).unwrap(),
+        complete(FieldExpr::lex_with("t", parser)).unwrap(),
     ));
 
     let t_expr = || t_expr.clone();
 
     let f_expr = CombinedExpr::Simple(SimpleExpr::Field(
-        complete(FieldExpr::lex_with("f", scheme)).unwrap(),
+        complete(FieldExpr::lex_with("f", parser)).unwrap(),
     ));
 
     let f_expr = || f_expr.clone();
 
-    assert_ok!(CombinedExpr::lex_with("t", scheme), t_expr());
+    assert_ok!(CombinedExpr::lex_with("t", parser), t_expr());
 
     ctx.set_field_value("t", true).unwrap();
     ctx.set_field_value("f", false).unwrap();
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t and t", scheme),
+            CombinedExpr::lex_with("t and t", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::And,
                 items: vec![t_expr(), t_expr()],
@@ -165,7 +798,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t and f", scheme),
+            CombinedExpr::lex_with("t and f", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::And,
                 items: vec![t_expr(), f_expr()],
@@ -196,7 +829,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t or f", scheme),
+            CombinedExpr::lex_with("t or f", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::Or,
                 items: vec![t_expr(), f_expr()],
@@ -227,7 +860,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("f or f", scheme),
+            CombinedExpr::lex_with("f or f", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::Or,
                 items: vec![f_expr(), f_expr()],
@@ -241,7 +874,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("t xor f", scheme),
+            CombinedExpr::lex_with("t xor f", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::Xor,
                 items: vec![t_expr(), f_expr()],
@@ -272,7 +905,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("f xor f", scheme),
+            CombinedExpr::lex_with("f xor f", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::Xor,
                 items: vec![f_expr(), f_expr()],
@@ -286,7 +919,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            CombinedExpr::lex_with("f xor t", scheme),
+            CombinedExpr::lex_with("f xor t", parser),
             CombinedExpr::Combining {
                 op: CombiningOp::Xor,
                 items: vec![f_expr(), t_expr()],
@@ -299,7 +932,7 @@ fn test() {
     }
 
     assert_ok!(
-        CombinedExpr::lex_with("t or t && t and t or t ^^ t and t || t", scheme),
+        CombinedExpr::lex_with("t or t && t and t or t ^^ t and t || t", parser),
         CombinedExpr::Combining {
             op: CombiningOp::Or,
             items: vec![
@@ -323,3 +956,539 @@ fn test() {
         }
     );
 }
+
+#[test]
+fn test_fused_contains() {
+    use execution_context::ExecutionContext;
+
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        http.ua: Bytes,
+        tcp.port: Int,
+    };
+
+    let fused = scheme
+        .parse(r#"http.host contains "abc" or tcp.port == 80 or http.host contains "xyz" or http.ua contains "bot" or http.host contains 62:63:64"#)
+        .unwrap()
+        .compile();
+
+    // Parenthesized items are compiled on their own.
+    let unfused = scheme
+        .parse(r#"(http.host contains "abc") or (tcp.port == 80) or (http.host contains "xyz") or (http.ua contains "bot") or (http.host contains 62:63:64)"#)
+        .unwrap()
+        .compile();
+
+    let with_empty = scheme
+        .parse(r#"http.host contains "abc" or http.host contains """#)
+        .unwrap()
+        .compile();
+
+    for &host in &["", "abc", "xxabcxx", "xyz", "zyx", "bcd", "ab", "c"] {
+        for &ua in &["", "googlebot", "curl"] {
+            for &port in &[80, 443] {
+                let mut ctx = ExecutionContext::new(scheme);
+                ctx.set_field_value("http.host", host).unwrap();
+                ctx.set_field_value("http.ua", ua).unwrap();
+                ctx.set_field_value("tcp.port", port).unwrap();
+
+                assert_eq!(
+                    fused.execute(&ctx),
+                    unfused.execute(&ctx),
+                    "{:?} {:?} {:?}",
+                    host,
+                    ua,
+                    port
+                );
+
+                assert_eq!(with_empty.execute(&ctx), Ok(true));
+            }
+        }
+    }
+
+    // Unset fields still don't match.
+    let ctx = ExecutionContext::new(scheme);
+    assert_eq!(fused.execute(&ctx), Ok(false));
+    assert_eq!(with_empty.execute(&ctx), Ok(false));
+}
+
+#[test]
+fn test_fused_contains_items() {
+    use execution_context::ExecutionContext;
+    use functions::Function;
+    use types::Type;
+
+    let mut scheme = Scheme! { http.ua: Bytes };
+    scheme
+        .add_map_field("http.headers".to_owned(), Type::Bytes)
+        .unwrap();
+    scheme
+        .add_function("lower".to_owned(), Function::lower())
+        .unwrap();
+
+    // Items of the same map key and the same calls are fused, but not
+    // across different ones.
+    let fused = scheme
+        .parse(r#"http.headers["ua"] contains any of { "sqlmap" "nikto" } or http.headers["via"] contains "nikto" or lower(http.ua) contains any of { "acunetix" "curl" }"#)
+        .unwrap()
+        .compile();
+
+    let unfused = scheme
+        .parse(r#"(http.headers["ua"] contains "sqlmap") or (http.headers["ua"] contains "nikto") or (http.headers["via"] contains "nikto") or (lower(http.ua) contains "acunetix") or (lower(http.ua) contains "curl")"#)
+        .unwrap()
+        .compile();
+
+    for &ua in &["", "sqlmap/1.0", "Nikto", "Acunetix"] {
+        for &via in &[None, Some("nikto")] {
+            let mut ctx = ExecutionContext::new(&scheme);
+            ctx.set_field_value("http.ua", ua).unwrap();
+            ctx.set_map_value("http.headers", "ua", ua).unwrap();
+            if let Some(via) = via {
+                ctx.set_map_value("http.headers", "via", via).unwrap();
+            }
+
+            assert_eq!(
+                fused.execute(&ctx),
+                unfused.execute(&ctx),
+                "{:?} {:?}",
+                ua,
+                via
+            );
+        }
+    }
+}
+
+#[test]
+fn test_fused_contains_order() {
+    use execution_context::ExecutionContext;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+    };
+
+    let port_calls = AtomicUsize::new(0);
+    let context = || {
+        let mut ctx = ExecutionContext::new(scheme);
+        ctx.set_field_value("http.host", "abc").unwrap();
+        ctx.set_field_accessor("tcp.port", || {
+            port_calls.fetch_add(1, Ordering::SeqCst);
+            80
+        });
+        ctx
+    };
+
+    // A single check, or the first one of a fused search, still
+    // short-circuits the items after it.
+    for filter in &[
+        r#"http.host contains "a" || tcp.port == 1"#,
+        r#"http.host contains "x" || http.host contains "a" || tcp.port == 1"#,
+    ] {
+        let ast = scheme.parse(filter).unwrap();
+
+        assert_eq!(ast.clone().compile().execute(&context()), Ok(true));
+        assert_eq!(ast.clone().compile_program().execute(&context()), Ok(true));
+        assert_eq!(port_calls.load(Ordering::SeqCst), 0, "{}", filter);
+
+        let (_, trace) = ast.execute_traced(&context()).unwrap();
+        assert!(!trace.entries().last().unwrap().evaluated, "{}", filter);
+        port_calls.store(0, Ordering::SeqCst);
+    }
+
+    let ast = scheme
+        .parse(r#"tcp.port == 1 || http.host contains "x" || http.host contains "a""#)
+        .unwrap();
+    assert_eq!(ast.compile().execute(&context()), Ok(true));
+    assert_eq!(port_calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_node_count_and_depth() {
+    let scheme = &Scheme! {
+        t: Bool,
+        f: Bool,
+        port: Int,
+    };
+
+    let ast = scheme.parse("t").unwrap();
+    assert_eq!((ast.node_count(), ast.depth()), (1, 1));
+
+    let ast = scheme
+        .parse("t && (f || not t) && port in { 80 443 }")
+        .unwrap();
+    assert_eq!(ast.node_count(), 9);
+    assert_eq!(ast.depth(), 4);
+
+    let ast = scheme.parse("port in { 80 443 } or t").unwrap();
+    assert_eq!((ast.node_count(), ast.depth()), (5, 3));
+}
+
+#[test]
+fn test_canonicalize() {
+    let scheme = &Scheme! {
+        t: Bool,
+        f: Bool,
+        port: Int,
+    };
+
+    let canonical = |filter| scheme.parse(filter).unwrap().canonicalize();
+
+    assert_eq!(canonical("t && port == 80"), canonical("port == 80 && t"));
+    assert_ne!(
+        scheme.parse("t && port == 80").unwrap(),
+        scheme.parse("port == 80 && t").unwrap()
+    );
+
+    assert_eq!(
+        canonical("(f || not t) && port in { 443 80 }"),
+        canonical("port in { 80 443 } && (not t || f)")
+    );
+
+    assert_ne!(canonical("t && f"), canonical("t || f"));
+    assert_ne!(
+        canonical("(t && f) && port == 80"),
+        canonical("t && f && port == 80")
+    );
+}
+
+#[test]
+fn test_debug_tree() {
+    use indoc::indoc;
+
+    let scheme = &Scheme! {
+        t: Bool,
+        f: Bool,
+        port: Int,
+    };
+
+    let filter = "t && (f || not port in { 80 443 }) ^^ port > 1024";
+    let ast = scheme.parse(filter).unwrap();
+
+    assert_eq!(
+        ast.debug_tree(filter),
+        indoc!(
+            "
+            xor
+              and
+                `t` 0..1
+                or
+                  `f` 6..7
+                  not
+                    `port in { 80 443 }` 15..33
+              `port > 1024` 38..49
+            "
+        )
+    );
+}
+
+#[test]
+fn test_simplify_ranges() {
+    let scheme = &Scheme! {
+        ip.src: Ip,
+        port: Int,
+    };
+
+    let simplified = |filter| scheme.parse(filter).unwrap().simplify_ranges();
+
+    assert_eq!(
+        simplified("ip.src in { 10.0.0.0/8 10.1.0.0/16 } && port in { 80 1..1024 8080 }"),
+        scheme
+            .parse("ip.src in { 10.0.0.0/8 } && port in { 1..1024 8080 }")
+            .unwrap()
+    );
+
+    // Overlapping items that don't cover each other, and items of different
+    // address families are preserved.
+    let filter = "ip.src in { 10.0.0.0/16 10.0.128.0..10.1.0.0 192.168.0.0/16 ::/0 }";
+    assert_eq!(simplified(filter), scheme.parse(filter).unwrap());
+}
+
+#[test]
+fn test_negate() {
+    use execution_context::ExecutionContext;
+
+    let scheme = &Scheme! { a: Int, b: Int, c: Bool };
+    let parse = |filter| scheme.parse(filter).unwrap();
+
+    let ast = parse("a == 1 && b == 2");
+    assert_eq!(ast.clone().negate(), parse("not a == 1 || not b == 2"));
+    assert_eq!(ast.clone().negate().negate(), ast);
+
+    assert_eq!(
+        parse("a == 1 || b == 2 && not c").negate(),
+        parse("not a == 1 && (not b == 2 || c)")
+    );
+    assert_eq!(
+        parse("(a == 1 ^^ c) && a != 2").negate(),
+        parse("(not a == 1 ^^ c) || not a != 2")
+    );
+
+    for filter in &[
+        "a == 1 && b == 2",
+        "a == 1 || b == 2 && not c",
+        "a < 5 ^^ c ^^ b > 2",
+    ] {
+        let original = parse(filter).compile();
+        let negated = parse(filter).negate().compile();
+
+        for &a in &[None, Some(1), Some(7)] {
+            for &b in &[None, Some(2), Some(3)] {
+                for &c in &[None, Some(true), Some(false)] {
+                    let mut ctx = ExecutionContext::new(scheme);
+                    if let Some(a) = a {
+                        ctx.set_field_value("a", a).unwrap();
+                    }
+                    if let Some(b) = b {
+                        ctx.set_field_value("b", b).unwrap();
+                    }
+                    if let Some(c) = c {
+                        ctx.set_field_value("c", c).unwrap();
+                    }
+
+                    assert_eq!(
+                        negated.execute(&ctx),
+                        original.execute(&ctx).map(|res| !res),
+                        "{} with a={:?} b={:?} c={:?}",
+                        filter,
+                        a,
+                        b,
+                        c
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_combine_operators() {
+    use execution_context::ExecutionContext;
+    use filter::SchemeMismatchError;
+
+    let scheme = &Scheme! { a: Int, b: Int, c: Bool };
+    let parse = |filter| scheme.parse(filter).unwrap();
+
+    assert_eq!(
+        parse("a == 1") & parse("b == 2"),
+        Ok(parse("a == 1 && b == 2"))
+    );
+    assert_eq!(
+        parse("a == 1 && b == 2") & parse("c && a != 3"),
+        Ok(parse("a == 1 && b == 2 && c && a != 3"))
+    );
+    assert_eq!(
+        parse("a == 1 || b == 2") & parse("c"),
+        Ok(parse("(a == 1 || b == 2) && c"))
+    );
+    assert_eq!(
+        parse("a == 1 && b == 2") | parse("c ^^ a == 3"),
+        Ok(parse("a == 1 && b == 2 || c ^^ a == 3"))
+    );
+
+    let cases = vec![
+        (
+            (parse("a == 1 || b == 2") & parse("not c")).unwrap(),
+            "(a == 1 || b == 2) && not c",
+        ),
+        (
+            (parse("a == 1 && b == 2") | parse("c")).unwrap(),
+            "a == 1 && b == 2 || c",
+        ),
+    ];
+
+    for (combined, filter) in cases {
+        let combined = combined.compile();
+        let parsed = parse(filter).compile();
+
+        for &a in &[None, Some(1), Some(7)] {
+            for &b in &[None, Some(2)] {
+                for &c in &[None, Some(true), Some(false)] {
+                    let mut ctx = ExecutionContext::new(scheme);
+                    if let Some(a) = a {
+                        ctx.set_field_value("a", a).unwrap();
+                    }
+                    if let Some(b) = b {
+                        ctx.set_field_value("b", b).unwrap();
+                    }
+                    if let Some(c) = c {
+                        ctx.set_field_value("c", c).unwrap();
+                    }
+
+                    assert_eq!(combined.execute(&ctx), parsed.execute(&ctx), "{}", filter);
+                }
+            }
+        }
+    }
+
+    let other_scheme = Scheme! { a: Int };
+    let other = other_scheme.parse("a == 1").unwrap();
+    assert_eq!(parse("a == 1") & other.clone(), Err(SchemeMismatchError));
+    assert_eq!(parse("a == 1") | other, Err(SchemeMismatchError));
+}
+
+#[test]
+fn test_fingerprint() {
+    let scheme = &Scheme! { a: Int, b: Int, host: Bytes, ssl: Bool };
+    let fingerprint = |filter| scheme.parse(filter).unwrap().fingerprint();
+
+    let ab = fingerprint(r#"a == 1 && host == "x""#);
+    assert_eq!(ab.len(), 64);
+    assert!(ab.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(fingerprint(r#"host == "x" && a == 1"#), ab);
+    assert_eq!(fingerprint(r#"(a == 1) && host == 78"#), ab);
+    assert_ne!(fingerprint(r#"a == 1 || host == "x""#), ab);
+    assert_ne!(fingerprint(r#"a == 1 && host == "y""#), ab);
+
+    assert_eq!(
+        fingerprint(r#"not ssl || host in { "b" 61 } || b in { 3 1..2 }"#),
+        fingerprint(r#"b in { 1..2 3 } || host in { "a" 62 } || not ssl"#)
+    );
+    assert_ne!(fingerprint("not ssl"), fingerprint("ssl"));
+    assert_ne!(
+        fingerprint(r#"host matches "a""#),
+        fingerprint(r#"host contains "a""#)
+    );
+}
+
+#[test]
+fn test_optimize() {
+    use parser::{ParserOptions, UnknownFieldPolicy};
+
+    let scheme = &Scheme! { a: Int, b: Int, host: Bytes, ssl: Bool };
+    let parser = Parser::with_options(
+        scheme,
+        ParserOptions {
+            unknown_field_policy: UnknownFieldPolicy::TreatAsMissing,
+            ..ParserOptions::default()
+        },
+    );
+    let optimize = |filter| parser.parse(filter).unwrap().optimize().op;
+    let parse = |filter| parser.parse(filter).unwrap().op;
+
+    assert_eq!(
+        optimize(r#"host matches "x" || a == 1 || (ssl || a == 1)"#),
+        parse(r#"a == 1 || ssl || host matches "x""#)
+    );
+    assert_eq!(
+        optimize(r#"host matches "x" && (a == 1 && b == 2) && a == 1"#),
+        parse(r#"a == 1 && b == 2 && host matches "x""#)
+    );
+    assert_eq!(optimize("not not (ssl)"), parse("ssl"));
+    assert_eq!(
+        optimize("(a == 1 || b == 2) && ssl"),
+        parse("ssl && (a == 1 || b == 2)")
+    );
+    assert_eq!(
+        optimize("((ssl && a == 1)) || b == 2"),
+        parse("b == 2 || ssl && a == 1")
+    );
+
+    // Comparisons that never match and their negations.
+    assert_eq!(optimize("a == 1 || not b in {}"), parse("not b in {}"));
+    assert_eq!(optimize("a == 1 && b in {}"), parse("b in {}"));
+    assert_eq!(optimize("a == 1 && not unknown == 1"), parse("a == 1"));
+    assert_eq!(optimize("(b in {} || unknown == 1) || ssl"), parse("ssl"));
+    assert_eq!(optimize("b in {} || unknown == 1"), parse("b in {}"));
+    assert_eq!(optimize("ssl ^^ b in {}"), parse("ssl"));
+
+    // `xor` chains are neither deduplicated nor reordered.
+    assert_eq!(
+        optimize(r#"host matches "x" ^^ ssl ^^ ssl"#),
+        parse(r#"host matches "x" ^^ ssl ^^ ssl"#)
+    );
+}
+
+#[test]
+fn test_display() {
+    use functions::Function;
+    use types::Type;
+
+    let mut scheme = Scheme! {
+        ip: Ip,
+        host: Bytes,
+        port: Int,
+        ssl: Bool,
+        net: Cidr,
+        peer: Endpoint,
+        score: Float,
+        time: Timestamp,
+        mac: Mac,
+    };
+    scheme.add_field("`or`".into(), Type::Int).unwrap();
+    scheme.add_map_field("headers".into(), Type::Bytes).unwrap();
+    scheme
+        .add_array_field("cookies".into(), Type::Bytes)
+        .unwrap();
+    scheme.add_function("len".into(), Function::len()).unwrap();
+    scheme.add_constant("limit".into(), Type::Int).unwrap();
+    scheme.add_list("blocked".into(), Type::Ip).unwrap();
+
+    for &(filter, expected) in &[
+        ("ssl and not ssl or ssl xor ssl", "ssl && not ssl || ssl ^^ ssl"),
+        ("!(ssl||ssl)&&ssl", "not (ssl || ssl) && ssl"),
+        (
+            r#"host=="a\"b\\c" and host contains 61:62 nocase"#,
+            r#"host == "a\"b\\c" && host contains 61:62 nocase"#,
+        ),
+        (
+            r#"host matches "[a"]\"\\.x" || host matches $pattern"#,
+            r#"host matches "[a"]\"\\.x" || host matches $pattern"#,
+        ),
+        (
+            "port in {443 80..90} && port & 0x3 == 1 && port >> 1 && port < $limit",
+            "port in { 443 80..90 } && port & 3 == 1 && port >> 1 && port < $limit",
+        ),
+        (
+            "port between -5 .. -1 || port == -3",
+            "port in { -5..-1 } || port == -3",
+        ),
+        (
+            "ip in { 10.0.0.1 10.0.0.0/8 10.0.0.1-10.0.0.5 fe80::1..fe80::2 } || ip in $blocked",
+            "ip in { 10.0.0.1 10.0.0.0/8 10.0.0.1..10.0.0.5 fe80::1..fe80::2 } || ip in $blocked",
+        ),
+        (
+            "ip == 10.0.0.0/8 || ip == 10.0.0.1/32 || ip != fe80::1%eth0",
+            "ip == 10.0.0.0/8 || ip == 10.0.0.1/32 || ip != fe80::1%eth0",
+        ),
+        ("net in { 10.0.0.0/8 } && net == 10.0.0.1", "net in { 10.0.0.0/8 } && net == 10.0.0.1"),
+        ("peer == [::1]:80 || peer in { 1.2.3.4:443 }", "peer == [::1]:80 || peer in { 1.2.3.4:443 }"),
+        (
+            "score > 1 || score in { 0.5..1.5e2 -1e999 }",
+            "score > 1.0 || score in { 0.5..150.0 -1e309 }",
+        ),
+        (
+            r#"time >= 1704067200 || time in { "2024-01-01T00:00:00.5Z".."2025-01-01T00:00:00+01:00" }"#,
+            r#"time >= "2024-01-01T00:00:00Z" || time in { "2024-01-01T00:00:00.5Z".."2024-12-31T23:00:00Z" }"#,
+        ),
+        (
+            "mac == 00:1A:2B:3C:4D:5E || mac in { 00:1a:2b/24 00:1a:2b:00:00:00..00:1a:2b:00:00:ff }",
+            "mac == 00:1a:2b:3c:4d:5e || mac in { 00:1a:2b:00:00:00..00:1a:2b:ff:ff:ff 00:1a:2b:00:00:00..00:1a:2b:00:00:ff }",
+        ),
+        (
+            r#"headers["x-a"] == "1" && cookies[0] == "a" && any(cookies contains "s" nocase)"#,
+            r#"headers["x-a"] == "1" && cookies[0] == "a" && any(cookies contains "s" nocase)"#,
+        ),
+        (
+            r#"len(headers["host"]) > 3 || `or` == 1"#,
+            r#"len(headers["host"]) > 3 || `or` == 1"#,
+        ),
+    ] {
+        let ast = scheme.parse(filter).unwrap();
+        assert_eq!(ast.to_string(), expected);
+        assert_eq!(scheme.parse(expected).unwrap(), ast);
+    }
+
+    // Sets and operands are printed in order after canonicalization.
+    assert_eq!(
+        scheme
+            .parse("port in { 443 80 } && ssl")
+            .unwrap()
+            .canonicalize()
+            .to_string(),
+        scheme
+            .parse("ssl && port in { 80 443 }")
+            .unwrap()
+            .canonicalize()
+            .to_string()
+    );
+}
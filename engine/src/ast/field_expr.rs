@@ -1,32 +1,57 @@
-use super::{CompiledExpr, Expr};
+use super::{
+    profile::Profiler, ternary::Ternary, trace::TraceEntry, write_tree_line, CompiledExpr, Expr,
+};
+use aho_corasick::{AcAutomaton, Automaton};
+use cidr::{Cidr, IpCidr};
+use execution_context::ExecutionContext;
 use fnv::FnvBuildHasher;
+use functions::{Function, FunctionValue};
 use heap_searcher::HeapSearcher;
 use indexmap::IndexSet;
-use lex::{skip_space, span, Lex, LexErrorKind, LexResult, LexWith};
+use lex::{expect, peek, skip_space, span, take_while, Lex, LexErrorKind, LexResult, LexWith};
 use memmem::Searcher;
+use parser::Parser;
+use program::Instruction;
 use range_set::RangeSet;
-use rhs_types::{Bytes, ExplicitIpRange, Regex};
-use scheme::{Field, Scheme};
+use rhs_types::{
+    lex_int_expr, lex_interval, lex_zone, Bytes, Endpoint, ExplicitIpRange, IpAddrOrCidr, IpRange,
+    Regex, Wildcard,
+};
+use scheme::Field;
 use serde::{Serialize, Serializer};
-use std::{cmp::Ordering, net::IpAddr};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, Ipv6Addr},
+    ops::{Range, RangeInclusive},
+};
 use strict_partial_ord::StrictPartialOrd;
-use types::{GetType, LhsValue, RhsValue, RhsValues, Type};
+use types::{lex_cast, GetType, LhsValue, Literal, RhsValue, RhsValues, Type};
 
 const LESS: u8 = 0b001;
 const GREATER: u8 = 0b010;
 const EQUAL: u8 = 0b100;
 
-lex_enum!(#[repr(u8)] OrderingOp {
-    "eq" | "==" => Equal = EQUAL,
-    "ne" | "!=" => NotEqual = LESS | GREATER,
-    "ge" | ">=" => GreaterThanEqual = GREATER | EQUAL,
-    "le" | "<=" => LessThanEqual = LESS | EQUAL,
-    "gt" | ">" => GreaterThan = GREATER,
-    "lt" | "<" => LessThan = LESS,
-});
+lex_enum!(
+    /// An ordering comparison operator, applicable to fields of any type.
+    #[repr(u8)] OrderingOp {
+        /// `eq` / `==`
+        "eq" | "==" => Equal = EQUAL,
+        /// `ne` / `!=` / `<>`
+        "ne" | "!=" | "<>" => NotEqual = LESS | GREATER,
+        /// `ge` / `>=`
+        "ge" | ">=" => GreaterThanEqual = GREATER | EQUAL,
+        /// `le` / `<=`
+        "le" | "<=" => LessThanEqual = LESS | EQUAL,
+        /// `gt` / `>`
+        "gt" | ">" => GreaterThan = GREATER,
+        /// `lt` / `<`
+        "lt" | "<" => LessThan = LESS,
+    }
+);
 
 impl OrderingOp {
-    pub fn matches(self, ordering: Ordering) -> bool {
+    pub(crate) fn matches(self, ordering: Ordering) -> bool {
         let mask = self as u8;
         let flag = match ordering {
             Ordering::Less => LESS,
@@ -36,7 +61,7 @@ impl OrderingOp {
         mask & flag != 0
     }
 
-    pub fn matches_opt(self, ordering: Option<Ordering>) -> bool {
+    pub(crate) fn matches_opt(self, ordering: Option<Ordering>) -> bool {
         match ordering {
             Some(ordering) => self.matches(ordering),
             // only `!=` should be true for incomparable types
@@ -45,25 +70,144 @@ impl OrderingOp {
     }
 }
 
-lex_enum!(IntOp {
-    "&" | "bitwise_and" => BitwiseAnd,
-});
+lex_enum!(
+    /// An operator applicable only to integer fields.
+    IntOp {
+        /// `&` / `bitwise_and`
+        "&" | "bitwise_and" => BitwiseAnd,
+        /// `>>`
+        ">>" => ShiftRight,
+        /// `<<`
+        "<<" => ShiftLeft,
+    }
+);
+
+impl IntOp {
+    pub(crate) fn apply(self, lhs: i32, rhs: i32) -> i32 {
+        match self {
+            IntOp::BitwiseAnd => lhs & rhs,
+            // Shift amounts are checked to be below 32 when parsing.
+            IntOp::ShiftRight => lhs >> rhs,
+            IntOp::ShiftLeft => lhs << rhs,
+        }
+    }
+
+    // Shifting by the width of `i32` or more would overflow.
+    pub(crate) fn is_valid_rhs(self, rhs: i32) -> bool {
+        match self {
+            IntOp::BitwiseAnd => true,
+            IntOp::ShiftRight | IntOp::ShiftLeft => rhs >= 0 && rhs < 32,
+        }
+    }
+}
+
+lex_enum!(
+    /// An operator applicable only to bytes fields.
+    BytesOp {
+        /// `contains`
+        "contains" => Contains,
+        /// `~` / `matches`
+        "~" | "matches" => Matches,
+        /// `wildcard` / `like`
+        "wildcard" | "like" => Wildcard,
+    }
+);
+
+lex_enum!(
+    /// Any operator that can be used in a field comparison.
+    ComparisonOp {
+        /// `in`, a check against a list of values.
+        "in" => In,
+        /// `between`, a check against a single inclusive range.
+        "between" => Between,
+        /// An integer-only operator. These are lexed before ordering ones,
+        /// so that `>>` isn't seen as `>`.
+        IntOp => Int,
+        /// An ordering operator.
+        OrderingOp => Ordering,
+        /// A bytes-only operator.
+        BytesOp => Bytes,
+    }
+);
+
+lex_enum!(
+    /// A quantifier of `contains any { ... }` and `contains all { ... }`, or
+    /// of comparisons of array elements like `any(...)`.
+    ContainsQuantifier {
+        "any" => Any,
+        "all" => All,
+    }
+);
+
+/// Returns the operators that can be used on a field of the given type,
+/// e.g. to suggest alternatives for an unsupported one. Boolean fields are
+/// only ever checked on their own, so they have none.
+pub(crate) fn supported_ops(field_type: Type) -> &'static [&'static str] {
+    match field_type {
+        Type::Ip => &["==", "!=", "<", "<=", ">", ">=", "in", "between"],
+        Type::Bytes => &[
+            "==", "!=", "<", "<=", ">", ">=", "in", "contains", "matches", "wildcard",
+        ],
+        Type::Int => &[
+            "==", "!=", "<", "<=", ">", ">=", "in", "between", "&", ">>", "<<",
+        ],
+        Type::Bool => &[],
+        Type::Cidr => &["==", "!=", "in"],
+        Type::Endpoint => &["==", "!=", "<", "<=", ">", ">=", "in"],
+        Type::Float => &["==", "!=", "<", "<=", ">", ">=", "in"],
+        Type::Timestamp => &["==", "!=", "<", "<=", ">", ">=", "in"],
+        Type::Mac => &["==", "!=", "<", "<=", ">", ">=", "in"],
+    }
+}
+
+impl Display for OrderingOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OrderingOp::Equal => "==",
+            OrderingOp::NotEqual => "!=",
+            OrderingOp::GreaterThanEqual => ">=",
+            OrderingOp::LessThanEqual => "<=",
+            OrderingOp::GreaterThan => ">",
+            OrderingOp::LessThan => "<",
+        })
+    }
+}
+
+impl Display for IntOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IntOp::BitwiseAnd => "&",
+            IntOp::ShiftRight => ">>",
+            IntOp::ShiftLeft => "<<",
+        })
+    }
+}
 
-lex_enum!(BytesOp {
-    "contains" => Contains,
-    "~" | "matches" => Matches,
-});
+impl Display for BytesOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BytesOp::Contains => "contains",
+            BytesOp::Matches => "matches",
+            BytesOp::Wildcard => "wildcard",
+        })
+    }
+}
 
-lex_enum!(ComparisonOp {
-    "in" => In,
-    OrderingOp => Ordering,
-    IntOp => Int,
-    BytesOp => Bytes,
-});
+impl Display for ComparisonOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ComparisonOp::In => f.write_str("in"),
+            ComparisonOp::Between => f.write_str("between"),
+            ComparisonOp::Ordering(op) => op.fmt(f),
+            ComparisonOp::Int(op) => op.fmt(f),
+            ComparisonOp::Bytes(op) => op.fmt(f),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 #[serde(untagged)]
-enum FieldOp {
+pub(crate) enum FieldOp {
     #[serde(serialize_with = "serialize_is_true")]
     IsTrue,
 
@@ -72,22 +216,75 @@ enum FieldOp {
         rhs: RhsValue,
     },
 
+    // `==` and `!=` between an IP and a CIDR check for membership.
+    Cidr {
+        op: OrderingOp,
+        rhs: IpCidr,
+    },
+
     Int {
         op: IntOp,
         rhs: i32,
     },
 
+    // The result of an integer operator compared with a value, like
+    // `tcp.flags & 0x02 != 0`, rather than just checked to be non-zero.
+    IntOrdering {
+        op: IntOp,
+        rhs: i32,
+        ordering: OrderingOp,
+        value: i32,
+    },
+
+    // `==` and `!=` with bytes that ignore ASCII case, like `== "a" nocase`.
+    #[serde(serialize_with = "serialize_ordering_nocase")]
+    OrderingIgnoreCase {
+        op: OrderingOp,
+        rhs: Bytes,
+    },
+
     #[serde(serialize_with = "serialize_contains")]
     Contains(Bytes),
 
+    #[serde(serialize_with = "serialize_contains_nocase")]
+    ContainsIgnoreCase(Bytes),
+
     #[serde(serialize_with = "serialize_matches")]
     Matches(Regex),
 
+    // A regex supplied at execution time, referenced as `$name`.
+    #[serde(serialize_with = "serialize_matches_pattern")]
+    MatchesPattern(String),
+
+    #[serde(serialize_with = "serialize_wildcard")]
+    Wildcard(Wildcard),
+
+    // `==` and `!=` with an IPv6 address that has a zone, like `fe80::1%eth0`.
+    ZonedIp {
+        op: OrderingOp,
+        rhs: Ipv6Addr,
+        zone: String,
+    },
+
+    // A value supplied at execution time, referenced as `$name`.
+    OrderingConstant {
+        op: OrderingOp,
+        constant: String,
+    },
+
     #[serde(serialize_with = "serialize_one_of")]
     OneOf(RhsValues),
+
+    // A list supplied at execution time, referenced as `$name`.
+    #[serde(serialize_with = "serialize_one_of_list")]
+    OneOfList(String),
+
+    // `==` and `!=` with `[]`, which check whether an array field is empty.
+    #[serde(serialize_with = "serialize_empty_array")]
+    EmptyArray(OrderingOp),
 }
 
-fn serialize_op_rhs<T: Serialize, S: Serializer>(
+fn serialize_op_rhs<T: ?Sized + Serialize, S: Serializer>(
     op: &'static str,
     rhs: &T,
     ser: S,
@@ -100,6 +297,22 @@ fn serialize_op_rhs<T: Serialize, S: Serializer>(
     out.end()
 }
 
+// Comparisons that ignore case are serialized like the plain ones, but with
+// `"nocase": true`.
+fn serialize_op_rhs_nocase<O: ?Sized + Serialize, T: ?Sized + Serialize, S: Serializer>(
+    op: &O,
+    rhs: &T,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+
+    let mut out = ser.serialize_struct("FieldOp", 3)?;
+    out.serialize_field("op", op)?;
+    out.serialize_field("rhs", rhs)?;
+    out.serialize_field("nocase", &true)?;
+    out.end()
+}
+
 fn serialize_is_true<S: Serializer>(ser: S) -> Result<S::Ok, S::Error> {
     use serde::ser::SerializeStruct;
 
@@ -112,302 +325,3749 @@ fn serialize_contains<S: Serializer>(rhs: &Bytes, ser: S) -> Result<S::Ok, S::Er
     serialize_op_rhs("Contains", rhs, ser)
 }
 
+// Serde passes fields by reference.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn serialize_ordering_nocase<S: Serializer>(
+    op: &OrderingOp,
+    rhs: &Bytes,
+    ser: S,
+) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs_nocase(op, rhs, ser)
+}
+
+fn serialize_contains_nocase<S: Serializer>(rhs: &Bytes, ser: S) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs_nocase("Contains", rhs, ser)
+}
+
 fn serialize_matches<S: Serializer>(rhs: &Regex, ser: S) -> Result<S::Ok, S::Error> {
     serialize_op_rhs("Matches", rhs, ser)
 }
 
+fn serialize_matches_pattern<S: Serializer>(rhs: &str, ser: S) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs("MatchesPattern", rhs, ser)
+}
+
+fn serialize_wildcard<S: Serializer>(rhs: &Wildcard, ser: S) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs("Wildcard", rhs, ser)
+}
+
 fn serialize_one_of<S: Serializer>(rhs: &RhsValues, ser: S) -> Result<S::Ok, S::Error> {
     serialize_op_rhs("OneOf", rhs, ser)
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
-pub struct FieldExpr<'s> {
-    field: Field<'s>,
+fn serialize_one_of_list<S: Serializer>(list: &str, ser: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
 
-    #[serde(flatten)]
-    op: FieldOp,
+    let mut out = ser.serialize_struct("FieldOp", 2)?;
+    out.serialize_field("op", "OneOf")?;
+    out.serialize_field("list", list)?;
+    out.end()
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
-        let initial_input = input;
+// Serde passes fields by reference.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn serialize_empty_array<S: Serializer>(op: &OrderingOp, ser: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
 
-        let (field, input) = Field::lex_with(input, scheme)?;
-        let field_type = field.get_type();
+    let mut out = ser.serialize_struct("FieldOp", 2)?;
+    out.serialize_field("op", op)?;
+    out.serialize_field("rhs", &[(); 0])?;
+    out.end()
+}
 
-        let (op, input) = if field_type == Type::Bool {
-            (FieldOp::IsTrue, input)
-        } else {
-            let (op, input) = ComparisonOp::lex(skip_space(input))?;
+/// A typed view of the right-hand side of a [`FieldExpr`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum ComparisonRhs<'a> {
+    /// A boolean field used on its own doesn't have a right-hand side.
+    None,
+    /// A single literal value.
+    Literal(LhsValue<'a>),
+    /// A CIDR network for `==` and `!=`, represented as an inclusive range
+    /// from its first to the last address.
+    Cidr(RangeInclusive<LhsValue<'a>>),
+    /// An IPv6 address with a zone identifier, like `fe80::1%eth0`, for `==`
+    /// and `!=`. The zone is without the leading `%`.
+    ZonedIp(Ipv6Addr, &'a str),
+    /// A regular expression as written in the filter.
+    Regex(&'a str),
+    /// A name of a regular expression supplied at execution time, without
+    /// the leading `$`.
+    Pattern(&'a str),
+    /// A name of a constant supplied at execution time, without the leading
+    /// `$`.
+    Constant(&'a str),
+    /// A name of a list supplied at execution time for the `in` operator,
+    /// without the leading `$`.
+    NamedList(&'a str),
+    /// The empty array literal `[]`, compared with a whole array field.
+    EmptyArray,
+    /// A list of values for the `in` operator.
+    ///
+    /// Each item is represented as an inclusive range, so single values have
+    /// the same start and end, and CIDR networks span from their first to the
+    /// last address. For `cidr` fields, each network is a single value.
+    List(Vec<RangeInclusive<LhsValue<'a>>>),
+}
 
-            let input_after_op = input;
+/// Picks the compared value of a map or an array field.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum Subscript {
+    /// An item of a map, like `["host"]`.
+    Key(Bytes),
+    /// An element of an array, like `[0]`.
+    Index(usize),
+    /// Each element of an array, like in `any(http.cookies == "a")`.
+    Each(ContainsQuantifier),
+    /// Each value of a map item whose key appears multiple times, like in
+    /// `all(http.headers["cookie"][*] contains "a")`.
+    EachOfKey(Bytes, ContainsQuantifier),
+}
 
-            let input = skip_space(input);
+impl Subscript {
+    /// Returns the quantifier if the comparison is of each of the values.
+    pub(crate) fn quantifier(&self) -> Option<ContainsQuantifier> {
+        match self {
+            Subscript::Each(quantifier) | Subscript::EachOfKey(_, quantifier) => Some(*quantifier),
+            Subscript::Key(_) | Subscript::Index(_) => None,
+        }
+    }
+}
 
-            match (field_type, op) {
-                (_, ComparisonOp::In) => {
-                    let (rhs, input) = RhsValues::lex_with(input, field_type)?;
-                    (FieldOp::OneOf(rhs), input)
-                }
-                (_, ComparisonOp::Ordering(op)) => {
-                    let (rhs, input) = RhsValue::lex_with(input, field_type)?;
-                    (FieldOp::Ordering { op, rhs }, input)
-                }
-                (Type::Int, ComparisonOp::Int(op)) => {
-                    let (rhs, input) = i32::lex(input)?;
-                    (FieldOp::Int { op, rhs }, input)
-                }
-                (Type::Bytes, ComparisonOp::Bytes(op)) => match op {
-                    BytesOp::Contains => {
-                        let (bytes, input) = Bytes::lex(input)?;
-                        (FieldOp::Contains(bytes), input)
-                    }
-                    BytesOp::Matches => {
-                        let (regex, input) = Regex::lex(input)?;
-                        (FieldOp::Matches(regex), input)
-                    }
-                },
-                _ => {
-                    return Err((
-                        LexErrorKind::UnsupportedOp { field_type },
-                        span(initial_input, input_after_op),
-                    ));
-                }
+// Subscripts are flattened into comparisons as `"key"`, `"index"` and
+// `"quantifier"` properties, where `[*]` of a map item has both a key and a
+// quantifier.
+impl Serialize for Subscript {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut out = ser.serialize_map(None)?;
+        match self {
+            Subscript::Key(key) => out.serialize_entry("key", key)?,
+            Subscript::Index(index) => out.serialize_entry("index", index)?,
+            Subscript::Each(quantifier) => out.serialize_entry("quantifier", quantifier)?,
+            Subscript::EachOfKey(key, quantifier) => {
+                out.serialize_entry("key", key)?;
+                out.serialize_entry("quantifier", quantifier)?;
             }
-        };
+        }
+        out.end()
+    }
+}
 
-        Ok((FieldExpr { field, op }, input))
+// Formats the subscript after the field name. Quantifiers go around the
+// whole comparison instead, so they are written by `FieldExpr`.
+impl Display for Subscript {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Subscript::Key(key) => {
+                f.write_str("[")?;
+                key.fmt_literal(f)?;
+                f.write_str("]")
+            }
+            Subscript::Index(index) => write!(f, "[{}]", index),
+            Subscript::Each(_) => Ok(()),
+            Subscript::EachOfKey(key, _) => {
+                f.write_str("[")?;
+                key.fmt_literal(f)?;
+                f.write_str("][*]")
+            }
+        }
     }
 }
 
-impl<'s> Expr<'s> for FieldExpr<'s> {
-    fn uses(&self, field: Field<'s>) -> bool {
-        self.field == field
+// Returns the compared value, which for `Subscript::Each` and
+// `Subscript::EachOfKey` is the element or the value at `index`, and the
+// only value otherwise.
+fn get_value<'e>(
+    ctx: &ExecutionContext<'e>,
+    field: Field<'_>,
+    subscript: Option<&Subscript>,
+    index: usize,
+) -> Option<LhsValue<'e>> {
+    match subscript {
+        None => ctx.get_field_value_unchecked(field),
+        Some(Subscript::Key(key)) => ctx.get_map_value_unchecked(field, key),
+        Some(Subscript::Index(index)) => ctx.get_array_element_unchecked(field, *index),
+        Some(Subscript::Each(_)) => ctx.get_array_element_unchecked(field, index),
+        Some(Subscript::EachOfKey(key, _)) => ctx.get_map_value_at_unchecked(field, key, index),
     }
+}
 
-    fn compile(self) -> CompiledExpr<'s> {
-        let field = self.field;
+// Returns the part of the input a span points to. Spans are kept as offsets
+// from the end, as that's what's left to lex when a comparison starts and ends.
+pub(crate) fn span_text(span: (usize, usize), input: &str) -> Option<&str> {
+    // The parser ignores surrounding whitespace, so do the same here.
+    let input = input.trim();
+    let (start, end) = span;
+    input.get(input.len().checked_sub(start)?..input.len().checked_sub(end)?)
+}
 
-        macro_rules! cast_field {
-            ($ctx:ident, $ty:ident) => {
-                match $ctx.get_field_value_unchecked(field) {
-                    LhsValue::$ty(value) => value,
-                    _ => unreachable!(),
+/// An argument of a [`FunctionCall`].
+#[derive(PartialEq, Eq, Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum FunctionArg<'s> {
+    Field {
+        field: Field<'s>,
+        #[serde(flatten)]
+        subscript: Option<Subscript>,
+    },
+    Literal {
+        literal: RhsValue,
+    },
+}
+
+impl<'s> fmt::Debug for FunctionArg<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionArg::Field { field, subscript } => match subscript {
+                Some(Subscript::Key(key)) => write!(f, "{:?}[{:?}]", field, key),
+                Some(subscript) => write!(f, "{:?}[{:?}]", field, subscript),
+                None => write!(f, "{:?}", field),
+            },
+            FunctionArg::Literal { literal } => write!(f, "{:?}", literal),
+        }
+    }
+}
+
+impl<'s> Display for FunctionArg<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionArg::Field { field, subscript } => {
+                f.write_str(field.name())?;
+                match subscript {
+                    Some(subscript) => subscript.fmt(f),
+                    None => Ok(()),
                 }
-            };
+            }
+            FunctionArg::Literal { literal } => literal.fmt(f),
         }
+    }
+}
 
-        match self.op {
-            FieldOp::IsTrue => CompiledExpr::new(move |ctx| *cast_field!(ctx, Bool)),
-            FieldOp::Ordering { op, rhs } => CompiledExpr::new(move |ctx| {
-                op.matches_opt(
-                    ctx.get_field_value_unchecked(field)
-                        .strict_partial_cmp(&rhs),
-                )
-            }),
-            FieldOp::Int {
-                op: IntOp::BitwiseAnd,
-                rhs,
-            } => CompiledExpr::new(move |ctx| cast_field!(ctx, Int) & rhs != 0),
-            FieldOp::Contains(bytes) => {
-                let searcher = HeapSearcher::from(bytes);
+/// A call of a function registered in the scheme, like `lower(http.host)`,
+/// whose result is compared instead of a field.
+#[derive(Clone, Serialize)]
+pub(crate) struct FunctionCall<'s> {
+    name: &'s str,
 
-                CompiledExpr::new(move |ctx| searcher.search_in(cast_field!(ctx, Bytes)).is_some())
-            }
-            FieldOp::Matches(regex) => {
-                CompiledExpr::new(move |ctx| regex.is_match(cast_field!(ctx, Bytes)))
-            }
-            FieldOp::OneOf(values) => match values {
-                RhsValues::Ip(ranges) => {
-                    let mut v4 = Vec::new();
-                    let mut v6 = Vec::new();
-                    for range in ranges {
-                        match range.clone().into() {
-                            ExplicitIpRange::V4(range) => v4.push(range),
-                            ExplicitIpRange::V6(range) => v6.push(range),
-                        }
-                    }
-                    let v4 = RangeSet::from(v4);
-                    let v6 = RangeSet::from(v6);
-                    CompiledExpr::new(move |ctx| match cast_field!(ctx, Ip) {
-                        IpAddr::V4(addr) => v4.contains(addr),
-                        IpAddr::V6(addr) => v6.contains(addr),
-                    })
-                }
-                RhsValues::Int(values) => {
-                    let values: RangeSet<_> = values.iter().cloned().collect();
-                    CompiledExpr::new(move |ctx| values.contains(cast_field!(ctx, Int)))
-                }
-                RhsValues::Bytes(values) => {
-                    let values: IndexSet<Box<[u8]>, FnvBuildHasher> =
-                        values.into_iter().map(|value| value.into()).collect();
+    #[serde(skip)]
+    function: &'s Function,
 
-                    CompiledExpr::new(move |ctx| values.contains(cast_field!(ctx, Bytes) as &[u8]))
-                }
-                RhsValues::Bool(_) => unreachable!(),
-            },
+    args: Vec<FunctionArg<'s>>,
+}
+
+impl<'s> PartialEq for FunctionCall<'s> {
+    fn eq(&self, other: &Self) -> bool {
+        // Names are unique within a scheme, and fields already compare
+        // schemes.
+        self.name == other.name && self.args == other.args
+    }
+}
+
+impl<'s> Eq for FunctionCall<'s> {}
+
+impl<'s> fmt::Debug for FunctionCall<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple(self.name);
+        for arg in &self.args {
+            f.field(arg);
         }
+        f.finish()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cidr::{Cidr, IpCidr};
-    use execution_context::ExecutionContext;
-    use lazy_static::lazy_static;
-    use rhs_types::IpRange;
-    use std::net::IpAddr;
+impl<'s> Display for FunctionCall<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            arg.fmt(f)?;
+        }
+        f.write_str(")")
+    }
+}
 
-    lazy_static! {
-        static ref SCHEME: Scheme = Scheme! {
-            http.host: Bytes,
-            ip.addr: Ip,
-            ssl: Bool,
-            tcp.port: Int,
-        };
+impl<'s> FunctionCall<'s> {
+    pub(crate) fn new(name: &'s str, function: &'s Function, args: Vec<FunctionArg<'s>>) -> Self {
+        FunctionCall {
+            name,
+            function,
+            args,
+        }
     }
 
-    fn field(name: &'static str) -> Field<'static> {
-        SCHEME.get_field_index(name).unwrap()
+    pub(crate) fn return_type(&self) -> Type {
+        self.function.return_type()
     }
 
-    #[test]
-    fn test_is_true() {
-        let expr = assert_ok!(
-            FieldExpr::lex_with("ssl", &SCHEME),
-            FieldExpr {
-                field: field("ssl"),
-                op: FieldOp::IsTrue
-            }
-        );
+    pub(crate) fn fields<'a>(&'a self) -> impl Iterator<Item = Field<'s>> + 'a {
+        self.args.iter().filter_map(|arg| match arg {
+            FunctionArg::Field { field, .. } => Some(*field),
+            FunctionArg::Literal { .. } => None,
+        })
+    }
 
-        assert_json!(
-            expr,
-            {
-                "field": "ssl",
-                "op": "IsTrue"
+    // Whether all field arguments are set, so that the function is called.
+    fn is_known(&self, ctx: &ExecutionContext<'_>) -> bool {
+        self.args.iter().all(|arg| match arg {
+            FunctionArg::Field { field, subscript } => {
+                get_value(ctx, *field, subscript.as_ref(), 0).is_some()
             }
-        );
+            FunctionArg::Literal { .. } => true,
+        })
+    }
 
-        let expr = expr.compile();
-        let ctx = &mut ExecutionContext::new(&SCHEME);
+    fn execute<'a, 'e: 'a>(
+        &'a self,
+        ctx: &'a ExecutionContext<'e>,
+        index: usize,
+    ) -> Option<FunctionValue<'a>> {
+        let mut args = Vec::with_capacity(self.args.len());
+        for arg in &self.args {
+            args.push(match arg {
+                FunctionArg::Field { field, subscript } => {
+                    get_value(ctx, *field, subscript.as_ref(), index)?
+                }
+                FunctionArg::Literal { literal } => literal.into(),
+            });
+        }
+        self.function.call(&args)
+    }
+}
 
-        ctx.set_field_value("ssl", true).unwrap();
-        assert_eq!(expr.execute(ctx), true);
+/// The compared value of `contains`, by which disjunctions are fused.
+pub(crate) type ContainsLhs<'s> = (Field<'s>, Option<Subscript>, Option<FunctionCall<'s>>);
 
-        ctx.set_field_value("ssl", false).unwrap();
-        assert_eq!(expr.execute(ctx), false);
-    }
+// The compared value of a comparison, which is either a field, or a result
+// of a function call owned by the closure that computes it.
+enum Lhs<'s> {
+    Field(Field<'s>, Option<Subscript>),
+    Call(FunctionCall<'s>),
+}
 
-    #[test]
-    fn test_ip_compare() {
-        let expr = assert_ok!(
-            FieldExpr::lex_with("ip.addr <= 10:20:30:40:50:60:70:80", &SCHEME),
-            FieldExpr {
-                field: field("ip.addr"),
-                op: FieldOp::Ordering {
-                    op: OrderingOp::LessThanEqual,
-                    rhs: RhsValue::Ip(IpAddr::from([
-                        0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80
-                    ]))
-                },
+impl<'s> Lhs<'s> {
+    fn get<'a, 'e: 'a>(
+        &'a self,
+        ctx: &'a ExecutionContext<'e>,
+        index: usize,
+    ) -> Option<FunctionValue<'a>> {
+        match self {
+            Lhs::Field(field, subscript) => {
+                get_value(ctx, *field, subscript.as_ref(), index).map(FunctionValue::Value)
             }
-        );
+            Lhs::Call(call) => call.execute(ctx, index),
+        }
+    }
+}
 
-        assert_json!(
-            expr,
-            {
-                "field": "ip.addr",
-                "op": "LessThanEqual",
-                "rhs": "10:20:30:40:50:60:70:80"
-            }
-        );
+// A compiled comparison of a single value, which for `any(...)` and
+// `all(...)` is called with the index of each element in turn.
+type ValueExpr<'s> = Box<dyn 's + Fn(&ExecutionContext<'_>, usize) -> bool + Send + Sync>;
 
-        let expr = expr.compile();
-        let ctx = &mut ExecutionContext::new(&SCHEME);
+/// A comparison of a field against a literal, like `tcp.port == 443`.
+///
+/// Boolean fields used on their own are represented by this node as well.
+///
+/// Comparisons are equal if they have the same field and operation,
+/// regardless of where they were parsed from.
+#[derive(Clone, Serialize)]
+pub struct FieldExpr<'s> {
+    field: Field<'s>,
 
-        ctx.set_field_value("ip.addr", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
-            .unwrap();
+    #[serde(flatten)]
+    subscript: Option<Subscript>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    call: Option<FunctionCall<'s>>,
+
+    #[serde(flatten)]
+    op: FieldOp,
+
+    // Start and end of the source text as byte offsets from the end of the
+    // input, because that's all lexers know while looking at the rest of it.
+    #[serde(skip)]
+    span: Option<(usize, usize)>,
+}
+
+impl<'s> PartialEq for FieldExpr<'s> {
+    fn eq(&self, other: &Self) -> bool {
+        self.field == other.field
+            && self.subscript == other.subscript
+            && self.call == other.call
+            && self.op == other.op
+    }
+}
+
+impl<'s> Eq for FieldExpr<'s> {}
+
+impl<'s> fmt::Debug for FieldExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("FieldExpr");
+        f.field("field", &self.field);
+        if let Some(subscript) = &self.subscript {
+            f.field("subscript", subscript);
+        }
+        if let Some(call) = &self.call {
+            f.field("call", call);
+        }
+        f.field("op", &self.op).finish()
+    }
+}
+
+/// Formats the comparison as filter source, like `tcp.port in { 80 443 }`.
+impl<'s> Display for FieldExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let quantifier = match self.subscript.as_ref().and_then(Subscript::quantifier) {
+            Some(ContainsQuantifier::Any) => Some("any"),
+            Some(ContainsQuantifier::All) => Some("all"),
+            None => None,
+        };
+        if let Some(quantifier) = quantifier {
+            write!(f, "{}(", quantifier)?;
+        }
+
+        match &self.call {
+            Some(call) => call.fmt(f)?,
+            None => {
+                f.write_str(self.field.name())?;
+                if let Some(subscript) = &self.subscript {
+                    subscript.fmt(f)?;
+                }
+            }
+        }
+
+        match &self.op {
+            FieldOp::IsTrue => {}
+            FieldOp::Ordering { op, rhs } => write!(f, " {} {}", op, rhs)?,
+            // Host networks are written with the prefix length, as they would
+            // be lexed as addresses otherwise.
+            FieldOp::Cidr { op, rhs } => write!(
+                f,
+                " {} {}/{}",
+                op,
+                rhs.first_address(),
+                rhs.network_length()
+            )?,
+            FieldOp::Int { op, rhs } => write!(f, " {} {}", op, rhs)?,
+            FieldOp::IntOrdering {
+                op,
+                rhs,
+                ordering,
+                value,
+            } => write!(f, " {} {} {} {}", op, rhs, ordering, value)?,
+            FieldOp::OrderingIgnoreCase { op, rhs } => {
+                write!(f, " {} ", op)?;
+                rhs.fmt_literal(f)?;
+                f.write_str(" nocase")?;
+            }
+            FieldOp::Contains(rhs) => {
+                f.write_str(" contains ")?;
+                rhs.fmt_literal(f)?;
+            }
+            FieldOp::ContainsIgnoreCase(rhs) => {
+                f.write_str(" contains ")?;
+                rhs.fmt_literal(f)?;
+                f.write_str(" nocase")?;
+            }
+            FieldOp::Matches(regex) => write!(f, " matches {}", regex)?,
+            FieldOp::MatchesPattern(name) => write!(f, " matches ${}", name)?,
+            FieldOp::Wildcard(wildcard) => write!(f, " wildcard {}", wildcard)?,
+            FieldOp::ZonedIp { op, rhs, zone } => write!(f, " {} {}%{}", op, rhs, zone)?,
+            FieldOp::OrderingConstant { op, constant } => write!(f, " {} ${}", op, constant)?,
+            FieldOp::OneOf(values) => write!(f, " in {}", values)?,
+            FieldOp::OneOfList(name) => write!(f, " in ${}", name)?,
+            FieldOp::EmptyArray(op) => write!(f, " {} []", op)?,
+        }
+
+        if quantifier.is_some() {
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'s> FieldExpr<'s> {
+    pub(crate) fn new(field: Field<'s>, op: FieldOp) -> Self {
+        FieldExpr {
+            field,
+            op,
+            subscript: None,
+            call: None,
+            span: None,
+        }
+    }
+
+    /// Creates a comparison of a map or array item, or of a function result,
+    /// whose first field argument has to be `field`.
+    pub(crate) fn with_lhs(
+        field: Field<'s>,
+        subscript: Option<Subscript>,
+        call: Option<FunctionCall<'s>>,
+        op: FieldOp,
+    ) -> Self {
+        FieldExpr {
+            subscript,
+            call,
+            ..FieldExpr::new(field, op)
+        }
+    }
+
+    /// Returns the exact part of the filter this comparison was parsed from.
+    ///
+    /// `input` must be the same string that was passed to
+    /// [`Scheme::parse`](::Scheme::parse), or the whole document for filters
+    /// from [`Scheme::parse_all`](::Scheme::parse_all). Returns `None` for
+    /// comparisons created with a [`FilterBuilder`](::FilterBuilder) or if the
+    /// input doesn't match.
+    pub fn source_text<'i>(&self, input: &'i str) -> Option<&'i str> {
+        span_text(self.span?, input)
+    }
+
+    pub(crate) fn span(&self) -> Option<(usize, usize)> {
+        self.span
+    }
+
+    /// Returns the byte range of [`source_text`](FieldExpr::source_text)
+    /// within `input`.
+    pub(crate) fn source_range(&self, input: &str) -> Option<Range<usize>> {
+        let text = self.source_text(input)?;
+        let start = text.as_ptr() as usize - input.as_ptr() as usize;
+        Some(start..start + text.len())
+    }
+
+    /// Whether the comparison doesn't match any value, like `x in {}`.
+    ///
+    /// `all(...)` is excluded, as it matches empty arrays regardless.
+    pub(crate) fn never_matches(&self) -> bool {
+        let quantifier = self.subscript.as_ref().and_then(Subscript::quantifier);
+        match (&self.op, quantifier) {
+            (_, Some(ContainsQuantifier::All)) => false,
+            (FieldOp::OneOf(values), _) => values.len() == 0,
+            _ => false,
+        }
+    }
+
+    /// Whether this is a `matches` comparison with a regex that's likely to
+    /// match any value, like `"a?"`.
+    pub(crate) fn matches_empty(&self) -> bool {
+        match &self.op {
+            FieldOp::Matches(regex) => regex.matches_empty(),
+            _ => false,
+        }
+    }
+
+    /// Returns the name of a `$name` constant, list or pattern this
+    /// comparison references, if any.
+    pub(crate) fn reference(&self) -> Option<&str> {
+        match &self.op {
+            FieldOp::MatchesPattern(name) | FieldOp::OneOfList(name) => Some(name),
+            FieldOp::OrderingConstant { constant, .. } => Some(constant),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the compared field, or of the first field passed
+    /// to a function.
+    pub fn field(&self) -> &'s str {
+        self.field.name()
+    }
+
+    /// Returns the name of the called function, if the comparison is of its
+    /// result.
+    pub fn function(&self) -> Option<&'s str> {
+        self.call.as_ref().map(|call| call.name)
+    }
+
+    /// Returns all fields the comparison looks at, which for function calls
+    /// are the field arguments.
+    pub(crate) fn fields(&self) -> Vec<Field<'s>> {
+        match &self.call {
+            Some(call) => call.fields().collect(),
+            None => vec![self.field],
+        }
+    }
+
+    pub(crate) fn call(&self) -> Option<&FunctionCall<'s>> {
+        self.call.as_ref()
+    }
+
+    /// Returns the key of the compared item if the field is a map.
+    pub fn key(&self) -> Option<&[u8]> {
+        match &self.subscript {
+            Some(Subscript::Key(key)) | Some(Subscript::EachOfKey(key, _)) => Some(key),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn subscript(&self) -> Option<&Subscript> {
+        self.subscript.as_ref()
+    }
+
+    /// Returns the comparison operator, or `None` for a boolean field used
+    /// on its own.
+    pub fn op(&self) -> Option<ComparisonOp> {
+        Some(match self.op {
+            FieldOp::IsTrue => return None,
+            FieldOp::Ordering { op, .. }
+            | FieldOp::Cidr { op, .. }
+            | FieldOp::OrderingIgnoreCase { op, .. }
+            | FieldOp::ZonedIp { op, .. }
+            | FieldOp::OrderingConstant { op, .. }
+            | FieldOp::EmptyArray(op) => ComparisonOp::Ordering(op),
+            FieldOp::Int { op, .. } | FieldOp::IntOrdering { op, .. } => ComparisonOp::Int(op),
+            FieldOp::Contains(_) | FieldOp::ContainsIgnoreCase(_) => {
+                ComparisonOp::Bytes(BytesOp::Contains)
+            }
+            FieldOp::Matches(_) | FieldOp::MatchesPattern(_) => {
+                ComparisonOp::Bytes(BytesOp::Matches)
+            }
+            FieldOp::Wildcard(_) => ComparisonOp::Bytes(BytesOp::Wildcard),
+            FieldOp::OneOf(_) | FieldOp::OneOfList(_) => ComparisonOp::In,
+        })
+    }
+
+    /// Returns the comparison of the result of an integer operator, like
+    /// `!= 0` in `tcp.flags & 0x02 != 0`.
+    ///
+    /// Returns `None` if there's no integer operator, or if its result is
+    /// just checked to be non-zero, like in `tcp.flags & 0x02`.
+    pub fn int_result_comparison(&self) -> Option<(OrderingOp, i32)> {
+        match self.op {
+            FieldOp::IntOrdering {
+                ordering, value, ..
+            } => Some((ordering, value)),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the comparison ignores ASCII case, like
+    /// `http.host == "example.com" nocase`.
+    ///
+    /// The right-hand side of such comparisons is returned as written.
+    pub fn ignores_case(&self) -> bool {
+        match self.op {
+            FieldOp::OrderingIgnoreCase { .. } | FieldOp::ContainsIgnoreCase(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the right-hand side of the comparison.
+    pub fn rhs(&self) -> ComparisonRhs<'_> {
+        match &self.op {
+            FieldOp::IsTrue => ComparisonRhs::None,
+            FieldOp::Ordering { rhs, .. } => ComparisonRhs::Literal(rhs.into()),
+            FieldOp::Cidr { rhs, .. } => ComparisonRhs::Cidr(
+                LhsValue::Ip(rhs.first_address())..=LhsValue::Ip(rhs.last_address()),
+            ),
+            FieldOp::ZonedIp { rhs, zone, .. } => ComparisonRhs::ZonedIp(*rhs, zone),
+            FieldOp::Int { rhs, .. } | FieldOp::IntOrdering { rhs, .. } => {
+                ComparisonRhs::Literal(LhsValue::Int(*rhs))
+            }
+            FieldOp::OrderingIgnoreCase { rhs: bytes, .. }
+            | FieldOp::Contains(bytes)
+            | FieldOp::ContainsIgnoreCase(bytes) => ComparisonRhs::Literal(LhsValue::Bytes(bytes)),
+            FieldOp::Wildcard(wildcard) => {
+                ComparisonRhs::Literal(LhsValue::Bytes(wildcard.pattern()))
+            }
+            FieldOp::Matches(regex) => ComparisonRhs::Regex(regex.as_str()),
+            FieldOp::MatchesPattern(name) => ComparisonRhs::Pattern(name),
+            FieldOp::OrderingConstant { constant, .. } => ComparisonRhs::Constant(constant),
+            FieldOp::OneOfList(name) => ComparisonRhs::NamedList(name),
+            FieldOp::EmptyArray(_) => ComparisonRhs::EmptyArray,
+            FieldOp::OneOf(values) => ComparisonRhs::List(match values {
+                RhsValues::Ip(ranges) => ranges
+                    .iter()
+                    .map(|range| match ExplicitIpRange::from(range.clone()) {
+                        ExplicitIpRange::V4(range) => {
+                            LhsValue::Ip(IpAddr::V4(*range.start()))
+                                ..=LhsValue::Ip(IpAddr::V4(*range.end()))
+                        }
+                        ExplicitIpRange::V6(range) => {
+                            LhsValue::Ip(IpAddr::V6(*range.start()))
+                                ..=LhsValue::Ip(IpAddr::V6(*range.end()))
+                        }
+                    })
+                    .collect(),
+                RhsValues::Bytes(values) => values
+                    .iter()
+                    .map(|value| LhsValue::Bytes(value)..=LhsValue::Bytes(value))
+                    .collect(),
+                RhsValues::Int(ranges) => ranges
+                    .iter()
+                    .map(|range| LhsValue::Int(*range.start())..=LhsValue::Int(*range.end()))
+                    .collect(),
+                RhsValues::Bool(values) => values.iter().map(|value| match *value {}).collect(),
+                RhsValues::Cidr(values) => values
+                    .iter()
+                    .map(|value| LhsValue::Cidr(value.clone())..=LhsValue::Cidr(value.clone()))
+                    .collect(),
+                RhsValues::Endpoint(values) => values
+                    .iter()
+                    .map(|value| LhsValue::Endpoint(*value)..=LhsValue::Endpoint(*value))
+                    .collect(),
+                RhsValues::Float(ranges) => ranges
+                    .iter()
+                    .map(|range| {
+                        LhsValue::Float(range.start().value())
+                            ..=LhsValue::Float(range.end().value())
+                    })
+                    .collect(),
+                RhsValues::Timestamp(ranges) => ranges
+                    .iter()
+                    .map(|range| {
+                        LhsValue::Timestamp(*range.start())..=LhsValue::Timestamp(*range.end())
+                    })
+                    .collect(),
+                RhsValues::Mac(ranges) => ranges
+                    .iter()
+                    .map(|range| LhsValue::Mac(*range.start())..=LhsValue::Mac(*range.end()))
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Collects literal values of the given type written on the right-hand
+    /// side of the comparison.
+    ///
+    /// Regexes count as `bytes` literals, networks written for IP fields as
+    /// `cidr` ones, and each range in an `in` list contributes its bounds.
+    pub(crate) fn collect_literals<'a>(&'a self, ty: Type, literals: &mut Vec<LhsValue<'a>>) {
+        let mut push = |value: LhsValue<'a>| {
+            if value.get_type() == ty {
+                literals.push(value);
+            }
+        };
+
+        match &self.op {
+            FieldOp::Cidr { rhs, .. } => push(LhsValue::Cidr(rhs.clone())),
+            FieldOp::Matches(regex) => push(LhsValue::Bytes(regex.as_str().as_bytes())),
+            _ => match self.rhs() {
+                ComparisonRhs::Literal(value) => push(value),
+                ComparisonRhs::ZonedIp(addr, _) => push(LhsValue::Ip(addr.into())),
+                ComparisonRhs::List(ranges) => {
+                    for range in ranges {
+                        let (start, end) = range.into_inner();
+                        if start != end {
+                            push(start);
+                            push(end);
+                        } else {
+                            push(start);
+                        }
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// Splits `lhs contains "..."` into the compared value and the searched
+    /// bytes. Elements of `any(...)` and `all(...)` are left as they are, as
+    /// those are different for each comparison.
+    pub(crate) fn into_contains(self) -> Result<(ContainsLhs<'s>, Bytes), Self> {
+        if self.contains_lhs().is_none() {
+            return Err(self);
+        }
+
+        match self.op {
+            FieldOp::Contains(bytes) => Ok(((self.field, self.subscript, self.call), bytes)),
+            op => Err(FieldExpr {
+                field: self.field,
+                subscript: self.subscript,
+                call: self.call,
+                op,
+                span: self.span,
+            }),
+        }
+    }
+
+    /// Returns the compared value of a comparison that
+    /// [`into_contains`](FieldExpr::into_contains) would split.
+    pub(crate) fn contains_lhs(&self) -> Option<ContainsLhs<'s>> {
+        match self.op {
+            FieldOp::Contains(_)
+                if self
+                    .subscript
+                    .as_ref()
+                    .and_then(Subscript::quantifier)
+                    .is_none() =>
+            {
+                Some((self.field, self.subscript.clone(), self.call.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Compiles a disjunction of `contains` on the same value into a single
+    /// Aho-Corasick automaton, so that the value is scanned only once.
+    pub(crate) fn compile_contains_any(
+        (field, subscript, call): ContainsLhs<'s>,
+        mut needles: Vec<Bytes>,
+    ) -> CompiledExpr<'s> {
+        // The automaton never reports empty needles, but those match any value
+        // on their own, so the rest of the needles don't matter.
+        if needles.iter().any(|needle| needle.is_empty()) {
+            needles.retain(|needle| needle.is_empty());
+            needles.truncate(1);
+        }
+
+        if needles.len() == 1 {
+            return FieldExpr::with_lhs(
+                field,
+                subscript,
+                call,
+                FieldOp::Contains(needles.remove(0)),
+            )
+            .compile();
+        }
+
+        let automaton = AcAutomaton::new(needles.into_iter().map(Box::<[u8]>::from));
+
+        let lhs = match call {
+            Some(call) => Lhs::Call(call),
+            None => Lhs::Field(field, subscript),
+        };
+
+        CompiledExpr::new(move |ctx| {
+            let value = lhs.get(ctx, 0);
+            match value.as_ref().map(FunctionValue::as_lhs) {
+                Some(LhsValue::Bytes(value)) => automaton.find(value).next().is_some(),
+                Some(_) => unreachable!(),
+                None => false,
+            }
+        })
+    }
+}
+
+// A field, its subscript and a function call, if the field is the first
+// argument of one.
+type LexedLhs<'s> = (Field<'s>, Option<Subscript>, Option<FunctionCall<'s>>);
+
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for FieldExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let ((expr, _), input) = FieldExpr::lex_negatable(input, parser, false)?;
+        Ok((expr, input))
+    }
+}
+
+impl<'s> FieldExpr<'s> {
+    /// Lexes a comparison, additionally accepting `field !~ "regex"` and
+    /// `field not between a .. b` if `allow_negation` is set, which are
+    /// returned as the positive comparison with a `true` flag, so that it can
+    /// be wrapped into a `not`.
+    pub(crate) fn lex_negatable<'i, 'p>(
+        input: &'i str,
+        parser: &'p Parser<'s>,
+        allow_negation: bool,
+    ) -> LexResult<'i, (Self, bool)> {
+        let initial_input = input;
+
+        // A whole array field can only be compared with `[]`.
+        if let Some((expr, rest)) = Self::lex_empty_array(input, parser) {
+            return Ok(((expr, false), rest));
+        }
+
+        // `any(...)` and `all(...)` compare each element of an array field,
+        // or each value of a map item, like `http.headers["cookie"][*]`.
+        let (quantifier, input) = match Self::lex_quantifier(input) {
+            Some((quantifier, input)) => (Some(quantifier), skip_space(input)),
+            None => (None, input),
+        };
+
+        let ((field, subscript, call), input) = match quantifier {
+            Some(quantifier) => Self::lex_each(quantifier, input, parser)?,
+            None => Self::lex_lhs(input, parser)?,
+        };
+
+        // Negated operators would apply to each value rather than to the
+        // result, so they are not allowed for any of these.
+        let allow_negation =
+            allow_negation && subscript.as_ref().and_then(Subscript::quantifier).is_none();
+        let field_type = match &call {
+            Some(call) => call.return_type(),
+            None => field.get_type(),
+        };
+
+        let mut negated = false;
+
+        let (op, input) = if field_type == Type::Bool {
+            (FieldOp::IsTrue, input)
+        } else {
+            let input = skip_space(input);
+
+            // Negated operators have to be checked before other ones, as
+            // otherwise `!~` would be seen as an unknown one starting with `!`.
+            let (op, input) = match Self::lex_negated_op(input) {
+                Some((op, input)) if allow_negation => {
+                    negated = true;
+                    (op, input)
+                }
+                _ => ComparisonOp::lex(input)?,
+            };
+
+            let input_after_op = input;
+
+            let input = skip_space(input);
+
+            match (field_type, op) {
+                // Constants are supported for all types with ordering.
+                (Type::Ip, ComparisonOp::Ordering(op))
+                | (Type::Bytes, ComparisonOp::Ordering(op))
+                | (Type::Int, ComparisonOp::Ordering(op))
+                    if peek(input, "$") =>
+                {
+                    let (constant, input) = Self::lex_constant(input, field_type, parser)?;
+                    (FieldOp::OrderingConstant { op, constant }, input)
+                }
+                // A single network doesn't need to be wrapped into a list.
+                (Type::Cidr, ComparisonOp::In) if !peek(input, "{") => {
+                    let (rhs, input) = IpCidr::lex(input)?;
+                    (FieldOp::OneOf(RhsValues::Cidr(vec![rhs])), input)
+                }
+                (_, ComparisonOp::In) if peek(input, "$") => {
+                    let (list, input) = Self::lex_list(input, field_type, parser)?;
+                    (FieldOp::OneOfList(list), input)
+                }
+                (Type::Int, ComparisonOp::In) if peek(input, "[") || peek(input, "(") => {
+                    let (range, input) = lex_interval(input)?;
+                    (FieldOp::OneOf(RhsValues::Int(vec![range])), input)
+                }
+                (_, ComparisonOp::In) => {
+                    let (rhs, input) = RhsValues::lex_with(input, (field_type, &parser.options))?;
+                    (FieldOp::OneOf(rhs), input)
+                }
+                (Type::Ip, ComparisonOp::Ordering(op))
+                    if op == OrderingOp::Equal || op == OrderingOp::NotEqual =>
+                {
+                    // `ip("...")` forces the argument to be a single address.
+                    if let Some(res) = lex_cast(input, field_type) {
+                        let (rhs, input) = res?;
+                        (FieldOp::Ordering { op, rhs }, input)
+                    } else {
+                        match IpAddrOrCidr::lex(input)? {
+                            (IpAddrOrCidr::Addr(addr), rest) => {
+                                match lex_zone(input, addr, rest)? {
+                                    (Some((rhs, zone)), rest) => (
+                                        FieldOp::ZonedIp {
+                                            op,
+                                            rhs,
+                                            zone: zone.to_owned(),
+                                        },
+                                        rest,
+                                    ),
+                                    (None, rest) => (
+                                        FieldOp::Ordering {
+                                            op,
+                                            rhs: RhsValue::Ip(addr),
+                                        },
+                                        rest,
+                                    ),
+                                }
+                            }
+                            (IpAddrOrCidr::Cidr(rhs), input) => (FieldOp::Cidr { op, rhs }, input),
+                        }
+                    }
+                }
+                // `between` is the same as `in` with a single range, except
+                // that it allows spaces around `..`.
+                (Type::Int, ComparisonOp::Between) => {
+                    let (first, rest) = i32::lex(input)?;
+                    let rest = expect(skip_space(rest), "..")?;
+                    let (last, rest) = i32::lex(skip_space(rest))?;
+                    if last < first {
+                        return Err((LexErrorKind::IncompatibleRangeBounds, span(input, rest)));
+                    }
+                    (FieldOp::OneOf(RhsValues::Int(vec![first..=last])), rest)
+                }
+                (Type::Ip, ComparisonOp::Between) => {
+                    let (range, input) = ExplicitIpRange::lex_spaced(input)?;
+                    (
+                        FieldOp::OneOf(RhsValues::Ip(vec![IpRange::Explicit(range)])),
+                        input,
+                    )
+                }
+                (Type::Cidr, ComparisonOp::Ordering(op))
+                    if op != OrderingOp::Equal && op != OrderingOp::NotEqual =>
+                {
+                    return Err((
+                        LexErrorKind::UnsupportedOp {
+                            field_type,
+                            op: ComparisonOp::Ordering(op),
+                        },
+                        span(initial_input, input_after_op),
+                    ));
+                }
+                (Type::Bytes, ComparisonOp::Ordering(op))
+                    if op == OrderingOp::Equal || op == OrderingOp::NotEqual =>
+                {
+                    // Enum values are names of the field's own values, not of
+                    // function results.
+                    let enum_values = match call {
+                        Some(_) => None,
+                        None => parser.scheme.get_enum_values(field),
+                    };
+                    let (rhs, input) =
+                        match enum_values.and_then(|values| Self::lex_enum_value(input, values)) {
+                            Some(res) => res?,
+                            None => RhsValue::lex_with(input, (field_type, &parser.options))?,
+                        };
+                    match (rhs, Self::lex_nocase(input)) {
+                        (RhsValue::Bytes(rhs), Some(input)) => {
+                            (FieldOp::OrderingIgnoreCase { op, rhs }, input)
+                        }
+                        (rhs, _) => (FieldOp::Ordering { op, rhs }, input),
+                    }
+                }
+                // Constant arithmetic is folded into a single value.
+                (Type::Int, ComparisonOp::Ordering(op))
+                    if lex_cast(input, field_type).is_none() =>
+                {
+                    let (rhs, input) = lex_int_expr(input)?;
+                    (
+                        FieldOp::Ordering {
+                            op,
+                            rhs: RhsValue::Int(rhs),
+                        },
+                        input,
+                    )
+                }
+                (_, ComparisonOp::Ordering(op)) => {
+                    let (rhs, input) = RhsValue::lex_with(input, (field_type, &parser.options))?;
+                    (FieldOp::Ordering { op, rhs }, input)
+                }
+                (Type::Int, ComparisonOp::Int(op)) => {
+                    let (rhs, rest) = lex_int_expr(input)?;
+                    if !op.is_valid_rhs(rhs) {
+                        return Err((LexErrorKind::InvalidShift, span(input, rest)));
+                    }
+
+                    // Without an explicit comparison, the result is checked
+                    // to be non-zero. Another integer operator, like `<<`
+                    // after `&`, isn't a comparison even though it starts
+                    // like one.
+                    match OrderingOp::lex(skip_space(rest)) {
+                        Ok(_) if IntOp::lex(skip_space(rest)).is_ok() => {
+                            (FieldOp::Int { op, rhs }, rest)
+                        }
+                        Ok((ordering, input)) => {
+                            let (value, input) = lex_int_expr(skip_space(input))?;
+                            (
+                                FieldOp::IntOrdering {
+                                    op,
+                                    rhs,
+                                    ordering,
+                                    value,
+                                },
+                                input,
+                            )
+                        }
+                        Err(_) => (FieldOp::Int { op, rhs }, rest),
+                    }
+                }
+                (Type::Bytes, ComparisonOp::Bytes(op)) => match op {
+                    BytesOp::Contains => {
+                        let (bytes, input) = Bytes::lex_with(input, &parser.options)?;
+                        match Self::lex_nocase(input) {
+                            Some(input) => (FieldOp::ContainsIgnoreCase(bytes), input),
+                            None => (FieldOp::Contains(bytes), input),
+                        }
+                    }
+                    BytesOp::Wildcard => {
+                        let (wildcard, input) = Wildcard::lex_with(input, &parser.options)?;
+                        (FieldOp::Wildcard(wildcard), input)
+                    }
+                    BytesOp::Matches => {
+                        parser
+                            .add_regex()
+                            .map_err(|err| (err, span(initial_input, input_after_op)))?;
+
+                        match expect(input, "$") {
+                            Ok(input) => {
+                                let (name, input) = take_while(input, "pattern name", |c| {
+                                    c.is_ascii_alphanumeric() || c == '_'
+                                })?;
+                                (FieldOp::MatchesPattern(name.to_owned()), input)
+                            }
+                            Err(_) => {
+                                let (regex, input) = Regex::lex_with(input, &parser.options)?;
+                                (FieldOp::Matches(regex), input)
+                            }
+                        }
+                    }
+                },
+                _ => {
+                    return Err((
+                        LexErrorKind::UnsupportedOp { field_type, op },
+                        span(initial_input, input_after_op),
+                    ));
+                }
+            }
+        };
+
+        let input = match quantifier {
+            Some(_) => expect(skip_space(input), ")")?,
+            None => input,
+        };
+
+        let span = Some((initial_input.len(), input.len()));
+
+        Ok((
+            (
+                FieldExpr {
+                    field,
+                    subscript,
+                    call,
+                    op,
+                    span,
+                },
+                negated,
+            ),
+            input,
+        ))
+    }
+
+    /// Lexes `field contains any { ... }` or `field contains all { ... }`
+    /// into separate `contains` comparisons for each item of the list, or
+    /// returns `None` if the input doesn't look like either of those.
+    pub(crate) fn lex_contains_list<'i>(
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> Option<LexResult<'i, (ContainsQuantifier, Vec<Self>)>> {
+        let initial_input = input;
+
+        let ((field, subscript, call), rest) = Self::lex_lhs(input, parser).ok()?;
+        let rest = expect(skip_space(rest), "contains").ok()?;
+        let input_after_op = rest;
+        let (quantifier, rest) = ContainsQuantifier::lex(skip_space(rest)).ok()?;
+        let rest = Self::skip_of(rest);
+
+        let field_type = match &call {
+            Some(call) => call.return_type(),
+            None => field.get_type(),
+        };
+
+        if field_type != Type::Bytes {
+            return Some(Err((
+                LexErrorKind::UnsupportedOp {
+                    field_type,
+                    op: ComparisonOp::Bytes(BytesOp::Contains),
+                },
+                span(initial_input, input_after_op),
+            )));
+        }
+
+        let (values, rest) =
+            match RhsValues::lex_with(skip_space(rest), (field_type, &parser.options)) {
+                Ok((RhsValues::Bytes(values), rest)) => (values, rest),
+                Ok(_) => unreachable!(),
+                Err(err) => return Some(Err(err)),
+            };
+
+        let span = Some((initial_input.len(), rest.len()));
+
+        let items = values
+            .into_iter()
+            .map(|bytes| FieldExpr {
+                field,
+                subscript: subscript.clone(),
+                call: call.clone(),
+                op: FieldOp::Contains(bytes),
+                span,
+            })
+            .collect();
+
+        Some(Ok(((quantifier, items), rest)))
+    }
+
+    /// Lexes an array field followed by `== []` or `!= []`, or returns
+    /// `None` if the input doesn't look like either of those.
+    fn lex_empty_array<'i>(input: &'i str, parser: &Parser<'s>) -> Option<(Self, &'i str)> {
+        let (field, rest) = Field::lex_with(input, parser.scheme).ok()?;
+        if !field.is_array() {
+            return None;
+        }
+
+        let (op, rest) = OrderingOp::lex(skip_space(rest)).ok()?;
+        if op != OrderingOp::Equal && op != OrderingOp::NotEqual {
+            return None;
+        }
+
+        let rest = expect(skip_space(rest), "[").ok()?;
+        let rest = expect(skip_space(rest), "]").ok()?;
+
+        let expr = FieldExpr {
+            span: Some((input.len(), rest.len())),
+            ..FieldExpr::new(field, FieldOp::EmptyArray(op))
+        };
+        Some((expr, rest))
+    }
+
+    /// Whether this is `== []` or `!= []` with an array field.
+    fn is_empty_array_check(&self) -> bool {
+        match self.op {
+            FieldOp::EmptyArray(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Skips the optional `of` in `contains any of { ... }`.
+    pub(crate) fn skip_of(input: &str) -> &str {
+        match expect(skip_space(input), "of") {
+            Ok(rest) if !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') => rest,
+            _ => input,
+        }
+    }
+
+    /// Lexes a `$name` reference to a constant of the given type.
+    fn lex_constant<'i>(input: &'i str, ty: Type, parser: &Parser<'s>) -> LexResult<'i, String> {
+        let (name, rest) = take_while(expect(input, "$")?, "constant name", |c| {
+            c.is_ascii_alphanumeric() || c == '_'
+        })?;
+
+        match parser.scheme.get_constant_type(name) {
+            Some(actual) if actual == ty => Ok((name.to_owned(), rest)),
+            Some(actual) => Err((
+                LexErrorKind::TypeMismatch {
+                    expected: ty,
+                    actual,
+                },
+                span(input, rest),
+            )),
+            None => Err((LexErrorKind::UnknownConstant, span(input, rest))),
+        }
+    }
+
+    /// Lexes a `$name` reference to a list of the given type.
+    fn lex_list<'i>(input: &'i str, ty: Type, parser: &Parser<'s>) -> LexResult<'i, String> {
+        let (name, rest) = take_while(expect(input, "$")?, "list name", |c| {
+            c.is_ascii_alphanumeric() || c == '_'
+        })?;
+
+        match parser.scheme.get_list_type(name) {
+            Some(actual) if actual == ty => Ok((name.to_owned(), rest)),
+            Some(actual) => Err((
+                LexErrorKind::TypeMismatch {
+                    expected: ty,
+                    actual,
+                },
+                span(input, rest),
+            )),
+            None => Err((LexErrorKind::UnknownList, span(input, rest))),
+        }
+    }
+
+    /// Lexes the compared value, which is either a field with an optional
+    /// subscript, or a function call. For calls, the field is the first
+    /// field argument.
+    fn lex_lhs<'i>(input: &'i str, parser: &Parser<'s>) -> LexResult<'i, LexedLhs<'s>> {
+        if let Some(res) = Self::lex_call(input, parser) {
+            let ((field, call), input) = res?;
+            return Ok(((field, None, Some(call)), input));
+        }
+
+        let ((field, subscript), input) = Self::lex_field(input, parser)?;
+
+        // A map item followed by `[*]` is compared by each of its values, the
+        // same as if it was wrapped into `any(...)`.
+        match (subscript, expect(skip_space(input), "[*]")) {
+            (Some(Subscript::Key(key)), Ok(input)) => {
+                let subscript = Subscript::EachOfKey(key, ContainsQuantifier::Any);
+                Ok(((field, Some(subscript), None), input))
+            }
+            (subscript, _) => Ok(((field, subscript, None), input)),
+        }
+    }
+
+    /// Lexes the compared values inside `any(...)` and `all(...)`, which are
+    /// either an array field, or a map item followed by `[*]`.
+    fn lex_each<'i>(
+        quantifier: ContainsQuantifier,
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> LexResult<'i, LexedLhs<'s>> {
+        let initial_input = input;
+        let (field, input) = Field::lex_with(input, parser.scheme)?;
+
+        if field.is_map() {
+            let (key, input) = Self::lex_key(input, parser)?;
+            let input = expect(skip_space(input), "[*]")?;
+            return Ok((
+                (field, Some(Subscript::EachOfKey(key, quantifier)), None),
+                input,
+            ));
+        }
+
+        if !field.is_array() {
+            return Err((
+                LexErrorKind::ExpectedName("array field"),
+                span(initial_input, input),
+            ));
+        }
+        Ok(((field, Some(Subscript::Each(quantifier)), None), input))
+    }
+
+    /// Lexes a field with an optional subscript.
+    fn lex_field<'i>(
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> LexResult<'i, (Field<'s>, Option<Subscript>)> {
+        match Self::lex_field_name(input, parser)? {
+            ((field, None), input) => {
+                let (subscript, input) = Self::lex_subscript(field, input, parser)?;
+                Ok(((field, subscript), input))
+            }
+            res => Ok(res),
+        }
+    }
+
+    /// Lexes a field name. Names under a namespace that aren't fields
+    /// themselves are items of the namespace with the rest of the name as
+    /// the key, so they come with a subscript.
+    fn lex_field_name<'i>(
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> LexResult<'i, (Field<'s>, Option<Subscript>)> {
+        match Field::lex_with(input, parser.scheme) {
+            Ok((field, input)) => Ok(((field, None), input)),
+            Err((LexErrorKind::UnknownField(err), name)) => {
+                match parser.scheme.get_namespace_field(name) {
+                    Some((field, key)) => {
+                        let key = Bytes::from(key.to_owned());
+                        Ok(((field, Some(Subscript::Key(key))), &input[name.len()..]))
+                    }
+                    None => Err((LexErrorKind::UnknownField(err), name)),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Lexes `name(arg, ...)`, or returns `None` if the input doesn't start
+    /// with a name followed by `(`, which a field never is.
+    fn lex_call<'i>(
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> Option<LexResult<'i, (Field<'s>, FunctionCall<'s>)>> {
+        let initial_input = input;
+
+        let (name, rest) = take_while(input, "function name", |c| {
+            c.is_ascii_alphanumeric() || c == '_'
+        })
+        .ok()?;
+        let rest = expect(skip_space(rest), "(").ok()?;
+
+        let (name, function) = match parser.scheme.get_function(name) {
+            Some(function) => function,
+            None => return Some(Err((LexErrorKind::UnknownFunction, name))),
+        };
+
+        let params = function.params();
+        let mut args = Vec::with_capacity(params.len());
+        let mut input = skip_space(rest);
+
+        for &ty in params {
+            if peek(input, ")") {
+                return Some(Err((
+                    LexErrorKind::CountMismatch {
+                        name: "argument",
+                        actual: args.len(),
+                        expected: params.len(),
+                    },
+                    span(initial_input, &input[1..]),
+                )));
+            }
+
+            if !args.is_empty() {
+                input = match expect(input, ",") {
+                    Ok(input) => skip_space(input),
+                    Err(err) => return Some(Err(err)),
+                };
+            }
+
+            let (arg, rest) = match Self::lex_arg(input, ty, parser) {
+                Ok(res) => res,
+                Err(err) => return Some(Err(err)),
+            };
+            args.push(arg);
+            input = skip_space(rest);
+        }
+
+        let input = match expect(input, ")") {
+            Ok(input) => input,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let call = FunctionCall::new(name, function, args);
+
+        let field = call.fields().next();
+
+        Some(match field {
+            Some(field) => Ok(((field, call), input)),
+            None => Err((
+                LexErrorKind::ExpectedName("field argument"),
+                span(initial_input, input),
+            )),
+        })
+    }
+
+    /// Lexes a function argument of the given type, which is a field if the
+    /// input starts with a known field name, and a literal otherwise.
+    fn lex_arg<'i>(
+        input: &'i str,
+        ty: Type,
+        parser: &Parser<'s>,
+    ) -> LexResult<'i, FunctionArg<'s>> {
+        let field_err = match Self::lex_field_name(input, parser) {
+            Ok(((field, subscript), rest)) => {
+                let (subscript, rest) = match subscript {
+                    Some(subscript) => (Some(subscript), rest),
+                    None => Self::lex_subscript(field, rest, parser)?,
+                };
+                let actual = field.get_type();
+                if actual != ty {
+                    return Err((
+                        LexErrorKind::TypeMismatch {
+                            expected: ty,
+                            actual,
+                        },
+                        span(input, rest),
+                    ));
+                }
+                return Ok((FunctionArg::Field { field, subscript }, rest));
+            }
+            Err(err) => err,
+        };
+
+        match RhsValue::lex_with(input, (ty, &parser.options)) {
+            Ok((literal, rest)) => Ok((FunctionArg::Literal { literal }, rest)),
+            // Something that looks like a name is more likely a misspelled
+            // field than a literal.
+            Err(err) => match field_err.0 {
+                LexErrorKind::UnknownField(_) => Err(field_err),
+                _ => Err(err),
+            },
+        }
+    }
+
+    /// Lexes `any(` or `all(` before a comparison of array elements.
+    pub(crate) fn lex_quantifier(input: &str) -> Option<(ContainsQuantifier, &str)> {
+        let (quantifier, rest) = ContainsQuantifier::lex(input).ok()?;
+        let rest = expect(skip_space(rest), "(").ok()?;
+        Some((quantifier, rest))
+    }
+
+    /// Lexes the key of a map field item, like `["host"]`, which has to be a
+    /// quoted string, or the index of an array field element, like `[0]`.
+    /// Other fields don't have subscripts.
+    fn lex_subscript<'i>(
+        field: Field<'s>,
+        input: &'i str,
+        parser: &Parser<'s>,
+    ) -> LexResult<'i, Option<Subscript>> {
+        if field.is_map() {
+            let (key, input) = Self::lex_key(input, parser)?;
+            return Ok((Some(Subscript::Key(key)), input));
+        }
+
+        if !field.is_array() {
+            return Ok((None, input));
+        }
+
+        let input = skip_space(expect(input, "[")?);
+        let (digits, input) = take_while(input, "digit", |c| c.is_ascii_digit())?;
+        let index = digits
+            .parse()
+            .map_err(|err| (LexErrorKind::ParseInt { err, radix: 10 }, digits))?;
+        let input = expect(skip_space(input), "]")?;
+        Ok((Some(Subscript::Index(index)), input))
+    }
+
+    /// Lexes the key of a map field item, like `["host"]`.
+    fn lex_key<'i>(input: &'i str, parser: &Parser<'s>) -> LexResult<'i, Bytes> {
+        let input = skip_space(expect(input, "[")?);
+        if !input.starts_with('"') {
+            return Err((LexErrorKind::ExpectedLiteral("\""), input));
+        }
+        let (key, input) = Bytes::lex_with(input, &parser.options)?;
+        let input = expect(skip_space(input), "]")?;
+        Ok((key, input))
+    }
+
+    /// Lexes a bare identifier as one of the enum values registered for a
+    /// field, or returns `None` if the input looks like another literal.
+    fn lex_enum_value<'i>(input: &'i str, values: &[String]) -> Option<LexResult<'i, RhsValue>> {
+        let (word, rest) = take_while(input, "identifier", |c| {
+            c.is_ascii_alphanumeric() || c == '_'
+        })
+        .ok()?;
+
+        if values.iter().any(|value| value == word) {
+            return Some(Ok((RhsValue::Bytes(word.to_owned().into()), rest)));
+        }
+
+        // Leave hex bytes like `DE:AD` and casts like `bytes("...")` alone.
+        if word.chars().all(|c| c.is_ascii_hexdigit()) || skip_space(rest).starts_with('(') {
+            return None;
+        }
+
+        Some(Err((
+            LexErrorKind::UnknownEnumValue {
+                expected: values.join(", "),
+            },
+            word,
+        )))
+    }
+
+    /// Lexes the `nocase` modifier after `==`, `!=` and `contains` with
+    /// bytes. It has to be a whole word, so that `nocase_field` is left for
+    /// the caller to reject.
+    pub(crate) fn lex_nocase(input: &str) -> Option<&str> {
+        let rest = expect(skip_space(input), "nocase").ok()?;
+        if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+
+    /// Lexes `!~` and `not between`, negated forms of `matches` and `between`.
+    pub(crate) fn lex_negated_op(input: &str) -> Option<(ComparisonOp, &str)> {
+        if let Ok(input) = expect(input, "!~") {
+            return Some((ComparisonOp::Bytes(BytesOp::Matches), input));
+        }
+
+        let input = expect(input, "not").ok()?;
+        let rest = skip_space(input);
+
+        if rest.len() == input.len() {
+            return None;
+        }
+
+        expect(rest, "between")
+            .ok()
+            .map(|input| (ComparisonOp::Between, input))
+    }
+}
+
+impl<'s> Expr<'s> for FieldExpr<'s> {
+    fn uses(&self, field: Field<'s>) -> bool {
+        self.fields().contains(&field)
+    }
+
+    fn collect_comparisons<'a>(&'a self, comparisons: &mut Vec<&'a FieldExpr<'s>>) {
+        comparisons.push(self);
+    }
+
+    fn cost(&self) -> u64 {
+        match &self.op {
+            FieldOp::IsTrue
+            | FieldOp::Ordering { .. }
+            | FieldOp::OrderingIgnoreCase { .. }
+            | FieldOp::Cidr { .. }
+            | FieldOp::ZonedIp { .. }
+            | FieldOp::OrderingConstant { .. }
+            | FieldOp::Int { .. }
+            | FieldOp::IntOrdering { .. }
+            | FieldOp::EmptyArray(_) => 1,
+            // Lists can be large, but lookups are either hash- or binary-
+            // search-based.
+            FieldOp::Contains(_)
+            | FieldOp::ContainsIgnoreCase(_)
+            | FieldOp::Wildcard(_)
+            | FieldOp::OneOfList(_) => 10,
+            FieldOp::Matches(_) | FieldOp::MatchesPattern(_) => 100,
+            FieldOp::OneOf(values) => 1 + values.len() as u64,
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        match &self.op {
+            FieldOp::OneOf(values) => 1 + values.len(),
+            _ => 1,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match &self.op {
+            FieldOp::OneOf(values) if values.len() != 0 => 2,
+            _ => 1,
+        }
+    }
+
+    fn write_tree(&self, input: &str, indent: usize, out: &mut String) {
+        let label = match self.source_range(input) {
+            Some(range) => format!("`{}` {:?}", &input[range.clone()], range),
+            // Built with a `FilterBuilder`, so there's no source text.
+            None => match self.op() {
+                Some(op) => format!("`{} {} ...`", self.field(), op),
+                None => format!("`{}`", self.field()),
+            },
+        };
+        write_tree_line(out, indent, &label);
+    }
+
+    fn write_fingerprint(&self, out: &mut String) {
+        fn raw(bytes: &mut Bytes) {
+            *bytes = bytes.to_vec().into();
+        }
+
+        let mut op = self.op.clone();
+        match &mut op {
+            FieldOp::Ordering {
+                rhs: RhsValue::Bytes(bytes),
+                ..
+            }
+            | FieldOp::OrderingIgnoreCase { rhs: bytes, .. }
+            | FieldOp::Contains(bytes)
+            | FieldOp::ContainsIgnoreCase(bytes) => raw(bytes),
+            FieldOp::Wildcard(wildcard) => {
+                *wildcard = Wildcard::new(wildcard.pattern().to_vec().into())
+            }
+            FieldOp::OneOf(values) => {
+                if let RhsValues::Bytes(values) = values {
+                    values.iter_mut().for_each(raw);
+                }
+                values.sort();
+            }
+            _ => {}
+        }
+
+        // Regexes are debug-printed without quotes, so quote them to keep the
+        // serialization unambiguous.
+        let op = match op {
+            FieldOp::Matches(regex) => format!("Matches({:?})", regex.as_str()),
+            op => format!("{:?}", op),
+        };
+        out.push_str(&format!("{:?}", self.field.name()));
+        if let Some(call) = &self.call {
+            let mut call = call.clone();
+            for arg in &mut call.args {
+                if let FunctionArg::Literal {
+                    literal: RhsValue::Bytes(bytes),
+                } = arg
+                {
+                    raw(bytes);
+                }
+            }
+            out.push_str(&format!(" {:?}", call));
+        }
+        match &self.subscript {
+            Some(Subscript::Key(key)) => out.push_str(&format!("[{:?}]", key.to_vec())),
+            Some(Subscript::EachOfKey(key, quantifier)) => {
+                out.push_str(&format!("[{:?}][{:?}]", key.to_vec(), quantifier))
+            }
+            Some(subscript) => out.push_str(&format!("[{:?}]", subscript)),
+            None => {}
+        }
+        out.push_str(&format!(" {}", op));
+    }
+
+    fn canonicalize(&mut self) {
+        if let FieldOp::OneOf(values) = &mut self.op {
+            values.sort();
+        }
+    }
+
+    fn simplify_ranges(&mut self) {
+        if let FieldOp::OneOf(values) = &mut self.op {
+            values.remove_covered();
+        }
+    }
+
+    fn offset_spans(&mut self, offset: usize) {
+        if let Some((start, end)) = &mut self.span {
+            *start += offset;
+            *end += offset;
+        }
+    }
+
+    fn lower(self, program: &mut Vec<Instruction<'s>>) {
+        program.push(Instruction::Test(self.compile()));
+    }
+
+    fn compile_profiled(self, profiler: &mut Profiler) -> CompiledExpr<'s> {
+        profiler.leaf(self.to_string(), self.span, self.compile())
+    }
+
+    fn execute_traced<'a, 'e>(
+        &'a self,
+        ctx: &ExecutionContext<'e>,
+        reached: bool,
+        trace: &mut Vec<TraceEntry<'a, 's, 'e>>,
+    ) -> bool {
+        let result = self.clone().compile().execute(ctx);
+
+        trace.push(TraceEntry {
+            comparison: self,
+            evaluated: reached,
+            value: match (&self.subscript, &self.call) {
+                // There's no single value for all elements, and results of
+                // functions aren't kept after the comparison.
+                (Some(Subscript::Each(_)), _)
+                | (Some(Subscript::EachOfKey(..)), _)
+                | (_, Some(_)) => None,
+                (subscript, None) => get_value(ctx, self.field, subscript.as_ref(), 0),
+            },
+            result,
+        });
+
+        result
+    }
+
+    fn execute_matching<'a>(
+        &'a self,
+        ctx: &ExecutionContext<'_>,
+        leaves: &mut Vec<&'a FieldExpr<'s>>,
+    ) -> bool {
+        let result = self.clone().compile().execute(ctx);
+        if result {
+            leaves.push(self);
+        }
+        result
+    }
+
+    fn execute_ternary(&self, ctx: &ExecutionContext<'_>) -> Ternary {
+        let is_known = match (&self.subscript, &self.call) {
+            _ if self.is_empty_array_check() => ctx.get_array_len(self.field).is_some(),
+            (_, Some(call)) => call.is_known(ctx),
+            (Some(Subscript::Each(_)), None) => ctx.get_array_len(self.field).is_some(),
+            (Some(Subscript::EachOfKey(key, _)), None) => {
+                ctx.get_map_value_count(self.field, key).is_some()
+            }
+            (subscript, None) => get_value(ctx, self.field, subscript.as_ref(), 0).is_some(),
+        } && match &self.op {
+            FieldOp::MatchesPattern(name) => ctx.get_pattern(name).is_some(),
+            FieldOp::OrderingConstant { constant, .. } => ctx.get_constant(constant).is_some(),
+            FieldOp::OneOfList(name) => ctx.get_list(name).is_some(),
+            _ => true,
+        };
+
+        if is_known {
+            self.clone().compile().execute(ctx).into()
+        } else {
+            Ternary::Unknown
+        }
+    }
+
+    fn compile(self) -> CompiledExpr<'s> {
+        let each = match &self.subscript {
+            Some(Subscript::Each(quantifier)) => Some((None, *quantifier)),
+            Some(Subscript::EachOfKey(key, quantifier)) => Some((Some(key.clone()), *quantifier)),
+            _ => None,
+        };
+        let (key, quantifier) = match each {
+            Some(each) => each,
+            None => {
+                let expr = self.compile_value();
+                return CompiledExpr::new(move |ctx| expr(ctx, 0));
+            }
+        };
+
+        let field = self.field;
+        let expr = self.compile_value();
+
+        // Like unset fields, unset arrays and missing keys never match, but
+        // `all` matches empty arrays.
+        CompiledExpr::new(move |ctx| {
+            let len = match &key {
+                Some(key) => ctx.get_map_value_count(field, key),
+                None => ctx.get_array_len(field),
+            };
+            let len = match len {
+                Some(len) => len,
+                None => return false,
+            };
+
+            let mut results = (0..len).map(|index| expr(ctx, index));
+
+            match quantifier {
+                ContainsQuantifier::Any => results.any(|result| result),
+                ContainsQuantifier::All => results.all(|result| result),
+            }
+        })
+    }
+}
+
+impl<'s> FieldExpr<'s> {
+    // Compiles the comparison of a single value, which for `any(...)` and
+    // `all(...)` is the element at the index it's called with.
+    fn compile_value(self) -> ValueExpr<'s> {
+        // Zones are only set for plain fields.
+        let zone_field = match self.subscript {
+            None if self.call.is_none() => Some(self.field),
+            _ => None,
+        };
+
+        let field = self.field;
+
+        let lhs = match self.call {
+            Some(call) => Lhs::Call(call),
+            None => Lhs::Field(self.field, self.subscript),
+        };
+
+        // Function results are owned by the closure call, so the compared
+        // value is bound as `$value` for the duration of `$body`.
+        macro_rules! compile {
+            (|$ctx:ident, $value:ident| $body:expr) => {
+                Box::new(move |$ctx: &ExecutionContext<'_>, index| {
+                    let $value = lhs.get($ctx, index);
+                    let $value = $value.as_ref().map(FunctionValue::as_lhs);
+                    $body
+                })
+            };
+        }
+
+        // Unset fields resolve the whole comparison to `false`.
+        macro_rules! cast_field {
+            ($value:ident, $ty:ident) => {
+                match $value {
+                    Some(LhsValue::$ty(value)) => value,
+                    Some(_) => unreachable!(),
+                    None => return false,
+                }
+            };
+        }
+
+        match self.op {
+            // Like unset fields, unset arrays are neither empty nor not.
+            FieldOp::EmptyArray(op) => {
+                let is_equal = op == OrderingOp::Equal;
+
+                Box::new(move |ctx: &ExecutionContext<'_>, _| {
+                    ctx.get_array_len(field)
+                        .map_or(false, |len| (len == 0) == is_equal)
+                })
+            }
+            FieldOp::IsTrue => compile!(|ctx, value| cast_field!(value, Bool)),
+            FieldOp::Ordering { op, rhs } => compile!(|ctx, value| match value {
+                Some(value) => op.matches_opt(value.strict_partial_cmp(&rhs)),
+                None => false,
+            }),
+            FieldOp::Cidr { op, rhs } => {
+                let is_equal = op == OrderingOp::Equal;
+
+                compile!(|ctx, value| rhs.contains(&cast_field!(value, Ip)) == is_equal)
+            }
+            FieldOp::ZonedIp { op, rhs, zone } => {
+                let is_equal = op == OrderingOp::Equal;
+
+                compile!(|ctx, value| {
+                    let matches = cast_field!(value, Ip) == IpAddr::V6(rhs)
+                        && zone_field
+                            .map_or(false, |field| ctx.get_ip_zone(field) == Some(&zone[..]));
+                    matches == is_equal
+                })
+            }
+            FieldOp::Int { op, rhs } => {
+                compile!(|ctx, value| op.apply(cast_field!(value, Int), rhs) != 0)
+            }
+            FieldOp::IntOrdering {
+                op,
+                rhs,
+                ordering,
+                value: expected,
+            } => compile!(|ctx, value| {
+                let result = op.apply(cast_field!(value, Int), rhs);
+                ordering.matches(result.cmp(&expected))
+            }),
+            FieldOp::Contains(bytes) => {
+                let searcher = HeapSearcher::from(bytes);
+
+                compile!(|ctx, value| searcher.search_in(cast_field!(value, Bytes)).is_some())
+            }
+            FieldOp::OrderingIgnoreCase { op, rhs } => {
+                let is_equal = op == OrderingOp::Equal;
+
+                compile!(
+                    |ctx, value| cast_field!(value, Bytes).eq_ignore_ascii_case(&rhs) == is_equal
+                )
+            }
+            FieldOp::ContainsIgnoreCase(bytes) => {
+                // Empty needles match anything, and `windows` needs a
+                // non-zero size.
+                if bytes.is_empty() {
+                    return compile!(|ctx, value| value.is_some());
+                }
+
+                compile!(|ctx, value| cast_field!(value, Bytes)
+                    .windows(bytes.len())
+                    .any(|window| window.eq_ignore_ascii_case(&bytes)))
+            }
+            FieldOp::Matches(regex) => compile!(|ctx, value| {
+                let value = cast_field!(value, Bytes);
+                ctx.fits_regex_input_limit(value) && regex.is_match(value)
+            }),
+            FieldOp::Wildcard(wildcard) => {
+                compile!(|ctx, value| wildcard.is_match(cast_field!(value, Bytes)))
+            }
+            FieldOp::MatchesPattern(name) => compile!(|ctx, value| {
+                let value = cast_field!(value, Bytes);
+                if !ctx.fits_regex_input_limit(value) {
+                    return false;
+                }
+                match ctx.get_pattern(&name) {
+                    Some(regex) => regex.is_match(value),
+                    None => false,
+                }
+            }),
+            FieldOp::OrderingConstant { op, constant } => compile!(|ctx, value| {
+                let lhs = match value {
+                    Some(lhs) => lhs,
+                    None => return false,
+                };
+
+                let ordering = match (&lhs, ctx.get_constant(&constant)) {
+                    (LhsValue::Ip(lhs), Some(LhsValue::Ip(rhs))) => lhs.strict_partial_cmp(rhs),
+                    (LhsValue::Bytes(lhs), Some(LhsValue::Bytes(rhs))) => {
+                        lhs.strict_partial_cmp(*rhs)
+                    }
+                    (LhsValue::Int(lhs), Some(LhsValue::Int(rhs))) => lhs.strict_partial_cmp(rhs),
+                    // The types are checked when parsing and setting the
+                    // constant, so only missing constants end up here.
+                    _ => return false,
+                };
+
+                op.matches_opt(ordering)
+            }),
+            FieldOp::OneOfList(name) => compile!(|ctx, value| match (value, ctx.get_list(&name)) {
+                (Some(value), Some(list)) => list.contains(&value),
+                _ => false,
+            }),
+            FieldOp::OneOf(values) => match values {
+                RhsValues::Ip(ranges) => {
+                    let mut v4 = Vec::new();
+                    let mut v6 = Vec::new();
+                    for range in ranges {
+                        match range.clone().into() {
+                            ExplicitIpRange::V4(range) => v4.push(range),
+                            ExplicitIpRange::V6(range) => v6.push(range),
+                        }
+                    }
+                    let v4 = RangeSet::from(v4);
+                    let v6 = RangeSet::from(v6);
+                    compile!(|ctx, value| match cast_field!(value, Ip) {
+                        IpAddr::V4(addr) => v4.contains(&addr),
+                        IpAddr::V6(addr) => v6.contains(&addr),
+                    })
+                }
+                RhsValues::Int(values) => {
+                    let values: RangeSet<_> = values.iter().cloned().collect();
+                    compile!(|ctx, value| values.contains(&cast_field!(value, Int)))
+                }
+                RhsValues::Bytes(values) => {
+                    let values: IndexSet<Box<[u8]>, FnvBuildHasher> =
+                        values.into_iter().map(|value| value.into()).collect();
+
+                    compile!(|ctx, value| values.contains(cast_field!(value, Bytes) as &[u8]))
+                }
+                RhsValues::Bool(_) => unreachable!(),
+                RhsValues::Cidr(networks) => compile!(|ctx, value| {
+                    let prefix = cast_field!(value, Cidr);
+                    // `contains` is already false for different families.
+                    networks.iter().any(|network| {
+                        network.network_length() <= prefix.network_length()
+                            && network.contains(&prefix.first_address())
+                    })
+                }),
+                RhsValues::Endpoint(values) => {
+                    let values: IndexSet<Endpoint, FnvBuildHasher> = values.into_iter().collect();
+
+                    compile!(|ctx, value| values.contains(&cast_field!(value, Endpoint)))
+                }
+                // Comparisons with bounds are false for NaN, so it's never in
+                // any range.
+                RhsValues::Float(ranges) => compile!(|ctx, value| {
+                    let value = cast_field!(value, Float);
+                    ranges
+                        .iter()
+                        .any(|range| value >= *range.start() && value <= *range.end())
+                }),
+                RhsValues::Timestamp(ranges) => {
+                    let ranges: RangeSet<_> = ranges.into_iter().collect();
+                    compile!(|ctx, value| ranges.contains(&cast_field!(value, Timestamp)))
+                }
+                RhsValues::Mac(ranges) => {
+                    let ranges: RangeSet<_> = ranges.into_iter().collect();
+                    compile!(|ctx, value| ranges.contains(&cast_field!(value, Mac)))
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cidr::{Cidr, IpCidr};
+    use execution_context::{ExecutionContext, FieldValueTypeMismatchError};
+    use lazy_static::lazy_static;
+    use lex::complete;
+    use rhs_types::IpRange;
+    use scheme::{Scheme, UnknownFieldError};
+    use std::{net::IpAddr, str::FromStr};
+
+    lazy_static! {
+        static ref SCHEME: Scheme = Scheme! {
+            http.host: Bytes,
+            ip.addr: Ip,
+            ssl: Bool,
+            tcp.port: Int,
+        };
+        static ref PARSER: Parser<'static> = Parser::new(&SCHEME);
+    }
+
+    fn field(name: &'static str) -> Field<'static> {
+        SCHEME.get_field_index(name).unwrap()
+    }
+
+    #[test]
+    fn test_is_true() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("ssl", &PARSER),
+            FieldExpr {
+                field: field("ssl"),
+                op: FieldOp::IsTrue,
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "ssl",
+                "op": "IsTrue"
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("ssl", true).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("ssl", false).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+    }
+
+    #[test]
+    fn test_unsupported_op() {
+        assert_err!(
+            FieldExpr::lex_with(r#"tcp.port matches "^1""#, &PARSER),
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Int,
+                op: ComparisonOp::Bytes(BytesOp::Matches)
+            },
+            "tcp.port matches"
+        );
+
+        assert_eq!(
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Int,
+                op: ComparisonOp::Bytes(BytesOp::Matches)
+            }
+            .to_string(),
+            "cannot use operator matches on a field of type int"
+        );
+
+        assert_eq!(ComparisonOp::Bytes(BytesOp::Matches).to_string(), "matches");
+        assert_eq!(ComparisonOp::Ordering(OrderingOp::Equal).to_string(), "==");
+        assert_eq!(ComparisonOp::Int(IntOp::BitwiseAnd).to_string(), "&");
+        assert_eq!(ComparisonOp::In.to_string(), "in");
+    }
+
+    #[test]
+    fn test_accessors() {
+        let ast = SCHEME.parse("tcp.port == 443").unwrap();
+        let comparisons = ast.comparisons();
+        assert_eq!(comparisons.len(), 1);
+
+        let expr = comparisons[0];
+        assert_eq!(expr.field(), "tcp.port");
+        assert_eq!(expr.op(), Some(ComparisonOp::Ordering(OrderingOp::Equal)));
+        assert_eq!(expr.rhs(), ComparisonRhs::Literal(LhsValue::Int(443)));
+
+        let ast = SCHEME
+            .parse(r#"ssl and not (http.host matches "^a" or ip.addr in { 10.0.0.0/8 ::1 })"#)
+            .unwrap();
+        let comparisons = ast.comparisons();
+
+        assert_eq!(
+            comparisons
+                .iter()
+                .map(|expr| (expr.field(), expr.op()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ssl", None),
+                ("http.host", Some(ComparisonOp::Bytes(BytesOp::Matches))),
+                ("ip.addr", Some(ComparisonOp::In)),
+            ]
+        );
+
+        assert_eq!(comparisons[0].rhs(), ComparisonRhs::None);
+        assert_eq!(comparisons[1].rhs(), ComparisonRhs::Regex("^a"));
+
+        let ip = |s: &str| LhsValue::Ip(s.parse().unwrap());
+        assert_eq!(
+            comparisons[2].rhs(),
+            ComparisonRhs::List(vec![
+                ip("10.0.0.0")..=ip("10.255.255.255"),
+                ip("::1")..=ip("::1"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_source_text() {
+        let input = "  ssl and not (http.host matches \"^a\" or tcp.port in { 80 443 })\n";
+        let ast = SCHEME.parse(input).unwrap();
+
+        assert_eq!(
+            ast.comparisons()
+                .iter()
+                .map(|expr| expr.source_text(input))
+                .collect::<Vec<_>>(),
+            vec![
+                Some("ssl"),
+                Some(r#"http.host matches "^a""#),
+                Some("tcp.port in { 80 443 }"),
+            ]
+        );
+
+        assert_eq!(ast.comparisons()[2].source_text("ssl"), None);
+        assert_eq!(ast.source_text(input), Some(input.trim()));
+
+        let expr = FieldExpr::new(field("ssl"), FieldOp::IsTrue);
+        assert_eq!(expr.source_text(input), None);
+
+        let input = "/* a */ ssl; (tcp.port == 1 or ssl) /* b */;";
+        assert_eq!(
+            SCHEME
+                .parse_all(input)
+                .iter()
+                .map(|ast| ast.as_ref().unwrap().source_text(input))
+                .collect::<Vec<_>>(),
+            vec![Some("ssl"), Some("(tcp.port == 1 or ssl)")]
+        );
+    }
+
+    #[test]
+    fn test_ip_compare() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("ip.addr <= 10:20:30:40:50:60:70:80", &PARSER),
+            FieldExpr {
+                field: field("ip.addr"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::LessThanEqual,
+                    rhs: RhsValue::Ip(IpAddr::from([
+                        0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80
+                    ]))
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "ip.addr",
+                "op": "LessThanEqual",
+                "rhs": "10:20:30:40:50:60:70:80"
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value(
+            "ip.addr",
+            IpAddr::from([0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80]),
+        )
+        .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value(
+            "ip.addr",
+            IpAddr::from([0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x81]),
+        )
+        .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([127, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+    }
+
+    #[test]
+    fn test_ip_zone() {
+        let link_local = IpAddr::from([0xfe80, 0, 0, 0, 0, 0, 0, 1]);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("ip.addr == fe80::1%eth0", &PARSER),
+            FieldExpr {
+                field: field("ip.addr"),
+                op: FieldOp::ZonedIp {
+                    op: OrderingOp::Equal,
+                    rhs: [0xfe80, 0, 0, 0, 0, 0, 0, 1].into(),
+                    zone: "eth0".to_owned(),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "ip.addr",
+                "op": "Equal",
+                "rhs": "fe80::1",
+                "zone": "eth0"
+            }
+        );
+
+        assert_eq!(
+            expr.rhs(),
+            ComparisonRhs::ZonedIp([0xfe80, 0, 0, 0, 0, 0, 0, 1].into(), "eth0")
+        );
+
+        let zoned = expr.compile();
+        let unzoned = FieldExpr::lex_with("ip.addr == fe80::1", &PARSER)
+            .unwrap()
+            .0
+            .compile();
+        let not_zoned = FieldExpr::lex_with("ip.addr != fe80::1%eth0", &PARSER)
+            .unwrap()
+            .0
+            .compile();
+        let in_list = FieldExpr::lex_with("ip.addr in { fe80::/10 }", &PARSER)
+            .unwrap()
+            .0
+            .compile();
+
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+        ctx.set_field_value("ip.addr", link_local).unwrap();
+
+        // Without a zone in the context, only the unzoned literal matches.
+        assert_eq!(zoned.execute(ctx), false);
+        assert_eq!(not_zoned.execute(ctx), true);
+        assert_eq!(unzoned.execute(ctx), true);
+        assert_eq!(in_list.execute(ctx), true);
+
+        ctx.set_ip_zone("ip.addr", Some("eth0")).unwrap();
+        assert_eq!(zoned.execute(ctx), true);
+        assert_eq!(not_zoned.execute(ctx), false);
+        assert_eq!(unzoned.execute(ctx), true);
+        assert_eq!(in_list.execute(ctx), true);
+
+        ctx.set_ip_zone("ip.addr", Some("eth1")).unwrap();
+        assert_eq!(zoned.execute(ctx), false);
+        assert_eq!(not_zoned.execute(ctx), true);
+
+        ctx.set_ip_zone("ip.addr", None).unwrap();
+        assert_eq!(zoned.execute(ctx), false);
+
+        assert_eq!(
+            ctx.set_ip_zone("tcp.port", Some("eth0")),
+            Err(FieldValueTypeMismatchError {
+                field_type: Type::Int,
+                value_type: Type::Ip,
+            })
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("ip.addr == fe80::1%", &PARSER),
+            LexErrorKind::ParseNetwork(cidr::NetworkParseError::AddrParseError(
+                IpAddr::from_str("fe80::1%").unwrap_err()
+            )),
+            "fe80::1%"
+        );
+        assert_err!(
+            FieldExpr::lex_with("ip.addr == 10.0.0.1%eth0", &PARSER),
+            LexErrorKind::ParseNetwork(cidr::NetworkParseError::AddrParseError(
+                IpAddr::from_str("10.0.0.1%eth0").unwrap_err()
+            )),
+            "10.0.0.1%eth0"
+        );
+    }
+
+    #[test]
+    fn test_float_field() {
+        let scheme = &Scheme! { score: Float };
+        let parser = Parser::new(scheme);
+        let compile = |input| {
+            complete(FieldExpr::lex_with(input, &parser))
+                .unwrap()
+                .compile()
+        };
+
+        let ctx = &mut ExecutionContext::new(scheme);
+        ctx.set_field_value("score", 0.9).unwrap();
+
+        assert_eq!(compile("score > 0.85").execute(ctx), true);
+        assert_eq!(compile("score <= 9e-1").execute(ctx), true);
+        assert_eq!(compile("score == 1").execute(ctx), false);
+        assert_eq!(compile("score in { 0..0.5 0.75..1.0 }").execute(ctx), true);
+        assert_eq!(compile("score in { -1.5..0.5 }").execute(ctx), false);
+
+        ctx.set_field_value("score", std::f64::NAN).unwrap();
+        assert_eq!(compile("score > 0.85").execute(ctx), false);
+        assert_eq!(compile("score < 0.85").execute(ctx), false);
+        assert_eq!(compile("score != 0.85").execute(ctx), true);
+        assert_eq!(compile("score in { 0..1 }").execute(ctx), false);
+
+        assert_err!(
+            FieldExpr::lex_with("score in { 1.5..0.5 }", &parser),
+            LexErrorKind::IncompatibleRangeBounds,
+            "1.5..0.5"
+        );
+    }
+
+    #[test]
+    fn test_timestamp_field() {
+        use rhs_types::Timestamp;
+
+        let scheme = &Scheme! { now: Timestamp };
+        let parser = Parser::new(scheme);
+        let compile = |input| {
+            complete(FieldExpr::lex_with(input, &parser))
+                .unwrap()
+                .compile()
+        };
+
+        let ctx = &mut ExecutionContext::new(scheme);
+        ctx.set_field_value("now", Timestamp::new(1_704_067_200, 500_000_000).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            compile(r#"now >= "2024-01-01T00:00:00Z""#).execute(ctx),
+            true
+        );
+        assert_eq!(
+            compile(r#"now > "2024-01-01T00:00:00.5Z""#).execute(ctx),
+            false
+        );
+        assert_eq!(compile("now < 1704067201").execute(ctx), true);
+        assert_eq!(
+            compile(r#"now == "2024-01-01T01:00:00.5+01:00""#).execute(ctx),
+            true
+        );
+        assert_eq!(
+            compile(r#"now in { "2023-12-01T00:00:00Z".."2024-02-01T00:00:00Z" }"#).execute(ctx),
+            true
+        );
+        assert_eq!(compile("now in { 0..1000 1800000000 }").execute(ctx), false);
+
+        let expr = complete(FieldExpr::lex_with(
+            r#"now in { "2024-01-01T00:00:00Z"..1706745600 }"#,
+            &parser,
+        ))
+        .unwrap();
+        assert_json!(
+            expr,
+            {
+                "field": "now",
+                "op": "OneOf",
+                "rhs": [{
+                    "start": "2024-01-01T00:00:00Z",
+                    "end": "2024-02-01T00:00:00Z",
+                }],
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with(r#"now == "yesterday""#, &parser),
+            LexErrorKind::InvalidTimestamp,
+            "yesterday"
+        );
+    }
+
+    #[test]
+    fn test_mac_field() {
+        use rhs_types::Mac;
+
+        let scheme = &Scheme! { eth.src: Mac };
+        let parser = Parser::new(scheme);
+        let compile = |input| {
+            complete(FieldExpr::lex_with(input, &parser))
+                .unwrap()
+                .compile()
+        };
+
+        let ctx = &mut ExecutionContext::new(scheme);
+        ctx.set_field_value("eth.src", Mac::new([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e]))
+            .unwrap();
+
+        assert_eq!(compile("eth.src == 00:1A:2B:3C:4D:5E").execute(ctx), true);
+        assert_eq!(compile("eth.src != 00:1a:2b:3c:4d:5e").execute(ctx), false);
+        assert_eq!(compile("eth.src < 00:1a:2b:3c:4d:5f").execute(ctx), true);
+        assert_eq!(compile("eth.src >= 00:1a:2c:00:00:00").execute(ctx), false);
+        assert_eq!(
+            compile("eth.src in { aa:bb:cc/24 00:1a:2b/24 }").execute(ctx),
+            true
+        );
+        assert_eq!(
+            compile("eth.src in { 00:1a:2b:3c:40/36 }").execute(ctx),
+            true
+        );
+        assert_eq!(compile("eth.src in { 00:1a:2b:80/25 }").execute(ctx), false);
+
+        let expr = complete(FieldExpr::lex_with("eth.src in { 00:1a:2b/24 }", &parser)).unwrap();
+        assert_json!(
+            expr,
+            {
+                "field": "eth.src",
+                "op": "OneOf",
+                "rhs": [{
+                    "start": "00:1a:2b:00:00:00",
+                    "end": "00:1a:2b:ff:ff:ff",
+                }],
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("eth.src == 00:1a:2b/24", &parser),
+            LexErrorKind::ExpectedLiteral(":"),
+            "/24"
+        );
+        assert_err!(
+            FieldExpr::lex_with("eth.src contains 00:1a", &parser),
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Mac,
+                op: ComparisonOp::Bytes(BytesOp::Contains),
+            },
+            "eth.src contains"
+        );
+    }
+
+    #[test]
+    fn test_map_field() {
+        let mut scheme = Scheme::new();
+        scheme
+            .add_map_field("http.headers".to_owned(), Type::Bytes)
+            .unwrap();
+        let parser = Parser::new(&scheme);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(
+                r#"http.headers[ "x-forwarded-for" ] == "192.0.2.1""#,
+                &parser
+            ),
+            FieldExpr {
+                field: scheme.get_field_index("http.headers").unwrap(),
+                subscript: Some(Subscript::Key("x-forwarded-for".to_owned().into())),
+                call: None,
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Bytes("192.0.2.1".to_owned().into()),
+                },
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "http.headers",
+                "key": "x-forwarded-for",
+                "op": "Equal",
+                "rhs": "192.0.2.1"
+            }
+        );
+        assert_eq!(expr.key(), Some(&b"x-forwarded-for"[..]));
+
+        let contains = FieldExpr::lex_contains_list(
+            r#"http.headers["user-agent"] contains any { "curl" "wget" }"#,
+            &parser,
+        )
+        .unwrap()
+        .unwrap()
+        .0
+         .1;
+
+        let ctx = &mut ExecutionContext::new(&scheme);
+        assert_eq!(expr.clone().compile().execute(ctx), false);
+
+        ctx.set_map_value("http.headers", "user-agent", "curl/7.64")
+            .unwrap();
+        ctx.set_map_value("http.headers", "x-forwarded-for", "192.0.2.2")
+            .unwrap();
+        assert_eq!(expr.clone().compile().execute(ctx), false);
+        assert_eq!(contains[0].clone().compile().execute(ctx), true);
+        assert_eq!(contains[1].clone().compile().execute(ctx), false);
+
+        ctx.set_map_value("http.headers", "x-forwarded-for", "192.0.2.1")
+            .unwrap();
+        assert_eq!(expr.compile().execute(ctx), true);
+
+        assert_err!(
+            FieldExpr::lex_with(r#"http.headers == "a""#, &parser),
+            LexErrorKind::ExpectedLiteral("["),
+            r#" == "a""#
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"http.headers[host] == "a""#, &parser),
+            LexErrorKind::ExpectedLiteral("\""),
+            r#"host] == "a""#
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"http.headers["host" == "a""#, &parser),
+            LexErrorKind::ExpectedLiteral("]"),
+            r#"== "a""#
+        );
+    }
+
+    #[test]
+    fn test_map_field_each() {
+        let mut scheme = Scheme::new();
+        scheme
+            .add_map_field("http.headers".to_owned(), Type::Bytes)
+            .unwrap();
+        let parser = Parser::new(&scheme);
+        let field = scheme.get_field_index("http.headers").unwrap();
+
+        let any = assert_ok!(
+            FieldExpr::lex_with(r#"http.headers["cookie"] [*] contains "admin""#, &parser),
+            FieldExpr {
+                field,
+                subscript: Some(Subscript::EachOfKey(
+                    "cookie".to_owned().into(),
+                    ContainsQuantifier::Any
+                )),
+                call: None,
+                op: FieldOp::Contains("admin".to_owned().into()),
+                span: None,
+            }
+        );
+
+        assert_json!(
+            any,
+            {
+                "field": "http.headers",
+                "key": "cookie",
+                "quantifier": "Any",
+                "op": "Contains",
+                "rhs": "admin"
+            }
+        );
+        assert_eq!(any.key(), Some(&b"cookie"[..]));
+        assert_eq!(
+            any.to_string(),
+            r#"any(http.headers["cookie"][*] contains "admin")"#
+        );
+        assert_eq!(
+            complete(FieldExpr::lex_with(&any.to_string(), &parser)).unwrap(),
+            any
+        );
+
+        let all = assert_ok!(
+            FieldExpr::lex_with(
+                r#"all(http.headers["cookie"][*] contains "admin")"#,
+                &parser
+            ),
+            FieldExpr {
+                field,
+                subscript: Some(Subscript::EachOfKey(
+                    "cookie".to_owned().into(),
+                    ContainsQuantifier::All
+                )),
+                call: None,
+                op: FieldOp::Contains("admin".to_owned().into()),
+                span: None,
+            }
+        );
+
+        let first = FieldExpr::lex_with(r#"http.headers["cookie"] contains "admin""#, &parser)
+            .unwrap()
+            .0
+            .compile();
+        let (any, all) = (any.compile(), all.compile());
+
+        // Missing keys never match.
+        let ctx = &mut ExecutionContext::new(&scheme);
+        assert_eq!(any.execute(ctx), false);
+        assert_eq!(all.execute(ctx), false);
+
+        ctx.add_map_value("http.headers", "cookie", "user=guest")
+            .unwrap();
+        ctx.add_map_value("http.headers", "cookie", "role=admin")
+            .unwrap();
+        assert_eq!(first.execute(ctx), false);
+        assert_eq!(any.execute(ctx), true);
+        assert_eq!(all.execute(ctx), false);
+
+        ctx.set_map_value("http.headers", "cookie", "admin=1")
+            .unwrap();
+        ctx.add_map_value("http.headers", "cookie", "admin=2")
+            .unwrap();
+        assert_eq!(first.execute(ctx), true);
+        assert_eq!(any.execute(ctx), true);
+        assert_eq!(all.execute(ctx), true);
+
+        assert_err!(
+            FieldExpr::lex_with(r#"any(http.headers["cookie"] contains "a")"#, &parser),
+            LexErrorKind::ExpectedLiteral("[*]"),
+            r#"contains "a")"#
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"http.headers["cookie"][*] !contains "a""#, &parser),
+            LexErrorKind::ExpectedName("ComparisonOp"),
+            r#"!contains "a""#
+        );
+    }
+
+    #[test]
+    fn test_array_field() {
+        let mut scheme = Scheme::new();
+        scheme
+            .add_array_field("http.cookies".to_owned(), Type::Bytes)
+            .unwrap();
+        scheme.add_field("tcp.port".to_owned(), Type::Int).unwrap();
+        let parser = Parser::new(&scheme);
+        let compile = |input| {
+            complete(FieldExpr::lex_with(input, &parser))
+                .unwrap()
+                .compile()
+        };
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"any( http.cookies contains "session" )"#, &parser),
+            FieldExpr {
+                field: scheme.get_field_index("http.cookies").unwrap(),
+                subscript: Some(Subscript::Each(ContainsQuantifier::Any)),
+                call: None,
+                op: FieldOp::Contains("session".to_owned().into()),
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "http.cookies",
+                "quantifier": "Any",
+                "op": "Contains",
+                "rhs": "session"
+            }
+        );
+
+        let any = expr.compile();
+        let all = compile(r#"all(http.cookies contains "session")"#);
+        let first = compile(r#"http.cookies[0] == "session=1""#);
+        let second = compile(r#"http.cookies[ 1 ] == "session=1""#);
+
+        let ctx = &mut ExecutionContext::new(&scheme);
+        assert_eq!(any.execute(ctx), false);
+        assert_eq!(all.execute(ctx), false);
+        assert_eq!(first.execute(ctx), false);
+
+        ctx.set_array_values("http.cookies", Vec::<&str>::new())
+            .unwrap();
+        assert_eq!(any.execute(ctx), false);
+        assert_eq!(all.execute(ctx), true);
+
+        ctx.set_array_values("http.cookies", vec!["session=1", "lang=en"])
+            .unwrap();
+        assert_eq!(any.execute(ctx), true);
+        assert_eq!(all.execute(ctx), false);
+        assert_eq!(first.execute(ctx), true);
+        assert_eq!(second.execute(ctx), false);
+        assert_eq!(compile(r#"http.cookies[2] != "a""#).execute(ctx), false);
+
+        ctx.set_array_values("http.cookies", vec!["session=1", "session=2"])
+            .unwrap();
+        assert_eq!(all.execute(ctx), true);
+
+        assert_err!(
+            FieldExpr::lex_with("any(tcp.port == 1)", &parser),
+            LexErrorKind::ExpectedName("array field"),
+            "tcp.port"
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"any(http.cookies == "a""#, &parser),
+            LexErrorKind::ExpectedLiteral(")"),
+            ""
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"http.cookies == "a""#, &parser),
+            LexErrorKind::ExpectedLiteral("["),
+            r#" == "a""#
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"http.cookies[-1] == "a""#, &parser),
+            LexErrorKind::ExpectedName("digit"),
+            r#"-1] == "a""#
+        );
+    }
+
+    #[test]
+    fn test_array_compare_empty() {
+        let mut scheme = Scheme::new();
+        scheme
+            .add_array_field("names".to_owned(), Type::Bytes)
+            .unwrap();
+        scheme
+            .add_array_field("ports".to_owned(), Type::Int)
+            .unwrap();
+        let parser = Parser::new(&scheme);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("names == []", &parser),
+            FieldExpr::new(
+                scheme.get_field_index("names").unwrap(),
+                FieldOp::EmptyArray(OrderingOp::Equal)
+            )
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "names",
+                "op": "Equal",
+                "rhs": []
+            }
+        );
+        assert_eq!(expr.to_string(), "names == []");
+        assert_eq!(expr.rhs(), ComparisonRhs::EmptyArray);
+
+        let names_empty = expr.compile();
+        let names_not_empty = complete(FieldExpr::lex_with("names != [ ]", &parser))
+            .unwrap()
+            .compile();
+        let ports_empty = complete(FieldExpr::lex_with("ports == []", &parser))
+            .unwrap()
+            .compile();
+
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        // unset array is not the same as an empty one
+        assert_eq!(names_empty.execute(ctx), false);
+        assert_eq!(names_not_empty.execute(ctx), false);
+        assert_eq!(ports_empty.execute(ctx), false);
+
+        ctx.set_array_values("names", Vec::<&str>::new()).unwrap();
+        ctx.set_array_values("ports", Vec::<i32>::new()).unwrap();
+        assert_eq!(names_empty.execute(ctx), true);
+        assert_eq!(names_not_empty.execute(ctx), false);
+        assert_eq!(ports_empty.execute(ctx), true);
+
+        ctx.set_array_values("names", vec!["a"]).unwrap();
+        ctx.set_array_values("ports", vec![80]).unwrap();
+        assert_eq!(names_empty.execute(ctx), false);
+        assert_eq!(names_not_empty.execute(ctx), true);
+        assert_eq!(ports_empty.execute(ctx), false);
+
+        assert_err!(
+            FieldExpr::lex_with("names > []", &parser),
+            LexErrorKind::ExpectedLiteral("["),
+            " > []"
+        );
+        // elements are not arrays themselves
+        assert!(FieldExpr::lex_with("any(names == [])", &parser).is_err());
+    }
+
+    #[test]
+    fn test_function_call() {
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            http.path: Bytes,
+            tcp.port: Int,
+        };
+        scheme
+            .add_map_field("http.headers".to_owned(), Type::Bytes)
+            .unwrap();
+        scheme
+            .add_function("lower".to_owned(), Function::lower())
+            .unwrap();
+        scheme
+            .add_function("len".to_owned(), Function::len())
+            .unwrap();
+        scheme
+            .add_function("concat".to_owned(), Function::concat())
+            .unwrap();
+        let parser = Parser::new(&scheme);
+
+        let host = scheme.get_field_index("http.host").unwrap();
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"lower( http.host ) == "example.org""#, &parser),
+            FieldExpr {
+                field: host,
+                subscript: None,
+                call: Some(FunctionCall {
+                    name: "lower",
+                    function: scheme.get_function("lower").unwrap().1,
+                    args: vec![FunctionArg::Field {
+                        field: host,
+                        subscript: None,
+                    }],
+                }),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Bytes("example.org".to_owned().into()),
+                },
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "http.host",
+                "call": {
+                    "name": "lower",
+                    "args": [{ "field": "http.host" }]
+                },
+                "op": "Equal",
+                "rhs": "example.org"
+            }
+        );
+        assert_eq!(expr.function(), Some("lower"));
+
+        let len = FieldExpr::lex_with("len(http.path) > 8", &parser)
+            .unwrap()
+            .0;
+        let concat = FieldExpr::lex_with(
+            r#"concat(http.headers["x-scheme"], http.host) contains "s://""#,
+            &parser,
+        )
+        .unwrap()
+        .0;
+        let concat_literal =
+            FieldExpr::lex_with(r#"concat("www.", http.host) == "www.Example.org""#, &parser)
+                .unwrap()
+                .0;
+        assert_eq!(
+            concat.fields(),
+            vec![scheme.get_field_index("http.headers").unwrap(), host]
+        );
+
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        // Unset arguments don't match, just like unset fields.
+        assert_eq!(expr.clone().compile().execute(ctx), false);
+        assert_eq!(len.clone().compile().execute(ctx), false);
+        assert_eq!(concat_literal.clone().compile().execute(ctx), false);
+
+        ctx.set_field_value("http.host", "Example.org").unwrap();
+        ctx.set_field_value("http.path", "/index.html").unwrap();
+        assert_eq!(expr.compile().execute(ctx), true);
+        assert_eq!(len.compile().execute(ctx), true);
+        assert_eq!(concat_literal.compile().execute(ctx), true);
+        assert_eq!(concat.clone().compile().execute(ctx), false);
+
+        ctx.set_map_value("http.headers", "x-scheme", "https://")
+            .unwrap();
+        assert_eq!(concat.compile().execute(ctx), true);
+
+        assert_err!(
+            FieldExpr::lex_with("upper(http.host)", &parser),
+            LexErrorKind::UnknownFunction,
+            "upper"
+        );
+        assert_err!(
+            FieldExpr::lex_with("lower(http.hots)", &parser),
+            LexErrorKind::UnknownField(UnknownFieldError),
+            "http.hots"
+        );
+        assert_err!(
+            FieldExpr::lex_with("len(tcp.port) > 1", &parser),
+            LexErrorKind::TypeMismatch {
+                expected: Type::Bytes,
+                actual: Type::Int,
+            },
+            "tcp.port"
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"concat(http.host) == "a""#, &parser),
+            LexErrorKind::CountMismatch {
+                name: "argument",
+                actual: 1,
+                expected: 2,
+            },
+            "concat(http.host)"
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"lower(http.host, "a") == "a""#, &parser),
+            LexErrorKind::ExpectedLiteral(")"),
+            r#", "a") == "a""#
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"lower("A") == "a""#, &parser),
+            LexErrorKind::ExpectedName("field argument"),
+            r#"lower("A")"#
+        );
+        assert_err!(
+            FieldExpr::lex_with(r#"len(http.host) contains "a""#, &parser),
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Int,
+                op: ComparisonOp::Bytes(BytesOp::Contains),
+            },
+            "len(http.host) contains"
+        );
+    }
+
+    #[test]
+    fn test_cidr_field() {
+        let scheme = &Scheme! { route.prefix: Cidr };
+        let parser = &Parser::new(scheme);
+        let net = |s: &str| -> IpCidr { s.parse().unwrap() };
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("route.prefix in 10.0.0.0/8", parser),
+            FieldExpr {
+                field: scheme.get_field_index("route.prefix").unwrap(),
+                op: FieldOp::OneOf(RhsValues::Cidr(vec![net("10.0.0.0/8")])),
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "route.prefix",
+                "op": "OneOf",
+                "rhs": ["10.0.0.0/8"]
+            }
+        );
+
+        let matches = |expr: &CompiledExpr<'_>, prefix: &str| {
+            let mut ctx = ExecutionContext::new(scheme);
+            ctx.set_field_value("route.prefix", net(prefix)).unwrap();
+            expr.execute(&ctx)
+        };
+
+        let expr = expr.compile();
+
+        assert_eq!(matches(&expr, "10.1.0.0/16"), true);
+        assert_eq!(matches(&expr, "10.1.2.3/32"), true);
+        // Equal prefixes are contained in each other.
+        assert_eq!(matches(&expr, "10.0.0.0/8"), true);
+        assert_eq!(matches(&expr, "10.0.0.0/7"), false);
+        assert_eq!(matches(&expr, "11.0.0.0/16"), false);
+        // Networks of different families are never contained in each other.
+        assert_eq!(matches(&expr, "::a00:0/104"), false);
+        assert_eq!(matches(&expr, "::/0"), false);
+
+        let expr = complete(FieldExpr::lex_with(
+            "route.prefix in { 192.168.0.0/16 2001:db8::/32 }",
+            parser,
+        ))
+        .unwrap()
+        .compile();
+
+        assert_eq!(matches(&expr, "2001:db8:1::/48"), true);
+        assert_eq!(matches(&expr, "192.168.1.0/24"), true);
+        assert_eq!(matches(&expr, "2001:db9::/32"), false);
+
+        let expr = complete(FieldExpr::lex_with("route.prefix == 10.0.0.0/8", parser))
+            .unwrap()
+            .compile();
+
+        assert_eq!(matches(&expr, "10.0.0.0/8"), true);
+        assert_eq!(matches(&expr, "10.0.0.0/16"), false);
+
+        assert_err!(
+            FieldExpr::lex_with("route.prefix < 10.0.0.0/8", parser),
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Cidr,
+                op: ComparisonOp::Ordering(OrderingOp::LessThan),
+            },
+            "route.prefix <"
+        );
+    }
+
+    #[test]
+    fn test_int_interval() {
+        let matches = |filter: &str, port: i32| {
+            let filter = complete(FieldExpr::lex_with(filter, &PARSER))
+                .unwrap()
+                .compile();
+            let mut ctx = ExecutionContext::new(&SCHEME);
+            ctx.set_field_value("tcp.port", port).unwrap();
+            filter.execute(&ctx)
+        };
+
+        for &(filter, first, last) in &[
+            ("tcp.port in [1, 10]", true, true),
+            ("tcp.port in [1, 10)", true, false),
+            ("tcp.port in (1, 10]", false, true),
+            ("tcp.port in ( 1 , 10 )", false, false),
+        ] {
+            assert_eq!(matches(filter, 0), false, "{} 0", filter);
+            assert_eq!(matches(filter, 1), first, "{} 1", filter);
+            assert_eq!(matches(filter, 2), true, "{} 2", filter);
+            assert_eq!(matches(filter, 9), true, "{} 9", filter);
+            assert_eq!(matches(filter, 10), last, "{} 10", filter);
+            assert_eq!(matches(filter, 11), false, "{} 11", filter);
+        }
+
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port in [-5, 0x10)", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::OneOf(RhsValues::Int(vec![-5..=15])),
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port in [10, 1]", &PARSER),
+            LexErrorKind::IncompatibleRangeBounds,
+            "[10, 1]"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port in (1, 2)", &PARSER),
+            LexErrorKind::IncompatibleRangeBounds,
+            "(1, 2)"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port in [1, 10}", &PARSER),
+            LexErrorKind::ExpectedLiteral(")"),
+            "}"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port in [1 10]", &PARSER),
+            LexErrorKind::ExpectedLiteral(","),
+            "10]"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_field() {
+        let scheme = &Scheme! { endpoint: Endpoint };
+        let parser = &Parser::new(scheme);
+        let endpoint = |s: &str| -> Endpoint { s.parse().unwrap() };
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("endpoint == 192.0.2.1:443", parser),
+            FieldExpr {
+                field: scheme.get_field_index("endpoint").unwrap(),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Endpoint(endpoint("192.0.2.1:443")),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "endpoint",
+                "op": "Equal",
+                "rhs": "192.0.2.1:443"
+            }
+        );
+
+        let matches = |expr: &CompiledExpr<'_>, value: &str| {
+            let mut ctx = ExecutionContext::new(scheme);
+            ctx.set_field_value("endpoint", endpoint(value)).unwrap();
+            expr.execute(&ctx)
+        };
+
+        let expr = expr.compile();
+
+        assert_eq!(matches(&expr, "192.0.2.1:443"), true);
+        assert_eq!(matches(&expr, "192.0.2.1:80"), false);
+        assert_eq!(matches(&expr, "[::ffff:192.0.2.1]:443"), false);
+
+        let expr = complete(FieldExpr::lex_with(
+            "endpoint in { [2001:db8::1]:443 10.0.0.1:53 }",
+            parser,
+        ))
+        .unwrap()
+        .compile();
+
+        assert_eq!(matches(&expr, "[2001:db8::1]:443"), true);
+        assert_eq!(matches(&expr, "10.0.0.1:53"), true);
+        assert_eq!(matches(&expr, "[2001:db8::1]:53"), false);
+
+        assert_err!(
+            FieldExpr::lex_with("endpoint == 192.0.2.1:65536", parser),
+            LexErrorKind::ParseInt {
+                err: "65536".parse::<u16>().unwrap_err(),
+                radix: 10,
+            },
+            "65536"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("endpoint == 2001:db8::1:443", parser),
+            LexErrorKind::ParseNetwork(cidr::NetworkParseError::AddrParseError(
+                "2001".parse::<std::net::Ipv4Addr>().unwrap_err()
+            )),
+            "2001"
+        );
+    }
+
+    #[test]
+    fn test_ip_compare_cidr() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("ip.addr == 10.0.0.0/8", &PARSER),
+            FieldExpr {
+                field: field("ip.addr"),
+                op: FieldOp::Cidr {
+                    op: OrderingOp::Equal,
+                    rhs: "10.0.0.0/8".parse().unwrap(),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "ip.addr",
+                "op": "Equal",
+                "rhs": "10.0.0.0/8"
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 1, 2, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 2, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = complete(FieldExpr::lex_with("ip.addr != 10.0.0.0/8", &PARSER))
+            .unwrap()
+            .compile();
+
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 1, 2, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 2, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        // addresses without a prefix are still compared as-is
+        let expr = complete(FieldExpr::lex_with("ip.addr == 10.0.0.0", &PARSER))
+            .unwrap()
+            .compile();
+
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 1, 2, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        // other orderings still only accept a single address
+        assert!(FieldExpr::lex_with("ip.addr >= 10.0.0.0/8", &PARSER).is_err());
+    }
+
+    #[test]
+    fn test_ip_cast() {
+        use cidr::NetworkParseError;
+        use std::str::FromStr;
+
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        for &(filter, addr) in &[
+            (r#"ip.addr == ip("10.0.0.1")"#, IpAddr::from([10, 0, 0, 1])),
+            (
+                r#"ip.addr == ip("2001:db8::1")"#,
+                IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]),
+            ),
+        ] {
+            let expr = assert_ok!(
+                FieldExpr::lex_with(filter, &PARSER),
+                FieldExpr {
+                    field: field("ip.addr"),
+                    op: FieldOp::Ordering {
+                        op: OrderingOp::Equal,
+                        rhs: RhsValue::Ip(addr),
+                    },
+                    subscript: None,
+                    call: None,
+                    span: None,
+                }
+            );
+
+            let expr = expr.compile();
+
+            ctx.set_field_value("ip.addr", addr).unwrap();
+            assert_eq!(expr.execute(ctx), true);
+
+            ctx.set_field_value("ip.addr", IpAddr::from([10, 0, 0, 2]))
+                .unwrap();
+            assert_eq!(expr.execute(ctx), false);
+        }
+
+        let expr = complete(FieldExpr::lex_with(r#"ip.addr > ip("10.0.0.1")"#, &PARSER))
+            .unwrap()
+            .compile();
+        assert_eq!(expr.execute(ctx), true);
+
+        assert_err!(
+            FieldExpr::lex_with(r#"ip.addr == ip("10.0.0.256")"#, &PARSER),
+            LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+                IpAddr::from_str("10.0.0.256").unwrap_err()
+            )),
+            "10.0.0.256"
+        );
+
+        // the argument is a single address, not a network
+        assert!(FieldExpr::lex_with(r#"ip.addr == ip("10.0.0.0/8")"#, &PARSER).is_err());
+    }
+
+    #[test]
+    fn test_bytes_compare() {
+        // just check that parsing doesn't conflict with IPv6
+        {
+            let expr = assert_ok!(
+                FieldExpr::lex_with("http.host >= 10:20:30:40:50:60:70:80", &PARSER),
+                FieldExpr {
+                    field: field("http.host"),
+                    op: FieldOp::Ordering {
+                        op: OrderingOp::GreaterThanEqual,
+                        rhs: RhsValue::Bytes(
+                            vec![0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80].into()
+                        ),
+                    },
+                    subscript: None,
+                    call: None,
+                    span: None,
+                }
+            );
+
+            assert_json!(
+                expr,
+                {
+                    "field": "http.host",
+                    "op": "GreaterThanEqual",
+                    "rhs": [0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80]
+                }
+            );
+        }
+
+        // just check that parsing doesn't conflict with regular numbers
+        {
+            let expr = assert_ok!(
+                FieldExpr::lex_with(r#"http.host < 12"#, &PARSER),
+                FieldExpr {
+                    field: field("http.host"),
+                    op: FieldOp::Ordering {
+                        op: OrderingOp::LessThan,
+                        rhs: RhsValue::Bytes(vec![0x12].into()),
+                    },
+                    subscript: None,
+                    call: None,
+                    span: None,
+                }
+            );
+
+            assert_json!(
+                expr,
+                {
+                    "field": "http.host",
+                    "op": "LessThan",
+                    "rhs": [0x12]
+                }
+            );
+        }
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"http.host == "example.org""#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Bytes("example.org".to_owned().into())
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "http.host",
+                "op": "Equal",
+                "rhs": "example.org"
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("http.host", "example.com").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), true);
+    }
+
+    #[test]
+    fn test_bytes_compare_empty() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"http.host == """#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Bytes(String::new().into())
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "http.host",
+                "op": "Equal",
+                "rhs": ""
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        // unset field is not the same as an empty one
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("http.host", "").unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+    }
+
+    #[test]
+    fn test_unset_field() {
+        let ctx = &ExecutionContext::new(&SCHEME);
+
+        for filter in &[
+            "ssl",
+            r#"http.host != """#,
+            r#"http.host contains "a""#,
+            r#"http.host in { "a" }"#,
+            "ip.addr in { 127.0.0.0/8 }",
+            "tcp.port != 80",
+            "tcp.port & 1",
+        ] {
+            let expr = complete(FieldExpr::lex_with(filter, &PARSER)).unwrap();
+
+            assert_eq!(expr.compile().execute(ctx), false, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn test_bitwise_and() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.port & 1", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::Int {
+                    op: IntOp::BitwiseAnd,
+                    rhs: 1,
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "tcp.port",
+                "op": "BitwiseAnd",
+                "rhs": 1
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("tcp.port", 80).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("tcp.port", 443).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+    }
+
+    #[test]
+    fn test_int_result_comparison() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.port & 0x02 != 0", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::IntOrdering {
+                    op: IntOp::BitwiseAnd,
+                    rhs: 2,
+                    ordering: OrderingOp::NotEqual,
+                    value: 0,
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "tcp.port",
+                "op": "BitwiseAnd",
+                "rhs": 2,
+                "ordering": "NotEqual",
+                "value": 0
+            }
+        );
+
+        assert_eq!(
+            expr.int_result_comparison(),
+            Some((OrderingOp::NotEqual, 0))
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("tcp.port", 0x12).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 0x11).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.port >> 4 == 3", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::IntOrdering {
+                    op: IntOp::ShiftRight,
+                    rhs: 4,
+                    ordering: OrderingOp::Equal,
+                    value: 3,
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        let expr = expr.compile();
+
+        ctx.set_field_value("tcp.port", 0x3F).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 0x4F).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.port << 1", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::Int {
+                    op: IntOp::ShiftLeft,
+                    rhs: 1,
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_eq!(expr.int_result_comparison(), None);
+
+        // `>` and `>=` are still ordering operators.
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port >= 4", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::GreaterThanEqual,
+                    rhs: RhsValue::Int(4),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port >> 32 == 0", &PARSER),
+            LexErrorKind::InvalidShift,
+            "32"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port << -1", &PARSER),
+            LexErrorKind::InvalidShift,
+            "-1"
+        );
+    }
+
+    #[test]
+    fn test_int_in() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"tcp.port in { 80 443 2082..2083 }"#, &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::OneOf(RhsValues::Int(vec![80..=80, 443..=443, 2082..=2083])),
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "tcp.port",
+                "op": "OneOf",
+                "rhs": [
+                    { "start": 80, "end": 80 },
+                    { "start": 443, "end": 443 },
+                    { "start": 2082, "end": 2083 },
+                ]
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("tcp.port", 80).unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value(
-            "ip.addr",
-            IpAddr::from([0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80]),
-        )
-        .unwrap();
+        ctx.set_field_value("tcp.port", 8080).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("tcp.port", 443).unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value(
-            "ip.addr",
-            IpAddr::from([0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x81]),
-        )
-        .unwrap();
+        ctx.set_field_value("tcp.port", 2081).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("tcp.port", 2082).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 2083).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 2084).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+    }
+
+    #[test]
+    fn test_bytes_in() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"http.host in { "example.org" "example.com" }"#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::OneOf(RhsValues::Bytes(
+                    ["example.org", "example.com",]
+                        .iter()
+                        .map(|s| s.to_string().into())
+                        .collect()
+                )),
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "http.host",
+                "op": "OneOf",
+                "rhs": [
+                    "example.org",
+                    "example.com",
+                ]
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("http.host", "example.com").unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("http.host", "example.net").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = complete(FieldExpr::lex_with("http.host in {}", &PARSER))
+            .unwrap()
+            .compile();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("http.host", "").unwrap();
         assert_eq!(expr.execute(ctx), false);
 
+        assert_err!(
+            FieldExpr::lex_with(r#"http.host in { "example.org" int("1") }"#, &PARSER),
+            LexErrorKind::TypeMismatch {
+                expected: Type::Bytes,
+                actual: Type::Int,
+            },
+            "int"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with(r#"tcp.port in { 80 "example.org" 443 }"#, &PARSER),
+            LexErrorKind::TypeMismatch {
+                expected: Type::Int,
+                actual: Type::Bytes,
+            },
+            r#""example.org""#
+        );
+    }
+
+    #[test]
+    fn test_ip_in() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(
+                r#"ip.addr in { 127.0.0.0/8 ::1 10.0.0.0..10.0.255.255 }"#,
+                &PARSER
+            ),
+            FieldExpr {
+                field: field("ip.addr"),
+                op: FieldOp::OneOf(RhsValues::Ip(vec![
+                    IpRange::Cidr(IpCidr::new([127, 0, 0, 0].into(), 8).unwrap()),
+                    IpRange::Cidr(IpCidr::new_host([0, 0, 0, 0, 0, 0, 0, 1].into())),
+                    IpRange::Explicit(ExplicitIpRange::V4(
+                        [10, 0, 0, 0].into()..=[10, 0, 255, 255].into()
+                    )),
+                ])),
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "field": "ip.addr",
+                "op": "OneOf",
+                "rhs": [
+                    "127.0.0.0/8",
+                    "::1",
+                    { "start": "10.0.0.0", "end": "10.0.255.255" },
+                ]
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
         ctx.set_field_value("ip.addr", IpAddr::from([127, 0, 0, 1]))
             .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([127, 0, 0, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([255, 255, 255, 255]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 2]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        // Ranges with a dash don't have to align to networks.
+        let expr = complete(FieldExpr::lex_with(
+            "ip.addr in { 192.0.2.10-192.0.2.50 2001:db8::1-2001:db8::5 }",
+            &PARSER,
+        ))
+        .unwrap()
+        .compile();
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 2, 50]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 2, 51]))
+            .unwrap();
         assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 3]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
     }
 
     #[test]
-    fn test_bytes_compare() {
-        // just check that parsing doesn't conflict with IPv6
-        {
-            let expr = assert_ok!(
-                FieldExpr::lex_with("http.host >= 10:20:30:40:50:60:70:80", &SCHEME),
-                FieldExpr {
-                    field: field("http.host"),
-                    op: FieldOp::Ordering {
-                        op: OrderingOp::GreaterThanEqual,
-                        rhs: RhsValue::Bytes(
-                            vec![0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80].into()
-                        ),
-                    },
-                }
-            );
+    fn test_contains_bytes() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"http.host contains "abc""#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::Contains("abc".to_owned().into()),
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
 
-            assert_json!(
-                expr,
-                {
-                    "field": "http.host",
-                    "op": "GreaterThanEqual",
-                    "rhs": [0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0x80]
-                }
-            );
-        }
+        assert_json!(
+            expr,
+            {
+                "field": "http.host",
+                "op": "Contains",
+                "rhs": "abc",
+            }
+        );
 
-        // just check that parsing doesn't conflict with regular numbers
-        {
-            let expr = assert_ok!(
-                FieldExpr::lex_with(r#"http.host < 12"#, &SCHEME),
-                FieldExpr {
-                    field: field("http.host"),
-                    op: FieldOp::Ordering {
-                        op: OrderingOp::LessThan,
-                        rhs: RhsValue::Bytes(vec![0x12].into()),
-                    },
-                }
-            );
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
 
-            assert_json!(
-                expr,
-                {
-                    "field": "http.host",
-                    "op": "LessThan",
-                    "rhs": [0x12]
-                }
-            );
-        }
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), false);
 
+        ctx.set_field_value("http.host", "abc.net.au").unwrap();
+        assert_eq!(expr.execute(ctx), true);
+    }
+
+    #[test]
+    fn test_contains_str() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host == "example.org""#, &SCHEME),
+            FieldExpr::lex_with(r#"http.host contains 6F:72:67"#, &PARSER),
             FieldExpr {
                 field: field("http.host"),
-                op: FieldOp::Ordering {
-                    op: OrderingOp::Equal,
-                    rhs: RhsValue::Bytes("example.org".to_owned().into())
-                }
+                op: FieldOp::Contains(vec![0x6F, 0x72, 0x67].into()),
+                subscript: None,
+                call: None,
+                span: None,
             }
         );
 
@@ -415,8 +4075,8 @@ mod tests {
             expr,
             {
                 "field": "http.host",
-                "op": "Equal",
-                "rhs": "example.org"
+                "op": "Contains",
+                "rhs": [0x6F, 0x72, 0x67],
             }
         );
 
@@ -431,249 +4091,507 @@ mod tests {
     }
 
     #[test]
-    fn test_bitwise_and() {
+    fn test_nocase() {
         let expr = assert_ok!(
-            FieldExpr::lex_with("tcp.port & 1", &SCHEME),
+            FieldExpr::lex_with(r#"http.host == "Example.COM" nocase"#, &PARSER),
             FieldExpr {
-                field: field("tcp.port"),
-                op: FieldOp::Int {
-                    op: IntOp::BitwiseAnd,
-                    rhs: 1,
-                }
+                field: field("http.host"),
+                op: FieldOp::OrderingIgnoreCase {
+                    op: OrderingOp::Equal,
+                    rhs: "Example.COM".to_owned().into(),
+                },
+                subscript: None,
+                call: None,
+                span: None,
             }
         );
 
         assert_json!(
             expr,
             {
-                "field": "tcp.port",
-                "op": "BitwiseAnd",
-                "rhs": 1
+                "field": "http.host",
+                "op": "Equal",
+                "rhs": "Example.COM",
+                "nocase": true,
             }
         );
 
+        assert!(expr.ignores_case());
+        assert_eq!(
+            expr.rhs(),
+            ComparisonRhs::Literal(LhsValue::Bytes(b"Example.COM"))
+        );
+
         let expr = expr.compile();
         let ctx = &mut ExecutionContext::new(&SCHEME);
 
-        ctx.set_field_value("tcp.port", 80).unwrap();
-        assert_eq!(expr.execute(ctx), false);
+        ctx.set_field_value("http.host", "example.com").unwrap();
+        assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("tcp.port", 443).unwrap();
+        ctx.set_field_value("http.host", "EXAMPLE.COM").unwrap();
         assert_eq!(expr.execute(ctx), true);
-    }
 
-    #[test]
-    fn test_int_in() {
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"tcp.port in { 80 443 2082..2083 }"#, &SCHEME),
+            FieldExpr::lex_with(r#"http.host contains "ORG" nocase"#, &PARSER),
             FieldExpr {
-                field: field("tcp.port"),
-                op: FieldOp::OneOf(RhsValues::Int(vec![80..=80, 443..=443, 2082..=2083])),
+                field: field("http.host"),
+                op: FieldOp::ContainsIgnoreCase("ORG".to_owned().into()),
+                subscript: None,
+                call: None,
+                span: None,
             }
         );
 
         assert_json!(
             expr,
             {
-                "field": "tcp.port",
-                "op": "OneOf",
-                "rhs": [
-                    { "start": 80, "end": 80 },
-                    { "start": 443, "end": 443 },
-                    { "start": 2082, "end": 2083 },
-                ]
+                "field": "http.host",
+                "op": "Contains",
+                "rhs": "ORG",
+                "nocase": true,
             }
         );
 
         let expr = expr.compile();
-        let ctx = &mut ExecutionContext::new(&SCHEME);
 
-        ctx.set_field_value("tcp.port", 80).unwrap();
+        ctx.set_field_value("http.host", "example.org").unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("tcp.port", 8080).unwrap();
+        ctx.set_field_value("http.host", "or").unwrap();
         assert_eq!(expr.execute(ctx), false);
 
-        ctx.set_field_value("tcp.port", 443).unwrap();
-        assert_eq!(expr.execute(ctx), true);
+        let expr = FieldExpr::lex_with(r#"http.host != "A" nocase"#, &PARSER)
+            .unwrap()
+            .0
+            .compile();
 
-        ctx.set_field_value("tcp.port", 2081).unwrap();
+        ctx.set_field_value("http.host", "a").unwrap();
         assert_eq!(expr.execute(ctx), false);
 
-        ctx.set_field_value("tcp.port", 2082).unwrap();
+        ctx.set_field_value("http.host", "b").unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("tcp.port", 2083).unwrap();
+        // Only a whole word is a modifier.
+        assert_ok!(
+            FieldExpr::lex_with(r#"http.host == "a" nocases"#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Bytes("a".to_owned().into()),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            " nocases"
+        );
+
+        // Other operators don't ignore case.
+        assert_ok!(
+            FieldExpr::lex_with(r#"http.host < "a" nocase"#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::LessThan,
+                    rhs: RhsValue::Bytes("a".to_owned().into()),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            " nocase"
+        );
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_matches_raw_bytes() {
+        // Patterns are matched against bytes, so escapes like `\xFF` match
+        // that exact byte even if it's not valid UTF-8.
+        let expr = complete(FieldExpr::lex_with(
+            r#"http.host matches "^\xFF\x00+[\x80-\xFF]$""#,
+            &PARSER,
+        ))
+        .unwrap()
+        .compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("http.host", &b"\xFF\x00\x00\x80"[..])
+            .unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("tcp.port", 2084).unwrap();
+        ctx.set_field_value("http.host", &b"\xFF\x00\x7F"[..])
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        // U+00FF is encoded as two bytes, neither of which is 0xFF.
+        ctx.set_field_value("http.host", "\u{ff}\x00\u{80}")
+            .unwrap();
         assert_eq!(expr.execute(ctx), false);
     }
 
+    #[cfg(feature = "regex")]
     #[test]
-    fn test_bytes_in() {
+    fn test_matches_pattern() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host in { "example.org" "example.com" }"#, &SCHEME),
+            FieldExpr::lex_with("http.host matches $hosts_1;", &PARSER),
             FieldExpr {
                 field: field("http.host"),
-                op: FieldOp::OneOf(RhsValues::Bytes(
-                    ["example.org", "example.com",]
-                        .iter()
-                        .map(|s| s.to_string().into())
-                        .collect()
-                )),
-            }
+                op: FieldOp::MatchesPattern("hosts_1".to_owned()),
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            ";"
         );
 
         assert_json!(
             expr,
             {
                 "field": "http.host",
-                "op": "OneOf",
-                "rhs": [
-                    "example.org",
-                    "example.com",
-                ]
+                "op": "MatchesPattern",
+                "rhs": "hosts_1",
             }
         );
 
+        assert_eq!(expr.op(), Some(ComparisonOp::Bytes(BytesOp::Matches)));
+        assert_eq!(expr.rhs(), ComparisonRhs::Pattern("hosts_1"));
+
         let expr = expr.compile();
         let ctx = &mut ExecutionContext::new(&SCHEME);
 
-        ctx.set_field_value("http.host", "example.com").unwrap();
-        assert_eq!(expr.execute(ctx), true);
-
         ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_pattern("hosts_1", "org$").unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("http.host", "example.net").unwrap();
-        assert_eq!(expr.execute(ctx), false);
+        assert_err!(
+            FieldExpr::lex_with("http.host matches $", &PARSER),
+            LexErrorKind::ExpectedName("pattern name"),
+            ""
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port matches $hosts", &PARSER),
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Int,
+                op: ComparisonOp::Bytes(BytesOp::Matches)
+            },
+            "tcp.port matches"
+        );
     }
 
     #[test]
-    fn test_ip_in() {
+    fn test_wildcard() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(
-                r#"ip.addr in { 127.0.0.0/8 ::1 10.0.0.0..10.0.255.255 }"#,
-                &SCHEME
-            ),
+            FieldExpr::lex_with(r#"http.host like "*.example.?om""#, &PARSER),
             FieldExpr {
-                field: field("ip.addr"),
-                op: FieldOp::OneOf(RhsValues::Ip(vec![
-                    IpRange::Cidr(IpCidr::new([127, 0, 0, 0].into(), 8).unwrap()),
-                    IpRange::Cidr(IpCidr::new_host([0, 0, 0, 0, 0, 0, 0, 1].into())),
-                    IpRange::Explicit(ExplicitIpRange::V4(
-                        [10, 0, 0, 0].into()..=[10, 0, 255, 255].into()
-                    )),
-                ])),
+                field: field("http.host"),
+                op: FieldOp::Wildcard(Wildcard::new("*.example.?om".to_owned().into())),
+                subscript: None,
+                call: None,
+                span: None,
             }
         );
 
         assert_json!(
             expr,
             {
-                "field": "ip.addr",
-                "op": "OneOf",
-                "rhs": [
-                    "127.0.0.0/8",
-                    "::1",
-                    { "start": "10.0.0.0", "end": "10.0.255.255" },
-                ]
+                "field": "http.host",
+                "op": "Wildcard",
+                "rhs": "*.example.?om",
             }
         );
 
+        assert_eq!(expr.op(), Some(ComparisonOp::Bytes(BytesOp::Wildcard)));
+        assert_eq!(
+            expr.rhs(),
+            ComparisonRhs::Literal(LhsValue::Bytes(b"*.example.?om"))
+        );
+        assert_eq!(expr.to_string(), r#"http.host wildcard "*.example.?om""#);
+
         let expr = expr.compile();
         let ctx = &mut ExecutionContext::new(&SCHEME);
 
-        ctx.set_field_value("ip.addr", IpAddr::from([127, 0, 0, 1]))
-            .unwrap();
+        ctx.set_field_value("http.host", "www.example.com").unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("ip.addr", IpAddr::from([127, 0, 0, 3]))
-            .unwrap();
-        assert_eq!(expr.execute(ctx), true);
+        ctx.set_field_value("http.host", "example.com").unwrap();
+        assert_eq!(expr.execute(ctx), false);
 
-        ctx.set_field_value("ip.addr", IpAddr::from([255, 255, 255, 255]))
+        ctx.set_field_value("http.host", "www.example.com.org")
             .unwrap();
         assert_eq!(expr.execute(ctx), false);
 
-        ctx.set_field_value("ip.addr", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
-            .unwrap();
+        let expr = complete(FieldExpr::lex_with(
+            r#"http.host wildcard "\\*\x00?""#,
+            &PARSER,
+        ))
+        .unwrap()
+        .compile();
+
+        ctx.set_field_value("http.host", &b"*\x00\xFF"[..]).unwrap();
         assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("ip.addr", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 2]))
-            .unwrap();
+        ctx.set_field_value("http.host", &b"a\x00\xFF"[..]).unwrap();
         assert_eq!(expr.execute(ctx), false);
+
+        assert_err!(
+            FieldExpr::lex_with(r#"tcp.port wildcard "*""#, &PARSER),
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Int,
+                op: ComparisonOp::Bytes(BytesOp::Wildcard)
+            },
+            "tcp.port wildcard"
+        );
     }
 
     #[test]
-    fn test_contains_bytes() {
+    fn test_constants() {
+        use execution_context::FieldValueTypeMismatchError;
+        use scheme::ConstantError;
+
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            ip.addr: Ip,
+            tcp.port: Int,
+        };
+
+        scheme
+            .add_constant("max_port".to_owned(), Type::Int)
+            .unwrap();
+        scheme
+            .add_constant("origin".to_owned(), Type::Bytes)
+            .unwrap();
+        scheme.add_constant("gateway".to_owned(), Type::Ip).unwrap();
+
+        assert_eq!(
+            scheme.add_constant("max_port".to_owned(), Type::Int),
+            Err(ConstantError::Redefinition("max_port".to_owned()))
+        );
+        assert_eq!(
+            scheme.add_constant("tls".to_owned(), Type::Bool),
+            Err(ConstantError::UnsupportedType(Type::Bool))
+        );
+
+        let parser = Parser::new(&scheme);
+
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host contains "abc""#, &SCHEME),
+            FieldExpr::lex_with("tcp.port > $max_port;", &parser),
             FieldExpr {
-                field: field("http.host"),
-                op: FieldOp::Contains("abc".to_owned().into())
-            }
+                field: scheme.get_field_index("tcp.port").unwrap(),
+                op: FieldOp::OrderingConstant {
+                    op: OrderingOp::GreaterThan,
+                    constant: "max_port".to_owned(),
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            ";"
         );
 
-        assert_json!(
-            expr,
-            {
-                "field": "http.host",
-                "op": "Contains",
-                "rhs": "abc",
-            }
+        assert_eq!(
+            expr.op(),
+            Some(ComparisonOp::Ordering(OrderingOp::GreaterThan))
         );
+        assert_eq!(expr.rhs(), ComparisonRhs::Constant("max_port"));
 
         let expr = expr.compile();
-        let ctx = &mut ExecutionContext::new(&SCHEME);
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        ctx.set_field_value("tcp.port", 8080).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_constant("max_port", 1024).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 80).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        assert_eq!(
+            ctx.set_constant("max_port", "1024"),
+            Err(FieldValueTypeMismatchError {
+                field_type: Type::Int,
+                value_type: Type::Bytes,
+            })
+        );
+
+        let expr = complete(FieldExpr::lex_with("http.host != $origin", &parser))
+            .unwrap()
+            .compile();
 
         ctx.set_field_value("http.host", "example.org").unwrap();
+        ctx.set_constant("origin", "example.org").unwrap();
         assert_eq!(expr.execute(ctx), false);
 
-        ctx.set_field_value("http.host", "abc.net.au").unwrap();
+        ctx.set_constant("origin", "example.com").unwrap();
         assert_eq!(expr.execute(ctx), true);
+
+        let expr = complete(FieldExpr::lex_with("ip.addr == $gateway", &parser))
+            .unwrap()
+            .compile();
+
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 0, 0, 1]))
+            .unwrap();
+        ctx.set_constant("gateway", IpAddr::from([10, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        // addresses of different families are never equal
+        ctx.set_constant("gateway", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port > $origin", &parser),
+            LexErrorKind::TypeMismatch {
+                expected: Type::Int,
+                actual: Type::Bytes,
+            },
+            "$origin"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port > $min_port", &parser),
+            LexErrorKind::UnknownConstant,
+            "$min_port"
+        );
     }
 
     #[test]
-    fn test_contains_str() {
+    fn test_lists() {
+        use scheme::ListError;
+
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            ip.addr: Ip,
+            tcp.port: Int,
+        };
+
+        scheme.add_list("bad_actors".to_owned(), Type::Ip).unwrap();
+        scheme.add_list("hosts".to_owned(), Type::Bytes).unwrap();
+        scheme.add_list("ports".to_owned(), Type::Int).unwrap();
+
+        assert_eq!(
+            scheme.add_list("ports".to_owned(), Type::Int),
+            Err(ListError::Redefinition("ports".to_owned()))
+        );
+        assert_eq!(
+            scheme.add_list("scores".to_owned(), Type::Float),
+            Err(ListError::UnsupportedType(Type::Float))
+        );
+
+        let parser = Parser::new(&scheme);
+
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"http.host contains 6F:72:67"#, &SCHEME),
+            FieldExpr::lex_with("ip.addr in $bad_actors;", &parser),
             FieldExpr {
-                field: field("http.host"),
-                op: FieldOp::Contains(vec![0x6F, 0x72, 0x67].into()),
-            }
+                field: scheme.get_field_index("ip.addr").unwrap(),
+                op: FieldOp::OneOfList("bad_actors".to_owned()),
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            ";"
         );
 
+        assert_eq!(expr.op(), Some(ComparisonOp::In));
+        assert_eq!(expr.rhs(), ComparisonRhs::NamedList("bad_actors"));
+
         assert_json!(
             expr,
             {
-                "field": "http.host",
-                "op": "Contains",
-                "rhs": [0x6F, 0x72, 0x67],
+                "field": "ip.addr",
+                "op": "OneOf",
+                "list": "bad_actors"
             }
         );
 
         let expr = expr.compile();
-        let ctx = &mut ExecutionContext::new(&SCHEME);
+        let ctx = &mut ExecutionContext::new(&scheme);
 
-        ctx.set_field_value("http.host", "example.com").unwrap();
+        ctx.set_field_value("ip.addr", IpAddr::from([10, 1, 2, 3]))
+            .unwrap();
         assert_eq!(expr.execute(ctx), false);
 
-        ctx.set_field_value("http.host", "example.org").unwrap();
+        let network: IpCidr = "10.0.0.0/8".parse().unwrap();
+        ctx.set_list(
+            "bad_actors",
+            vec![
+                LhsValue::Ip(IpAddr::from([192, 0, 2, 1])),
+                LhsValue::Cidr(network),
+            ],
+        )
+        .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        // Lists can be swapped without recompiling.
+        ctx.set_list("bad_actors", vec![IpAddr::from([192, 0, 2, 1])])
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("ip.addr", IpAddr::from([192, 0, 2, 1]))
+            .unwrap();
         assert_eq!(expr.execute(ctx), true);
+
+        assert_eq!(
+            ctx.set_list("bad_actors", vec!["192.0.2.1"]),
+            Err(FieldValueTypeMismatchError {
+                field_type: Type::Ip,
+                value_type: Type::Bytes,
+            })
+        );
+
+        let hosts = complete(FieldExpr::lex_with("http.host in $hosts", &parser))
+            .unwrap()
+            .compile();
+        let ports = complete(FieldExpr::lex_with("tcp.port in $ports", &parser))
+            .unwrap()
+            .compile();
+
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        ctx.set_field_value("tcp.port", 443).unwrap();
+        ctx.set_list("hosts", vec!["example.com", "example.org"])
+            .unwrap();
+        ctx.set_list("ports", vec![80, 8080]).unwrap();
+        assert_eq!(hosts.execute(ctx), true);
+        assert_eq!(ports.execute(ctx), false);
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port in $hosts", &parser),
+            LexErrorKind::TypeMismatch {
+                expected: Type::Int,
+                actual: Type::Bytes,
+            },
+            "$hosts"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port in $good_actors", &parser),
+            LexErrorKind::UnknownList,
+            "$good_actors"
+        );
     }
 
     #[test]
     fn test_int_compare() {
         let expr = assert_ok!(
-            FieldExpr::lex_with(r#"tcp.port < 8000"#, &SCHEME),
+            FieldExpr::lex_with(r#"tcp.port < 8000"#, &PARSER),
             FieldExpr {
                 field: field("tcp.port"),
                 op: FieldOp::Ordering {
                     op: OrderingOp::LessThan,
                     rhs: RhsValue::Int(8000)
                 },
+                subscript: None,
+                call: None,
+                span: None,
             }
         );
 
@@ -694,5 +4612,94 @@ mod tests {
 
         ctx.set_field_value("tcp.port", 8080).unwrap();
         assert_eq!(expr.execute(ctx), false);
+
+        // Arithmetic is folded when parsing.
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port == 8000 + 80)", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Int(8080)
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            ")"
+        );
+
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port & 1 << 2", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::Int {
+                    op: IntOp::BitwiseAnd,
+                    rhs: 1,
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            },
+            " << 2"
+        );
+
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port >> 2 * 2 == (1 + 2)", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::IntOrdering {
+                    op: IntOp::ShiftRight,
+                    rhs: 4,
+                    ordering: OrderingOp::Equal,
+                    value: 3,
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port > 1 % 0", &PARSER),
+            LexErrorKind::DivisionByZero,
+            "1 % 0"
+        );
+    }
+
+    #[test]
+    fn test_sql_not_equal() {
+        assert_eq!(
+            FieldExpr::lex_with("tcp.port <> 80", &PARSER),
+            FieldExpr::lex_with("tcp.port != 80", &PARSER)
+        );
+
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port <80", &PARSER),
+            FieldExpr {
+                field: field("tcp.port"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::LessThan,
+                    rhs: RhsValue::Int(80)
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
+
+        assert_ok!(
+            FieldExpr::lex_with(r#"http.host <"=""#, &PARSER),
+            FieldExpr {
+                field: field("http.host"),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::LessThan,
+                    rhs: RhsValue::Bytes("=".to_owned().into())
+                },
+                subscript: None,
+                call: None,
+                span: None,
+            }
+        );
     }
 }
@@ -0,0 +1,332 @@
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{ContainsQuantifier, FieldExpr, FieldOp, IntOp, OrderingOp, Subscript},
+    simple_expr::{SimpleExpr, UnaryOp},
+    FilterAst,
+};
+use cidr::{Cidr, IpCidr};
+use rhs_types::{Bytes, Endpoint, Float, IpRange, Mac, Timestamp};
+use scheme::Scheme;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+};
+use types::{GetType, RhsValue, RhsValues, Type};
+
+const ORDERING_OPS: &[OrderingOp] = &[
+    OrderingOp::Equal,
+    OrderingOp::NotEqual,
+    OrderingOp::GreaterThanEqual,
+    OrderingOp::LessThanEqual,
+    OrderingOp::GreaterThan,
+    OrderingOp::LessThan,
+];
+
+const EQUALITY_OPS: &[OrderingOp] = &[OrderingOp::Equal, OrderingOp::NotEqual];
+
+const COMBINING_OPS: &[CombiningOp] = &[CombiningOp::And, CombiningOp::Or, CombiningOp::Xor];
+
+/// A generator of random filters over a [`Scheme`](struct@Scheme), e.g. for
+/// fuzzing executors or testing that filters print and parse back.
+///
+/// Filters compare fields of the scheme using operators and literals of their
+/// types, with subscripts for map and array fields, so they are always valid
+/// for the scheme and print into source that parses back into an equal AST.
+/// The same seed always produces the same sequence of filters.
+pub struct FilterGenerator<'s> {
+    scheme: &'s Scheme,
+    state: u64,
+    max_depth: usize,
+}
+
+impl<'s> FilterGenerator<'s> {
+    /// Creates a generator of filters over the scheme from a seed.
+    pub fn new(scheme: &'s Scheme, seed: u64) -> Self {
+        FilterGenerator {
+            scheme,
+            state: seed,
+            max_depth: 4,
+        }
+    }
+
+    /// Sets how deeply expressions can be nested, which is 4 by default.
+    /// With 0, filters are single comparisons.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Generates the next filter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scheme has no fields.
+    pub fn generate(&mut self) -> FilterAst<'s> {
+        assert!(
+            self.scheme.get_field_count() != 0,
+            "can't generate filters without fields"
+        );
+
+        let depth = self.max_depth;
+        FilterAst {
+            scheme: self.scheme,
+            op: self.combined(depth),
+            warnings: Vec::new(),
+            comments: Vec::new(),
+            span: None,
+        }
+    }
+
+    // SplitMix64, which is good enough for tests and doesn't need a crate.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+
+    fn combined(&mut self, depth: usize) -> CombinedExpr<'s> {
+        if depth == 0 || self.below(3) == 0 {
+            return CombinedExpr::Simple(self.simple(depth));
+        }
+
+        let op = self.pick(COMBINING_OPS);
+        let len = 2 + self.below(3);
+        let mut items = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            let item = self.combined(depth - 1);
+
+            // Only chains binding tighter than this one can be written
+            // without parentheses, just like in parsed filters.
+            let parenthesized = match &item {
+                CombinedExpr::Combining { op: item_op, .. } => {
+                    item_op.precedence() <= op.precedence()
+                }
+                CombinedExpr::Simple(_) => false,
+            };
+
+            items.push(if parenthesized {
+                CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(item)))
+            } else {
+                item
+            });
+        }
+
+        CombinedExpr::Combining { op, items }
+    }
+
+    fn simple(&mut self, depth: usize) -> SimpleExpr<'s> {
+        match self.below(4) {
+            0 if depth > 0 => SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg: Box::new(self.simple(depth - 1)),
+            },
+            1 if depth > 0 => SimpleExpr::Parenthesized(Box::new(self.combined(depth - 1))),
+            _ => SimpleExpr::Field(self.comparison()),
+        }
+    }
+
+    fn comparison(&mut self) -> FieldExpr<'s> {
+        let index = self.below(self.scheme.get_field_count());
+        let field = self.scheme.iter_fields().nth(index).unwrap();
+
+        let subscript = if field.is_map() {
+            Some(match self.below(4) {
+                0 => Subscript::EachOfKey(self.bytes(), ContainsQuantifier::Any),
+                1 => Subscript::EachOfKey(self.bytes(), ContainsQuantifier::All),
+                _ => Subscript::Key(self.bytes()),
+            })
+        } else if field.is_array() {
+            Some(match self.below(3) {
+                0 => Subscript::Each(ContainsQuantifier::Any),
+                1 => Subscript::Each(ContainsQuantifier::All),
+                _ => Subscript::Index(self.below(4)),
+            })
+        } else {
+            None
+        };
+
+        let op = self.field_op(field.get_type());
+        FieldExpr::with_lhs(field, subscript, None, op)
+    }
+
+    fn field_op(&mut self, ty: Type) -> FieldOp {
+        // `in` lists are generated for every type but booleans.
+        if ty != Type::Bool && self.below(4) == 0 {
+            let len = 1 + self.below(3);
+            return FieldOp::OneOf(self.values(ty, len));
+        }
+
+        let other_op = self.below(3) == 0;
+
+        match ty {
+            Type::Bool => FieldOp::IsTrue,
+            Type::Int if other_op => FieldOp::Int {
+                op: IntOp::BitwiseAnd,
+                rhs: self.int(),
+            },
+            Type::Bytes if other_op => {
+                let rhs = self.bytes();
+                if self.below(2) == 0 {
+                    FieldOp::Contains(rhs)
+                } else {
+                    FieldOp::OrderingIgnoreCase {
+                        op: self.pick(EQUALITY_OPS),
+                        rhs,
+                    }
+                }
+            }
+            Type::Cidr => FieldOp::Ordering {
+                op: self.pick(EQUALITY_OPS),
+                rhs: RhsValue::Cidr(IpCidr::new_host(self.ip())),
+            },
+            _ => FieldOp::Ordering {
+                op: self.pick(ORDERING_OPS),
+                rhs: self.value(ty),
+            },
+        }
+    }
+
+    fn value(&mut self, ty: Type) -> RhsValue {
+        match ty {
+            Type::Ip => RhsValue::Ip(self.ip()),
+            Type::Bytes => RhsValue::Bytes(self.bytes()),
+            Type::Int => RhsValue::Int(self.int()),
+            Type::Bool => unreachable!(),
+            Type::Cidr => RhsValue::Cidr(IpCidr::new_host(self.ip())),
+            Type::Endpoint => RhsValue::Endpoint(self.endpoint()),
+            Type::Float => RhsValue::Float(self.float()),
+            Type::Timestamp => RhsValue::Timestamp(self.timestamp()),
+            Type::Mac => RhsValue::Mac(self.mac()),
+        }
+    }
+
+    fn values(&mut self, ty: Type, len: usize) -> RhsValues {
+        match ty {
+            Type::Ip => RhsValues::Ip(
+                (0..len)
+                    .map(|_| IpRange::Cidr(IpCidr::new_host(self.ip())))
+                    .collect(),
+            ),
+            Type::Bytes => RhsValues::Bytes((0..len).map(|_| self.bytes()).collect()),
+            Type::Int => RhsValues::Int((0..len).map(|_| self.range(Self::int)).collect()),
+            Type::Bool => unreachable!(),
+            Type::Cidr => RhsValues::Cidr((0..len).map(|_| IpCidr::new_host(self.ip())).collect()),
+            Type::Endpoint => RhsValues::Endpoint((0..len).map(|_| self.endpoint()).collect()),
+            Type::Float => RhsValues::Float((0..len).map(|_| self.range(Self::float)).collect()),
+            Type::Timestamp => {
+                RhsValues::Timestamp((0..len).map(|_| self.range(Self::timestamp)).collect())
+            }
+            Type::Mac => RhsValues::Mac((0..len).map(|_| self.range(Self::mac)).collect()),
+        }
+    }
+
+    fn range<T: Ord>(&mut self, mut value: impl FnMut(&mut Self) -> T) -> RangeInclusive<T> {
+        let first = value(self);
+        let last = value(self);
+        if first <= last {
+            first..=last
+        } else {
+            last..=first
+        }
+    }
+
+    fn int(&mut self) -> i32 {
+        self.below(2001) as i32 - 1000
+    }
+
+    // Quarters are exact, so they print without rounding.
+    fn float(&mut self) -> Float {
+        Float::new(f64::from(self.int()) / 4.0).unwrap()
+    }
+
+    fn timestamp(&mut self) -> Timestamp {
+        Timestamp::new(self.below(2_000_000_000) as i64, 0).unwrap()
+    }
+
+    // Only a few OUIs, so that ranges within the same one are common.
+    fn mac(&mut self) -> Mac {
+        let value = self.next();
+        Mac::new([
+            0,
+            0x1a,
+            self.below(4) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ])
+    }
+
+    fn ip(&mut self) -> IpAddr {
+        if self.below(2) == 0 {
+            IpAddr::V4(Ipv4Addr::from(self.next() as u32))
+        } else {
+            let segments = [0xfe80, 0, 0, 0, 0, 0, 0, self.below(0x1_0000) as u16];
+            IpAddr::V6(Ipv6Addr::from(segments))
+        }
+    }
+
+    fn endpoint(&mut self) -> Endpoint {
+        let addr = self.ip();
+        Endpoint::new(addr, self.below(0x1_0000) as u16)
+    }
+
+    fn bytes(&mut self) -> Bytes {
+        const CHARS: &[u8] = b"abcxyz019_-. ";
+        let len = self.below(6);
+        let s: Vec<u8> = (0..len).map(|_| self.pick(CHARS)).collect();
+        String::from_utf8(s).unwrap().into()
+    }
+}
+
+#[test]
+fn test_generated_filters_round_trip() {
+    use execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! {
+        ip: Ip,
+        host: Bytes,
+        port: Int,
+        ssl: Bool,
+        net: Cidr,
+        peer: Endpoint,
+        score: Float,
+        time: Timestamp,
+        mac: Mac,
+    };
+    scheme.add_map_field("headers".into(), Type::Bytes).unwrap();
+    scheme.add_array_field("ports".into(), Type::Int).unwrap();
+    scheme.add_array_field("flags".into(), Type::Bool).unwrap();
+
+    let ctx = ExecutionContext::new(&scheme);
+
+    let mut generator = FilterGenerator::new(&scheme, 42);
+    for _ in 0..500 {
+        let ast = generator.generate();
+        let source = ast.to_string();
+        assert_eq!(scheme.parse(&source), Ok(ast.clone()), "{}", source);
+        assert!(ast.compile().execute(&ctx).is_ok());
+    }
+
+    let generate = |seed| FilterGenerator::new(&scheme, seed).generate();
+    assert_eq!(generate(1), generate(1));
+    assert_ne!(generate(1), generate(2));
+
+    let mut generator = FilterGenerator::new(&scheme, 7).max_depth(0);
+    for _ in 0..10 {
+        match generator.generate().op {
+            CombinedExpr::Simple(SimpleExpr::Field(_)) => {}
+            op => panic!("{:?} is not a comparison", op),
+        }
+    }
+}
@@ -0,0 +1,730 @@
+use super::{
+    builder::BuildError,
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{
+        BytesOp, ComparisonOp, ContainsQuantifier, FieldExpr, FieldOp, FunctionArg, FunctionCall,
+        IntOp, OrderingOp, Subscript,
+    },
+    simple_expr::{SimpleExpr, UnaryOp},
+    FilterAst,
+};
+use cidr::IpCidr;
+use failure::Fail;
+use lex::{complete, Lex, LexErrorKind};
+use parser::UnknownFieldPolicy;
+use rhs_types::{Bytes, Endpoint, Float, IpAddrOrCidr, IpRange, Mac, Regex, Timestamp, Wildcard};
+use scheme::{Field, Scheme};
+use serde_json::{self, Value};
+use std::{i32, net::IpAddr, str::FromStr};
+use types::{GetType, RhsValue, RhsValues, Type};
+
+/// An error that occurs when reading a filter with
+/// [`Scheme::filter_from_json`](::Scheme::filter_from_json) or
+/// [`Parser::filter_from_json`](::Parser::filter_from_json).
+#[derive(Debug, Fail)]
+pub enum FilterJsonError {
+    /// The input is not valid JSON.
+    #[fail(display = "{}", _0)]
+    Json(#[cause] serde_json::Error),
+
+    /// A node doesn't look like any node of a serialized filter.
+    #[fail(display = "invalid node {}: {}", node, reason)]
+    InvalidNode {
+        /// The node as JSON.
+        node: String,
+        /// What's wrong with it.
+        reason: &'static str,
+    },
+
+    /// A comparison doesn't type-check against the scheme.
+    #[fail(display = "{}", _0)]
+    Build(#[cause] BuildError),
+}
+
+type Result<T> = ::std::result::Result<T, FilterJsonError>;
+
+fn invalid(node: &Value, reason: &'static str) -> FilterJsonError {
+    FilterJsonError::InvalidNode {
+        node: node.to_string(),
+        reason,
+    }
+}
+
+fn build_error(field: &str, kind: LexErrorKind) -> FilterJsonError {
+    FilterJsonError::Build(BuildError::new(field, kind))
+}
+
+fn get<'a>(node: &'a Value, key: &'static str) -> Result<&'a Value> {
+    node.get(key).ok_or_else(|| invalid(node, "missing a key"))
+}
+
+fn ordering_op(name: &str) -> Option<OrderingOp> {
+    Some(match name {
+        "Equal" => OrderingOp::Equal,
+        "NotEqual" => OrderingOp::NotEqual,
+        "GreaterThanEqual" => OrderingOp::GreaterThanEqual,
+        "LessThanEqual" => OrderingOp::LessThanEqual,
+        "GreaterThan" => OrderingOp::GreaterThan,
+        "LessThan" => OrderingOp::LessThan,
+        _ => return None,
+    })
+}
+
+fn nocase(node: &Value) -> Result<bool> {
+    match node.get("nocase") {
+        None => Ok(false),
+        Some(value) => value
+            .as_bool()
+            .ok_or_else(|| invalid(value, "expected a boolean")),
+    }
+}
+
+fn string(value: &Value) -> Result<&str> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(value, "expected a string"))
+}
+
+fn array(value: &Value) -> Result<&[Value]> {
+    value
+        .as_array()
+        .map(|items| &items[..])
+        .ok_or_else(|| invalid(value, "expected an array"))
+}
+
+pub(crate) fn filter_from_json<'s>(
+    scheme: &'s Scheme,
+    json: &str,
+    policy: UnknownFieldPolicy,
+) -> Result<FilterAst<'s>> {
+    let node = serde_json::from_str(json).map_err(FilterJsonError::Json)?;
+    Ok(FilterAst {
+        scheme,
+        op: combined(&node, scheme, policy)?,
+        warnings: Vec::new(),
+        comments: Vec::new(),
+        span: None,
+    })
+}
+
+// Returns the operator and items of an `and`, `or` or `xor` node.
+fn combining(node: &Value) -> Result<Option<(CombiningOp, &[Value])>> {
+    let items = match node.get("items") {
+        Some(items) => array(items)?,
+        None => return Ok(None),
+    };
+
+    let op = match string(get(node, "op")?)? {
+        "And" => CombiningOp::And,
+        "Or" => CombiningOp::Or,
+        "Xor" => CombiningOp::Xor,
+        _ => return Err(invalid(node, "expected And, Or or Xor")),
+    };
+
+    Ok(Some((op, items)))
+}
+
+// Parentheses are not serialized, so they are restored where they are
+// needed, just like with a `FilterBuilder`.
+fn combined<'s>(
+    node: &Value,
+    scheme: &'s Scheme,
+    policy: UnknownFieldPolicy,
+) -> Result<CombinedExpr<'s>> {
+    let (op, items) = match combining(node)? {
+        Some(combining) => combining,
+        None => return simple(node, scheme, policy).map(CombinedExpr::Simple),
+    };
+
+    let items = items
+        .iter()
+        .map(|item| match combining(item)? {
+            Some((item_op, _)) if item_op.precedence() > op.precedence() => {
+                combined(item, scheme, policy)
+            }
+            _ => simple(item, scheme, policy).map(CombinedExpr::Simple),
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(CombinedExpr::Combining { op, items })
+}
+
+fn simple<'s>(
+    node: &Value,
+    scheme: &'s Scheme,
+    policy: UnknownFieldPolicy,
+) -> Result<SimpleExpr<'s>> {
+    if combining(node)?.is_some() {
+        return Ok(SimpleExpr::Parenthesized(Box::new(combined(
+            node, scheme, policy,
+        )?)));
+    }
+
+    if let Some(arg) = node.get("arg") {
+        return match string(get(node, "op")?)? {
+            "Not" => Ok(SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg: Box::new(simple(arg, scheme, policy)?),
+            }),
+            _ => Err(invalid(node, "expected Not")),
+        };
+    }
+
+    let name = string(get(node, "field")?)?;
+
+    match scheme.get_field_index(name) {
+        Ok(field) => field_expr(node, name, field, scheme).map(SimpleExpr::Field),
+        // Comparisons of unknown fields are serialized without an operator,
+        // but with their source, by parsers that treat them as missing.
+        Err(_) if policy == UnknownFieldPolicy::TreatAsMissing && node.get("op").is_none() => {
+            Ok(SimpleExpr::Missing {
+                field: name.to_owned(),
+                source: match node.get("source") {
+                    Some(source) => string(source)?.to_owned(),
+                    None => name.to_owned(),
+                },
+            })
+        }
+        Err(err) => Err(build_error(name, LexErrorKind::UnknownField(err))),
+    }
+}
+
+fn subscript(node: &Value, name: &str, field: Field<'_>) -> Result<Option<Subscript>> {
+    let quantifier = match node.get("quantifier") {
+        Some(quantifier) => Some(match string(quantifier)? {
+            "Any" => ContainsQuantifier::Any,
+            "All" => ContainsQuantifier::All,
+            _ => return Err(invalid(quantifier, "expected Any or All")),
+        }),
+        None => None,
+    };
+
+    let subscript = if let Some(key) = node.get("key") {
+        let key = bytes(key)?;
+        Some(match quantifier {
+            Some(quantifier) => Subscript::EachOfKey(key, quantifier),
+            None => Subscript::Key(key),
+        })
+    } else if let Some(index) = node.get("index") {
+        let index = index
+            .as_u64()
+            .ok_or_else(|| invalid(index, "expected an index"))?;
+        Some(Subscript::Index(index as usize))
+    } else {
+        quantifier.map(Subscript::Each)
+    };
+
+    let kind = match (&subscript, field.is_map(), field.is_array()) {
+        (None, false, false)
+        | (Some(Subscript::Key(_)), true, _)
+        | (Some(Subscript::EachOfKey(..)), true, _) => return Ok(subscript),
+        (Some(Subscript::Index(_)), _, true) | (Some(Subscript::Each(_)), _, true) => {
+            return Ok(subscript);
+        }
+        (None, ..) => LexErrorKind::ExpectedLiteral("["),
+        (Some(Subscript::Key(_)), ..) | (Some(Subscript::EachOfKey(..)), ..) => {
+            LexErrorKind::ExpectedName("map field")
+        }
+        (Some(_), ..) => LexErrorKind::ExpectedName("array field"),
+    };
+    Err(build_error(name, kind))
+}
+
+fn call<'s>(node: &Value, name: &str, scheme: &'s Scheme) -> Result<FunctionCall<'s>> {
+    let (function_name, function) = scheme
+        .get_function(string(get(node, "name")?)?)
+        .ok_or_else(|| build_error(name, LexErrorKind::UnknownFunction))?;
+
+    let args = array(get(node, "args")?)?;
+    let params = function.params();
+
+    if args.len() != params.len() {
+        return Err(build_error(
+            name,
+            LexErrorKind::CountMismatch {
+                name: "argument",
+                actual: args.len(),
+                expected: params.len(),
+            },
+        ));
+    }
+
+    let args = args
+        .iter()
+        .zip(params)
+        .map(|(arg, &ty)| {
+            if let Some(literal) = arg.get("literal") {
+                return Ok(FunctionArg::Literal {
+                    literal: rhs_value(literal, name, ty)?,
+                });
+            }
+
+            let arg_name = string(get(arg, "field")?)?;
+            let field = scheme
+                .get_field_index(arg_name)
+                .map_err(|err| build_error(arg_name, LexErrorKind::UnknownField(err)))?;
+
+            let subscript = match subscript(arg, arg_name, field)? {
+                Some(Subscript::Each(_)) | Some(Subscript::EachOfKey(..)) => {
+                    return Err(invalid(arg, "arguments can't have quantifiers"));
+                }
+                subscript => subscript,
+            };
+
+            if field.get_type() != ty {
+                return Err(build_error(
+                    arg_name,
+                    LexErrorKind::TypeMismatch {
+                        expected: ty,
+                        actual: field.get_type(),
+                    },
+                ));
+            }
+
+            Ok(FunctionArg::Field { field, subscript })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(FunctionCall::new(function_name, function, args))
+}
+
+fn field_expr<'s>(
+    node: &Value,
+    name: &str,
+    field: Field<'s>,
+    scheme: &'s Scheme,
+) -> Result<FieldExpr<'s>> {
+    let error = |kind| build_error(name, kind);
+
+    // A whole array field can only be compared with `[]`.
+    let is_empty_array = |rhs: &Value| rhs.as_array().map_or(false, Vec::is_empty);
+    if field.is_array()
+        && ["call", "index", "quantifier"]
+            .iter()
+            .all(|key| node.get(key).is_none())
+        && node.get("rhs").map_or(false, is_empty_array)
+    {
+        return match ordering_op(string(get(node, "op")?)?) {
+            Some(op) if op == OrderingOp::Equal || op == OrderingOp::NotEqual => {
+                Ok(FieldExpr::new(field, FieldOp::EmptyArray(op)))
+            }
+            _ => Err(invalid(node, "expected == or != with an empty array")),
+        };
+    }
+
+    let (subscript, call) = match node.get("call") {
+        Some(call_node) => {
+            let call = call(call_node, name, scheme)?;
+            if call.fields().next() != Some(field) {
+                return Err(invalid(
+                    node,
+                    "expected the first field argument as the field",
+                ));
+            }
+            (None, Some(call))
+        }
+        None => (subscript(node, name, field)?, None),
+    };
+
+    let field_type = match &call {
+        Some(call) => call.return_type(),
+        None => field.get_type(),
+    };
+
+    let op_name = string(get(node, "op")?)?;
+    let op = match op_name {
+        "IsTrue" => None,
+        "BitwiseAnd" => Some(ComparisonOp::Int(IntOp::BitwiseAnd)),
+        "ShiftRight" => Some(ComparisonOp::Int(IntOp::ShiftRight)),
+        "ShiftLeft" => Some(ComparisonOp::Int(IntOp::ShiftLeft)),
+        "Contains" => Some(ComparisonOp::Bytes(BytesOp::Contains)),
+        "Matches" | "MatchesPattern" => Some(ComparisonOp::Bytes(BytesOp::Matches)),
+        "Wildcard" => Some(ComparisonOp::Bytes(BytesOp::Wildcard)),
+        "OneOf" => Some(ComparisonOp::In),
+        _ => match ordering_op(op_name) {
+            Some(op) => Some(ComparisonOp::Ordering(op)),
+            None => return Err(invalid(node, "unknown operator")),
+        },
+    };
+
+    let unsupported = |op| error(LexErrorKind::UnsupportedOp { field_type, op });
+    let equality = |op| op == OrderingOp::Equal || op == OrderingOp::NotEqual;
+
+    let nocase = nocase(node)?;
+
+    let op = match (op, field_type) {
+        (Some(ComparisonOp::Ordering(op)), Type::Bytes) if nocase && equality(op) => {
+            FieldOp::OrderingIgnoreCase {
+                op,
+                rhs: bytes(get(node, "rhs")?)?,
+            }
+        }
+        (Some(ComparisonOp::Bytes(BytesOp::Contains)), Type::Bytes) if nocase => {
+            FieldOp::ContainsIgnoreCase(bytes(get(node, "rhs")?)?)
+        }
+        _ if nocase => {
+            return Err(invalid(
+                node,
+                "nocase is only supported for ==, != and contains with bytes",
+            ));
+        }
+        (None, Type::Bool) => FieldOp::IsTrue,
+        // Parser expects an operator after any non-boolean field.
+        (None, _) => return Err(error(LexErrorKind::ExpectedName("ComparisonOp"))),
+        (Some(op), Type::Bool) => return Err(unsupported(op)),
+        (Some(ComparisonOp::Ordering(op)), _) if node.get("constant").is_some() => {
+            match field_type {
+                Type::Ip | Type::Bytes | Type::Int => {}
+                _ => return Err(unsupported(ComparisonOp::Ordering(op))),
+            }
+
+            let constant = string(get(node, "constant")?)?;
+            match scheme.get_constant_type(constant) {
+                Some(actual) if actual == field_type => {}
+                Some(actual) => {
+                    return Err(error(LexErrorKind::TypeMismatch {
+                        expected: field_type,
+                        actual,
+                    }));
+                }
+                None => return Err(error(LexErrorKind::UnknownConstant)),
+            }
+
+            FieldOp::OrderingConstant {
+                op,
+                constant: constant.to_owned(),
+            }
+        }
+        (Some(ComparisonOp::Ordering(op)), Type::Ip) if equality(op) => {
+            let rhs = get(node, "rhs")?;
+            match (parse::<IpAddrOrCidr>(rhs, name)?, node.get("zone")) {
+                (IpAddrOrCidr::Addr(IpAddr::V6(rhs)), Some(zone)) => {
+                    let zone = string(zone)?;
+                    if zone.is_empty() {
+                        return Err(invalid(node, "expected a non-empty zone"));
+                    }
+                    FieldOp::ZonedIp {
+                        op,
+                        rhs,
+                        zone: zone.to_owned(),
+                    }
+                }
+                (_, Some(_)) => return Err(invalid(node, "expected an IPv6 address with a zone")),
+                (IpAddrOrCidr::Addr(addr), None) => FieldOp::Ordering {
+                    op,
+                    rhs: RhsValue::Ip(addr),
+                },
+                (IpAddrOrCidr::Cidr(rhs), None) => FieldOp::Cidr { op, rhs },
+            }
+        }
+        (Some(ComparisonOp::Ordering(op)), Type::Cidr) if !equality(op) => {
+            return Err(unsupported(ComparisonOp::Ordering(op)));
+        }
+        (Some(ComparisonOp::Ordering(op)), _) => FieldOp::Ordering {
+            op,
+            rhs: rhs_value(get(node, "rhs")?, name, field_type)?,
+        },
+        (Some(ComparisonOp::Int(op)), Type::Int) => {
+            let rhs = int(get(node, "rhs")?)?;
+            if !op.is_valid_rhs(rhs) {
+                return Err(error(LexErrorKind::InvalidShift));
+            }
+
+            match node.get("ordering") {
+                Some(ordering) => FieldOp::IntOrdering {
+                    op,
+                    rhs,
+                    ordering: ordering_op(string(ordering)?)
+                        .ok_or_else(|| invalid(ordering, "unknown operator"))?,
+                    value: int(get(node, "value")?)?,
+                },
+                None => FieldOp::Int { op, rhs },
+            }
+        }
+        (Some(ComparisonOp::Bytes(BytesOp::Contains)), Type::Bytes) => {
+            FieldOp::Contains(bytes(get(node, "rhs")?)?)
+        }
+        (Some(ComparisonOp::Bytes(BytesOp::Wildcard)), Type::Bytes) => {
+            FieldOp::Wildcard(Wildcard::new(bytes(get(node, "rhs")?)?))
+        }
+        (Some(ComparisonOp::Bytes(BytesOp::Matches)), Type::Bytes) => {
+            let rhs = string(get(node, "rhs")?)?;
+            if op_name == "MatchesPattern" {
+                FieldOp::MatchesPattern(rhs.to_owned())
+            } else {
+                FieldOp::Matches(
+                    Regex::from_str(rhs).map_err(|err| error(LexErrorKind::ParseRegex(err)))?,
+                )
+            }
+        }
+        (Some(ComparisonOp::In), _) if node.get("list").is_some() => {
+            let list = string(get(node, "list")?)?;
+            match scheme.get_list_type(list) {
+                Some(actual) if actual == field_type => {}
+                Some(actual) => {
+                    return Err(error(LexErrorKind::TypeMismatch {
+                        expected: field_type,
+                        actual,
+                    }));
+                }
+                None => return Err(error(LexErrorKind::UnknownList)),
+            }
+            FieldOp::OneOfList(list.to_owned())
+        }
+        (Some(ComparisonOp::In), _) => {
+            FieldOp::OneOf(rhs_values(get(node, "rhs")?, name, field_type)?)
+        }
+        (Some(op), _) => return Err(unsupported(op)),
+    };
+
+    Ok(FieldExpr::with_lhs(field, subscript, call, op))
+}
+
+// Addresses, networks and endpoints are serialized the same way they are
+// written in filters, so they are lexed from strings to be exactly the same.
+fn parse<T: for<'i> Lex<'i>>(value: &Value, name: &str) -> Result<T> {
+    complete(T::lex(string(value)?)).map_err(|(kind, _)| build_error(name, kind))
+}
+
+fn bytes(value: &Value) -> Result<Bytes> {
+    if let Some(value) = value.as_str() {
+        return Ok(value.to_owned().into());
+    }
+
+    array(value)?
+        .iter()
+        .map(|byte| match byte.as_u64() {
+            Some(byte) if byte <= 0xFF => Ok(byte as u8),
+            _ => Err(invalid(value, "expected a string or an array of bytes")),
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Bytes::from)
+}
+
+fn int(value: &Value) -> Result<i32> {
+    match value.as_i64() {
+        Some(value) if value >= i32::MIN.into() && value <= i32::MAX.into() => Ok(value as i32),
+        _ => Err(invalid(value, "expected an int")),
+    }
+}
+
+fn float(value: &Value) -> Result<Float> {
+    value
+        .as_f64()
+        .and_then(Float::new)
+        .ok_or_else(|| invalid(value, "expected a float"))
+}
+
+// Timestamps are serialized as RFC 3339 strings without quotes.
+fn timestamp(value: &Value) -> Result<Timestamp> {
+    string(value)?
+        .parse()
+        .map_err(|()| invalid(value, "expected an RFC 3339 timestamp"))
+}
+
+fn mac(value: &Value) -> Result<Mac> {
+    string(value)?
+        .parse()
+        .map_err(|()| invalid(value, "expected a MAC address"))
+}
+
+fn rhs_value(value: &Value, name: &str, ty: Type) -> Result<RhsValue> {
+    Ok(match ty {
+        Type::Ip => RhsValue::Ip(parse(value, name)?),
+        Type::Bytes => RhsValue::Bytes(bytes(value)?),
+        Type::Int => RhsValue::Int(int(value)?),
+        Type::Bool => return Err(invalid(value, "booleans can't be compared with literals")),
+        Type::Cidr => RhsValue::Cidr(parse(value, name)?),
+        Type::Endpoint => RhsValue::Endpoint(parse(value, name)?),
+        Type::Float => RhsValue::Float(float(value)?),
+        Type::Timestamp => RhsValue::Timestamp(timestamp(value)?),
+        Type::Mac => RhsValue::Mac(mac(value)?),
+    })
+}
+
+fn rhs_values(value: &Value, name: &str, ty: Type) -> Result<RhsValues> {
+    fn collect<T>(items: &[Value], item: impl Fn(&Value) -> Result<T>) -> Result<Vec<T>> {
+        items.iter().map(item).collect()
+    }
+
+    // Ranges of ints and floats are serialized as objects with bounds.
+    fn range<T: PartialOrd>(
+        node: &Value,
+        name: &str,
+        bound: impl Fn(&Value) -> Result<T>,
+    ) -> Result<(T, T)> {
+        let start = bound(get(node, "start")?)?;
+        let end = bound(get(node, "end")?)?;
+        if end < start {
+            return Err(build_error(name, LexErrorKind::IncompatibleRangeBounds));
+        }
+        Ok((start, end))
+    }
+
+    let items = array(value)?;
+
+    Ok(match ty {
+        Type::Ip => RhsValues::Ip(collect(items, |item| {
+            if item.is_object() {
+                let (start, end) = (string(get(item, "start")?)?, string(get(item, "end")?)?);
+                let range = format!("{}..{}", start, end);
+                complete(IpRange::lex(&range)).map_err(|(kind, _)| build_error(name, kind))
+            } else {
+                parse(item, name)
+            }
+        })?),
+        Type::Bytes => RhsValues::Bytes(collect(items, bytes)?),
+        Type::Int => RhsValues::Int(collect(items, |item| {
+            range(item, name, int).map(|(start, end)| start..=end)
+        })?),
+        Type::Bool => return Err(invalid(value, "booleans can't be compared with literals")),
+        Type::Cidr => RhsValues::Cidr(collect(items, |item| parse::<IpCidr>(item, name))?),
+        Type::Endpoint => {
+            RhsValues::Endpoint(collect(items, |item| parse::<Endpoint>(item, name))?)
+        }
+        Type::Float => RhsValues::Float(collect(items, |item| {
+            range(item, name, float).map(|(start, end)| start..=end)
+        })?),
+        Type::Timestamp => RhsValues::Timestamp(collect(items, |item| {
+            range(item, name, timestamp).map(|(start, end)| start..=end)
+        })?),
+        Type::Mac => RhsValues::Mac(collect(items, |item| {
+            range(item, name, mac).map(|(start, end)| start..=end)
+        })?),
+    })
+}
+
+#[test]
+fn test_round_trip() {
+    use functions::Function;
+
+    let mut scheme = Scheme! {
+        tcp.port: Int,
+        ip.addr: Ip,
+        http.host: Bytes,
+        ssl: Bool,
+        score: Float,
+        route.prefix: Cidr,
+        endpoint: Endpoint,
+        now: Timestamp,
+        eth.src: Mac,
+    };
+    scheme
+        .add_map_field("http.headers".to_owned(), Type::Bytes)
+        .unwrap();
+    scheme
+        .add_array_field("http.cookies".to_owned(), Type::Bytes)
+        .unwrap();
+    scheme
+        .add_function("lower".to_owned(), Function::lower())
+        .unwrap();
+    scheme
+        .add_function("concat".to_owned(), Function::concat())
+        .unwrap();
+    scheme
+        .add_constant("max_port".to_owned(), Type::Int)
+        .unwrap();
+    scheme.add_list("bad_actors".to_owned(), Type::Ip).unwrap();
+
+    for filter in &[
+        "ssl",
+        "tcp.port == 443 && ip.addr in { 10.0.0.0/8 ::1 }",
+        "(ssl || tcp.port & 1) && !(http.host matches \"^a\" ^^ http.host contains \"b\")",
+        "ssl || tcp.port < 80 && tcp.port > 8 ^^ ssl",
+        "ip.addr == 10.0.0.0/8 || ip.addr != fe80::1%eth0 || ip.addr >= 10.0.0.1",
+        "ip.addr in { 10.0.0.1..10.0.0.5 192.0.2.1 } && not ip.addr in $bad_actors",
+        "tcp.port in { 1 5..10 } && tcp.port <= $max_port",
+        "tcp.port & 0x02 != 0 && tcp.port >> 4 == 3 || tcp.port << 1 > 8",
+        "score in { 0.5..1.5 } || score > 2.5",
+        r#"now in { 0..86400 } || now >= "2024-01-01T00:00:00.5+01:00""#,
+        "route.prefix in { 10.0.0.0/8 } || endpoint == 192.0.2.1:443",
+        "eth.src in { 00:1a:2b/24 } || eth.src > 00:1a:2b:3c:4d:5e",
+        r#"http.host == "a\x00b" || http.host matches $hosts"#,
+        r#"http.headers["host"] == "a" && http.cookies[1] == "b""#,
+        "http.cookies == [] || http.cookies != []",
+        r#"any(http.cookies contains "session") || all(http.cookies != "x")"#,
+        r#"http.headers["cookie"][*] contains "a" && all(http.headers["b"][*] == "c")"#,
+        r#"http.host == "A" nocase || http.host contains "b" nocase"#,
+        r#"http.host wildcard "*.example.com" || http.host like "a?\\*""#,
+        r#"lower(http.headers["host"]) == "a" && concat(http.host, "/") in { "a/" }"#,
+    ] {
+        let ast = scheme.parse(filter).unwrap();
+        let json = serde_json::to_string(&ast).unwrap();
+        assert_eq!(scheme.filter_from_json(&json).unwrap(), ast, "{}", json);
+    }
+
+    let parser = ::Parser::with_options(
+        &scheme,
+        ::ParserOptions {
+            unknown_field_policy: ::UnknownFieldPolicy::TreatAsMissing,
+            ..::ParserOptions::default()
+        },
+    );
+    let ast = parser.parse(r#"foo["k"] !~ "a" || ssl"#).unwrap();
+    let json = serde_json::to_string(&ast).unwrap();
+    assert_eq!(parser.filter_from_json(&json).unwrap(), ast);
+
+    // Like when parsing, unknown fields are an error unless the parser
+    // treats them as missing.
+    match scheme.filter_from_json(&json) {
+        Err(FilterJsonError::Build(err)) => assert_eq!(
+            err,
+            BuildError::new("foo", LexErrorKind::UnknownField(::UnknownFieldError))
+        ),
+        result => panic!("{:?}", result),
+    }
+    assert!(scheme.filter_from_json(r#"{"field":"foo"}"#).is_err());
+}
+
+#[test]
+fn test_errors() {
+    use scheme::UnknownFieldError;
+
+    let scheme = &Scheme! { tcp.port: Int, ssl: Bool };
+
+    let build_error = |json| match scheme.filter_from_json(json) {
+        Err(FilterJsonError::Build(err)) => err,
+        result => panic!("{:?}", result),
+    };
+
+    assert_eq!(
+        build_error(r#"{"field":"foo","op":"Equal","rhs":1}"#),
+        BuildError::new("foo", LexErrorKind::UnknownField(UnknownFieldError))
+    );
+    assert_eq!(
+        build_error(r#"{"field":"tcp.port","op":"Matches","rhs":"a"}"#),
+        BuildError::new(
+            "tcp.port",
+            LexErrorKind::UnsupportedOp {
+                field_type: Type::Int,
+                op: ComparisonOp::Bytes(BytesOp::Matches),
+            }
+        )
+    );
+    assert_eq!(
+        build_error(r#"{"field":"tcp.port","op":"OneOf","rhs":[{"start":2,"end":1}]}"#),
+        BuildError::new("tcp.port", LexErrorKind::IncompatibleRangeBounds)
+    );
+    assert_eq!(
+        build_error(r#"{"field":"tcp.port","op":"IsTrue"}"#),
+        BuildError::new("tcp.port", LexErrorKind::ExpectedName("ComparisonOp"))
+    );
+
+    match scheme.filter_from_json(r#"{"op":"Nand","items":[]}"#) {
+        Err(FilterJsonError::InvalidNode { node, reason }) => {
+            assert_eq!(node, r#"{"items":[],"op":"Nand"}"#);
+            assert_eq!(reason, "expected And, Or or Xor");
+        }
+        result => panic!("{:?}", result),
+    }
+
+    match scheme.filter_from_json(r#"{"field":"tcp.port","op":"Equal","rhs":"443"}"#) {
+        Err(FilterJsonError::InvalidNode { reason, .. }) => assert_eq!(reason, "expected an int"),
+        result => panic!("{:?}", result),
+    }
+
+    match scheme.filter_from_json("{") {
+        Err(FilterJsonError::Json(_)) => {}
+        result => panic!("{:?}", result),
+    }
+}
@@ -1,17 +1,92 @@
+mod analysis;
+mod bpf;
+mod builder;
 mod combined_expr;
-mod field_expr;
+pub(crate) mod field_expr;
+#[cfg(feature = "generator")]
+mod generator;
+#[cfg(feature = "json")]
+mod json;
+pub(crate) mod precedence;
+mod profile;
+mod residual;
 mod simple_expr;
+mod subset;
+mod ternary;
+mod trace;
 
-use self::combined_expr::CombinedExpr;
-use filter::{CompiledExpr, Filter};
+pub use self::{
+    analysis::FilterAnalysis,
+    bpf::{BpfCompileError, BpfInstruction},
+    builder::{field, BuildError, FieldBuilder, FilterBuilder},
+    field_expr::{BytesOp, ComparisonOp, ComparisonRhs, FieldExpr, IntOp, OrderingOp},
+    profile::{NodeProfile, ProfiledFilter},
+    residual::Residual,
+    ternary::{Ternary, UnknownResultError},
+    trace::{Trace, TraceEntry},
+};
+
+#[cfg(feature = "generator")]
+pub use self::generator::FilterGenerator;
+#[cfg(feature = "json")]
+pub(crate) use self::json::filter_from_json;
+#[cfg(feature = "json")]
+pub use self::json::FilterJsonError;
+
+use self::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::span_text,
+    profile::Profiler,
+    simple_expr::SimpleExpr,
+};
+use execution_context::ExecutionContext;
+use filter::{CompiledExpr, Filter, SchemeMismatchError};
 use lex::{LexResult, LexWith};
+use parser::{Parser, Warning};
+use program::{Instruction, Program};
 use scheme::{Field, Scheme, UnknownFieldError};
 use serde::Serialize;
-use std::fmt::{self, Debug};
+use sha256::sha256;
+use std::{
+    fmt::{self, Debug, Display},
+    ops::{BitAnd, BitOr},
+};
+use types::{GetType, LhsValue, Type};
 
-trait Expr<'s>: Sized + Eq + Debug + for<'i> LexWith<'i, &'s Scheme> + Serialize {
+trait Expr<'s>: Sized + Eq + Debug + for<'i, 'p> LexWith<'i, &'p Parser<'s>> + Serialize {
     fn uses(&self, field: Field<'s>) -> bool;
+    fn collect_comparisons<'a>(&'a self, comparisons: &mut Vec<&'a FieldExpr<'s>>);
+    fn cost(&self) -> u64;
+    fn node_count(&self) -> usize;
+    fn depth(&self) -> usize;
+    fn write_tree(&self, input: &str, indent: usize, out: &mut String);
+    fn write_fingerprint(&self, out: &mut String);
+    fn canonicalize(&mut self);
+    fn simplify_ranges(&mut self);
+    fn offset_spans(&mut self, offset: usize);
     fn compile(self) -> CompiledExpr<'s>;
+    fn lower(self, program: &mut Vec<Instruction<'s>>);
+    fn compile_profiled(self, profiler: &mut Profiler) -> CompiledExpr<'s>;
+    fn execute_traced<'a, 'e>(
+        &'a self,
+        ctx: &ExecutionContext<'e>,
+        reached: bool,
+        trace: &mut Vec<TraceEntry<'a, 's, 'e>>,
+    ) -> bool;
+    fn execute_matching<'a>(
+        &'a self,
+        ctx: &ExecutionContext<'_>,
+        leaves: &mut Vec<&'a FieldExpr<'s>>,
+    ) -> bool;
+    fn execute_ternary(&self, ctx: &ExecutionContext<'_>) -> Ternary;
+}
+
+fn write_tree_line(out: &mut String, indent: usize, label: &str) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+    out.push_str(label);
+    out.push('\n');
 }
 
 /// A parsed filter AST.
@@ -19,25 +94,125 @@ trait Expr<'s>: Sized + Eq + Debug + for<'i> LexWith<'i, &'s Scheme> + Serialize
 /// It's attached to its corresponding [`Scheme`](struct@Scheme) because all
 /// parsed fields are represented as indices and are valid only when
 /// [`ExecutionContext`](::ExecutionContext) is created from the same scheme.
-#[derive(PartialEq, Eq, Serialize, Clone)]
+///
+/// Filters are equal if they have the same scheme, nodes, warnings and
+/// comments, regardless of where they were parsed from.
+#[derive(Serialize, Clone)]
 #[serde(transparent)]
 pub struct FilterAst<'s> {
     #[serde(skip)]
     scheme: &'s Scheme,
 
     op: CombinedExpr<'s>,
+
+    #[serde(skip)]
+    pub(crate) warnings: Vec<Warning>,
+
+    #[serde(skip)]
+    pub(crate) comments: Vec<String>,
+
+    // Like spans of comparisons, offsets from the end of the input.
+    #[serde(skip)]
+    span: Option<(usize, usize)>,
 }
 
+impl<'s> PartialEq for FilterAst<'s> {
+    fn eq(&self, other: &Self) -> bool {
+        self.scheme == other.scheme
+            && self.op == other.op
+            && self.warnings == other.warnings
+            && self.comments == other.comments
+    }
+}
+
+impl<'s> Eq for FilterAst<'s> {}
+
 impl<'s> Debug for FilterAst<'s> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.op.fmt(f)
+        Debug::fmt(&self.op, f)
+    }
+}
+
+/// Formats the filter back as source text, which parses into an equal AST.
+///
+/// The output is normalized: operators are written as `&&`, `||`, `^^` and
+/// `not`, with single spaces around them, strings are quoted and escaped in
+/// one way, and lists are written as `{ a b }`. Parentheses and the order of
+/// operands and list items are kept as they are in the AST, so equivalent
+/// filters print the same after [`canonicalize`](FilterAst::canonicalize),
+/// e.g. for storing normalized rules or diffing them.
+///
+/// Comments are dropped, unless they were kept with
+/// [`ParserOptions::keep_comments`](::ParserOptions::keep_comments) and the
+/// alternate format (`{:#}`) is used, in which case they are written on their
+/// own lines before the filter, in their original order.
+impl<'s> Display for FilterAst<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            for comment in &self.comments {
+                writeln!(f, "{}", comment)?;
+            }
+        }
+        Display::fmt(&self.op, f)
+    }
+}
+
+impl<'s> FilterAst<'s> {
+    fn combine(
+        mut self,
+        op: CombiningOp,
+        other: FilterAst<'s>,
+    ) -> Result<FilterAst<'s>, SchemeMismatchError> {
+        if self.scheme != other.scheme {
+            return Err(SchemeMismatchError);
+        }
+
+        self.op = self.op.combine(op, other.op);
+        self.span = None;
+        self.warnings.extend(other.warnings);
+        self.comments.extend(other.comments);
+        Ok(self)
+    }
+}
+
+/// Combines two filters over the same scheme into `self and other`, just
+/// like if they were parsed from a single string.
+///
+/// Fails if the filters use different schemes.
+impl<'s> BitAnd for FilterAst<'s> {
+    type Output = Result<FilterAst<'s>, SchemeMismatchError>;
+
+    fn bitand(self, other: FilterAst<'s>) -> Self::Output {
+        self.combine(CombiningOp::And, other)
+    }
+}
+
+/// Combines two filters over the same scheme into `self or other`, just
+/// like if they were parsed from a single string.
+///
+/// Fails if the filters use different schemes.
+impl<'s> BitOr for FilterAst<'s> {
+    type Output = Result<FilterAst<'s>, SchemeMismatchError>;
+
+    fn bitor(self, other: FilterAst<'s>) -> Self::Output {
+        self.combine(CombiningOp::Or, other)
     }
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for FilterAst<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
-        let (op, input) = CombinedExpr::lex_with(input, scheme)?;
-        Ok((FilterAst { scheme, op }, input))
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for FilterAst<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let initial_input = input;
+        let (op, input) = CombinedExpr::lex_with(input, parser)?;
+        Ok((
+            FilterAst {
+                scheme: parser.scheme,
+                op,
+                warnings: Vec::new(),
+                comments: Vec::new(),
+                span: Some((initial_input.len(), input.len())),
+            },
+            input,
+        ))
     }
 }
 
@@ -51,8 +226,431 @@ impl<'s> FilterAst<'s> {
             .map(|field| self.op.uses(field))
     }
 
+    pub(crate) fn scheme(&self) -> &'s Scheme {
+        self.scheme
+    }
+
+    // Moves spans of the filter and its comparisons further from the end of
+    // the input, for filters parsed from a part of it.
+    pub(crate) fn offset_spans(&mut self, offset: usize) {
+        if let Some((start, end)) = &mut self.span {
+            *start += offset;
+            *end += offset;
+        }
+        self.op.offset_spans(offset);
+    }
+
+    /// Returns the exact part of `input` the whole filter was parsed from,
+    /// without surrounding whitespace and comments, the same as
+    /// [`FieldExpr::source_text`] does for each of its comparisons.
+    ///
+    /// Returns `None` for filters that weren't parsed, and for ones that
+    /// were combined or rewritten since, like by
+    /// [`optimize`](FilterAst::optimize), as their source differs.
+    pub fn source_text<'i>(&self, input: &'i str) -> Option<&'i str> {
+        span_text(self.span?, input)
+    }
+
+    // Returns comparisons that have to match for the whole filter to match,
+    // i.e. the ones that are `and`-ed at the top level.
+    pub(crate) fn required_comparisons(&self) -> Vec<&FieldExpr<'s>> {
+        fn collect<'a, 's>(expr: &'a CombinedExpr<'s>, out: &mut Vec<&'a FieldExpr<'s>>) {
+            match expr {
+                CombinedExpr::Simple(SimpleExpr::Field(expr)) => out.push(expr),
+                CombinedExpr::Simple(SimpleExpr::Parenthesized(expr)) => collect(expr, out),
+                CombinedExpr::Combining {
+                    op: CombiningOp::And,
+                    items,
+                } => {
+                    for item in items {
+                        collect(item, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut comparisons = Vec::new();
+        collect(&self.op, &mut comparisons);
+        comparisons
+    }
+
+    /// Returns all comparisons in the filter, in the order they appear in.
+    pub fn comparisons(&self) -> Vec<&FieldExpr<'s>> {
+        let mut comparisons = Vec::new();
+        self.op.collect_comparisons(&mut comparisons);
+        comparisons
+    }
+
+    /// Returns all literal values of the given type in the filter, in the
+    /// order they appear in, e.g. to check strings in a filter against a
+    /// policy.
+    ///
+    /// Besides plain comparisons, this includes items of `in` lists, where
+    /// ranges are represented by their bounds, and regexes, which are
+    /// returned as `bytes` literals. Networks compared with IP fields are
+    /// `cidr` literals.
+    pub fn literals(&self, ty: Type) -> Vec<LhsValue<'_>> {
+        let mut literals = Vec::new();
+        for comparison in self.comparisons() {
+            comparison.collect_literals(ty, &mut literals);
+        }
+        literals
+    }
+
+    /// Returns likely mistakes found in the filter while parsing it.
+    ///
+    /// Checks are opt-in via [`ParserOptions`](::ParserOptions), so this is
+    /// always empty unless some of them were enabled.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Returns the comments of the filter, with their delimiters, in the
+    /// order they were written.
+    ///
+    /// This is always empty unless
+    /// [`ParserOptions::keep_comments`](::ParserOptions::keep_comments) was
+    /// enabled.
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// Returns the fields and operators used by the filter.
+    pub fn analyze(&self) -> FilterAnalysis<'s> {
+        let mut analysis = FilterAnalysis::default();
+
+        for comparison in self.comparisons() {
+            analysis
+                .used_fields
+                .extend(comparison.fields().iter().map(|field| field.name()));
+            analysis.used_operators.extend(comparison.op());
+        }
+
+        analysis
+    }
+
+    /// Returns the number of nodes in the filter.
+    ///
+    /// Each comparison, `not` and a chain of same logical operators like
+    /// `a && b && c` counts as one node, and so does each item of an `in`
+    /// list. Parentheses don't count as they only affect grouping.
+    pub fn node_count(&self) -> usize {
+        self.op.node_count()
+    }
+
+    /// Returns the number of nodes on the longest path from the root of the
+    /// filter to a leaf, counting nodes the same way as
+    /// [`node_count`](FilterAst::node_count).
+    pub fn depth(&self) -> usize {
+        self.op.depth()
+    }
+
+    /// Renders the filter as an indented tree, one node per line, for quick
+    /// inspection when debugging.
+    ///
+    /// `input` must be the string the filter was parsed from. Comparisons
+    /// are shown as their source text with its byte range, and the rest of
+    /// nodes as `and`, `or`, `xor` and `not`, with their operands nested
+    /// below them.
+    pub fn debug_tree(&self, input: &str) -> String {
+        let mut out = String::new();
+        self.op.write_tree(input, 0, &mut out);
+        out
+    }
+
+    /// Returns the filter with operands of commutative nodes sorted in a
+    /// stable order, so that equivalent filters like `a && b` and `b && a`
+    /// end up equal.
+    ///
+    /// This sorts the items of `and`, `or` and `xor` chains and of `in` lists.
+    /// Nothing else is reordered or rewritten; in particular, parenthesized
+    /// groups are not flattened into the enclosing chain.
+    pub fn canonicalize(mut self) -> Self {
+        self.op.canonicalize();
+        self.span = None;
+        self
+    }
+
+    /// Returns the logical negation of the filter, which matches exactly
+    /// when this one doesn't.
+    ///
+    /// Instead of wrapping the whole filter into `not`, the negation is
+    /// pushed down to comparisons by De Morgan's laws, so `a && b` becomes
+    /// `not a || not b`, and double negations cancel out.
+    pub fn negate(mut self) -> Self {
+        self.op = self.op.negate();
+        self.span = None;
+        self
+    }
+
+    /// Returns a stable fingerprint of the filter as a hex SHA-256 digest,
+    /// e.g. to content-address compiled filters in a cache.
+    ///
+    /// The digest is computed over a serialization where operands of `and`,
+    /// `or` and `xor` and items of `in` lists are sorted, parentheses are
+    /// dropped and string literals are stored as raw bytes, so filters that
+    /// differ only in those, like `a && b` and `b && a` or `x == "a"` and
+    /// `x == 61`, share a fingerprint. It doesn't depend on the scheme
+    /// beyond names of the fields.
+    pub fn fingerprint(&self) -> String {
+        let mut out = String::new();
+        self.op.write_fingerprint(&mut out);
+        sha256(out.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Checks whether everything this filter matches is also matched by the
+    /// `other` one, i.e. whether it implies the other filter.
+    ///
+    /// This is a best-effort structural check, which returns `false` when it
+    /// can't decide. It recognizes:
+    ///  - equal filters, looking through parentheses;
+    ///  - `a || b` if both sides imply `other`, and `a && b` if either does;
+    ///  - filters implying either side of `other` if it's an `or`, or both
+    ///    sides if it's an `and`;
+    ///  - `not a` implying `not b` if `b` implies `a`;
+    ///  - comparisons of the same field where all values matched on the left
+    ///    are matched on the right, for `==`, `in` and, for integers, other
+    ///    ordering operators, like `port == 5` and `port in { 5 6 }`;
+    ///  - `contains` where the right needle is a part of the left one.
+    ///
+    /// Filters over different schemes are never subsets of each other.
+    pub fn is_subset_of(&self, other: &FilterAst<'s>) -> bool {
+        self.scheme == other.scheme && subset::is_subset(&self.op, &other.op)
+    }
+
+    /// Checks whether this filter implies the `other` one, which is the same
+    /// as [`is_subset_of`](FilterAst::is_subset_of), e.g. to find rules that
+    /// are shadowed by earlier ones.
+    pub fn implies(&self, other: &FilterAst<'s>) -> bool {
+        self.is_subset_of(other)
+    }
+
+    /// Checks whether this filter and the `other` one never match together,
+    /// e.g. to find rules that are unreachable after earlier ones.
+    ///
+    /// Like [`is_subset_of`](FilterAst::is_subset_of), this is a best-effort
+    /// structural check, which returns `false` when it can't decide. It
+    /// recognizes:
+    ///  - comparisons of fields that are not in the scheme, which never match;
+    ///  - `a || b` if both sides are disjoint with `other`, and `a && b` if
+    ///    either is, and the same for the `other` filter;
+    ///  - `a` and `not b` if `a` implies `b`;
+    ///  - comparisons of the same field which don't share any value, for
+    ///    `==`, `in`, ranges of integers and networks, and `==` and `in` of
+    ///    strings that don't have a `contains` needle, like
+    ///    `port < 80` and `port in { 80..100 }`.
+    ///
+    /// `any(...)` and `all(...)` comparisons are never disjoint, as they can
+    /// be satisfied by different elements. Filters over different schemes are
+    /// never disjoint either.
+    pub fn is_disjoint_with(&self, other: &FilterAst<'s>) -> bool {
+        self.scheme == other.scheme && subset::is_disjoint(&self.op, &other.op)
+    }
+
+    /// Returns the filter with items of `in` lists that are covered by other
+    /// items of the same list removed, like `10.1.0.0/16` in
+    /// `{ 10.0.0.0/8 10.1.0.0/16 }`.
+    ///
+    /// This applies to lists of addresses, networks and integers, where items
+    /// are kept in their original order, and doesn't change what the filter
+    /// matches. Overlapping items that don't cover each other are left as is.
+    pub fn simplify_ranges(mut self) -> Self {
+        self.op.simplify_ranges();
+        self.span = None;
+        self
+    }
+
+    /// Returns an equivalent filter that's cheaper to execute, e.g. for
+    /// machine-generated filters with a lot of redundancy.
+    ///
+    /// This:
+    ///  - flattens nested `and`, `or` and `xor` chains and drops redundant
+    ///    parentheses and double `not`s;
+    ///  - removes duplicate items of `and` and `or`, like in `a || a`;
+    ///  - folds comparisons that never match, like ones of fields missing
+    ///    from the scheme or `x in {}`, and their negations, so
+    ///    `a || not x in {}` becomes just `not x in {}`;
+    ///  - reorders items of `and` and `or` by their cost, so that cheap
+    ///    comparisons run before regexes.
+    ///
+    /// The result always matches the same values, but
+    /// [`execute_traced`](FilterAst::execute_traced) and
+    /// [`execute_matching_leaves`](FilterAst::execute_matching_leaves) may
+    /// report comparisons in a different order.
+    pub fn optimize(mut self) -> Self {
+        self.op = self.op.optimize();
+        self.span = None;
+        self
+    }
+
+    /// Executes the filter against a provided context with values, recording
+    /// how each comparison evaluated, for debugging why a filter did or
+    /// didn't match.
+    ///
+    /// This is much slower than [`Filter::execute`], as every comparison is
+    /// compiled on the fly and none of them are skipped, so it's meant to be
+    /// used only after the fast path gave a surprising result. Comparisons
+    /// that [`Filter::execute`] would have skipped are still marked as such
+    /// in the trace.
+    pub fn execute_traced<'a, 'e>(
+        &'a self,
+        ctx: &ExecutionContext<'e>,
+    ) -> Result<(bool, Trace<'a, 's, 'e>), SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        let mut entries = Vec::new();
+        let result = self.op.execute_traced(ctx, true, &mut entries);
+        Ok((result, Trace { entries }))
+    }
+
+    /// Executes the filter against a provided context with values and
+    /// returns the result together with the comparisons that made it match.
+    ///
+    /// Execution short-circuits like [`Filter::execute`], and a comparison
+    /// contributes only if it matched and the result of every expression
+    /// around it was needed for the filter to match. That is, all items of
+    /// a matching `and`, the first matching item of an `or`, and matching
+    /// items of a matching `xor`. Skipped branches, branches that ended up
+    /// not matching and anything under `not` don't contribute, so the list
+    /// is always empty if the filter doesn't match, but it can be empty for
+    /// a match too, like for `not ssl`.
+    pub fn execute_matching_leaves<'a>(
+        &'a self,
+        ctx: &ExecutionContext<'_>,
+    ) -> Result<(bool, Vec<&'a FieldExpr<'s>>), SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        let mut leaves = Vec::new();
+        let result = self.op.execute_matching(ctx, &mut leaves);
+        Ok((result, leaves))
+    }
+
+    /// Executes the filter against a provided context with values in
+    /// three-valued logic, where comparisons of fields that are not set are
+    /// [`Unknown`](Ternary::Unknown) instead of `false`.
+    ///
+    /// Like [`execute_traced`](FilterAst::execute_traced), this compiles
+    /// comparisons on the fly, so it's meant for stricter checks rather than
+    /// the hot path.
+    pub fn execute_ternary(
+        &self,
+        ctx: &ExecutionContext<'_>,
+    ) -> Result<Ternary, SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        Ok(self.op.execute_ternary(ctx))
+    }
+
+    /// Substitutes values that are set in the context and returns what's left
+    /// of the filter, e.g. to check connection-level fields once and only
+    /// execute the rest for each request.
+    ///
+    /// Like for [`execute_ternary`](FilterAst::execute_ternary), comparisons
+    /// are known only if the values they depend on are set, and the rest of
+    /// them are kept as is. If the whole result is known, it's returned
+    /// instead of a filter.
+    pub fn partially_evaluate(
+        &self,
+        ctx: &ExecutionContext<'_>,
+    ) -> Result<Residual<'s>, SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        Ok(match residual::evaluate_combined(&self.op, ctx) {
+            Ok(value) => Residual::Known(value),
+            Err(op) => Residual::Filter(FilterAst {
+                scheme: self.scheme,
+                // Parentheses around the rest of a chain are not needed
+                // anymore once it's the whole filter.
+                op: match op {
+                    CombinedExpr::Simple(SimpleExpr::Parenthesized(op)) => *op,
+                    op => op,
+                },
+                warnings: self.warnings.clone(),
+                comments: self.comments.clone(),
+                span: None,
+            }),
+        })
+    }
+
+    /// Compiles a [`FilterAst`] into a [`Program`], which executes the same
+    /// way as [`Filter`], but without nested closure calls for `and`, `or`,
+    /// `xor` and `not`.
+    pub fn compile_program(self) -> Program<'s> {
+        let mut instructions = Vec::new();
+        self.op.lower(&mut instructions);
+        Program::new(instructions, self.scheme)
+    }
+
+    /// Compiles the filter into a classic BPF program for Ethernet frames,
+    /// which accepts the same packets as the filter does, e.g. to drop them
+    /// in the kernel before they get to userspace.
+    ///
+    /// Only comparisons of fields read from IPv4, TCP and UDP headers are
+    /// supported: `ip.src` and `ip.dst` of type `Ip`, and `ip.proto`,
+    /// `tcp.srcport`, `tcp.dstport`, `udp.srcport`, `udp.dstport` and
+    /// `tcp.flags` of type `Int`. Like unset fields, ports and flags don't
+    /// match in packets without them, such as other than first fragments.
+    /// Everything that can't be compiled is listed in the error.
+    pub fn compile_bpf(&self) -> Result<Vec<BpfInstruction>, BpfCompileError> {
+        bpf::compile(self)
+    }
+
+    /// Compiles the filter with counters of evaluations, matches,
+    /// short-circuits and time spent in each of its nodes.
+    ///
+    /// Each node is timed separately, and optimizations that merge
+    /// comparisons, like of several `contains` of the same field, are not
+    /// applied, so it's slower than [`compile`](FilterAst::compile) and
+    /// meant for sampling rather than for every execution.
+    pub fn compile_profiled(self) -> ProfiledFilter<'s> {
+        let mut profiler = Profiler::new();
+        let root_expr = self.op.compile_profiled(&mut profiler);
+        profiler.finish(root_expr, self.scheme)
+    }
+
     /// Compiles a [`FilterAst`] into a [`Filter`].
     pub fn compile(self) -> Filter<'s> {
-        Filter::new(self.op.compile(), self.scheme)
+        let cost = self.op.cost();
+
+        let mut fields = Vec::new();
+        let mut field_types = Vec::new();
+        let mut references = Vec::new();
+        for comparison in self.comparisons() {
+            for field in comparison.fields() {
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+                let ty = field.get_type();
+                if !field_types.contains(&ty) {
+                    field_types.push(ty);
+                }
+            }
+            if let Some(name) = comparison.reference() {
+                if !references.iter().any(|reference| reference == name) {
+                    references.push(name.to_owned());
+                }
+            }
+        }
+
+        Filter::new(
+            self.op.compile(),
+            self.scheme,
+            cost,
+            fields,
+            field_types,
+            references,
+        )
     }
 }
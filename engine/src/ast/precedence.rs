@@ -0,0 +1,175 @@
+/// Associativity of an operator in the filter syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    /// `a op b op c` is grouped as `(a op b) op c`.
+    Left,
+    /// `op op a` is grouped as `op (op a)`, used by prefix operators.
+    Right,
+    /// The operator can't be chained without explicit parentheses.
+    None,
+}
+
+/// Precedence and associativity of an operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatorPrecedence {
+    /// All the spellings recognised by the parser for this operator.
+    pub spellings: &'static [&'static str],
+    /// Binding strength of the operator - higher values bind tighter.
+    pub precedence: u8,
+    /// Grouping of consecutive operators with the same precedence.
+    pub associativity: Associativity,
+}
+
+pub(crate) const OR_PRECEDENCE: u8 = 1;
+pub(crate) const XOR_PRECEDENCE: u8 = 2;
+pub(crate) const AND_PRECEDENCE: u8 = 3;
+pub(crate) const NOT_PRECEDENCE: u8 = 4;
+pub(crate) const COMPARISON_PRECEDENCE: u8 = 5;
+pub(crate) const INT_OP_PRECEDENCE: u8 = 6;
+
+/// Precedence table of all boolean, comparison and integer operators, from
+/// the loosest to the tightest binding.
+///
+/// This is the same table the parser uses, so it can be relied upon by
+/// alternative frontends to produce equivalent groupings.
+pub const PRECEDENCE_TABLE: &[OperatorPrecedence] = &[
+    OperatorPrecedence {
+        spellings: &["or", "||"],
+        precedence: OR_PRECEDENCE,
+        associativity: Associativity::Left,
+    },
+    OperatorPrecedence {
+        spellings: &["xor", "^^"],
+        precedence: XOR_PRECEDENCE,
+        associativity: Associativity::Left,
+    },
+    OperatorPrecedence {
+        spellings: &["and", "&&"],
+        precedence: AND_PRECEDENCE,
+        associativity: Associativity::Left,
+    },
+    OperatorPrecedence {
+        spellings: &["not", "!"],
+        precedence: NOT_PRECEDENCE,
+        associativity: Associativity::Right,
+    },
+    OperatorPrecedence {
+        spellings: &[
+            "eq",
+            "==",
+            "ne",
+            "!=",
+            "<>",
+            "ge",
+            ">=",
+            "le",
+            "<=",
+            "gt",
+            ">",
+            "lt",
+            "<",
+            "in",
+            "between",
+            "not between",
+            "contains",
+            "~",
+            "matches",
+            "!~",
+            "wildcard",
+            "like",
+        ],
+        precedence: COMPARISON_PRECEDENCE,
+        associativity: Associativity::None,
+    },
+    // `port & 1 == 0` applies `&` before comparing its result.
+    OperatorPrecedence {
+        spellings: &["&", "bitwise_and", ">>", "<<"],
+        precedence: INT_OP_PRECEDENCE,
+        associativity: Associativity::None,
+    },
+];
+
+fn find(op: &str) -> Option<&'static OperatorPrecedence> {
+    PRECEDENCE_TABLE
+        .iter()
+        .find(|entry| entry.spellings.contains(&op))
+}
+
+/// Returns precedence of an operator given any of its spellings.
+pub fn precedence(op: &str) -> Option<u8> {
+    find(op).map(|entry| entry.precedence)
+}
+
+/// Returns associativity of an operator given any of its spellings.
+pub fn associativity(op: &str) -> Option<Associativity> {
+    find(op).map(|entry| entry.associativity)
+}
+
+#[test]
+fn test_precedence() {
+    assert!(precedence("and") > precedence("or"));
+    assert!(precedence("&&") > precedence("||"));
+    assert!(precedence("==") > precedence("and"));
+    assert!(precedence("==") > precedence("or"));
+    assert!(precedence("matches") > precedence("not"));
+    assert_eq!(precedence("=>"), None);
+    assert!(precedence("&") > precedence("=="));
+    assert_eq!(precedence("<<"), precedence("bitwise_and"));
+
+    assert_eq!(associativity("or"), Some(Associativity::Left));
+    assert_eq!(associativity("!"), Some(Associativity::Right));
+    assert_eq!(associativity("in"), Some(Associativity::None));
+    assert_eq!(precedence("not between"), precedence("between"));
+    assert!(precedence("between") > precedence("not"));
+}
+
+#[test]
+fn test_spellings_match_lexer() {
+    use super::{
+        combined_expr::CombiningOp,
+        field_expr::{ComparisonOp, IntOp},
+        simple_expr::UnaryOp,
+    };
+
+    fn spellings(precedence: u8) -> Vec<&'static str> {
+        let mut spellings: Vec<_> = PRECEDENCE_TABLE
+            .iter()
+            .filter(|entry| entry.precedence == precedence)
+            .flat_map(|entry| entry.spellings.iter().cloned())
+            .collect();
+        spellings.sort();
+        spellings
+    }
+
+    fn sorted(mut spellings: Vec<&'static str>) -> Vec<&'static str> {
+        spellings.sort();
+        spellings
+    }
+
+    let int_ops = IntOp::spellings();
+
+    // Negated forms are lexed separately from `ComparisonOp`.
+    let mut comparisons = vec!["!~", "not between"];
+    comparisons.extend(
+        ComparisonOp::spellings()
+            .into_iter()
+            .filter(|spelling| !int_ops.contains(spelling)),
+    );
+
+    let mut combining = CombiningOp::spellings();
+    combining.extend(UnaryOp::spellings());
+
+    assert_eq!(spellings(COMPARISON_PRECEDENCE), sorted(comparisons));
+    assert_eq!(spellings(INT_OP_PRECEDENCE), sorted(int_ops));
+    assert_eq!(
+        PRECEDENCE_TABLE
+            .iter()
+            .filter(|entry| entry.precedence <= NOT_PRECEDENCE)
+            .flat_map(|entry| entry.spellings.iter().cloned())
+            .count(),
+        combining.len()
+    );
+    for spelling in combining {
+        assert!(precedence(spelling) <= Some(NOT_PRECEDENCE), "{}", spelling);
+    }
+}
@@ -0,0 +1,301 @@
+use super::{
+    field_expr::{span_text, FieldExpr},
+    Expr,
+};
+use execution_context::ExecutionContext;
+use filter::{CompiledExpr, SchemeMismatchError};
+use scheme::Scheme;
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Counters of a single node of a [`ProfiledFilter`].
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Counters {
+    evaluations: u64,
+    matches: u64,
+    short_circuits: u64,
+    time: Duration,
+}
+
+/// A node of the filter being compiled by
+/// [`FilterAst::compile_profiled`](::FilterAst::compile_profiled), in the
+/// order the nodes appear in the filter, with parents before their children.
+pub(crate) struct ProfiledNode {
+    label: String,
+    depth: usize,
+    span: Option<(usize, usize)>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+/// Collects nodes of the filter while it's being compiled.
+pub(crate) struct Profiler {
+    nodes: Vec<ProfiledNode>,
+    depth: usize,
+}
+
+/// Shared counters of a node, updated by its compiled closure.
+#[derive(Clone)]
+pub(crate) struct NodeCounters(Arc<Mutex<Counters>>);
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Profiler {
+            nodes: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Adds a node and compiles its children one level deeper.
+    pub(crate) fn node<T>(
+        &mut self,
+        label: String,
+        span: Option<(usize, usize)>,
+        children: impl FnOnce(&mut Self) -> T,
+    ) -> (NodeCounters, T) {
+        let counters = Arc::new(Mutex::new(Counters::default()));
+        self.nodes.push(ProfiledNode {
+            label,
+            depth: self.depth,
+            span,
+            counters: counters.clone(),
+        });
+
+        self.depth += 1;
+        let children = children(self);
+        self.depth -= 1;
+
+        (NodeCounters(counters), children)
+    }
+
+    /// Adds a node without children and wraps its compiled expression.
+    pub(crate) fn leaf<'s>(
+        &mut self,
+        label: String,
+        span: Option<(usize, usize)>,
+        expr: CompiledExpr<'s>,
+    ) -> CompiledExpr<'s> {
+        let (counters, ()) = self.node(label, span, |_| ());
+        CompiledExpr::new(move |ctx| counters.time(|| (expr.execute(ctx), false)))
+    }
+
+    pub(crate) fn finish<'s>(
+        self,
+        root_expr: CompiledExpr<'s>,
+        scheme: &'s Scheme,
+    ) -> ProfiledFilter<'s> {
+        ProfiledFilter {
+            root_expr,
+            scheme,
+            nodes: self.nodes,
+        }
+    }
+}
+
+impl NodeCounters {
+    /// Evaluates the node, which returns its result and whether it was
+    /// decided before all of its children were evaluated.
+    pub(crate) fn time(&self, evaluate: impl FnOnce() -> (bool, bool)) -> bool {
+        let start = Instant::now();
+        let (result, short_circuited) = evaluate();
+        let elapsed = start.elapsed();
+
+        let mut counters = self.0.lock().unwrap();
+        counters.evaluations += 1;
+        counters.matches += u64::from(result);
+        counters.short_circuits += u64::from(short_circuited);
+        counters.time += elapsed;
+        result
+    }
+}
+
+/// Returns the span from the first comparison of a node to the last one.
+pub(super) fn comparisons_span<'s>(expr: &impl Expr<'s>) -> Option<(usize, usize)> {
+    let mut comparisons: Vec<&FieldExpr<'s>> = Vec::new();
+    expr.collect_comparisons(&mut comparisons);
+    let (start, _) = comparisons.first()?.span()?;
+    let (_, end) = comparisons.last()?.span()?;
+    Some((start, end))
+}
+
+/// A filter compiled with
+/// [`FilterAst::compile_profiled`](::FilterAst::compile_profiled), which
+/// records how often each node of the filter is evaluated and how long it
+/// takes, e.g. to reorder comparisons based on real traffic or to find the
+/// ones that take most of the time.
+///
+/// Counters accumulate across executions, including concurrent ones, until
+/// they are [reset](ProfiledFilter::reset).
+pub struct ProfiledFilter<'s> {
+    root_expr: CompiledExpr<'s>,
+    scheme: &'s Scheme,
+    nodes: Vec<ProfiledNode>,
+}
+
+impl<'s> ProfiledFilter<'s> {
+    /// Executes the filter against a provided context with values, updating
+    /// the counters of every node it evaluates.
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> Result<bool, SchemeMismatchError> {
+        if self.scheme == ctx.scheme() {
+            Ok(self.root_expr.execute(ctx))
+        } else {
+            Err(SchemeMismatchError)
+        }
+    }
+
+    /// Returns the counters of all nodes accumulated so far, in the order the
+    /// nodes appear in the filter, with each `and`, `or`, `xor` and `not`
+    /// before its operands.
+    pub fn profile(&self) -> Vec<NodeProfile> {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let counters = *node.counters.lock().unwrap();
+                NodeProfile {
+                    label: node.label.clone(),
+                    depth: node.depth,
+                    evaluations: counters.evaluations,
+                    matches: counters.matches,
+                    short_circuits: counters.short_circuits,
+                    time: counters.time,
+                    span: node.span,
+                }
+            })
+            .collect()
+    }
+
+    /// Resets the counters of all nodes.
+    pub fn reset(&self) {
+        for node in &self.nodes {
+            *node.counters.lock().unwrap() = Counters::default();
+        }
+    }
+}
+
+/// How a single node of a [`ProfiledFilter`] performed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeProfile {
+    /// `and`, `or`, `xor` or `not` for combinations of other nodes, and the
+    /// comparison itself otherwise, like `tcp.port == 80`.
+    pub label: String,
+    /// The number of `and`, `or`, `xor` and `not` around the node.
+    pub depth: usize,
+    /// How many times the node was evaluated.
+    pub evaluations: u64,
+    /// How many of the evaluations matched.
+    pub matches: u64,
+    /// How many evaluations of an `and` or an `or` were decided before its
+    /// last operand.
+    pub short_circuits: u64,
+    /// The total wall time spent evaluating the node, including its operands.
+    pub time: Duration,
+    span: Option<(usize, usize)>,
+}
+
+impl NodeProfile {
+    /// Returns the part of `input` the node was parsed from, which for
+    /// combinations of other nodes spans from their first comparison to the
+    /// last one.
+    ///
+    /// `input` must be the same string that was passed to
+    /// [`Scheme::parse`](::Scheme::parse). Returns `None` for filters created
+    /// with a [`FilterBuilder`](::FilterBuilder) or if the input doesn't
+    /// match.
+    pub fn source_text<'i>(&self, input: &'i str) -> Option<&'i str> {
+        span_text(self.span?, input)
+    }
+
+    /// Returns the byte range of [`source_text`](NodeProfile::source_text)
+    /// within `input`, e.g. to highlight the node.
+    pub fn source_range(&self, input: &str) -> Option<Range<usize>> {
+        let text = self.source_text(input)?;
+        let start = text.as_ptr() as usize - input.as_ptr() as usize;
+        Some(start..start + text.len())
+    }
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_profile() {
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ssl: Bool,
+        tcp.port: Int,
+    };
+
+    let input = r#" tcp.port == 22 || (http.host matches "^a" && not ssl) ^^ ssl"#;
+    let filter = scheme.parse(input).unwrap().compile_profiled();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("ssl", true).unwrap();
+
+    for &port in &[22, 22, 80] {
+        ctx.set_field_value("tcp.port", port).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(true));
+    }
+
+    let profile = filter.profile();
+    let summary = profile
+        .iter()
+        .map(|node| {
+            (
+                node.label.as_str(),
+                node.depth,
+                node.evaluations,
+                node.matches,
+                node.short_circuits,
+                node.source_text(input),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        &summary[..],
+        &[
+            ("or", 0, 3, 3, 2, Some(input.trim())),
+            ("tcp.port == 22", 1, 3, 2, 0, Some("tcp.port == 22")),
+            (
+                "xor",
+                1,
+                1,
+                1,
+                0,
+                Some(r#"http.host matches "^a" && not ssl) ^^ ssl"#)
+            ),
+            (
+                "and",
+                2,
+                1,
+                0,
+                1,
+                Some(r#"http.host matches "^a" && not ssl"#)
+            ),
+            (
+                r#"http.host matches "^a""#,
+                3,
+                1,
+                0,
+                0,
+                Some(r#"http.host matches "^a""#)
+            ),
+            ("not", 3, 0, 0, 0, Some("ssl")),
+            ("ssl", 4, 0, 0, 0, Some("ssl")),
+            ("ssl", 2, 1, 1, 0, Some("ssl")),
+        ][..]
+    );
+
+    assert!(profile[0].time >= profile[1].time);
+    assert_eq!(profile[1].source_range(input), Some(1..15));
+
+    filter.reset();
+    assert!(filter.profile().iter().all(|node| node.evaluations == 0));
+
+    let other_scheme = Scheme! { tcp.port: Int };
+    assert_eq!(
+        filter.execute(&ExecutionContext::new(&other_scheme)),
+        Err(SchemeMismatchError)
+    );
+}
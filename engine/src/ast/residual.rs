@@ -0,0 +1,147 @@
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    simple_expr::{SimpleExpr, UnaryOp},
+    ternary::Ternary,
+    Expr, FilterAst,
+};
+use execution_context::ExecutionContext;
+
+/// A result of [`FilterAst::partially_evaluate`].
+// The result is returned once per call rather than stored in bulk, so the
+// filter isn't worth boxing.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Residual<'s> {
+    /// The result doesn't depend on the values that are not set.
+    Known(bool),
+    /// The rest of the filter, which still has to be executed once the
+    /// remaining values are known.
+    Filter(FilterAst<'s>),
+}
+
+// Returns either the result of an expression, or what's left of it after
+// substituting the known values.
+pub(crate) fn evaluate_combined<'s>(
+    expr: &CombinedExpr<'s>,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, CombinedExpr<'s>> {
+    let (op, items) = match expr {
+        CombinedExpr::Simple(expr) => {
+            return evaluate_simple(expr, ctx).map_err(CombinedExpr::Simple);
+        }
+        CombinedExpr::Combining { op, items } => (*op, items),
+    };
+
+    let mut rest = Vec::new();
+    // The folded result of known `xor` items.
+    let mut parity = false;
+
+    for item in items {
+        match (op, evaluate_combined(item, ctx)) {
+            (CombiningOp::And, Ok(false)) => return Ok(false),
+            (CombiningOp::Or, Ok(true)) => return Ok(true),
+            (CombiningOp::Xor, Ok(value)) => parity ^= value,
+            (_, Ok(_)) => {}
+            (_, Err(item)) => rest.push(item),
+        }
+    }
+
+    let rest = match rest.len() {
+        0 => {
+            return Ok(match op {
+                CombiningOp::And => true,
+                CombiningOp::Or => false,
+                CombiningOp::Xor => parity,
+            });
+        }
+        1 => rest.pop().unwrap(),
+        _ => CombinedExpr::Combining { op, items: rest },
+    };
+
+    // `a ^ true` is `not a`.
+    Err(if parity { rest.negate() } else { rest })
+}
+
+fn evaluate_simple<'s>(
+    expr: &SimpleExpr<'s>,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, SimpleExpr<'s>> {
+    match expr {
+        SimpleExpr::Field(op) => match op.execute_ternary(ctx) {
+            Ternary::True => Ok(true),
+            Ternary::False => Ok(false),
+            Ternary::Unknown => Err(SimpleExpr::Field(op.clone())),
+        },
+        SimpleExpr::Parenthesized(op) => evaluate_combined(op, ctx).map_err(|op| match op {
+            CombinedExpr::Simple(op) => op,
+            op => SimpleExpr::Parenthesized(Box::new(op)),
+        }),
+        SimpleExpr::Unary {
+            op: UnaryOp::Not,
+            arg,
+        } => evaluate_simple(arg, ctx)
+            .map(|value| !value)
+            .map_err(|arg| SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg: Box::new(arg),
+            }),
+        SimpleExpr::Missing { .. } => Ok(false),
+    }
+}
+
+#[test]
+fn test_partially_evaluate() {
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    ctx.set_field_value("ssl", true).unwrap();
+
+    let evaluate = |filter: &str| {
+        scheme
+            .parse(filter)
+            .unwrap()
+            .partially_evaluate(&ctx)
+            .unwrap()
+    };
+    let residual = |filter: &str| Residual::Filter(scheme.parse(filter).unwrap());
+
+    assert_eq!(evaluate("tcp.port == 443 && ssl"), Residual::Known(true));
+    assert_eq!(evaluate("tcp.port == 80 && ssl"), Residual::Known(false));
+    assert_eq!(
+        evaluate(r#"http.host == "a" || not ssl"#),
+        residual(r#"http.host == "a""#)
+    );
+    assert_eq!(
+        evaluate(r#"tcp.port == 443 && (http.host == "a" || ip.src == 10.0.0.1)"#),
+        residual(r#"http.host == "a" || ip.src == 10.0.0.1"#)
+    );
+    assert_eq!(
+        evaluate(r#"(ssl && http.host == "a") || ip.src == 10.0.0.1"#),
+        residual(r#"http.host == "a" || ip.src == 10.0.0.1"#)
+    );
+    assert_eq!(
+        evaluate(r#"not (tcp.port == 443 && http.host == "a")"#),
+        residual(r#"not http.host == "a""#)
+    );
+    assert_eq!(
+        evaluate(r#"ssl ^^ http.host == "a" ^^ ip.src == 10.0.0.1"#),
+        residual(r#"not http.host == "a" ^^ ip.src == 10.0.0.1"#)
+    );
+    assert_eq!(
+        evaluate(r#"http.host == "a""#),
+        residual(r#"http.host == "a""#)
+    );
+
+    let other_scheme = Scheme! { tcp.port: Int };
+    assert!(other_scheme
+        .parse("tcp.port == 443")
+        .unwrap()
+        .partially_evaluate(&ctx)
+        .is_err());
+}
@@ -1,7 +1,19 @@
-use super::{combined_expr::CombinedExpr, field_expr::FieldExpr, CompiledExpr, Expr};
-use lex::{expect, skip_space, Lex, LexResult, LexWith};
-use scheme::{Field, Scheme};
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{BytesOp, ComparisonOp, ContainsQuantifier, FieldExpr, IntOp, OrderingOp},
+    profile::{comparisons_span, Profiler},
+    ternary::Ternary,
+    trace::TraceEntry,
+    write_tree_line, CompiledExpr, Expr,
+};
+use execution_context::ExecutionContext;
+use lex::{expect, peek, skip_space, span, Lex, LexErrorKind, LexResult, LexWith};
+use parser::{Parser, UnknownFieldPolicy};
+use program::Instruction;
+use scheme::Field;
 use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+use tokens::{lex_token, Token};
 
 lex_enum!(UnaryOp {
     "not" | "!" => Not,
@@ -16,19 +28,286 @@ pub enum SimpleExpr<'s> {
         op: UnaryOp,
         arg: Box<SimpleExpr<'s>>,
     },
+    // A comparison of a field that's not in the scheme, which never matches.
+    // Its source is kept so that it can be parsed again by a scheme that
+    // has the field, with a negated operator as the positive one in `not`.
+    Missing {
+        field: String,
+        source: String,
+    },
+}
+
+impl<'s> Display for SimpleExpr<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SimpleExpr::Field(op) => op.fmt(f),
+            SimpleExpr::Parenthesized(op) => write!(f, "({})", op),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => write!(f, "not {}", arg),
+            SimpleExpr::Missing { source, .. } => f.write_str(source),
+        }
+    }
+}
+
+impl<'s> SimpleExpr<'s> {
+    /// Returns the logical negation, removing a double `not` if possible.
+    ///
+    /// Comparisons are wrapped into `not` rather than inverted, as `x != 1`
+    /// doesn't match if `x` isn't set, unlike `not x == 1`.
+    pub(crate) fn negate(self) -> Self {
+        match self {
+            SimpleExpr::Parenthesized(op) => SimpleExpr::Parenthesized(Box::new(op.negate())),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => *arg,
+            expr => SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg: Box::new(expr),
+            },
+        }
+    }
+
+    /// Returns an equivalent expression that's cheaper to execute, see
+    /// [`CombinedExpr::optimize`].
+    ///
+    /// Parentheses are dropped around anything but chains, and double
+    /// `not`s cancel out.
+    pub(crate) fn optimize(self) -> Self {
+        match self {
+            SimpleExpr::Parenthesized(op) => match op.optimize() {
+                CombinedExpr::Simple(op) => op,
+                op => SimpleExpr::Parenthesized(Box::new(op)),
+            },
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => match arg.optimize() {
+                SimpleExpr::Unary {
+                    op: UnaryOp::Not,
+                    arg,
+                } => *arg,
+                arg => SimpleExpr::Unary {
+                    op: UnaryOp::Not,
+                    arg: Box::new(arg),
+                },
+            },
+            expr => expr,
+        }
+    }
+
+    /// Returns the result of an expression that doesn't depend on the
+    /// values, if it's known without executing it.
+    pub(crate) fn constant(&self) -> Option<bool> {
+        match self {
+            SimpleExpr::Field(op) if op.never_matches() => Some(false),
+            SimpleExpr::Field(_) => None,
+            SimpleExpr::Parenthesized(op) => op.constant(),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => arg.constant().map(|value| !value),
+            SimpleExpr::Missing { .. } => Some(false),
+        }
+    }
+}
+
+fn skip_until<'i>(mut input: &'i str, end: &'static str) -> LexResult<'i, ()> {
+    loop {
+        input = skip_space(input);
+
+        if let Ok(rest) = expect(input, end) {
+            return Ok(((), rest));
+        }
+
+        if input.is_empty() {
+            return Err((LexErrorKind::ExpectedLiteral(end), input));
+        }
+
+        input = lex_token(input)?.1;
+    }
+}
+
+fn skip_value(input: &str) -> LexResult<'_, ()> {
+    let input = skip_space(input);
+    let (token, rest) = lex_token(input)?;
+
+    match token {
+        Token::Punct("{") => skip_until(rest, "}"),
+        // Intervals like `[1, 10)` can end with either kind of bracket.
+        Token::Punct("[") | Token::Punct("(") => {
+            let mut rest = rest;
+            loop {
+                match lex_token(skip_space(rest))? {
+                    (Token::Punct(")"), rest) | (Token::Punct("]"), rest) => return Ok(((), rest)),
+                    (_, next) => rest = next,
+                }
+            }
+        }
+        Token::Punct("$") => Ok(((), lex_token(rest)?.1)),
+        Token::Ident(_) if skip_space(rest).starts_with('(') => {
+            skip_until(expect(skip_space(rest), "(")?, ")")
+        }
+        _ => Ok(((), rest)),
+    }
+}
+
+// Skips subscripts after the name of a field that's not in the scheme, like
+// `["host"]`, `[0]` or `["cookie"][*]`.
+fn skip_subscripts(mut input: &str) -> LexResult<'_, ()> {
+    while let Ok(rest) = expect(skip_space(input), "[") {
+        let rest = skip_space(rest);
+        let rest = match expect(rest, "*") {
+            Ok(rest) => rest,
+            Err(_) => lex_token(rest)?.1,
+        };
+        input = expect(skip_space(rest), "]")?;
+    }
+    Ok(((), input))
+}
+
+// Skips integer operators like `& 1` before a comparison, and the comparison
+// itself if there's one, like `== 0`.
+fn skip_int_ops(mut input: &str) -> LexResult<'_, ()> {
+    loop {
+        input = skip_value(input)?.1;
+        let rest = skip_space(input);
+
+        if rest.starts_with("&&") {
+            return Ok(((), input));
+        }
+        if let Ok((_, rest)) = IntOp::lex(rest) {
+            input = rest;
+            continue;
+        }
+        return match OrderingOp::lex(rest) {
+            Ok((_, rest)) => skip_value(rest),
+            Err(_) => Ok(((), input)),
+        };
+    }
+}
+
+// Skips the rest of a comparison of a field that's not in the scheme. Its
+// type is unknown, so the right-hand side is only checked to be made of valid
+// tokens, with brackets and `between` ranges taken as a whole.
+fn skip_missing_comparison(input: &str, allow_negation: bool) -> LexResult<'_, ()> {
+    let rest = skip_space(input);
+
+    // `&&` would be lexed as a bitwise `&` otherwise.
+    if rest.starts_with("&&") {
+        return Ok(((), input));
+    }
+
+    let rest = match FieldExpr::lex_negated_op(rest) {
+        Some((_, rest)) if allow_negation => rest,
+        _ => match ComparisonOp::lex(rest) {
+            // `contains any { ... }` and `contains all { ... }`
+            Ok((ComparisonOp::Bytes(BytesOp::Contains), rest)) => {
+                match ContainsQuantifier::lex(skip_space(rest)) {
+                    Ok((_, rest)) => FieldExpr::skip_of(rest),
+                    Err(_) => rest,
+                }
+            }
+            Ok((ComparisonOp::Int(_), rest)) => return skip_int_ops(rest),
+            Ok((_, rest)) => rest,
+            // Nothing that looks like an operator, so it's a boolean check.
+            Err(_) => return Ok(((), input)),
+        },
+    };
+
+    let ((), rest) = skip_value(rest)?;
+
+    let rest = match expect(skip_space(rest), "..") {
+        Ok(rest) => skip_value(rest)?.1,
+        Err(_) => rest,
+    };
+
+    Ok(((), FieldExpr::lex_nocase(rest).unwrap_or(rest)))
+}
+
+// Lexes a comparison of `field`, which is not in the scheme, or returns
+// `None` if it's not the compared field, like an argument of a function.
+fn lex_missing<'i, 's>(input: &'i str, field: &str) -> Option<LexResult<'i, SimpleExpr<'s>>> {
+    let (quantified, rest) = match FieldExpr::lex_quantifier(input) {
+        Some((_, rest)) => (true, skip_space(rest)),
+        None => (false, input),
+    };
+
+    if rest.as_ptr() != field.as_ptr() {
+        return None;
+    }
+
+    Some(lex_missing_comparison(
+        input,
+        field,
+        &rest[field.len()..],
+        quantified,
+    ))
+}
+
+fn lex_missing_comparison<'i, 's>(
+    input: &'i str,
+    field: &str,
+    rest: &'i str,
+    quantified: bool,
+) -> LexResult<'i, SimpleExpr<'s>> {
+    let ((), lhs_end) = skip_subscripts(rest)?;
+    let ((), rest) = skip_missing_comparison(lhs_end, !quantified)?;
+    let rest = if quantified {
+        expect(skip_space(rest), ")")?
+    } else {
+        rest
+    };
+
+    let missing = |source| SimpleExpr::Missing {
+        field: field.to_owned(),
+        source,
+    };
+
+    // Negated operators are kept as `not` with the positive one, the same as
+    // for known fields.
+    Ok(match FieldExpr::lex_negated_op(skip_space(lhs_end)) {
+        Some((op, rhs)) if !quantified => {
+            let source = format!(
+                "{} {} {}",
+                span(input, lhs_end),
+                op,
+                span(skip_space(rhs), rest)
+            );
+            (missing(source).negate(), rest)
+        }
+        _ => (missing(span(input, rest).to_owned()), rest),
+    })
 }
 
-impl<'i, 's> LexWith<'i, &'s Scheme> for SimpleExpr<'s> {
-    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+impl<'i, 'p, 's> LexWith<'i, &'p Parser<'s>> for SimpleExpr<'s> {
+    fn lex_with(input: &'i str, parser: &'p Parser<'s>) -> LexResult<'i, Self> {
+        let initial_input = input;
+
+        // Complete comments are skipped as space before getting here.
+        if peek(input, "/*") {
+            return Err((LexErrorKind::UnterminatedComment, input));
+        }
+
         Ok(if let Ok(input) = expect(input, "(") {
-            let input = skip_space(input);
-            let (op, input) = CombinedExpr::lex_with(input, scheme)?;
+            parser
+                .enter()
+                .map_err(|err| (err, span(initial_input, input)))?;
+            let res = CombinedExpr::lex_with(skip_space(input), parser);
+            parser.exit();
+            let (op, input) = res?;
             let input = skip_space(input);
             let input = expect(input, ")")?;
             (SimpleExpr::Parenthesized(Box::new(op)), input)
         } else if let Ok((op, input)) = UnaryOp::lex(input) {
-            let input = skip_space(input);
-            let (arg, input) = SimpleExpr::lex_with(input, scheme)?;
+            parser
+                .enter()
+                .map_err(|err| (err, span(initial_input, input)))?;
+            let res = SimpleExpr::lex_with(skip_space(input), parser);
+            parser.exit();
+            let (arg, input) = res?;
             (
                 SimpleExpr::Unary {
                     op,
@@ -36,9 +315,51 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for SimpleExpr<'s> {
                 },
                 input,
             )
+        } else if let Some(res) = FieldExpr::lex_contains_list(input, parser) {
+            // `field contains any { "a" "b" }` is a shorthand for
+            // `(field contains "a" or field contains "b")`, and `all` for the
+            // same with `and`. With no items, `any` never matches and `all`
+            // always does.
+            let ((quantifier, items), input) = res?;
+
+            let op = match quantifier {
+                ContainsQuantifier::Any => CombiningOp::Or,
+                ContainsQuantifier::All => CombiningOp::And,
+            };
+
+            let items = items
+                .into_iter()
+                .map(|item| CombinedExpr::Simple(SimpleExpr::Field(item)))
+                .collect();
+
+            (
+                SimpleExpr::Parenthesized(Box::new(CombinedExpr::Combining { op, items })),
+                input,
+            )
         } else {
-            let (op, input) = FieldExpr::lex_with(input, scheme)?;
-            (SimpleExpr::Field(op), input)
+            let res = FieldExpr::lex_negatable(input, parser, true);
+            if let Err((LexErrorKind::UnknownField(_), field)) = res {
+                if parser.options.unknown_field_policy == UnknownFieldPolicy::TreatAsMissing {
+                    if let Some(res) = lex_missing(input, field) {
+                        return res;
+                    }
+                }
+            }
+            let ((op, negated), input) = res?;
+            let expr = SimpleExpr::Field(op);
+
+            // `field !~ "regex"` is a shorthand for `not field ~ "regex"`.
+            if negated {
+                (
+                    SimpleExpr::Unary {
+                        op: UnaryOp::Not,
+                        arg: Box::new(expr),
+                    },
+                    input,
+                )
+            } else {
+                (expr, input)
+            }
         })
     }
 }
@@ -49,6 +370,103 @@ impl<'s> Expr<'s> for SimpleExpr<'s> {
             SimpleExpr::Field(op) => op.uses(field),
             SimpleExpr::Parenthesized(op) => op.uses(field),
             SimpleExpr::Unary { arg, .. } => arg.uses(field),
+            SimpleExpr::Missing { .. } => false,
+        }
+    }
+
+    fn collect_comparisons<'a>(&'a self, comparisons: &mut Vec<&'a FieldExpr<'s>>) {
+        match self {
+            SimpleExpr::Field(op) => op.collect_comparisons(comparisons),
+            SimpleExpr::Parenthesized(op) => op.collect_comparisons(comparisons),
+            SimpleExpr::Unary { arg, .. } => arg.collect_comparisons(comparisons),
+            SimpleExpr::Missing { .. } => {}
+        }
+    }
+
+    fn cost(&self) -> u64 {
+        match self {
+            SimpleExpr::Field(op) => op.cost(),
+            SimpleExpr::Parenthesized(op) => op.cost(),
+            SimpleExpr::Unary { arg, .. } => arg.cost(),
+            SimpleExpr::Missing { .. } => 0,
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        match self {
+            SimpleExpr::Field(op) => op.node_count(),
+            SimpleExpr::Parenthesized(op) => op.node_count(),
+            SimpleExpr::Unary { arg, .. } => 1 + arg.node_count(),
+            SimpleExpr::Missing { .. } => 1,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match self {
+            SimpleExpr::Field(op) => op.depth(),
+            SimpleExpr::Parenthesized(op) => op.depth(),
+            SimpleExpr::Unary { arg, .. } => 1 + arg.depth(),
+            SimpleExpr::Missing { .. } => 1,
+        }
+    }
+
+    fn write_tree(&self, input: &str, indent: usize, out: &mut String) {
+        match self {
+            SimpleExpr::Field(op) => op.write_tree(input, indent, out),
+            SimpleExpr::Parenthesized(op) => op.write_tree(input, indent, out),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                write_tree_line(out, indent, "not");
+                arg.write_tree(input, indent + 1, out);
+            }
+            SimpleExpr::Missing { source, .. } => {
+                write_tree_line(out, indent, &format!("missing {}", source));
+            }
+        }
+    }
+
+    fn write_fingerprint(&self, out: &mut String) {
+        match self {
+            SimpleExpr::Field(op) => op.write_fingerprint(out),
+            SimpleExpr::Parenthesized(op) => op.write_fingerprint(out),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                out.push_str("Not(");
+                arg.write_fingerprint(out);
+                out.push(')');
+            }
+            SimpleExpr::Missing { source, .. } => out.push_str(&format!("Missing({:?})", source)),
+        }
+    }
+
+    fn canonicalize(&mut self) {
+        match self {
+            SimpleExpr::Field(op) => op.canonicalize(),
+            SimpleExpr::Parenthesized(op) => op.canonicalize(),
+            SimpleExpr::Unary { arg, .. } => arg.canonicalize(),
+            SimpleExpr::Missing { .. } => {}
+        }
+    }
+
+    fn simplify_ranges(&mut self) {
+        match self {
+            SimpleExpr::Field(op) => op.simplify_ranges(),
+            SimpleExpr::Parenthesized(op) => op.simplify_ranges(),
+            SimpleExpr::Unary { arg, .. } => arg.simplify_ranges(),
+            SimpleExpr::Missing { .. } => {}
+        }
+    }
+
+    fn offset_spans(&mut self, offset: usize) {
+        match self {
+            SimpleExpr::Field(op) => op.offset_spans(offset),
+            SimpleExpr::Parenthesized(op) => op.offset_spans(offset),
+            SimpleExpr::Unary { arg, .. } => arg.offset_spans(offset),
+            SimpleExpr::Missing { .. } => {}
         }
     }
 
@@ -63,6 +481,95 @@ impl<'s> Expr<'s> for SimpleExpr<'s> {
                 let arg = arg.compile();
                 CompiledExpr::new(move |ctx| !arg.execute(ctx))
             }
+            SimpleExpr::Missing { .. } => CompiledExpr::new(|_| false),
+        }
+    }
+
+    fn compile_profiled(self, profiler: &mut Profiler) -> CompiledExpr<'s> {
+        match self {
+            SimpleExpr::Field(op) => op.compile_profiled(profiler),
+            SimpleExpr::Parenthesized(op) => op.compile_profiled(profiler),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                let span = comparisons_span(&*arg);
+                let (counters, arg) = profiler.node("not".to_owned(), span, |profiler| {
+                    arg.compile_profiled(profiler)
+                });
+                CompiledExpr::new(move |ctx| counters.time(|| (!arg.execute(ctx), false)))
+            }
+            SimpleExpr::Missing { source, .. } => profiler.leaf(
+                format!("missing {}", source),
+                None,
+                CompiledExpr::new(|_| false),
+            ),
+        }
+    }
+
+    fn lower(self, program: &mut Vec<Instruction<'s>>) {
+        match self {
+            SimpleExpr::Field(op) => op.lower(program),
+            SimpleExpr::Parenthesized(op) => op.lower(program),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                arg.lower(program);
+                program.push(Instruction::Not);
+            }
+            SimpleExpr::Missing { .. } => program.push(Instruction::Test(self.compile())),
+        }
+    }
+
+    fn execute_traced<'a, 'e>(
+        &'a self,
+        ctx: &ExecutionContext<'e>,
+        reached: bool,
+        trace: &mut Vec<TraceEntry<'a, 's, 'e>>,
+    ) -> bool {
+        match self {
+            SimpleExpr::Field(op) => op.execute_traced(ctx, reached, trace),
+            SimpleExpr::Parenthesized(op) => op.execute_traced(ctx, reached, trace),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => !arg.execute_traced(ctx, reached, trace),
+            SimpleExpr::Missing { .. } => false,
+        }
+    }
+
+    fn execute_matching<'a>(
+        &'a self,
+        ctx: &ExecutionContext<'_>,
+        leaves: &mut Vec<&'a FieldExpr<'s>>,
+    ) -> bool {
+        match self {
+            SimpleExpr::Field(op) => op.execute_matching(ctx, leaves),
+            SimpleExpr::Parenthesized(op) => op.execute_matching(ctx, leaves),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                // A negated match is made by comparisons that didn't match.
+                let len = leaves.len();
+                let result = !arg.execute_matching(ctx, leaves);
+                leaves.truncate(len);
+                result
+            }
+            SimpleExpr::Missing { .. } => false,
+        }
+    }
+
+    fn execute_ternary(&self, ctx: &ExecutionContext<'_>) -> Ternary {
+        match self {
+            SimpleExpr::Field(op) => op.execute_ternary(ctx),
+            SimpleExpr::Parenthesized(op) => op.execute_ternary(ctx),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => arg.execute_ternary(ctx).not(),
+            SimpleExpr::Missing { .. } => Ternary::Unknown,
         }
     }
 }
@@ -73,15 +580,16 @@ fn test() {
     use lex::complete;
 
     let scheme = &Scheme! { t: Bool };
+    let parser = &Parser::new(scheme);
 
     let ctx = &mut ExecutionContext::new(scheme);
     ctx.set_field_value("t", true).unwrap();
 
-    let t_expr = SimpleExpr::Field(complete(FieldExpr::lex_with("t", scheme)).unwrap());
+    let t_expr = SimpleExpr::Field(complete(FieldExpr::lex_with("t", parser)).unwrap());
     let t_expr = || t_expr.clone();
 
     {
-        let expr = assert_ok!(SimpleExpr::lex_with("t", scheme), t_expr());
+        let expr = assert_ok!(SimpleExpr::lex_with("t", parser), t_expr());
 
         assert_json!(
             expr,
@@ -100,7 +608,7 @@ fn test() {
 
     {
         let expr = assert_ok!(
-            SimpleExpr::lex_with("((t))", scheme),
+            SimpleExpr::lex_with("((t))", parser),
             parenthesized_expr(parenthesized_expr(t_expr()))
         );
 
@@ -123,7 +631,7 @@ fn test() {
     };
 
     {
-        let expr = assert_ok!(SimpleExpr::lex_with("not t", scheme), not_expr(t_expr()));
+        let expr = assert_ok!(SimpleExpr::lex_with("not t", parser), not_expr(t_expr()));
 
         assert_json!(
             expr,
@@ -141,11 +649,11 @@ fn test() {
         assert_eq!(expr.execute(ctx), false);
     }
 
-    assert_ok!(SimpleExpr::lex_with("!t", scheme), not_expr(t_expr()));
+    assert_ok!(SimpleExpr::lex_with("!t", parser), not_expr(t_expr()));
 
     {
         let expr = assert_ok!(
-            SimpleExpr::lex_with("!!t", scheme),
+            SimpleExpr::lex_with("!!t", parser),
             not_expr(not_expr(t_expr()))
         );
 
@@ -169,7 +677,217 @@ fn test() {
     }
 
     assert_ok!(
-        SimpleExpr::lex_with("! (not !t)", scheme),
+        SimpleExpr::lex_with("! (not !t)", parser),
         not_expr(parenthesized_expr(not_expr(not_expr(t_expr()))))
     );
 }
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex_shorthands() {
+    use super::field_expr::{BytesOp, ComparisonOp};
+    use execution_context::ExecutionContext;
+    use lex::LexErrorKind;
+    use types::Type;
+
+    let scheme = &Scheme! { http.host: Bytes, tcp.port: Int };
+    let parser = &Parser::new(scheme);
+
+    assert_eq!(
+        SimpleExpr::lex_with(r#"http.host ~ "^a""#, parser),
+        SimpleExpr::lex_with(r#"http.host matches "^a""#, parser)
+    );
+
+    let expr = assert_ok!(
+        SimpleExpr::lex_with(r#"http.host !~ "^a""#, parser),
+        SimpleExpr::lex_with(r#"not http.host matches "^a""#, parser)
+            .unwrap()
+            .0
+    );
+
+    let expr = expr.compile();
+    let ctx = &mut ExecutionContext::new(scheme);
+
+    ctx.set_field_value("http.host", "abc").unwrap();
+    assert_eq!(expr.execute(ctx), false);
+
+    ctx.set_field_value("http.host", "cba").unwrap();
+    assert_eq!(expr.execute(ctx), true);
+
+    // `!=` still works as usual
+    assert!(SimpleExpr::lex_with(r#"http.host != "^a""#, parser).is_ok());
+
+    assert_err!(
+        SimpleExpr::lex_with(r#"tcp.port !~ "^a""#, parser),
+        LexErrorKind::UnsupportedOp {
+            field_type: Type::Int,
+            op: ComparisonOp::Bytes(BytesOp::Matches),
+        },
+        "tcp.port !~"
+    );
+}
+
+#[test]
+fn test_between() {
+    use super::field_expr::ComparisonOp;
+    use execution_context::ExecutionContext;
+    use lex::LexErrorKind;
+    use std::net::IpAddr;
+    use types::Type;
+
+    let scheme = &Scheme! { http.host: Bytes, tcp.port: Int, ip.src: Ip };
+    let parser = &Parser::new(scheme);
+
+    assert_eq!(
+        SimpleExpr::lex_with("tcp.port between 1024 .. 2048", parser),
+        SimpleExpr::lex_with("tcp.port in { 1024..2048 }", parser)
+    );
+    assert_eq!(
+        SimpleExpr::lex_with("tcp.port between 1024..2048", parser),
+        SimpleExpr::lex_with("tcp.port in { 1024..2048 }", parser)
+    );
+
+    let expr = assert_ok!(
+        SimpleExpr::lex_with("tcp.port not between 1024 .. 2048", parser),
+        SimpleExpr::lex_with("not tcp.port between 1024 .. 2048", parser)
+            .unwrap()
+            .0
+    );
+
+    let positive = SimpleExpr::lex_with("tcp.port between 1024 .. 2048", parser)
+        .unwrap()
+        .0
+        .compile();
+    let negative = expr.compile();
+    let ctx = &mut ExecutionContext::new(scheme);
+
+    for &port in &[0, 1023, 1024, 1025, 2047, 2048, 2049] {
+        ctx.set_field_value("tcp.port", port).unwrap();
+        let expected = port >= 1024 && port <= 2048;
+        assert_eq!(positive.execute(ctx), expected, "{}", port);
+        assert_eq!(negative.execute(ctx), !expected, "{}", port);
+    }
+
+    let expr = SimpleExpr::lex_with("ip.src not between 10.0.0.0 .. ::1", parser);
+    assert_err!(
+        expr,
+        LexErrorKind::IncompatibleRangeBounds,
+        "10.0.0.0 .. ::1"
+    );
+
+    let expr = assert_ok!(
+        SimpleExpr::lex_with("ip.src between 10.0.0.0 ..10.0.0.255", parser),
+        SimpleExpr::lex_with("ip.src in { 10.0.0.0..10.0.0.255 }", parser)
+            .unwrap()
+            .0
+    );
+
+    let expr = expr.compile();
+    ctx.set_field_value("ip.src", IpAddr::from([10, 0, 0, 7]))
+        .unwrap();
+    assert_eq!(expr.execute(ctx), true);
+    ctx.set_field_value("ip.src", IpAddr::from([10, 0, 1, 0]))
+        .unwrap();
+    assert_eq!(expr.execute(ctx), false);
+
+    assert_err!(
+        SimpleExpr::lex_with("tcp.port between 2048 .. 1024", parser),
+        LexErrorKind::IncompatibleRangeBounds,
+        "2048 .. 1024"
+    );
+
+    assert_err!(
+        SimpleExpr::lex_with(r#"http.host not between "a" .. "b""#, parser),
+        LexErrorKind::UnsupportedOp {
+            field_type: Type::Bytes,
+            op: ComparisonOp::Between,
+        },
+        "http.host not between"
+    );
+}
+
+#[test]
+fn test_contains_list() {
+    use super::field_expr::{BytesOp, ComparisonOp};
+    use execution_context::ExecutionContext;
+    use lex::LexErrorKind;
+    use types::Type;
+
+    let scheme = &Scheme! { http.ua: Bytes, tcp.port: Int };
+    let parser = &Parser::new(scheme);
+
+    let any = assert_ok!(
+        SimpleExpr::lex_with(r#"http.ua contains any { "bot" "crawler" };"#, parser),
+        SimpleExpr::lex_with(
+            r#"(http.ua contains "bot" or http.ua contains "crawler")"#,
+            parser
+        )
+        .unwrap()
+        .0,
+        ";"
+    );
+
+    assert_eq!(
+        SimpleExpr::lex_with(r#"http.ua contains any of { "bot" "crawler" };"#, parser),
+        Ok((any.clone(), ";"))
+    );
+
+    let all = assert_ok!(
+        SimpleExpr::lex_with(r#"http.ua contains all { "bot" "crawler" }"#, parser),
+        SimpleExpr::lex_with(
+            r#"(http.ua contains "bot" and http.ua contains "crawler")"#,
+            parser
+        )
+        .unwrap()
+        .0
+    );
+
+    assert_eq!(
+        SimpleExpr::lex_with(r#"http.ua contains all of{ "bot" "crawler" }"#, parser),
+        Ok((all.clone(), ""))
+    );
+
+    let (any, all) = (any.compile(), all.compile());
+    let ctx = &mut ExecutionContext::new(scheme);
+
+    ctx.set_field_value("http.ua", "Mozilla/5.0").unwrap();
+    assert_eq!(any.execute(ctx), false);
+    assert_eq!(all.execute(ctx), false);
+
+    ctx.set_field_value("http.ua", "a bot").unwrap();
+    assert_eq!(any.execute(ctx), true);
+    assert_eq!(all.execute(ctx), false);
+
+    ctx.set_field_value("http.ua", "a crawler bot").unwrap();
+    assert_eq!(any.execute(ctx), true);
+    assert_eq!(all.execute(ctx), true);
+
+    let any = SimpleExpr::lex_with("http.ua contains any {}", parser)
+        .unwrap()
+        .0
+        .compile();
+    let all = SimpleExpr::lex_with("http.ua contains all {}", parser)
+        .unwrap()
+        .0
+        .compile();
+    assert_eq!(any.execute(ctx), false);
+    assert_eq!(all.execute(ctx), true);
+
+    assert_err!(
+        SimpleExpr::lex_with("tcp.port contains any { 80 }", parser),
+        LexErrorKind::UnsupportedOp {
+            field_type: Type::Int,
+            op: ComparisonOp::Bytes(BytesOp::Contains),
+        },
+        "tcp.port contains"
+    );
+
+    assert_err!(
+        SimpleExpr::lex_with(r#"http.ua contains any { "bot" int("80") }"#, parser),
+        LexErrorKind::TypeMismatch {
+            expected: Type::Bytes,
+            actual: Type::Int,
+        },
+        "int"
+    );
+}
@@ -0,0 +1,462 @@
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{BytesOp, ComparisonOp, ComparisonRhs, FieldExpr, OrderingOp, Subscript},
+    simple_expr::{SimpleExpr, UnaryOp},
+};
+use std::{i32, net::IpAddr, ops::RangeInclusive};
+use types::LhsValue;
+
+// A node of the filter with parentheses looked through, as they only group.
+#[derive(PartialEq, Clone, Copy)]
+enum Node<'a, 's: 'a> {
+    Combining(CombiningOp, &'a [CombinedExpr<'s>]),
+    Not(&'a SimpleExpr<'s>),
+    Field(&'a FieldExpr<'s>),
+    Missing,
+}
+
+impl<'a, 's> From<&'a CombinedExpr<'s>> for Node<'a, 's> {
+    fn from(expr: &'a CombinedExpr<'s>) -> Self {
+        match expr {
+            CombinedExpr::Simple(expr) => expr.into(),
+            CombinedExpr::Combining { op, items } => Node::Combining(*op, items),
+        }
+    }
+}
+
+impl<'a, 's> From<&'a SimpleExpr<'s>> for Node<'a, 's> {
+    fn from(expr: &'a SimpleExpr<'s>) -> Self {
+        match expr {
+            SimpleExpr::Field(expr) => Node::Field(expr),
+            SimpleExpr::Parenthesized(expr) => (&**expr).into(),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => Node::Not(arg),
+            SimpleExpr::Missing { .. } => Node::Missing,
+        }
+    }
+}
+
+// Values of all types mapped onto a single ordered space, tagged with the
+// type (and the address family for IPs), so that ranges of them can be
+// merged and compared uniformly.
+type Key = (u8, u128);
+
+fn int_key(value: i32) -> Key {
+    (0, (i64::from(value) - i64::from(i32::MIN)) as u128)
+}
+
+fn ip_key(addr: IpAddr) -> Key {
+    match addr {
+        IpAddr::V4(addr) => (4, u128::from(u32::from(addr))),
+        IpAddr::V6(addr) => (6, u128::from(addr)),
+    }
+}
+
+fn value_key(value: &LhsValue<'_>) -> Option<Key> {
+    match value {
+        LhsValue::Int(value) => Some(int_key(*value)),
+        LhsValue::Ip(addr) => Some(ip_key(*addr)),
+        _ => None,
+    }
+}
+
+enum Matched<'a> {
+    Ranges(Vec<RangeInclusive<Key>>),
+    Bytes(Vec<&'a [u8]>),
+}
+
+// Returns all values the comparison matches, for the kinds of comparisons
+// where those are easy to enumerate.
+fn matched_values<'a>(expr: &'a FieldExpr<'_>) -> Option<Matched<'a>> {
+    Some(match (expr.op()?, expr.rhs()) {
+        (ComparisonOp::In, ComparisonRhs::List(ranges)) => {
+            // Lists of bytes are made of single values, not ranges.
+            let bytes = ranges
+                .iter()
+                .map(|range| match range.start() {
+                    LhsValue::Bytes(bytes) => Some(*bytes),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>();
+
+            match bytes {
+                Some(ref bytes) if !bytes.is_empty() => Matched::Bytes(bytes.clone()),
+                _ => Matched::Ranges(
+                    ranges
+                        .iter()
+                        .map(|range| Some(value_key(range.start())?..=value_key(range.end())?))
+                        .collect::<Option<_>>()?,
+                ),
+            }
+        }
+        (ComparisonOp::Ordering(OrderingOp::Equal), ComparisonRhs::Literal(value)) => match value {
+            LhsValue::Bytes(bytes) => Matched::Bytes(vec![bytes]),
+            value => {
+                let key = value_key(&value)?;
+                Matched::Ranges(vec![key..=key])
+            }
+        },
+        (ComparisonOp::Ordering(OrderingOp::Equal), ComparisonRhs::Cidr(range)) => {
+            Matched::Ranges(vec![value_key(range.start())?..=value_key(range.end())?])
+        }
+        (ComparisonOp::Ordering(op), ComparisonRhs::Literal(LhsValue::Int(value))) => {
+            let below = value
+                .checked_sub(1)
+                .map(|below| int_key(i32::MIN)..=int_key(below));
+            let above = value
+                .checked_add(1)
+                .map(|above| int_key(above)..=int_key(i32::MAX));
+            let at_or_below = int_key(i32::MIN)..=int_key(value);
+            let at_or_above = int_key(value)..=int_key(i32::MAX);
+
+            Matched::Ranges(match op {
+                OrderingOp::Equal => vec![int_key(value)..=int_key(value)],
+                OrderingOp::NotEqual => below.into_iter().chain(above).collect(),
+                OrderingOp::GreaterThan => above.into_iter().collect(),
+                OrderingOp::GreaterThanEqual => vec![at_or_above],
+                OrderingOp::LessThan => below.into_iter().collect(),
+                OrderingOp::LessThanEqual => vec![at_or_below],
+            })
+        }
+        _ => return None,
+    })
+}
+
+// Checks that every range of `sub` is within a single range of `sup` after
+// merging overlapping and adjacent ranges of the latter.
+fn is_covered(sub: &[RangeInclusive<Key>], sup: &[RangeInclusive<Key>]) -> bool {
+    let mut sup = sup.to_vec();
+    sup.sort_by_key(|range| *range.start());
+
+    let mut merged: Vec<RangeInclusive<Key>> = Vec::new();
+    for range in sup {
+        let extends_last = match merged.last() {
+            Some(last) => {
+                range.start() <= last.end()
+                    || (range.start().0 == last.end().0
+                        && last.end().1.checked_add(1) == Some(range.start().1))
+            }
+            None => false,
+        };
+
+        if extends_last {
+            let last = merged.pop().unwrap();
+            let end = if range.end() > last.end() {
+                *range.end()
+            } else {
+                *last.end()
+            };
+            merged.push(*last.start()..=end);
+        } else {
+            merged.push(range);
+        }
+    }
+
+    sub.iter().all(|range| {
+        merged
+            .iter()
+            .any(|sup| sup.start() <= range.start() && range.end() <= sup.end())
+    })
+}
+
+fn is_comparison_subset(sub: &FieldExpr<'_>, sup: &FieldExpr<'_>) -> bool {
+    if sub == sup {
+        return true;
+    }
+
+    if sub.field() != sup.field() || sub.subscript() != sup.subscript() || sub.call() != sup.call()
+    {
+        return false;
+    }
+
+    // Values are compared exactly below, which is only meaningful if both
+    // sides treat case the same way.
+    if sub.ignores_case() != sup.ignores_case() {
+        return false;
+    }
+
+    let contains = ComparisonOp::Bytes(BytesOp::Contains);
+
+    if sub.op() == Some(contains) && sup.op() == Some(contains) {
+        // Anything that contains "abc" also contains "b".
+        return match (sub.rhs(), sup.rhs()) {
+            (
+                ComparisonRhs::Literal(LhsValue::Bytes(sub)),
+                ComparisonRhs::Literal(LhsValue::Bytes(sup)),
+            ) => contains_needle(sub, sup),
+            _ => false,
+        };
+    }
+
+    match (matched_values(sub), matched_values(sup)) {
+        (Some(Matched::Ranges(sub)), Some(Matched::Ranges(sup))) => is_covered(&sub, &sup),
+        (Some(Matched::Bytes(sub)), Some(Matched::Bytes(sup))) => {
+            sub.iter().all(|value| sup.contains(value))
+        }
+        _ => false,
+    }
+}
+
+fn is_node_subset(sub: Node<'_, '_>, sup: Node<'_, '_>) -> bool {
+    if sub == sup {
+        return true;
+    }
+
+    match sub {
+        // Comparisons of unknown fields never match.
+        Node::Missing => return true,
+        Node::Combining(CombiningOp::Or, items) => {
+            return items.iter().all(|item| is_node_subset(item.into(), sup));
+        }
+        _ => {}
+    }
+
+    match sup {
+        Node::Combining(CombiningOp::And, items) => {
+            return items.iter().all(|item| is_node_subset(sub, item.into()));
+        }
+        Node::Combining(CombiningOp::Or, items) => {
+            if items.iter().any(|item| is_node_subset(sub, item.into())) {
+                return true;
+            }
+        }
+        _ => {}
+    }
+
+    match (sub, sup) {
+        (Node::Combining(CombiningOp::And, items), _) => {
+            items.iter().any(|item| is_node_subset(item.into(), sup))
+        }
+        (Node::Field(sub), Node::Field(sup)) => is_comparison_subset(sub, sup),
+        // `not a` implies `not b` whenever `b` implies `a`.
+        (Node::Not(sub), Node::Not(sup)) => is_node_subset(sup.into(), sub.into()),
+        _ => false,
+    }
+}
+
+pub(crate) fn is_subset(sub: &CombinedExpr<'_>, sup: &CombinedExpr<'_>) -> bool {
+    is_node_subset(sub.into(), sup.into())
+}
+
+fn overlaps(left: &[RangeInclusive<Key>], right: &[RangeInclusive<Key>]) -> bool {
+    left.iter().any(|left| {
+        right
+            .iter()
+            .any(|right| left.start() <= right.end() && right.start() <= left.end())
+    })
+}
+
+fn contains_needle(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
+
+fn contains_rhs<'a>(expr: &'a FieldExpr<'_>) -> Option<&'a [u8]> {
+    match (expr.op()?, expr.rhs()) {
+        (
+            ComparisonOp::Bytes(BytesOp::Contains),
+            ComparisonRhs::Literal(LhsValue::Bytes(needle)),
+        ) => Some(needle),
+        _ => None,
+    }
+}
+
+fn are_comparisons_disjoint(left: &FieldExpr<'_>, right: &FieldExpr<'_>) -> bool {
+    if left.field() != right.field()
+        || left.subscript() != right.subscript()
+        || left.call() != right.call()
+        || left.ignores_case() != right.ignores_case()
+    {
+        return false;
+    }
+
+    // Quantifiers can be satisfied by different elements of the same array,
+    // or values of the same map item, and `all` by an empty array.
+    if left.subscript().and_then(Subscript::quantifier).is_some() {
+        return false;
+    }
+
+    match (matched_values(left), matched_values(right)) {
+        (Some(Matched::Ranges(left)), Some(Matched::Ranges(right))) => !overlaps(&left, &right),
+        (Some(Matched::Bytes(left)), Some(Matched::Bytes(right))) => {
+            !left.iter().any(|value| right.contains(value))
+        }
+        (Some(Matched::Bytes(values)), None) => contains_rhs(right).map_or(false, |needle| {
+            !values.iter().any(|value| contains_needle(value, needle))
+        }),
+        (None, Some(Matched::Bytes(values))) => contains_rhs(left).map_or(false, |needle| {
+            !values.iter().any(|value| contains_needle(value, needle))
+        }),
+        _ => false,
+    }
+}
+
+fn are_nodes_disjoint(left: Node<'_, '_>, right: Node<'_, '_>) -> bool {
+    match (left, right) {
+        (Node::Missing, _) | (_, Node::Missing) => true,
+        (Node::Combining(CombiningOp::Or, items), other)
+        | (other, Node::Combining(CombiningOp::Or, items)) => items
+            .iter()
+            .all(|item| are_nodes_disjoint(item.into(), other)),
+        (Node::Combining(CombiningOp::And, items), other)
+        | (other, Node::Combining(CombiningOp::And, items)) => items
+            .iter()
+            .any(|item| are_nodes_disjoint(item.into(), other)),
+        // Two negations both match when the fields are not set.
+        (Node::Not(_), Node::Not(_)) => false,
+        // `a` never matches together with `not b` if `a` implies `b`.
+        (Node::Not(negated), other) | (other, Node::Not(negated)) => {
+            is_node_subset(other, negated.into())
+        }
+        (Node::Field(left), Node::Field(right)) => are_comparisons_disjoint(left, right),
+        _ => false,
+    }
+}
+
+pub(crate) fn is_disjoint(left: &CombinedExpr<'_>, right: &CombinedExpr<'_>) -> bool {
+    are_nodes_disjoint(left.into(), right.into())
+}
+
+#[test]
+fn test_is_subset_of() {
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        http.ua: Bytes,
+        ip.src: Ip,
+        port: Int,
+        ssl: Bool,
+    };
+
+    let is_subset = |sub, sup| {
+        scheme
+            .parse(sub)
+            .unwrap()
+            .is_subset_of(&scheme.parse(sup).unwrap())
+    };
+
+    for &(sub, sup) in &[
+        ("port == 5", "port in { 5 6 }"),
+        ("port in { 1..10 20 }", "port in { 0..5 6..20 }"),
+        ("port > 1024", "port != 80"),
+        ("port >= 1024 && ssl", "port > 80"),
+        ("port == 80 || port == 443", "port in { 80 443 } || ssl"),
+        ("ssl", "ssl"),
+        ("(ssl)", "ssl || port == 80"),
+        ("ip.src == 10.1.2.3", "ip.src in { 10.0.0.0/8 }"),
+        (
+            "ip.src in { 10.0.0.0/9 10.128.0.0/9 }",
+            "ip.src == 10.0.0.0/8",
+        ),
+        (r#"http.host == "a""#, r#"http.host in { "a" "b" }"#),
+        (r#"http.ua contains "curl/7""#, r#"http.ua contains "curl""#),
+        ("not port in { 5 6 }", "not port == 5"),
+        ("port == 5", "port == 5 && port < 10"),
+    ] {
+        assert!(is_subset(sub, sup), "{} should be a subset of {}", sub, sup);
+    }
+
+    for &(sub, sup) in &[
+        ("port in { 5 6 }", "port == 5"),
+        ("port == 5", "port == 6"),
+        ("port != 80", "port > 1024"),
+        ("ssl", "port == 80"),
+        ("port == 80 || ssl", "port == 80"),
+        ("port == 80", "port == 80 && ssl"),
+        ("ip.src in { 10.0.0.0/8 }", "ip.src == 10.1.0.0/16"),
+        (r#"http.ua contains "curl""#, r#"http.ua contains "curl/7""#),
+        (r#"http.host == "a""#, r#"http.ua == "a""#),
+        ("not port == 5", "not port in { 5 6 }"),
+        // Hard cases are conservatively not recognized.
+        ("port == 5", "port == 5 ^^ port == 6"),
+    ] {
+        assert!(
+            !is_subset(sub, sup),
+            "{} should not be a subset of {}",
+            sub,
+            sup
+        );
+    }
+
+    let other_scheme = &Scheme! { port: Int };
+    assert!(!scheme
+        .parse("port == 5")
+        .unwrap()
+        .is_subset_of(&other_scheme.parse("port == 5").unwrap()));
+}
+
+#[test]
+fn test_is_disjoint_with() {
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        port: Int,
+        ssl: Bool,
+    };
+    scheme
+        .add_array_field("http.cookies".to_owned(), ::Type::Bytes)
+        .unwrap();
+    let scheme = &scheme;
+
+    let is_disjoint = |left, right| {
+        scheme
+            .parse(left)
+            .unwrap()
+            .is_disjoint_with(&scheme.parse(right).unwrap())
+    };
+
+    for &(left, right) in &[
+        ("port == 5", "port == 6"),
+        ("port < 80", "port in { 80..100 }"),
+        ("port > 1024 && ssl", "port == 80"),
+        ("port == 80 || port == 443", "port in { 1..79 8080 }"),
+        ("ip.src in { 10.0.0.0/8 }", "ip.src == 192.168.0.0/16"),
+        ("ip.src == 10.0.0.1", "ip.src == ::1"),
+        (r#"http.host == "a""#, r#"http.host in { "b" "c" }"#),
+        (
+            r#"http.host == "example.org""#,
+            r#"http.host contains "test""#,
+        ),
+        ("ssl", "not ssl"),
+        ("port == 5", "not port in { 1..10 }"),
+    ] {
+        assert!(
+            is_disjoint(left, right),
+            "{} should be disjoint with {}",
+            left,
+            right
+        );
+        assert!(
+            is_disjoint(right, left),
+            "{} should be disjoint with {}",
+            right,
+            left
+        );
+    }
+
+    for &(left, right) in &[
+        ("port == 5", "port in { 1..10 }"),
+        ("port != 80", "port > 1024"),
+        ("port == 80 || ssl", "port == 443"),
+        ("ssl", "port == 80"),
+        (r#"http.host == "test.org""#, r#"http.host contains "test""#),
+        ("not port == 5", "not port == 6"),
+        (r#"any(http.cookies == "a")"#, r#"any(http.cookies == "b")"#),
+    ] {
+        assert!(
+            !is_disjoint(left, right),
+            "{} should not be disjoint with {}",
+            left,
+            right
+        );
+    }
+
+    let other_scheme = &Scheme! { port: Int };
+    assert!(!scheme
+        .parse("port == 5")
+        .unwrap()
+        .is_disjoint_with(&other_scheme.parse("port == 6").unwrap()));
+}
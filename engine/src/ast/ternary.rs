@@ -0,0 +1,174 @@
+use failure::Fail;
+
+/// A result of a comparison or a filter in three-valued logic, as returned by
+/// [`FilterAst::execute_ternary`](::FilterAst::execute_ternary).
+///
+/// Logical operators follow Kleene logic: `Unknown` is a value that could be
+/// either `True` or `False`, so the result is `Unknown` only if it depends on
+/// which one it is.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Ternary {
+    /// Definitely matches.
+    True,
+    /// Definitely doesn't match.
+    False,
+    /// Depends on a field, a pattern or a constant that isn't set.
+    Unknown,
+}
+
+impl From<bool> for Ternary {
+    fn from(value: bool) -> Self {
+        if value {
+            Ternary::True
+        } else {
+            Ternary::False
+        }
+    }
+}
+
+/// An error when [`Ternary::into_bool`] is called on [`Ternary::Unknown`].
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "the result depends on values that are not set")]
+pub struct UnknownResultError;
+
+impl Ternary {
+    /// Kleene conjunction: `False` if either side is `False`.
+    pub fn and(self, other: Ternary) -> Ternary {
+        match (self, other) {
+            (Ternary::False, _) | (_, Ternary::False) => Ternary::False,
+            (Ternary::True, Ternary::True) => Ternary::True,
+            _ => Ternary::Unknown,
+        }
+    }
+
+    /// Kleene disjunction: `True` if either side is `True`.
+    pub fn or(self, other: Ternary) -> Ternary {
+        match (self, other) {
+            (Ternary::True, _) | (_, Ternary::True) => Ternary::True,
+            (Ternary::False, Ternary::False) => Ternary::False,
+            _ => Ternary::Unknown,
+        }
+    }
+
+    /// Exclusive or, which is `Unknown` if either side is.
+    pub fn xor(self, other: Ternary) -> Ternary {
+        match (self, other) {
+            (Ternary::Unknown, _) | (_, Ternary::Unknown) => Ternary::Unknown,
+            (lhs, rhs) => Ternary::from(lhs != rhs),
+        }
+    }
+
+    /// Negation, which keeps `Unknown` as is.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Ternary {
+        match self {
+            Ternary::True => Ternary::False,
+            Ternary::False => Ternary::True,
+            Ternary::Unknown => Ternary::Unknown,
+        }
+    }
+
+    /// Maps `Unknown` to `false`, which is what
+    /// [`Filter::execute`](::Filter::execute) would return.
+    pub fn is_true(self) -> bool {
+        self == Ternary::True
+    }
+
+    /// Converts to a `bool`, failing if the result is `Unknown`.
+    pub fn into_bool(self) -> Result<bool, UnknownResultError> {
+        match self {
+            Ternary::True => Ok(true),
+            Ternary::False => Ok(false),
+            Ternary::Unknown => Err(UnknownResultError),
+        }
+    }
+}
+
+#[test]
+fn test_kleene_truth_tables() {
+    use self::Ternary::*;
+
+    let values = [True, False, Unknown];
+
+    let and = [
+        [True, False, Unknown],
+        [False, False, False],
+        [Unknown, False, Unknown],
+    ];
+    let or = [
+        [True, True, True],
+        [True, False, Unknown],
+        [True, Unknown, Unknown],
+    ];
+
+    for (i, &lhs) in values.iter().enumerate() {
+        for (j, &rhs) in values.iter().enumerate() {
+            assert_eq!(lhs.and(rhs), and[i][j], "{:?} and {:?}", lhs, rhs);
+            assert_eq!(lhs.or(rhs), or[i][j], "{:?} or {:?}", lhs, rhs);
+        }
+    }
+
+    assert_eq!(True.not(), False);
+    assert_eq!(False.not(), True);
+    assert_eq!(Unknown.not(), Unknown);
+
+    assert_eq!(True.xor(False), True);
+    assert_eq!(True.xor(True), False);
+    assert_eq!(False.xor(Unknown), Unknown);
+
+    assert_eq!(Unknown.is_true(), false);
+    assert_eq!(Unknown.into_bool(), Err(UnknownResultError));
+    assert_eq!(True.into_bool(), Ok(true));
+}
+
+#[test]
+fn test_execute_ternary() {
+    use execution_context::ExecutionContext;
+    use filter::SchemeMismatchError;
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+    };
+
+    let mut ctx = ExecutionContext::new(&scheme);
+
+    let execute = |filter: &str, ctx: &ExecutionContext<'_>| {
+        scheme.parse(filter).unwrap().execute_ternary(ctx).unwrap()
+    };
+
+    assert_eq!(execute("tcp.port == 80", &ctx), Ternary::Unknown);
+    assert_eq!(execute("not tcp.port == 80", &ctx), Ternary::Unknown);
+
+    ctx.set_field_value("http.host", "example.org").unwrap();
+
+    assert_eq!(
+        execute(r#"http.host == "example.org" || tcp.port == 80"#, &ctx),
+        Ternary::True
+    );
+    assert_eq!(
+        execute(r#"http.host == "example.org" && tcp.port == 80"#, &ctx),
+        Ternary::Unknown
+    );
+    assert_eq!(
+        execute(r#"http.host == "example.com" && tcp.port == 80"#, &ctx),
+        Ternary::False
+    );
+
+    ctx.set_field_value("tcp.port", 80).unwrap();
+
+    assert_eq!(
+        execute(r#"http.host == "example.org" && tcp.port == 80"#, &ctx),
+        Ternary::True
+    );
+
+    let other_scheme = Scheme! { http.host: Bytes };
+    let ctx = ExecutionContext::new(&other_scheme);
+    assert_eq!(
+        scheme
+            .parse("tcp.port == 80")
+            .unwrap()
+            .execute_ternary(&ctx),
+        Err(SchemeMismatchError)
+    );
+}
@@ -0,0 +1,213 @@
+use super::field_expr::FieldExpr;
+use std::ops::Range;
+use types::LhsValue;
+
+/// How a single comparison evaluated during
+/// [`FilterAst::execute_traced`](::FilterAst::execute_traced).
+#[derive(Debug, PartialEq)]
+pub struct TraceEntry<'a, 's, 'e> {
+    /// The comparison that was evaluated.
+    pub comparison: &'a FieldExpr<'s>,
+    /// Whether regular execution would have evaluated the comparison, rather
+    /// than skipping it because the result was already decided by the
+    /// previous items of an `and` or an `or`.
+    pub evaluated: bool,
+    /// The value of the compared field, or `None` if it wasn't set.
+    pub value: Option<LhsValue<'e>>,
+    /// Whether the comparison matched.
+    pub result: bool,
+}
+
+impl<'a, 's, 'e> TraceEntry<'a, 's, 'e> {
+    /// Returns the byte range of the comparison within `input`, which must be
+    /// the same string the filter was parsed from, e.g. to highlight it.
+    ///
+    /// Returns `None` for comparisons created with a
+    /// [`FilterBuilder`](::FilterBuilder) or if the input doesn't match.
+    pub fn source_range(&self, input: &str) -> Option<Range<usize>> {
+        self.comparison.source_range(input)
+    }
+}
+
+/// A record of how each comparison of a filter evaluated, in the order they
+/// appear in the filter.
+#[derive(Debug, PartialEq)]
+pub struct Trace<'a, 's, 'e> {
+    pub(crate) entries: Vec<TraceEntry<'a, 's, 'e>>,
+}
+
+impl<'a, 's, 'e> Trace<'a, 's, 'e> {
+    /// Returns results of all comparisons.
+    ///
+    /// Unlike regular execution, tracing doesn't short-circuit, so there's
+    /// one entry per item of [`FilterAst::comparisons`](::FilterAst::comparisons),
+    /// and the ones that would have been skipped are marked as not
+    /// [`evaluated`](TraceEntry::evaluated).
+    pub fn entries(&self) -> &[TraceEntry<'a, 's, 'e>] {
+        &self.entries
+    }
+}
+
+#[test]
+fn test_execute_matching_leaves() {
+    use execution_context::ExecutionContext;
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ssl: Bool,
+        tcp.port: Int,
+    };
+
+    let ast = scheme
+        .parse(r#"tcp.port == 22 || (http.host == "example.org" && tcp.port in { 80 443 }) || tcp.port > 0"#)
+        .unwrap();
+    let comparisons = ast.comparisons();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+
+    // `tcp.port > 0` matches too, but is never reached.
+    assert_eq!(
+        ast.execute_matching_leaves(&ctx).unwrap(),
+        (true, vec![comparisons[1], comparisons[2]])
+    );
+
+    ctx.set_field_value("tcp.port", 8080).unwrap();
+
+    // `http.host == "example.org"` matches, but its `and` doesn't.
+    assert_eq!(
+        ast.execute_matching_leaves(&ctx).unwrap(),
+        (true, vec![comparisons[3]])
+    );
+
+    ctx.set_field_value("tcp.port", -1).unwrap();
+    assert_eq!(ast.execute_matching_leaves(&ctx).unwrap(), (false, vec![]));
+
+    let ast = scheme.parse("not ssl && tcp.port == -1").unwrap();
+    ctx.set_field_value("ssl", false).unwrap();
+    assert_eq!(
+        ast.execute_matching_leaves(&ctx).unwrap(),
+        (true, vec![ast.comparisons()[1]])
+    );
+}
+
+#[test]
+fn test_execute_traced() {
+    use execution_context::ExecutionContext;
+    use filter::SchemeMismatchError;
+    use std::net::IpAddr;
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ip.addr: Ip,
+        ssl: Bool,
+        tcp.port: Int,
+    };
+
+    let ast = scheme
+        .parse(r#"http.host == "example.org" && (tcp.port in { 80 443 } || not ssl) ^^ ip.addr == 10.0.0.0/8"#)
+        .unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("tcp.port", 8080).unwrap();
+    ctx.set_field_value("ssl", false).unwrap();
+
+    let (result, trace) = ast.execute_traced(&ctx).unwrap();
+
+    assert_eq!(result, ast.clone().compile().execute(&ctx).unwrap());
+    assert_eq!(result, true);
+
+    let comparisons = ast.comparisons();
+    assert_eq!(
+        trace.entries(),
+        &[
+            TraceEntry {
+                comparison: comparisons[0],
+                evaluated: true,
+                value: Some(LhsValue::Bytes(b"example.org")),
+                result: true,
+            },
+            TraceEntry {
+                comparison: comparisons[1],
+                evaluated: true,
+                value: Some(LhsValue::Int(8080)),
+                result: false,
+            },
+            TraceEntry {
+                comparison: comparisons[2],
+                evaluated: true,
+                value: Some(LhsValue::Bool(false)),
+                result: false,
+            },
+            TraceEntry {
+                comparison: comparisons[3],
+                evaluated: true,
+                value: None,
+                result: false,
+            },
+        ][..]
+    );
+
+    ctx.set_field_value("ip.addr", IpAddr::from([10, 0, 0, 1]))
+        .unwrap();
+
+    let (result, trace) = ast.execute_traced(&ctx).unwrap();
+    assert_eq!(result, false);
+    assert_eq!(
+        trace
+            .entries()
+            .iter()
+            .map(|entry| entry.result)
+            .collect::<Vec<_>>(),
+        vec![true, false, false, true]
+    );
+
+    let other_scheme = Scheme! { http.host: Bytes };
+    let ctx = ExecutionContext::new(&other_scheme);
+    assert_eq!(ast.execute_traced(&ctx), Err(SchemeMismatchError));
+}
+
+#[test]
+fn test_trace_evaluated() {
+    use execution_context::ExecutionContext;
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ssl: Bool,
+        tcp.port: Int,
+    };
+
+    let input = r#"tcp.port == 80 && ssl || http.host == "a" || (tcp.port == 443 && not ssl)"#;
+    let ast = scheme.parse(input).unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "a").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    ctx.set_field_value("ssl", false).unwrap();
+
+    let (result, trace) = ast.execute_traced(&ctx).unwrap();
+    assert_eq!(result, true);
+
+    // `ssl` is skipped after `tcp.port == 80`, and the last group after
+    // `http.host == "a"` matched.
+    assert_eq!(
+        trace
+            .entries()
+            .iter()
+            .map(|entry| (
+                &input[entry.source_range(input).unwrap()],
+                entry.evaluated,
+                entry.result
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("tcp.port == 80", true, false),
+            ("ssl", false, false),
+            (r#"http.host == "a""#, true, true),
+            ("tcp.port == 443", false, true),
+            ("ssl", false, false),
+        ]
+    );
+}
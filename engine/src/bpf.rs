@@ -0,0 +1,255 @@
+use ast::FilterAst;
+use failure::Fail;
+use scheme::Scheme;
+
+/// An error that occurs when translating a BPF expression.
+#[derive(Debug, PartialEq, Fail)]
+pub enum BpfError {
+    /// The expression uses a construct outside of the supported subset.
+    #[fail(display = "unsupported BPF construct `{}`", _0)]
+    Unsupported(String),
+
+    /// The expression ended where more of it was expected.
+    #[fail(display = "unexpected end of BPF expression")]
+    UnexpectedEnd,
+
+    /// None of the fields a primitive maps to are in the scheme.
+    #[fail(display = "no fields for `{}` in the scheme", _0)]
+    MissingFields(String),
+
+    /// The translated filter doesn't parse, e.g. because of an invalid
+    /// address.
+    #[fail(display = "{}", _0)]
+    Parse(String),
+}
+
+fn tokenize(input: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if rest.starts_with("&&") || rest.starts_with("||") {
+            2
+        } else {
+            match rest.find(|c: char| c.is_whitespace() || "()!&|".contains(c)) {
+                Some(0) => c.len_utf8(),
+                Some(len) => len,
+                None => rest.len(),
+            }
+        };
+
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+
+    tokens
+}
+
+struct Translator<'i, 's> {
+    scheme: &'s Scheme,
+    tokens: Vec<&'i str>,
+    pos: usize,
+}
+
+impl<'i, 's> Translator<'i, 's> {
+    fn peek(&self) -> Option<&'i str> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Result<&'i str, BpfError> {
+        let token = self.peek().ok_or(BpfError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, words: &[&str]) -> Option<&'i str> {
+        let token = self.peek().filter(|token| words.contains(token))?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    fn or_expr(&mut self) -> Result<String, BpfError> {
+        let mut items = vec![self.and_expr()?];
+        while self.eat(&["or", "||"]).is_some() {
+            items.push(self.and_expr()?);
+        }
+        Ok(items.join(" or "))
+    }
+
+    fn and_expr(&mut self) -> Result<String, BpfError> {
+        let mut items = vec![self.unary_expr()?];
+        while self.eat(&["and", "&&"]).is_some() {
+            items.push(self.unary_expr()?);
+        }
+        Ok(items.join(" and "))
+    }
+
+    fn unary_expr(&mut self) -> Result<String, BpfError> {
+        if self.eat(&["not", "!"]).is_some() {
+            Ok(format!("not {}", self.unary_expr()?))
+        } else if self.eat(&["("]).is_some() {
+            let expr = self.or_expr()?;
+            match self.next()? {
+                ")" => Ok(format!("({})", expr)),
+                token => Err(BpfError::Unsupported(token.to_owned())),
+            }
+        } else {
+            self.primitive()
+        }
+    }
+
+    // Lexes `[tcp|udp] [src|dst] host|net|port value`.
+    fn primitive(&mut self) -> Result<String, BpfError> {
+        let proto = self.eat(&["tcp", "udp"]);
+        let dir = self.eat(&["src", "dst"]);
+        let kind = self.next()?;
+
+        let fields = match (kind, proto) {
+            ("host", None) | ("net", None) => match dir {
+                Some("src") => vec!["ip.src".to_owned()],
+                Some(_) => vec!["ip.dst".to_owned()],
+                None => vec!["ip.src".to_owned(), "ip.dst".to_owned()],
+            },
+            ("port", _) => {
+                let suffix = match dir {
+                    Some("src") => "srcport",
+                    Some(_) => "dstport",
+                    None => "port",
+                };
+                let protos = match proto {
+                    Some(proto) => vec![proto],
+                    None => vec!["tcp", "udp"],
+                };
+                protos
+                    .iter()
+                    .map(|proto| format!("{}.{}", proto, suffix))
+                    .collect()
+            }
+            (_, Some(proto)) => return Err(BpfError::Unsupported(proto.to_owned())),
+            _ => return Err(BpfError::Unsupported(kind.to_owned())),
+        };
+
+        let value = self.next()?;
+        if !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".:/".contains(c))
+        {
+            return Err(BpfError::Unsupported(value.to_owned()));
+        }
+
+        let comparisons = fields
+            .iter()
+            .filter(|name| self.scheme.get_field_index(name).is_ok())
+            .map(|name| match kind {
+                "net" => format!("{} in {{ {} }}", name, value),
+                _ => format!("{} == {}", name, value),
+            })
+            .collect::<Vec<_>>();
+
+        match comparisons.len() {
+            0 => Err(BpfError::MissingFields(
+                self.tokens[self.pos - 2].to_owned(),
+            )),
+            1 => Ok(comparisons.join("")),
+            _ => Ok(format!("({})", comparisons.join(" or "))),
+        }
+    }
+}
+
+/// Translates a tcpdump-like BPF expression into an equivalent filter.
+///
+/// Only a small subset of the syntax is supported:
+///  - `host <addr>` and `net <cidr>` compare `ip.src` or `ip.dst`;
+///  - `port <port>` compares `tcp.port` or `udp.port`;
+///  - `src` or `dst` before any of those, like `src host 10.0.0.1`, picks
+///    only one direction (`ip.src`, `tcp.srcport` and so on);
+///  - `tcp` or `udp` before `port` picks only one protocol;
+///  - `and`, `or`, `not`, their `&&`, `||`, `!` spellings and parentheses.
+///
+/// Fields the scheme doesn't have are left out, so that `port 53` is just
+/// `udp.port == 53` for a scheme without `tcp.port`.
+pub fn translate_bpf(scheme: &Scheme, input: &str) -> Result<String, BpfError> {
+    let mut translator = Translator {
+        scheme,
+        tokens: tokenize(input),
+        pos: 0,
+    };
+
+    let filter = translator.or_expr()?;
+
+    match translator.peek() {
+        Some(token) => Err(BpfError::Unsupported(token.to_owned())),
+        None => Ok(filter),
+    }
+}
+
+/// Translates a BPF expression with [`translate_bpf`] and parses the result.
+pub fn parse_bpf<'s>(scheme: &'s Scheme, input: &str) -> Result<FilterAst<'s>, BpfError> {
+    let filter = translate_bpf(scheme, input)?;
+    scheme
+        .parse(&filter)
+        .map_err(|err| BpfError::Parse(err.to_string()))
+}
+
+#[test]
+fn test_translate_bpf() {
+    use execution_context::ExecutionContext;
+    use std::net::IpAddr;
+
+    let scheme = &Scheme! {
+        ip.src: Ip,
+        ip.dst: Ip,
+        tcp.port: Int,
+        tcp.dstport: Int,
+        udp.port: Int,
+    };
+
+    assert_eq!(
+        translate_bpf(scheme, "host 10.0.0.1 and port 443"),
+        Ok(
+            "(ip.src == 10.0.0.1 or ip.dst == 10.0.0.1) and (tcp.port == 443 or udp.port == 443)"
+                .to_owned()
+        )
+    );
+
+    assert_eq!(
+        translate_bpf(scheme, "src net 10.0.0.0/8 || !(tcp dst port 22)"),
+        Ok("ip.src in { 10.0.0.0/8 } or not (tcp.dstport == 22)".to_owned())
+    );
+
+    let filter = parse_bpf(scheme, "dst host ::1 && not udp port 53")
+        .unwrap()
+        .compile();
+    let mut ctx = ExecutionContext::new(scheme);
+    ctx.set_field_value("ip.dst", IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]))
+        .unwrap();
+    ctx.set_field_value("udp.port", 443).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    ctx.set_field_value("udp.port", 53).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    assert_eq!(
+        translate_bpf(scheme, "ether host 00:11:22:33:44:55"),
+        Err(BpfError::Unsupported("ether".to_owned()))
+    );
+    assert_eq!(
+        translate_bpf(scheme, "host 10.0.0.1 or 10.0.0.2"),
+        Err(BpfError::Unsupported("10.0.0.2".to_owned()))
+    );
+    assert_eq!(
+        translate_bpf(scheme, "tcp host 10.0.0.1"),
+        Err(BpfError::Unsupported("tcp".to_owned()))
+    );
+    assert_eq!(
+        translate_bpf(scheme, "(port 80"),
+        Err(BpfError::UnexpectedEnd)
+    );
+    assert_eq!(
+        translate_bpf(scheme, "src port 80"),
+        Err(BpfError::MissingFields("port".to_owned()))
+    );
+    match parse_bpf(scheme, "host 10.0.0.256") {
+        Err(BpfError::Parse(_)) => {}
+        res => panic!("Expected a parse error, got {:?}", res.map(|_| ())),
+    }
+}
@@ -1,12 +1,22 @@
+use cidr::{Cidr, IpCidr};
 use failure::Fail;
+use fnv::FnvBuildHasher;
+use range_set::RangeSet;
+use rhs_types::{Regex, RegexError};
 use scheme::{Field, Scheme};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    sync::Mutex,
+};
 use types::{GetType, LhsValue, Type};
 
 /// An error that occurs if the type of the value for the field doesn't
 /// match the type specified in the [`Scheme`](struct@Scheme).
 #[derive(Debug, PartialEq, Fail)]
 #[fail(
-    display = "the field should have {:?} type, but {:?} was provided",
+    display = "the field should have {} type, but {} was provided",
     field_type, value_type
 )]
 pub struct FieldValueTypeMismatchError {
@@ -16,14 +26,191 @@ pub struct FieldValueTypeMismatchError {
     pub value_type: Type,
 }
 
+/// An error that occurs when setting a value of a field with
+/// [`ExecutionContext::set_field_value`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum SetFieldValueError {
+    /// The value doesn't have the type of the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] FieldValueTypeMismatchError),
+
+    /// The value was rejected by the validator of the field registered with
+    /// [`Scheme::add_field_validator`](::Scheme::add_field_validator).
+    #[fail(display = "invalid value of field {}: {}", field_name, reason)]
+    InvalidValue {
+        /// The name of the field.
+        field_name: String,
+        /// The reason returned by the validator.
+        reason: String,
+    },
+}
+
+impl From<FieldValueTypeMismatchError> for SetFieldValueError {
+    fn from(err: FieldValueTypeMismatchError) -> Self {
+        SetFieldValueError::TypeMismatch(err)
+    }
+}
+
+enum FieldValue<'e> {
+    Unset,
+    Value(LhsValue<'e>),
+    Lazy {
+        accessor: Box<dyn 'e + Fn() -> LhsValue<'e> + Send + Sync>,
+        // Filled on first access, so that the accessor is called at most once.
+        value: Mutex<Option<LhsValue<'e>>>,
+    },
+}
+
+// Items of a list supplied at execution time, indexed for lookups.
+pub(crate) enum ListValues<'e> {
+    Ip(RangeSet<Ipv4Addr>, RangeSet<Ipv6Addr>),
+    Bytes(HashSet<&'e [u8], FnvBuildHasher>),
+    Int(RangeSet<i32>),
+}
+
+impl<'e> ListValues<'e> {
+    pub(crate) fn contains(&self, value: &LhsValue<'_>) -> bool {
+        match (self, value) {
+            (ListValues::Ip(v4, _), LhsValue::Ip(IpAddr::V4(addr))) => v4.contains(addr),
+            (ListValues::Ip(_, v6), LhsValue::Ip(IpAddr::V6(addr))) => v6.contains(addr),
+            (ListValues::Bytes(values), LhsValue::Bytes(value)) => values.contains(value),
+            (ListValues::Int(values), LhsValue::Int(value)) => values.contains(value),
+            _ => false,
+        }
+    }
+}
+
 /// An execution context stores an associated [`Scheme`](struct@Scheme) and a
 /// set of runtime values to execute [`Filter`](::Filter) against.
 ///
 /// It acts as a map in terms of public API, but provides a constant-time
 /// index-based access to values for a filter during execution.
+///
+/// Bytes values are borrowed and never copied, so they can point directly
+/// into an existing buffer, like a captured packet, as long as the context
+/// doesn't outlive that buffer:
+///
+/// ```compile_fail
+/// use wirefilter::{ExecutionContext, Scheme};
+///
+/// let scheme = Scheme! { http.host: Bytes };
+/// let mut ctx = ExecutionContext::new(&scheme);
+///
+/// {
+///     let packet = b"Host: example.org".to_vec();
+///     ctx.set_field_value("http.host", &packet[6..]).unwrap();
+/// }
+///
+/// ctx.set_field_value("http.host", "example.com").unwrap();
+/// ```
+///
+/// Values can also be provided lazily via
+/// [`set_field_accessor`](ExecutionContext::set_field_accessor), or for all
+/// fields at once via
+/// [`set_field_resolver`](ExecutionContext::set_field_resolver), so that
+/// expensive ones are computed only if a filter actually needs them.
+///
+/// It also stores named patterns for `field matches $name` comparisons,
+/// which allow to supply regular expressions at runtime instead of embedding
+/// them into the filter, values of constants for comparisons like
+/// `field > $name`, and lists for `field in $name`.
+///
+/// Contexts are `Send` and `Sync`, so accessors, resolvers and providers
+/// have to be as well.
 pub struct ExecutionContext<'e> {
     scheme: &'e Scheme,
-    values: Box<[Option<LhsValue<'e>>]>,
+    values: Box<[FieldValue<'e>]>,
+    patterns: HashMap<String, Regex, FnvBuildHasher>,
+    constants: HashMap<String, LhsValue<'e>, FnvBuildHasher>,
+    lists: HashMap<String, ListValues<'e>, FnvBuildHasher>,
+    // Like `values`, the rest of per-field state is indexed by fields rather
+    // than looked up in maps, as it's read on every execution.
+    zones: Box<[Option<String>]>,
+    maps: Box<[Option<MapValues<'e>>]>,
+    arrays: Box<[Option<Vec<LhsValue<'e>>>]>,
+    normalize_ipv4_mapped: bool,
+    max_regex_input_len: Option<usize>,
+    provider: Option<&'e dyn FieldProvider<'e>>,
+    resolver: Option<Mutex<Box<FieldResolver<'e>>>>,
+    // Results of the resolver by field index, including unset fields.
+    resolved: Mutex<Box<[Resolved<'e>]>>,
+}
+
+// Keys can be repeated, like HTTP headers, so each one has all of its values
+// in the order they were added.
+type MapValues<'e> = HashMap<Box<[u8]>, Vec<LhsValue<'e>>, FnvBuildHasher>;
+
+#[derive(Clone)]
+enum Resolved<'e> {
+    Pending,
+    Done(Option<LhsValue<'e>>),
+}
+
+fn per_field<T>(scheme: &Scheme) -> Box<[Option<T>]> {
+    (0..scheme.get_field_count()).map(|_| None).collect()
+}
+
+type FieldResolver<'e> = dyn 'e + FnMut(&str) -> Option<LhsValue<'e>> + Send + Sync;
+
+/// A source of field values that are looked up by name during execution,
+/// as an alternative to setting each of them on an [`ExecutionContext`].
+///
+/// This is useful when values come from an existing structure, so that
+/// filters can read them from it directly.
+///
+/// Providers must be `Send` and `Sync`, so that contexts that use them can
+/// still be shared between threads.
+pub trait FieldProvider<'e>: Send + Sync {
+    /// Returns the value of a field, or `None` if it's not set.
+    ///
+    /// This is called every time a filter reads the field, so it should be
+    /// cheap. The value must have the type specified in the
+    /// [`Scheme`](struct@Scheme) and pass the validator of the field, if
+    /// any, or the execution panics.
+    fn get_field(&self, name: &str) -> Option<LhsValue<'e>>;
+}
+
+// Values computed during execution can't be checked upfront, so a wrong type
+// or a value rejected by the validator of the field is a bug in the code that
+// provides them.
+fn check_value_type<'e>(field: Field<'_>, value: LhsValue<'e>) -> LhsValue<'e> {
+    let field_type = field.get_type();
+    let value_type = value.get_type();
+
+    if field_type != value_type {
+        panic!(
+            "{}",
+            FieldValueTypeMismatchError {
+                field_type,
+                value_type,
+            }
+        );
+    }
+
+    if let Err(reason) = field.scheme().validate_field_value(field, &value) {
+        panic!(
+            "{}",
+            SetFieldValueError::InvalidValue {
+                field_name: field.name().to_owned(),
+                reason,
+            }
+        );
+    }
+
+    value
+}
+
+// Returns the IPv4 address `::ffff:a.b.c.d` maps to, if it's in that form.
+fn ipv4_mapped(addr: &Ipv6Addr) -> Option<IpAddr> {
+    match addr.segments() {
+        [0, 0, 0, 0, 0, 0xffff, _, _] => {
+            let octets = addr.octets();
+            Some(IpAddr::from([
+                octets[12], octets[13], octets[14], octets[15],
+            ]))
+        }
+        _ => None,
+    }
 }
 
 impl<'e> ExecutionContext<'e> {
@@ -33,46 +220,330 @@ impl<'e> ExecutionContext<'e> {
     pub fn new<'s: 'e>(scheme: &'s Scheme) -> Self {
         ExecutionContext {
             scheme,
-            values: vec![None; scheme.get_field_count()].into(),
+            values: (0..scheme.get_field_count())
+                .map(|_| FieldValue::Unset)
+                .collect(),
+            patterns: HashMap::default(),
+            constants: HashMap::default(),
+            lists: HashMap::default(),
+            zones: per_field(scheme),
+            maps: per_field(scheme),
+            arrays: per_field(scheme),
+            normalize_ipv4_mapped: false,
+            max_regex_input_len: None,
+            provider: None,
+            resolver: None,
+            resolved: Mutex::new(vec![Resolved::Pending; scheme.get_field_count()].into()),
+        }
+    }
+
+    /// Sets a provider to look up values of fields that weren't set on the
+    /// context itself.
+    ///
+    /// Values set with [`set_field_value`](ExecutionContext::set_field_value)
+    /// and similar take precedence over the provider.
+    pub fn set_field_provider(&mut self, provider: &'e dyn FieldProvider<'e>) {
+        self.provider = Some(provider);
+    }
+
+    /// Sets a closure to compute values of fields that weren't set on the
+    /// context itself, or by the [provider](ExecutionContext::set_field_provider).
+    ///
+    /// The closure is called with a field name the first time a filter reads
+    /// that field, and the result, including `None` for an unset field, is
+    /// memoised until another resolver is set. This way expensive fields,
+    /// like geo lookups, are computed only if some filter actually needs
+    /// them, and at most once for all filters executed against the context.
+    ///
+    /// # Panics
+    ///
+    /// Panics during execution if the closure returns a value of a type
+    /// different from the one specified in the [`Scheme`](struct@Scheme), or
+    /// one rejected by the validator of the field.
+    pub fn set_field_resolver<F>(&mut self, resolver: F)
+    where
+        F: 'e + FnMut(&str) -> Option<LhsValue<'e>> + Send + Sync,
+    {
+        self.resolver = Some(Mutex::new(Box::new(resolver)));
+        for value in self.resolved.get_mut().unwrap().iter_mut() {
+            *value = Resolved::Pending;
         }
     }
 
+    fn resolve(&self, field: Field<'_>) -> Option<LhsValue<'e>> {
+        let resolver = self.resolver.as_ref()?;
+
+        if let Resolved::Done(value) = &self.resolved.lock().unwrap()[field.index()] {
+            return value.clone();
+        }
+
+        // Concurrent executions may both call the resolver for the same
+        // field, but they store the same result.
+        let value = (&mut *resolver.lock().unwrap())(field.name())
+            .map(|value| check_value_type(field, value));
+        self.resolved.lock().unwrap()[field.index()] = Resolved::Done(value.clone());
+        value
+    }
+
     /// Returns an associated scheme.
     pub fn scheme(&self) -> &'e Scheme {
         self.scheme
     }
 
-    pub(crate) fn get_field_value_unchecked(&self, field: Field<'e>) -> &LhsValue<'e> {
+    /// Sets whether IPv4-mapped IPv6 addresses in values of `ip` fields, like
+    /// `::ffff:192.0.2.1`, are compared as the IPv4 addresses they map to,
+    /// like `192.0.2.1`.
+    ///
+    /// This applies to all comparisons, including `in` lists and networks,
+    /// but not to literals in the filter itself. It's disabled by default.
+    pub fn set_ipv4_mapped_normalization(&mut self, enabled: bool) {
+        self.normalize_ipv4_mapped = enabled;
+    }
+
+    /// Sets the zone identifier of an address in an `ip` field, like `eth0`
+    /// for `fe80::1%eth0`, or clears it with `None`.
+    ///
+    /// Zones are compared only by `==` and `!=` with literals that have a
+    /// zone themselves, which match only if both the address and the zone
+    /// are the same, so `fe80::1%eth0` doesn't match an address without a
+    /// zone. Literals without a zone, `in` lists and networks compare just
+    /// the address and ignore the zone. The zone is kept when the value of
+    /// the field changes.
+    pub fn set_ip_zone(
+        &mut self,
+        name: &str,
+        zone: Option<&str>,
+    ) -> Result<(), FieldValueTypeMismatchError> {
+        let field = self.scheme.get_field_index(name).unwrap();
+
+        if field.get_type() != Type::Ip {
+            return Err(FieldValueTypeMismatchError {
+                field_type: field.get_type(),
+                value_type: Type::Ip,
+            });
+        }
+
+        self.zones[field.index()] = zone.map(str::to_owned);
+        Ok(())
+    }
+
+    pub(crate) fn get_ip_zone(&self, field: Field<'_>) -> Option<&str> {
+        self.zones[field.index()].as_ref().map(String::as_str)
+    }
+
+    pub(crate) fn get_field_value_unchecked(&self, field: Field<'_>) -> Option<LhsValue<'e>> {
+        self.normalize(self.get_raw_field_value_unchecked(field))
+    }
+
+    // Returns the first value of a key, so that items with a single value
+    // don't need a `[*]`.
+    pub(crate) fn get_map_value_unchecked(
+        &self,
+        field: Field<'_>,
+        key: &[u8],
+    ) -> Option<LhsValue<'e>> {
+        self.get_map_value_at_unchecked(field, key, 0)
+    }
+
+    pub(crate) fn get_map_value_count(&self, field: Field<'_>, key: &[u8]) -> Option<usize> {
+        self.maps[field.index()]
+            .as_ref()
+            .and_then(|map| map.get(key))
+            .map(Vec::len)
+    }
+
+    pub(crate) fn get_map_value_at_unchecked(
+        &self,
+        field: Field<'_>,
+        key: &[u8],
+        index: usize,
+    ) -> Option<LhsValue<'e>> {
+        self.normalize(
+            self.maps[field.index()]
+                .as_ref()
+                .and_then(|map| map.get(key))
+                .and_then(|values| values.get(index))
+                .cloned(),
+        )
+    }
+
+    pub(crate) fn get_array_len(&self, field: Field<'_>) -> Option<usize> {
+        self.arrays[field.index()].as_ref().map(Vec::len)
+    }
+
+    pub(crate) fn get_array_element_unchecked(
+        &self,
+        field: Field<'_>,
+        index: usize,
+    ) -> Option<LhsValue<'e>> {
+        self.normalize(
+            self.arrays[field.index()]
+                .as_ref()
+                .and_then(|elements| elements.get(index))
+                .cloned(),
+        )
+    }
+
+    fn normalize(&self, value: Option<LhsValue<'e>>) -> Option<LhsValue<'e>> {
+        if self.normalize_ipv4_mapped {
+            if let Some(LhsValue::Ip(IpAddr::V6(addr))) = value {
+                if let Some(addr) = ipv4_mapped(&addr) {
+                    return Some(LhsValue::Ip(addr));
+                }
+            }
+        }
+
+        value
+    }
+
+    fn get_raw_field_value_unchecked(&self, field: Field<'_>) -> Option<LhsValue<'e>> {
         // This is safe because this code is reachable only from Filter::execute
         // which already performs the scheme compatibility check, but check that
         // invariant holds in the future at least in the debug mode.
         debug_assert!(self.scheme() == field.scheme());
 
-        // For now we panic in this, but later we are going to align behaviour
-        // with wireshark: resolve all subexpressions that don't have RHS value
-        // to `false`.
-        self.values[field.index()].as_ref().unwrap_or_else(|| {
-            panic!(
-                "Field {} was registered but not given a value",
-                field.name()
-            );
-        })
+        // Fields that were registered but not given a value are returned as
+        // `None`, and, aligned with wireshark behaviour, all subexpressions
+        // that don't have a value to compare resolve to `false`.
+        //
+        // This means that an unset field is not the same as an empty one:
+        // `field == ""` doesn't match if `field` was never set.
+        match &self.values[field.index()] {
+            FieldValue::Unset => self
+                .provider
+                .and_then(|provider| provider.get_field(field.name()))
+                .map(|value| check_value_type(field, value))
+                .or_else(|| self.resolve(field)),
+            FieldValue::Value(value) => Some(value.clone()),
+            FieldValue::Lazy { accessor, value } => Some(
+                value
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(|| check_value_type(field, accessor()))
+                    .clone(),
+            ),
+        }
     }
 
-    /// Sets a runtime value for a given field name.
-    pub fn set_field_value<'v: 'e, V: Into<LhsValue<'v>>>(
+    pub(crate) fn set_field_value_unchecked(&mut self, field: Field<'_>, value: LhsValue<'e>) {
+        debug_assert!(self.scheme() == field.scheme());
+        debug_assert!(field.get_type() == value.get_type());
+
+        self.values[field.index()] = FieldValue::Value(value);
+    }
+
+    /// Limits the length of values `matches` comparisons run regexes on, as
+    /// a bound on the time spent matching huge inputs.
+    ///
+    /// Comparisons of longer values don't match without running the regex,
+    /// like comparisons of unset fields, so `field !~ "..."` is true for
+    /// them. There's no limit by default.
+    pub fn set_max_regex_input_len(&mut self, limit: Option<usize>) {
+        self.max_regex_input_len = limit;
+    }
+
+    pub(crate) fn fits_regex_input_limit(&self, value: &[u8]) -> bool {
+        self.max_regex_input_len
+            .map_or(true, |limit| value.len() <= limit)
+    }
+
+    pub(crate) fn get_pattern(&self, name: &str) -> Option<&Regex> {
+        // Like unset fields, missing patterns make the comparison `false`.
+        self.patterns.get(name)
+    }
+
+    /// Compiles and sets a regular expression for a `$name` pattern.
+    ///
+    /// Comparisons referencing a pattern that was never set don't match.
+    pub fn set_pattern(&mut self, name: &str, pattern: &str) -> Result<(), RegexError> {
+        let regex = Regex::from_str(pattern)?;
+        self.patterns.insert(name.to_owned(), regex);
+        Ok(())
+    }
+
+    pub(crate) fn get_constant(&self, name: &str) -> Option<&LhsValue<'e>> {
+        // Like unset fields, missing constants make the comparison `false`.
+        self.constants.get(name)
+    }
+
+    pub(crate) fn get_list(&self, name: &str) -> Option<&ListValues<'e>> {
+        // Like unset fields, missing lists make the comparison `false`.
+        self.lists.get(name)
+    }
+
+    /// Sets the items of a list registered with
+    /// [`Scheme::add_list`](::Scheme::add_list), replacing the previous ones.
+    ///
+    /// Items of `ip` lists can be both addresses and `cidr` networks, which
+    /// contain all of their addresses. Comparisons referencing a list that
+    /// was never set don't match, while an empty list just contains nothing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the list isn't registered in the scheme.
+    pub fn set_list<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        name: &str,
+        items: impl IntoIterator<Item = V>,
+    ) -> Result<(), FieldValueTypeMismatchError> {
+        let field_type = self.scheme.get_list_type(name).unwrap();
+
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        let mut bytes = HashSet::default();
+        let mut ints = Vec::new();
+
+        for item in items {
+            match (field_type, item.into()) {
+                (Type::Ip, LhsValue::Ip(IpAddr::V4(addr))) => v4.push(addr..=addr),
+                (Type::Ip, LhsValue::Ip(IpAddr::V6(addr))) => v6.push(addr..=addr),
+                (Type::Ip, LhsValue::Cidr(IpCidr::V4(network))) => {
+                    v4.push(network.first_address()..=network.last_address())
+                }
+                (Type::Ip, LhsValue::Cidr(IpCidr::V6(network))) => {
+                    v6.push(network.first_address()..=network.last_address())
+                }
+                (Type::Bytes, LhsValue::Bytes(value)) => {
+                    bytes.insert(value);
+                }
+                (Type::Int, LhsValue::Int(value)) => ints.push(value..=value),
+                (field_type, value) => {
+                    return Err(FieldValueTypeMismatchError {
+                        field_type,
+                        value_type: value.get_type(),
+                    });
+                }
+            }
+        }
+
+        let values = match field_type {
+            Type::Ip => ListValues::Ip(v4.into(), v6.into()),
+            Type::Bytes => ListValues::Bytes(bytes),
+            _ => ListValues::Int(ints.into()),
+        };
+        self.lists.insert(name.to_owned(), values);
+        Ok(())
+    }
+
+    /// Sets a runtime value for a constant registered with
+    /// [`Scheme::add_constant`](::Scheme::add_constant).
+    ///
+    /// Comparisons referencing a constant that was never set don't match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the constant isn't registered in the scheme.
+    pub fn set_constant<'v: 'e, V: Into<LhsValue<'v>>>(
         &mut self,
         name: &str,
         value: V,
     ) -> Result<(), FieldValueTypeMismatchError> {
-        let field = self.scheme.get_field_index(name).unwrap();
+        let field_type = self.scheme.get_constant_type(name).unwrap();
         let value = value.into();
-
-        let field_type = field.get_type();
         let value_type = value.get_type();
 
         if field_type == value_type {
-            self.values[field.index()] = Some(value);
+            self.constants.insert(name.to_owned(), value);
             Ok(())
         } else {
             Err(FieldValueTypeMismatchError {
@@ -81,6 +552,396 @@ impl<'e> ExecutionContext<'e> {
             })
         }
     }
+
+    fn check_field_value(
+        &self,
+        field: Field<'_>,
+        value: &LhsValue<'_>,
+    ) -> Result<(), SetFieldValueError> {
+        let field_type = field.get_type();
+        let value_type = value.get_type();
+
+        if field_type != value_type {
+            return Err(SetFieldValueError::TypeMismatch(
+                FieldValueTypeMismatchError {
+                    field_type,
+                    value_type,
+                },
+            ));
+        }
+
+        self.scheme
+            .validate_field_value(field, value)
+            .map_err(|reason| SetFieldValueError::InvalidValue {
+                field_name: field.name().to_owned(),
+                reason,
+            })
+    }
+
+    /// Sets a runtime value for a given field name.
+    ///
+    /// The value is checked by the validator of the field, if the scheme has
+    /// one, and the previous value is kept if it's rejected.
+    pub fn set_field_value<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        name: &str,
+        value: V,
+    ) -> Result<(), SetFieldValueError> {
+        let field = self.scheme.get_field_index(name).unwrap();
+        let value = value.into();
+
+        self.check_field_value(field, &value)?;
+
+        self.values[field.index()] = FieldValue::Value(value);
+        Ok(())
+    }
+
+    // Sets a value of a field resolved upfront, which is only type-checked in
+    // debug builds, as typed setters can't be called with a wrong type unless
+    // the handle is of a different field.
+    fn set_typed_value(
+        &mut self,
+        field: Field<'_>,
+        value: LhsValue<'e>,
+    ) -> Result<(), SetFieldValueError> {
+        debug_assert!(self.scheme() == field.scheme());
+        debug_assert!(
+            field.get_type() == value.get_type(),
+            "{} field is of {} type, but {} value was set",
+            field.name(),
+            field.get_type(),
+            value.get_type()
+        );
+
+        if let Err(reason) = self.scheme.validate_field_value(field, &value) {
+            return Err(SetFieldValueError::InvalidValue {
+                field_name: field.name().to_owned(),
+                reason,
+            });
+        }
+
+        self.values[field.index()] = FieldValue::Value(value);
+        Ok(())
+    }
+
+    /// Sets a value of a `bytes` field resolved with
+    /// [`Scheme::get_field`](::Scheme::get_field).
+    ///
+    /// Unlike [`set_field_value`](ExecutionContext::set_field_value), this
+    /// doesn't look up the field by name, and the type is checked only in
+    /// debug builds, so it's meant for hot paths that set the same fields
+    /// for every request. The value is still checked by the validator of
+    /// the field, if the scheme has one.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if the field is of a different type or belongs
+    /// to a different scheme.
+    pub fn set_bytes_value(
+        &mut self,
+        field: Field<'_>,
+        value: &'e [u8],
+    ) -> Result<(), SetFieldValueError> {
+        self.set_typed_value(field, LhsValue::Bytes(value))
+    }
+
+    /// Sets a value of an `ip` field, like
+    /// [`set_bytes_value`](ExecutionContext::set_bytes_value).
+    pub fn set_ip_value(
+        &mut self,
+        field: Field<'_>,
+        value: IpAddr,
+    ) -> Result<(), SetFieldValueError> {
+        self.set_typed_value(field, LhsValue::Ip(value))
+    }
+
+    /// Sets a value of an `int` field, like
+    /// [`set_bytes_value`](ExecutionContext::set_bytes_value).
+    pub fn set_int_value(
+        &mut self,
+        field: Field<'_>,
+        value: i32,
+    ) -> Result<(), SetFieldValueError> {
+        self.set_typed_value(field, LhsValue::Int(value))
+    }
+
+    /// Sets a value of a `bool` field, like
+    /// [`set_bytes_value`](ExecutionContext::set_bytes_value).
+    pub fn set_bool_value(
+        &mut self,
+        field: Field<'_>,
+        value: bool,
+    ) -> Result<(), SetFieldValueError> {
+        self.set_typed_value(field, LhsValue::Bool(value))
+    }
+
+    /// Sets a runtime value for a key of a map field registered with
+    /// [`Scheme::add_map_field`](::Scheme::add_map_field), replacing any
+    /// values the key already has.
+    ///
+    /// The value is checked the same way as by
+    /// [`set_field_value`](ExecutionContext::set_field_value).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field isn't a map field.
+    pub fn set_map_value<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        name: &str,
+        key: &str,
+        value: V,
+    ) -> Result<(), SetFieldValueError> {
+        let (field, value) = self.check_map_value(name, value)?;
+
+        self.maps[field.index()]
+            .get_or_insert_with(HashMap::default)
+            .insert(key.as_bytes().into(), vec![value]);
+        Ok(())
+    }
+
+    /// Adds another runtime value for a key of a map field, like for an HTTP
+    /// header that appears multiple times.
+    ///
+    /// Comparisons of the item, like `http.headers["cookie"] == "a"`, look at
+    /// the first value, while `http.headers["cookie"][*]` looks at all of
+    /// them. The value is checked the same way as by
+    /// [`set_map_value`](ExecutionContext::set_map_value).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field isn't a map field.
+    pub fn add_map_value<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        name: &str,
+        key: &str,
+        value: V,
+    ) -> Result<(), SetFieldValueError> {
+        let (field, value) = self.check_map_value(name, value)?;
+
+        self.maps[field.index()]
+            .get_or_insert_with(HashMap::default)
+            .entry(key.as_bytes().into())
+            .or_insert_with(Vec::new)
+            .push(value);
+        Ok(())
+    }
+
+    fn check_map_value<'v: 'e, V: Into<LhsValue<'v>>>(
+        &self,
+        name: &str,
+        value: V,
+    ) -> Result<(Field<'e>, LhsValue<'e>), SetFieldValueError> {
+        let field = self.scheme.get_field_index(name).unwrap();
+        assert!(field.is_map(), "{} is not a map field", name);
+
+        let value = value.into();
+
+        self.check_field_value(field, &value)?;
+        Ok((field, value))
+    }
+
+    /// Sets runtime values of elements of an array field registered with
+    /// [`Scheme::add_array_field`](::Scheme::add_array_field).
+    ///
+    /// Each element is checked the same way as by
+    /// [`set_field_value`](ExecutionContext::set_field_value), and the
+    /// previous elements are kept if any of them is rejected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field isn't an array field.
+    pub fn set_array_values<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        name: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<(), SetFieldValueError> {
+        let field = self.scheme.get_field_index(name).unwrap();
+        assert!(field.is_array(), "{} is not an array field", name);
+
+        let mut elements = Vec::new();
+        for value in values {
+            let value = value.into();
+            self.check_field_value(field, &value)?;
+            elements.push(value);
+        }
+
+        self.arrays[field.index()] = Some(elements);
+        Ok(())
+    }
+
+    /// Sets a lazy accessor for a given field name.
+    ///
+    /// The accessor is invoked only when a filter evaluates the field, so
+    /// it's skipped altogether if the field isn't used or the comparison is
+    /// short-circuited. Its result is memoised, so it's invoked at most once
+    /// until the field is set again, even if referenced multiple times.
+    ///
+    /// # Panics
+    ///
+    /// Panics during execution if the accessor returns a value of a type
+    /// different from the one specified in the [`Scheme`](struct@Scheme), or
+    /// one rejected by the validator of the field. Both are checked once,
+    /// when the accessor is first invoked.
+    pub fn set_field_accessor<F, V>(&mut self, name: &str, accessor: F)
+    where
+        F: 'e + Fn() -> V + Send + Sync,
+        V: Into<LhsValue<'e>>,
+    {
+        let field = self.scheme.get_field_index(name).unwrap();
+
+        self.values[field.index()] = FieldValue::Lazy {
+            accessor: Box::new(move || accessor().into()),
+            value: Mutex::new(None),
+        };
+    }
+}
+
+/// An error that occurs if a JSON value can't be converted to the type of
+/// the field specified in the [`Scheme`](struct@Scheme).
+#[cfg(feature = "json")]
+#[derive(Debug, PartialEq, Fail)]
+#[fail(
+    display = "the JSON value of field {} can't be converted to {} type",
+    field_name, field_type
+)]
+pub struct JsonValueTypeMismatchError {
+    /// The name of the field.
+    pub field_name: String,
+    /// The type of the field specified in the [`Scheme`](struct@Scheme).
+    pub field_type: Type,
+}
+
+/// An error that occurs when setting values of fields with
+/// [`ExecutionContext::set_json_values`].
+#[cfg(feature = "json")]
+#[derive(Debug, PartialEq, Fail)]
+pub enum SetJsonValuesError {
+    /// The JSON value can't be converted to the type of the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] JsonValueTypeMismatchError),
+
+    /// The converted value was rejected by the validator of the field
+    /// registered with
+    /// [`Scheme::add_field_validator`](::Scheme::add_field_validator).
+    #[fail(display = "invalid value of field {}: {}", field_name, reason)]
+    InvalidValue {
+        /// The name of the field.
+        field_name: String,
+        /// The reason returned by the validator.
+        reason: String,
+    },
+}
+
+#[cfg(feature = "json")]
+impl From<JsonValueTypeMismatchError> for SetJsonValuesError {
+    fn from(err: JsonValueTypeMismatchError) -> Self {
+        SetJsonValuesError::TypeMismatch(err)
+    }
+}
+
+#[cfg(feature = "json")]
+fn json_to_value(field_type: Type, json: &serde_json::Value) -> Option<LhsValue<'_>> {
+    use rhs_types::Timestamp;
+    use serde_json::Value;
+    use std::i32;
+
+    match (field_type, json) {
+        (Type::Bytes, Value::String(s)) => Some(LhsValue::Bytes(s.as_bytes())),
+        (Type::Ip, Value::String(s)) => s.parse().ok().map(LhsValue::Ip),
+        (Type::Int, Value::Number(n)) => n
+            .as_i64()
+            .filter(|&n| n >= i64::from(i32::MIN) && n <= i64::from(i32::MAX))
+            .map(|n| LhsValue::Int(n as i32)),
+        (Type::Bool, Value::Bool(b)) => Some(LhsValue::Bool(*b)),
+        (Type::Cidr, Value::String(s)) => s.parse().ok().map(LhsValue::Cidr),
+        (Type::Endpoint, Value::String(s)) => s.parse().ok().map(LhsValue::Endpoint),
+        (Type::Float, Value::Number(n)) => n.as_f64().map(LhsValue::Float),
+        (Type::Timestamp, Value::String(s)) => s.parse().ok().map(LhsValue::Timestamp),
+        (Type::Timestamp, Value::Number(n)) => n
+            .as_i64()
+            .and_then(|secs| Timestamp::new(secs, 0))
+            .map(LhsValue::Timestamp),
+        (Type::Mac, Value::String(s)) => s.parse().ok().map(LhsValue::Mac),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "json")]
+impl<'e> ExecutionContext<'e> {
+    /// Sets values of all fields from a JSON document.
+    ///
+    /// Dotted field names are resolved by walking nested objects, so
+    /// `http.host` is read from `{"http": {"host": "..."}}`. Fields that are
+    /// missing or `null` in the document are left unset.
+    ///
+    /// Strings are converted to bytes, or to IP addresses for IP fields,
+    /// numbers to integers (as long as they fit) and booleans to booleans.
+    /// Map fields are read from objects and array fields from arrays, with
+    /// each item converted by the same rules. Items of map fields can also be
+    /// arrays of values, for keys that appear multiple times. Any other
+    /// combination is reported as an error.
+    ///
+    /// Converted values, including items of map and array fields, are then
+    /// checked by validators of their fields. Fields before the one that
+    /// failed either check keep their new values.
+    pub fn set_json_values(
+        &mut self,
+        json: &'e serde_json::Value,
+    ) -> Result<(), SetJsonValuesError> {
+        use serde_json::Value;
+
+        let scheme = self.scheme;
+
+        for field in self.scheme.iter_fields() {
+            let name = field.name();
+            let field_type = field.get_type();
+
+            let json = match name.split('.').try_fold(json, |json, key| json.get(key)) {
+                None | Some(Value::Null) => continue,
+                Some(json) => json,
+            };
+
+            let mismatch = || JsonValueTypeMismatchError {
+                field_name: name.to_owned(),
+                field_type,
+            };
+
+            let convert = |json: &'e Value| -> Result<LhsValue<'e>, SetJsonValuesError> {
+                let value = json_to_value(field_type, json).ok_or_else(mismatch)?;
+                scheme
+                    .validate_field_value(field, &value)
+                    .map_err(|reason| SetJsonValuesError::InvalidValue {
+                        field_name: name.to_owned(),
+                        reason,
+                    })?;
+                Ok(value)
+            };
+
+            if field.is_array() {
+                let items = json.as_array().ok_or_else(mismatch)?;
+                let elements = items.iter().map(convert).collect::<Result<Vec<_>, _>>()?;
+                self.arrays[field.index()] = Some(elements);
+            } else if field.is_map() {
+                let items = json.as_object().ok_or_else(mismatch)?;
+                let map = self.maps[field.index()].get_or_insert_with(HashMap::default);
+
+                for (key, json) in items {
+                    let values = match json {
+                        Value::Array(items) => {
+                            items.iter().map(convert).collect::<Result<_, _>>()?
+                        }
+                        json => vec![convert(json)?],
+                    };
+                    map.insert(key.as_bytes().into(), values);
+                }
+            } else {
+                let value = convert(json)?;
+                self.values[field.index()] = FieldValue::Value(value);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[test]
@@ -91,9 +952,580 @@ fn test_field_value_type_mismatch() {
 
     assert_eq!(
         ctx.set_field_value("foo", LhsValue::Bool(false)),
-        Err(FieldValueTypeMismatchError {
-            field_type: Type::Int,
-            value_type: Type::Bool
+        Err(SetFieldValueError::TypeMismatch(
+            FieldValueTypeMismatchError {
+                field_type: Type::Int,
+                value_type: Type::Bool
+            }
+        ))
+    );
+}
+
+#[test]
+fn test_field_validator() {
+    use scheme::UnknownFieldError;
+
+    let mut scheme = Scheme! { tcp.port: Int, tcp.flags: Int };
+
+    scheme
+        .add_field_validator("tcp.port", |value| match value {
+            LhsValue::Int(port) if *port >= 0 && *port <= 65535 => Ok(()),
+            _ => Err("port must be in 0..65535".to_owned()),
+        })
+        .unwrap();
+
+    assert_eq!(
+        scheme.add_field_validator("tcp.bogus", |_| Ok(())),
+        Err(UnknownFieldError)
+    );
+
+    let filter = scheme.parse("tcp.port == 443").unwrap().compile();
+    let mut ctx = ExecutionContext::new(&scheme);
+
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    assert_eq!(
+        ctx.set_field_value("tcp.port", 65536),
+        Err(SetFieldValueError::InvalidValue {
+            field_name: "tcp.port".to_owned(),
+            reason: "port must be in 0..65535".to_owned(),
         })
     );
+    assert_eq!(
+        ctx.set_field_value("tcp.port", -1).unwrap_err().to_string(),
+        "invalid value of field tcp.port: port must be in 0..65535"
+    );
+
+    // The rejected value doesn't replace the previous one.
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    // Fields without a validator accept any value of their type.
+    ctx.set_field_value("tcp.flags", -1).unwrap();
+}
+
+#[test]
+fn test_borrowed_bytes() {
+    let scheme = Scheme! { http.host: Bytes };
+    let filter = scheme
+        .parse(r#"http.host contains "example""#)
+        .unwrap()
+        .compile();
+
+    let packet = b"Host: example.org\r\n".to_vec();
+    let host = &packet[6..17];
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", host).unwrap();
+
+    // The context should point right into the packet buffer.
+    match ctx.values[0] {
+        FieldValue::Value(LhsValue::Bytes(value)) => assert_eq!(value.as_ptr(), host.as_ptr()),
+        _ => unreachable!(),
+    }
+
+    assert_eq!(filter.execute(&ctx), Ok(true));
+}
+
+#[test]
+fn test_typed_setters() {
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+    scheme
+        .add_field_validator("tcp.port", |value| match value {
+            LhsValue::Int(port) if *port >= 0 => Ok(()),
+            _ => Err("port must not be negative".to_owned()),
+        })
+        .unwrap();
+
+    let filter = scheme
+        .parse(r#"http.host == "example.org" && ip.src == 10.0.0.1 && tcp.port == 443 && ssl"#)
+        .unwrap()
+        .compile();
+
+    let host = scheme.get_field("http.host").unwrap();
+    let ip = scheme.get_field("ip.src").unwrap();
+    let port = scheme.get_field("tcp.port").unwrap();
+    let ssl = scheme.get_field("ssl").unwrap();
+    assert_eq!(host.get_type(), Type::Bytes);
+    assert_eq!(port.name(), "tcp.port");
+    assert!(scheme.get_field("tcp.bogus").is_err());
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_bytes_value(host, b"example.org").unwrap();
+    ctx.set_ip_value(ip, IpAddr::from([10, 0, 0, 1])).unwrap();
+    ctx.set_int_value(port, 443).unwrap();
+    ctx.set_bool_value(ssl, true).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        ctx.set_int_value(port, -1),
+        Err(SetFieldValueError::InvalidValue {
+            field_name: "tcp.port".to_owned(),
+            reason: "port must not be negative".to_owned(),
+        })
+    );
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_bool_value(ssl, false).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "tcp.port field is of int type, but bool value was set")]
+fn test_typed_setter_type_mismatch() {
+    let scheme = Scheme! { tcp.port: Int };
+    let port = scheme.get_field("tcp.port").unwrap();
+    ExecutionContext::new(&scheme)
+        .set_bool_value(port, true)
+        .unwrap();
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_patterns() {
+    let scheme = Scheme! { http.host: Bytes };
+    let filter = scheme
+        .parse("http.host matches $blocklist")
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+
+    // missing patterns don't match
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_pattern("blocklist", r"^example\.(com|org)$")
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_pattern("blocklist", r"^example\.com$").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    assert!(ctx.set_pattern("blocklist", "(").is_err());
+}
+
+#[test]
+fn test_field_accessor() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let scheme = Scheme! { foo: Int, bar: Int };
+    let filter = scheme
+        .parse("foo == 1 || (bar > 1 && bar < 10)")
+        .unwrap()
+        .compile();
+
+    let foo_calls = AtomicUsize::new(0);
+    let bar_calls = AtomicUsize::new(0);
+    let calls = || {
+        (
+            foo_calls.load(Ordering::SeqCst),
+            bar_calls.load(Ordering::SeqCst),
+        )
+    };
+
+    {
+        let mut ctx = ExecutionContext::new(&scheme);
+
+        ctx.set_field_accessor("foo", || {
+            foo_calls.fetch_add(1, Ordering::SeqCst);
+            1
+        });
+        ctx.set_field_accessor("bar", || {
+            bar_calls.fetch_add(1, Ordering::SeqCst);
+            5
+        });
+
+        // `bar` is never computed because `foo` short-circuits the filter.
+        assert_eq!(filter.execute(&ctx), Ok(true));
+        assert_eq!(calls(), (1, 0));
+
+        ctx.set_field_value("foo", 2).unwrap();
+
+        // `bar` is referenced twice, but computed only once.
+        assert_eq!(filter.execute(&ctx), Ok(true));
+        assert_eq!(filter.execute(&ctx), Ok(true));
+        assert_eq!(calls(), (1, 1));
+    }
+}
+
+#[test]
+#[should_panic(expected = "invalid value of field tcp.port: port must be in 0..65535")]
+fn test_field_accessor_invalid_value() {
+    let mut scheme = Scheme! { tcp.port: Int };
+    scheme
+        .add_field_validator("tcp.port", |value| match value {
+            LhsValue::Int(port) if *port >= 0 && *port <= 65535 => Ok(()),
+            _ => Err("port must be in 0..65535".to_owned()),
+        })
+        .unwrap();
+    let filter = scheme.parse("tcp.port == 443").unwrap().compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_accessor("tcp.port", || 70_000);
+    let _ = filter.execute(&ctx);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_values() {
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+        ssl: Bool,
+        missing: Int,
+    };
+    let filter = scheme
+        .parse(r#"http.host == "example.org" && ip.src == 10.0.0.1 && tcp.port == 443 && ssl"#)
+        .unwrap()
+        .compile();
+
+    let json = serde_json::json!({
+        "http": { "host": "example.org" },
+        "ip": { "src": "10.0.0.1" },
+        "tcp": { "port": 443 },
+        "ssl": true,
+        "missing": null,
+    });
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_json_values(&json).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(
+        scheme
+            .parse("missing == 0")
+            .unwrap()
+            .compile()
+            .execute(&ctx),
+        Ok(false)
+    );
+
+    let json = serde_json::json!({ "tcp": { "port": "443" } });
+    assert_eq!(
+        ExecutionContext::new(&scheme).set_json_values(&json),
+        Err(SetJsonValuesError::TypeMismatch(
+            JsonValueTypeMismatchError {
+                field_name: "tcp.port".to_owned(),
+                field_type: Type::Int,
+            }
+        ))
+    );
+
+    let json = serde_json::json!({ "tcp": { "port": 1u64 << 40 } });
+    assert!(ExecutionContext::new(&scheme)
+        .set_json_values(&json)
+        .is_err());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_value_validator() {
+    let mut scheme = Scheme! { tcp.port: Int };
+    scheme
+        .add_field_validator("tcp.port", |value| match value {
+            LhsValue::Int(port) if *port >= 0 && *port <= 65535 => Ok(()),
+            _ => Err("port must be in 0..65535".to_owned()),
+        })
+        .unwrap();
+
+    let json = serde_json::json!({ "tcp": { "port": 70_000 } });
+    assert_eq!(
+        ExecutionContext::new(&scheme).set_json_values(&json),
+        Err(SetJsonValuesError::InvalidValue {
+            field_name: "tcp.port".to_owned(),
+            reason: "port must be in 0..65535".to_owned(),
+        })
+    );
+}
+
+#[test]
+fn test_map_values() {
+    let mut scheme = Scheme::new();
+    scheme
+        .add_map_field("http.headers".to_owned(), Type::Bytes)
+        .unwrap();
+    scheme.add_field("tcp.port".to_owned(), Type::Int).unwrap();
+
+    let filter = scheme
+        .parse(r#"http.headers["x-forwarded-for"] == "192.0.2.1""#)
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_map_value("http.headers", "host", "example.org")
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_map_value("http.headers", "x-forwarded-for", "192.0.2.1")
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        ctx.set_map_value("http.headers", "x-forwarded-for", 1),
+        Err(SetFieldValueError::TypeMismatch(
+            FieldValueTypeMismatchError {
+                field_type: Type::Bytes,
+                value_type: Type::Int,
+            }
+        ))
+    );
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    #[cfg(feature = "json")]
+    {
+        let json = serde_json::json!({
+            "http": { "headers": { "x-forwarded-for": "192.0.2.1" } },
+        });
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_json_values(&json).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(true));
+
+        // Arrays are values of a repeated key.
+        let each = scheme
+            .parse(r#"all(http.headers["x-forwarded-for"][*] != "192.0.2.1")"#)
+            .unwrap()
+            .compile();
+        let json = serde_json::json!({
+            "http": { "headers": { "x-forwarded-for": ["192.0.2.2", "192.0.2.1"] } },
+        });
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_json_values(&json).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(false));
+        assert_eq!(each.execute(&ctx), Ok(false));
+
+        let json = serde_json::json!({ "http": { "headers": "192.0.2.1" } });
+        assert_eq!(
+            ExecutionContext::new(&scheme).set_json_values(&json),
+            Err(SetJsonValuesError::TypeMismatch(
+                JsonValueTypeMismatchError {
+                    field_name: "http.headers".to_owned(),
+                    field_type: Type::Bytes,
+                }
+            ))
+        );
+    }
+}
+
+#[test]
+fn test_array_values() {
+    let mut scheme = Scheme::new();
+    scheme
+        .add_array_field("http.cookies".to_owned(), Type::Bytes)
+        .unwrap();
+    scheme
+        .add_field_validator("http.cookies", |value| match value {
+            LhsValue::Bytes(value) if value.contains(&b'=') => Ok(()),
+            _ => Err("expected name=value".to_owned()),
+        })
+        .unwrap();
+
+    let filter = scheme
+        .parse(r#"any(http.cookies == "a=1")"#)
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_array_values("http.cookies", vec!["a=1"]).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        ctx.set_array_values("http.cookies", vec!["b=2", "a"]),
+        Err(SetFieldValueError::InvalidValue {
+            field_name: "http.cookies".to_owned(),
+            reason: "expected name=value".to_owned(),
+        })
+    );
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    #[cfg(feature = "json")]
+    {
+        let json = serde_json::json!({ "http": { "cookies": ["b=2", "a=1"] } });
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_json_values(&json).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(true));
+
+        let json = serde_json::json!({ "http": { "cookies": ["b=2", 1] } });
+        assert!(ExecutionContext::new(&scheme)
+            .set_json_values(&json)
+            .is_err());
+
+        let json = serde_json::json!({ "http": { "cookies": ["b=2", "a"] } });
+        assert_eq!(
+            ExecutionContext::new(&scheme).set_json_values(&json),
+            Err(SetJsonValuesError::InvalidValue {
+                field_name: "http.cookies".to_owned(),
+                reason: "expected name=value".to_owned(),
+            })
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "tcp.port is not a map field")]
+fn test_map_value_of_plain_field() {
+    let scheme = Scheme! { tcp.port: Int };
+    let _ = ExecutionContext::new(&scheme).set_map_value("tcp.port", "a", 1);
+}
+
+#[test]
+fn test_ipv4_mapped_normalization() {
+    let scheme = Scheme! { ip.src: Ip };
+
+    let filter = scheme
+        .parse("ip.src == 192.0.2.1 || ip.src in { 10.0.0.0/8 }")
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+
+    let mapped = IpAddr::from([0, 0, 0, 0, 0, 0xffff, 0xc000, 0x0201]);
+    ctx.set_field_value("ip.src", mapped).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_ipv4_mapped_normalization(true);
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    let mapped = IpAddr::from([0, 0, 0, 0, 0, 0xffff, 0x0a01, 0x0203]);
+    ctx.set_field_value("ip.src", mapped).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    let mapped = IpAddr::from([0, 0, 0, 0, 0, 0xffff, 0xc000, 0x0202]);
+    ctx.set_field_value("ip.src", mapped).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    // Only the `::ffff:0:0/96` prefix is mapped.
+    let other = IpAddr::from([0, 0, 0, 0, 0, 0, 0xc000, 0x0201]);
+    ctx.set_field_value("ip.src", other).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_max_regex_input_len() {
+    let scheme = Scheme! { http.body: Bytes };
+
+    let filter = scheme
+        .parse(r#"http.body matches "(a|aa)+b" || http.body matches $body"#)
+        .unwrap()
+        .compile();
+
+    let body = vec![b'a'; 1 << 20];
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_pattern("body", "a$").unwrap();
+    ctx.set_field_value("http.body", &body[..]).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_max_regex_input_len(Some(1024));
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_field_value("http.body", &body[..1024]).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_max_regex_input_len(None);
+    ctx.set_field_value("http.body", &body[..]).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+}
+
+#[test]
+fn test_field_provider() {
+    use std::collections::HashMap;
+
+    struct Headers(HashMap<&'static str, &'static str>);
+
+    impl<'e> FieldProvider<'e> for Headers {
+        fn get_field(&self, name: &str) -> Option<LhsValue<'e>> {
+            let name = name.trim_start_matches("http.");
+            self.0
+                .get(name)
+                .map(|value| LhsValue::Bytes(value.as_bytes()))
+        }
+    }
+
+    let scheme = Scheme! {
+        http.host: Bytes,
+        http.ua: Bytes,
+    };
+
+    let filter = scheme
+        .parse(r#"http.host == "example.org" && not http.ua contains "bot""#)
+        .unwrap()
+        .compile();
+
+    let mut headers = HashMap::new();
+    headers.insert("host", "example.org");
+    let headers = Headers(headers);
+
+    let provider: &dyn FieldProvider<'_> = &headers;
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_provider(provider);
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_field_value("http.ua", "googlebot").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_field_value("http.host", "example.com").unwrap();
+    ctx.set_field_value("http.ua", "curl").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+}
+
+#[test]
+fn test_field_resolver() {
+    use std::sync::{Arc, Mutex};
+
+    let scheme = Scheme! {
+        ip.src: Ip,
+        ip.geoip.country: Bytes,
+        tls.ja3: Bytes,
+    };
+
+    let filters = [
+        r#"ip.geoip.country == "PT" || tls.ja3 == "a""#,
+        r#"ip.src == 192.0.2.1 || ip.geoip.country in { "PT" "ES" }"#,
+    ]
+    .iter()
+    .map(|filter| scheme.parse(filter).unwrap().compile())
+    .collect::<Vec<_>>();
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("ip.src", IpAddr::from([192, 0, 2, 2]))
+        .unwrap();
+    ctx.set_field_resolver({
+        let calls = Arc::clone(&calls);
+        move |name| {
+            calls.lock().unwrap().push(name.to_owned());
+            match name {
+                "ip.geoip.country" => Some(LhsValue::Bytes(b"PT")),
+                _ => None,
+            }
+        }
+    });
+
+    for filter in &filters {
+        assert_eq!(filter.execute(&ctx), Ok(true));
+    }
+    // `tls.ja3` is short-circuited, and the country is looked up once.
+    assert_eq!(*calls.lock().unwrap(), ["ip.geoip.country"]);
+
+    ctx.set_field_resolver(|_| None);
+    assert_eq!(filters[0].execute(&ctx), Ok(false));
+
+    ctx.set_field_value("ip.geoip.country", "ES").unwrap();
+    assert_eq!(filters[1].execute(&ctx), Ok(true));
+}
+
+#[test]
+fn test_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ExecutionContext<'_>>();
 }
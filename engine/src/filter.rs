@@ -1,6 +1,9 @@
-use execution_context::ExecutionContext;
+use ast::FilterAst;
+use execution_context::{ExecutionContext, FieldValueTypeMismatchError};
 use failure::Fail;
-use scheme::Scheme;
+use scheme::{Field, ParseError, Scheme, UnknownFieldError};
+use std::sync::Arc;
+use types::{GetType, LhsValue, Type};
 
 /// An error that occurs if filter and provided [`ExecutionContext`] have
 /// different [schemes](struct@Scheme).
@@ -8,22 +11,69 @@ use scheme::Scheme;
 #[fail(display = "execution context doesn't match the scheme with which filter was parsed")]
 pub struct SchemeMismatchError;
 
+/// An error that occurs if columns passed to
+/// [`Filter::execute_batch`] can't be executed against.
+#[derive(Debug, PartialEq, Fail)]
+pub enum BatchError {
+    /// The execution context doesn't match the scheme of the filter.
+    #[fail(display = "{}", _0)]
+    SchemeMismatch(#[cause] SchemeMismatchError),
+
+    /// A value in a column doesn't have the type of its field.
+    #[fail(display = "column {}: {}", field_name, error)]
+    TypeMismatch {
+        /// The name of the field.
+        field_name: String,
+        /// The underlying type error.
+        #[cause]
+        error: FieldValueTypeMismatchError,
+    },
+
+    /// A value in a column was rejected by the validator of its field
+    /// registered with
+    /// [`Scheme::add_field_validator`](::Scheme::add_field_validator).
+    #[fail(display = "column {}: {}", field_name, reason)]
+    InvalidValue {
+        /// The name of the field.
+        field_name: String,
+        /// The reason returned by the validator.
+        reason: String,
+    },
+
+    /// Columns don't all have the same number of rows.
+    #[fail(
+        display = "column {} has {} rows, but {} were expected",
+        field_name, actual, expected
+    )]
+    LengthMismatch {
+        /// The name of the field.
+        field_name: String,
+        /// The number of rows in the first column.
+        expected: usize,
+        /// The number of rows in this column.
+        actual: usize,
+    },
+}
+
 // Each AST expression node gets compiled into CompiledExpr. Therefore, Filter
 // essentialy is a public API facade for a tree of CompiledExprs. When filter
 // gets executed it calls `execute` method on its root expression which then
 // under the hood propagates field values to its leafs by recursively calling
 // their `execute` methods and aggregating results into a single boolean value
 // as recursion unwinds.
-pub(crate) struct CompiledExpr<'s>(Box<dyn 's + Fn(&ExecutionContext<'s>) -> bool>);
+//
+// Compiled expressions accept a context with any lifetime, so that values can
+// be borrowed from short-lived buffers while the filter itself is reused.
+pub(crate) struct CompiledExpr<'s>(Box<dyn 's + Fn(&ExecutionContext<'_>) -> bool + Send + Sync>);
 
 impl<'s> CompiledExpr<'s> {
     /// Creates a compiled expression IR from a generic closure.
-    pub(crate) fn new(closure: impl 's + Fn(&ExecutionContext<'s>) -> bool) -> Self {
+    pub(crate) fn new(closure: impl 's + Fn(&ExecutionContext<'_>) -> bool + Send + Sync) -> Self {
         CompiledExpr(Box::new(closure))
     }
 
     /// Executes a filter against a provided context with values.
-    pub fn execute(&self, ctx: &ExecutionContext<'s>) -> bool {
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> bool {
         self.0(ctx)
     }
 }
@@ -43,31 +93,327 @@ impl<'s> CompiledExpr<'s> {
 /// In the future the underlying representation might change, but for now it
 /// provides the best trade-off between safety and performance of compilation
 /// and execution.
+///
+/// Filters are `Send` and `Sync`, so they can be compiled on one thread and
+/// executed concurrently from others. To store one without borrowing the
+/// scheme, see [`OwnedFilter`].
 pub struct Filter<'s> {
     root_expr: CompiledExpr<'s>,
     scheme: &'s Scheme,
+    cost: u64,
+    fields: Vec<Field<'s>>,
+    field_types: Vec<Type>,
+    references: Vec<String>,
 }
 
 impl<'s> Filter<'s> {
     /// Creates a compiled expression IR from a generic closure.
-    pub(crate) fn new(root_expr: CompiledExpr<'s>, scheme: &'s Scheme) -> Self {
-        Filter { root_expr, scheme }
+    pub(crate) fn new(
+        root_expr: CompiledExpr<'s>,
+        scheme: &'s Scheme,
+        cost: u64,
+        fields: Vec<Field<'s>>,
+        field_types: Vec<Type>,
+        references: Vec<String>,
+    ) -> Self {
+        Filter {
+            root_expr,
+            scheme,
+            cost,
+            fields,
+            field_types,
+            references,
+        }
+    }
+
+    /// Returns names of all fields the filter references, each one once, in
+    /// the order they first appear in the filter.
+    ///
+    /// Fields referenced via aliases are returned under their canonical
+    /// names.
+    pub fn fields(&self) -> impl Iterator<Item = &'s str> + '_ {
+        self.fields.iter().map(|field| field.name())
+    }
+
+    /// Checks whether the filter references a given field, or an alias of it.
+    ///
+    /// This is useful to compute expensive fields only if any of the
+    /// installed filters needs them.
+    pub fn uses(&self, field_name: &str) -> Result<bool, UnknownFieldError> {
+        self.scheme
+            .get_field_index(field_name)
+            .map(|field| self.fields.contains(&field))
+    }
+
+    /// Returns types of all fields the filter references, each one once, in
+    /// the order they first appear in the filter.
+    pub fn field_types(&self) -> &[Type] {
+        &self.field_types
+    }
+
+    /// Checks whether the filter references any field of a given type, e.g.
+    /// to reject filters that look at payload `Bytes` fields.
+    pub fn requires_fields_of_type(&self, ty: Type) -> bool {
+        self.field_types.contains(&ty)
+    }
+
+    /// Returns names of all constants, lists and patterns the filter
+    /// references as `$name`, without the `$`, each one once, in the order
+    /// they first appear in the filter.
+    pub fn references(&self) -> &[String] {
+        &self.references
+    }
+
+    /// Checks whether the filter references a given constant, list or pattern,
+    /// e.g. to find filters affected by a change of its value.
+    pub fn references_constant(&self, name: &str) -> bool {
+        self.references.iter().any(|reference| reference == name)
+    }
+
+    /// Returns a rough a priori estimate of how expensive it is to execute
+    /// this filter in the worst case, e.g. to reject filters over a budget.
+    ///
+    /// The cost is the sum of costs of all comparisons in the filter,
+    /// regardless of how they're combined, where:
+    ///  - a boolean field check, an ordering or a bitwise comparison costs 1;
+    ///  - `contains`, `wildcard` and `in $name` cost 10;
+    ///  - `matches` costs 100;
+    ///  - `in { ... }` costs 1 plus the number of items in the list.
+    ///
+    /// The numbers are relative and not tied to any unit of time.
+    pub fn complexity_cost(&self) -> u64 {
+        self.cost
     }
 
     /// Executes a filter against a provided context with values.
-    pub fn execute(&self, ctx: &ExecutionContext<'s>) -> Result<bool, SchemeMismatchError> {
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> Result<bool, SchemeMismatchError> {
         if self.scheme == ctx.scheme() {
             Ok(self.root_expr.execute(ctx))
         } else {
             Err(SchemeMismatchError)
         }
     }
+
+    /// Executes a filter against each row of a columnar batch, where each
+    /// column holds values of one field for all the rows.
+    ///
+    /// Fields without a column keep the values already set in `ctx`, which
+    /// is useful for values shared by all the rows. Returns a result per row,
+    /// identical to what [`execute`](Filter::execute) would return for it.
+    ///
+    /// # Panics
+    ///
+    /// Like [`ExecutionContext::set_field_value`], panics if a column
+    /// references a field that is not in the scheme.
+    pub fn execute_batch<'e>(
+        &self,
+        ctx: &mut ExecutionContext<'e>,
+        columns: &[(&str, &[LhsValue<'e>])],
+    ) -> Result<Vec<bool>, BatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(BatchError::SchemeMismatch(SchemeMismatchError));
+        }
+
+        let rows = columns.first().map_or(0, |(_, values)| values.len());
+
+        // Validate everything upfront so that the hot loop below doesn't
+        // have to look up fields or check values.
+        let columns = columns
+            .iter()
+            .map(|&(name, values)| {
+                let field = self.scheme.get_field_index(name).unwrap();
+
+                if values.len() != rows {
+                    return Err(BatchError::LengthMismatch {
+                        field_name: name.to_owned(),
+                        expected: rows,
+                        actual: values.len(),
+                    });
+                }
+
+                let field_type = field.get_type();
+
+                if let Some(value) = values.iter().find(|v| v.get_type() != field_type) {
+                    return Err(BatchError::TypeMismatch {
+                        field_name: name.to_owned(),
+                        error: FieldValueTypeMismatchError {
+                            field_type,
+                            value_type: value.get_type(),
+                        },
+                    });
+                }
+
+                for value in values {
+                    self.scheme
+                        .validate_field_value(field, value)
+                        .map_err(|reason| BatchError::InvalidValue {
+                            field_name: name.to_owned(),
+                            reason,
+                        })?;
+                }
+
+                Ok((field, values))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((0..rows)
+            .map(|row| {
+                for (field, values) in &columns {
+                    ctx.set_field_value_unchecked(*field, values[row].clone());
+                }
+                self.root_expr.execute(ctx)
+            })
+            .collect())
+    }
+
+    /// Executes a filter against each of the contexts, e.g. buffered records
+    /// of a pipeline, and returns a result per context.
+    ///
+    /// Schemes of all contexts are checked before any of them is executed,
+    /// so that the filter runs in a tight loop afterwards. Fails without
+    /// executing anything if any of the contexts has a different scheme.
+    pub fn execute_many(
+        &self,
+        contexts: &[ExecutionContext<'_>],
+    ) -> Result<Vec<bool>, SchemeMismatchError> {
+        if contexts.iter().any(|ctx| self.scheme != ctx.scheme()) {
+            return Err(SchemeMismatchError);
+        }
+
+        Ok(contexts
+            .iter()
+            .map(|ctx| self.root_expr.execute(ctx))
+            .collect())
+    }
+}
+
+/// A [`Filter`] that keeps its scheme alive through an [`Arc`] instead of
+/// borrowing it, so that it can be stored for as long as needed, e.g. in a
+/// global table of rules shared by worker threads.
+pub struct OwnedFilter {
+    // This borrows the scheme behind `scheme`, which stays at the same address
+    // for as long as the `Arc` is alive. Hence, we use `static` as a substitute
+    // lifetime, declare the filter first so that it's dropped first, and only
+    // ever hand it out with the lifetime of `self`.
+    filter: Filter<'static>,
+    scheme: Arc<Scheme>,
+}
+
+impl OwnedFilter {
+    /// Parses and compiles a filter with the default parser options.
+    pub fn parse(scheme: Arc<Scheme>, input: &str) -> Result<Self, ParseError<'_>> {
+        OwnedFilter::try_new(scheme, |scheme| scheme.parse(input).map(FilterAst::compile))
+    }
+
+    /// Creates a filter with a function that compiles it against the scheme,
+    /// e.g. with custom [`ParserOptions`](::ParserOptions).
+    pub fn try_new<E, F>(scheme: Arc<Scheme>, compile: F) -> Result<Self, E>
+    where
+        F: for<'s> FnOnce(&'s Scheme) -> Result<Filter<'s>, E>,
+    {
+        // The function has to work with any lifetime of the scheme, so the
+        // filter it returns can't borrow anything for longer than that.
+        let filter = compile(unsafe { &*(&*scheme as *const Scheme) })?;
+        Ok(OwnedFilter { filter, scheme })
+    }
+
+    /// Returns the scheme of the filter, e.g. to create execution contexts.
+    pub fn scheme(&self) -> &Arc<Scheme> {
+        &self.scheme
+    }
+
+    /// Returns the compiled filter.
+    pub fn filter(&self) -> &Filter<'_> {
+        &self.filter
+    }
+
+    /// Executes a filter against a provided context with values.
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> Result<bool, SchemeMismatchError> {
+        self.filter.execute(ctx)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SchemeMismatchError;
-    use execution_context::ExecutionContext;
+    use super::{BatchError, Filter, OwnedFilter, SchemeMismatchError};
+    use execution_context::{ExecutionContext, FieldValueTypeMismatchError};
+    use functions::Function;
+    use scheme::UnknownFieldError;
+    use types::{LhsValue, Type};
+
+    #[test]
+    fn test_field_types() {
+        let scheme = Scheme! {
+            http.body: Bytes,
+            ip.src: Ip,
+            tcp.port: Int,
+        };
+
+        let filter = scheme
+            .parse("tcp.port == 80 || ip.src in { 10.0.0.0/8 } || tcp.port == 443")
+            .unwrap()
+            .compile();
+        assert_eq!(filter.field_types(), &[Type::Int, Type::Ip]);
+        assert!(!filter.requires_fields_of_type(Type::Bytes));
+        assert!(filter.requires_fields_of_type(Type::Ip));
+
+        let filter = scheme
+            .parse(r#"tcp.port == 80 && http.body contains "secret""#)
+            .unwrap()
+            .compile();
+        assert!(filter.requires_fields_of_type(Type::Bytes));
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            ip.src: Ip,
+            tcp.port: Int,
+            tls.ja3: Bytes,
+        };
+        scheme.add_alias("src".to_owned(), "ip.src").unwrap();
+        scheme
+            .add_function("lower".to_owned(), Function::lower())
+            .unwrap();
+
+        let filter = scheme
+            .parse(r#"tcp.port == 80 || src in { 10.0.0.0/8 } || lower(http.host) == "a" || tcp.port == 443"#)
+            .unwrap()
+            .compile();
+        assert_eq!(
+            filter.fields().collect::<Vec<_>>(),
+            ["tcp.port", "ip.src", "http.host"]
+        );
+        assert_eq!(filter.uses("src"), Ok(true));
+        assert_eq!(filter.uses("http.host"), Ok(true));
+        assert_eq!(filter.uses("tls.ja3"), Ok(false));
+        assert_eq!(filter.uses("geo.country"), Err(UnknownFieldError));
+    }
+
+    #[test]
+    fn test_references() {
+        let mut scheme = Scheme! { http.host: Bytes, ip.src: Ip, tcp.port: Int };
+        scheme
+            .add_constant("blocklist".to_owned(), Type::Ip)
+            .unwrap();
+        scheme
+            .add_constant("max_port".to_owned(), Type::Int)
+            .unwrap();
+
+        let filter = scheme
+            .parse(r#"ip.src == $blocklist || (http.host matches $bots && ip.src != $blocklist)"#)
+            .unwrap()
+            .compile();
+        assert_eq!(filter.references(), &["blocklist", "bots"]);
+        assert!(filter.references_constant("blocklist"));
+        assert!(filter.references_constant("bots"));
+        assert!(!filter.references_constant("max_port"));
+        assert!(!filter.references_constant("$blocklist"));
+
+        let filter = scheme.parse("tcp.port > 1024").unwrap().compile();
+        assert!(filter.references().is_empty());
+    }
 
     #[test]
     fn test_scheme_mismatch() {
@@ -78,4 +424,219 @@ mod tests {
 
         assert_eq!(filter.execute(&ctx), Err(SchemeMismatchError));
     }
+
+    #[test]
+    fn test_complexity_cost() {
+        let scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+        let cost = |filter| scheme.parse(filter).unwrap().compile().complexity_cost();
+
+        let comparisons = cost(r#"http.host == "a" && (tcp.port > 80 || tcp.port & 1)"#);
+        let regexes = cost(r#"http.host matches "a" && (tcp.port > 80 || http.host ~ "b")"#);
+
+        assert_eq!(comparisons, 3);
+        assert_eq!(regexes, 201);
+        assert!(regexes > comparisons);
+
+        assert_eq!(cost("tcp.port in { 80 443 8000..8080 }"), 4);
+        assert_eq!(cost(r#"not http.host contains "a""#), 10);
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let scheme = Scheme! { tcp.port: Int, http.host: Bytes, ssl: Bool };
+        let filter = scheme
+            .parse(r#"(tcp.port in { 80 443 } || tcp.port > 8000) && (ssl || http.host == "a")"#)
+            .unwrap()
+            .compile();
+
+        // A tiny deterministic LCG to generate random rows.
+        let mut seed = 0x1234_5678u32;
+        let mut next = move |max: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) % max
+        };
+
+        let hosts = ["a", "b", ""];
+        let mut ports = Vec::new();
+        let mut host_values = Vec::new();
+        let mut ssl_values = Vec::new();
+
+        for _ in 0..1000 {
+            ports.push(LhsValue::from(match next(3) {
+                0 => 80,
+                1 => 443,
+                _ => next(10_000) as i32,
+            }));
+            host_values.push(LhsValue::from(hosts[next(3) as usize]));
+            ssl_values.push(LhsValue::from(next(2) == 0));
+        }
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        let results = filter
+            .execute_batch(
+                &mut ctx,
+                &[
+                    ("tcp.port", &ports),
+                    ("http.host", &host_values),
+                    ("ssl", &ssl_values),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(results.len(), 1000);
+        assert!(results.contains(&true));
+        assert!(results.contains(&false));
+
+        for (row, &result) in results.iter().enumerate() {
+            let mut ctx = ExecutionContext::new(&scheme);
+            ctx.set_field_value("tcp.port", ports[row].clone()).unwrap();
+            ctx.set_field_value("http.host", host_values[row].clone())
+                .unwrap();
+            ctx.set_field_value("ssl", ssl_values[row].clone()).unwrap();
+
+            assert_eq!(filter.execute(&ctx), Ok(result), "row {}", row);
+        }
+
+        // Fields without a column keep their value from the context.
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("ssl", true).unwrap();
+        let some_ports = [80.into(), 22.into(), 8080.into()];
+        assert_eq!(
+            filter.execute_batch(&mut ctx, &[("tcp.port", &some_ports)]),
+            Ok(vec![true, false, true])
+        );
+
+        assert_eq!(
+            filter.execute_batch(&mut ctx, &[("tcp.port", &some_ports), ("ssl", &ssl_values)]),
+            Err(BatchError::LengthMismatch {
+                field_name: "ssl".to_owned(),
+                expected: 3,
+                actual: 1000,
+            })
+        );
+
+        assert_eq!(
+            filter.execute_batch(&mut ctx, &[("tcp.port", &ssl_values)]),
+            Err(BatchError::TypeMismatch {
+                field_name: "tcp.port".to_owned(),
+                error: FieldValueTypeMismatchError {
+                    field_type: Type::Int,
+                    value_type: Type::Bool,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_execute_batch_invalid_value() {
+        let mut scheme = Scheme! { tcp.port: Int };
+        scheme
+            .add_field_validator("tcp.port", |value| match value {
+                LhsValue::Int(port) if *port >= 0 && *port <= 65535 => Ok(()),
+                _ => Err("port must be in 0..65535".to_owned()),
+            })
+            .unwrap();
+        let filter = scheme.parse("tcp.port == 443").unwrap().compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        let ports = [443.into(), 70_000.into()];
+        assert_eq!(
+            filter.execute_batch(&mut ctx, &[("tcp.port", &ports)]),
+            Err(BatchError::InvalidValue {
+                field_name: "tcp.port".to_owned(),
+                reason: "port must be in 0..65535".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_execute_many() {
+        let scheme = Scheme! { tcp.port: Int };
+        let other_scheme = Scheme! { tcp.port: Int };
+        let filter = scheme.parse("tcp.port > 1024").unwrap().compile();
+
+        let mut contexts = [80, 8080, 443]
+            .iter()
+            .map(|&port| {
+                let mut ctx = ExecutionContext::new(&scheme);
+                ctx.set_field_value("tcp.port", port).unwrap();
+                ctx
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(filter.execute_many(&contexts), Ok(vec![false, true, false]));
+        assert_eq!(filter.execute_many(&[]), Ok(vec![]));
+
+        contexts.push(ExecutionContext::new(&other_scheme));
+        assert_eq!(filter.execute_many(&contexts), Err(SchemeMismatchError));
+    }
+
+    #[test]
+    fn test_owned_filter() {
+        use std::{sync::Arc, thread};
+        use {FilterSet, Parser, ParserOptions, Program};
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Filter<'_>>();
+        assert_send_sync::<FilterSet<'_>>();
+        assert_send_sync::<Program<'_>>();
+        assert_send_sync::<OwnedFilter>();
+
+        let scheme = Arc::new(Scheme! { http.host: Bytes, tcp.port: Int });
+
+        let rules = Arc::new(vec![
+            OwnedFilter::parse(scheme.clone(), "tcp.port == 443").unwrap(),
+            OwnedFilter::parse(scheme.clone(), r#"http.host contains "example""#).unwrap(),
+            OwnedFilter::try_new(scheme.clone(), |scheme| {
+                Parser::with_options(
+                    scheme,
+                    ParserOptions {
+                        max_depth: Some(1),
+                        ..ParserOptions::default()
+                    },
+                )
+                .parse("tcp.port > 1024")
+                .map(|ast| ast.compile())
+            })
+            .unwrap(),
+        ]);
+
+        assert!(OwnedFilter::parse(scheme.clone(), "tcp.port ==").is_err());
+
+        // The filters outlive the original handle to the scheme.
+        drop(scheme);
+
+        let workers = [("example.com", 443), ("example.org", 8080), ("a.org", 80)]
+            .iter()
+            .map(|&(host, port)| {
+                let rules = rules.clone();
+                thread::spawn(move || {
+                    let mut ctx = ExecutionContext::new(rules[0].scheme());
+                    ctx.set_field_value("http.host", host).unwrap();
+                    ctx.set_field_value("tcp.port", port).unwrap();
+                    rules
+                        .iter()
+                        .map(|rule| rule.execute(&ctx).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect::<Vec<_>>(),
+            [
+                [true, true, false],
+                [false, true, true],
+                [false, false, false]
+            ]
+        );
+
+        assert_eq!(
+            rules[1].filter().fields().collect::<Vec<_>>(),
+            ["http.host"]
+        );
+    }
 }
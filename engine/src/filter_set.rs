@@ -0,0 +1,221 @@
+use ast::{ComparisonOp, ComparisonRhs, FilterAst, OrderingOp};
+use execution_context::ExecutionContext;
+use filter::{Filter, SchemeMismatchError};
+use fnv::FnvBuildHasher;
+use scheme::{Field, Scheme};
+use std::{collections::HashMap, hash::Hash, net::IpAddr};
+use types::LhsValue;
+
+type Index<K> = HashMap<K, Vec<usize>, FnvBuildHasher>;
+
+// Filters that can only match if a field is equal to one of the keys, by the
+// value of that field.
+enum Guards {
+    Int(Index<i32>),
+    Bytes(Index<Box<[u8]>>),
+    Ip(Index<IpAddr>),
+}
+
+fn insert<K: Hash + Eq>(index: &mut Index<K>, key: K, id: usize) {
+    index.entry(key).or_insert_with(Vec::new).push(id);
+}
+
+impl Guards {
+    fn new(value: &LhsValue<'_>) -> Option<Self> {
+        Some(match value {
+            LhsValue::Int(_) => Guards::Int(Index::default()),
+            LhsValue::Bytes(_) => Guards::Bytes(Index::default()),
+            LhsValue::Ip(_) => Guards::Ip(Index::default()),
+            _ => return None,
+        })
+    }
+
+    fn insert(&mut self, value: &LhsValue<'_>, id: usize) {
+        match (self, value) {
+            (Guards::Int(index), LhsValue::Int(value)) => insert(index, *value, id),
+            (Guards::Bytes(index), LhsValue::Bytes(value)) => insert(index, (*value).into(), id),
+            (Guards::Ip(index), LhsValue::Ip(value)) => insert(index, *value, id),
+            _ => unreachable!(),
+        }
+    }
+
+    fn get(&self, value: &LhsValue<'_>) -> &[usize] {
+        let ids = match (self, value) {
+            (Guards::Int(index), LhsValue::Int(value)) => index.get(value),
+            (Guards::Bytes(index), LhsValue::Bytes(value)) => index.get(*value),
+            (Guards::Ip(index), LhsValue::Ip(value)) => index.get(value),
+            _ => None,
+        };
+        ids.map_or(&[], Vec::as_slice)
+    }
+}
+
+// Finds a `field == literal` comparison that has to match for the whole
+// filter to match.
+fn find_guard<'a, 's>(ast: &'a FilterAst<'s>) -> Option<(Field<'s>, LhsValue<'a>)> {
+    ast.required_comparisons().into_iter().find_map(|expr| {
+        if expr.op() != Some(ComparisonOp::Ordering(OrderingOp::Equal))
+            || expr.subscript().is_some()
+            || expr.call().is_some()
+            || expr.ignores_case()
+        {
+            return None;
+        }
+
+        match expr.rhs() {
+            ComparisonRhs::Literal(value) => {
+                Guards::new(&value)?;
+                Some((expr.fields()[0], value))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// A set of filters over the same scheme, which finds all of them that
+/// match a context at once, e.g. for firewall rules.
+///
+/// Filters that require a field to be equal to a literal, like
+/// `http.host == "example.org" && ...`, are indexed by that value, so that
+/// only the ones with the right value are executed. The rest of them are
+/// executed one by one as usual.
+pub struct FilterSet<'s> {
+    scheme: &'s Scheme,
+    filters: Vec<Filter<'s>>,
+    guarded: Vec<(Field<'s>, Guards)>,
+    unguarded: Vec<usize>,
+}
+
+impl<'s> FilterSet<'s> {
+    /// Creates an empty set of filters over the scheme.
+    pub fn new(scheme: &'s Scheme) -> Self {
+        FilterSet {
+            scheme,
+            filters: Vec::new(),
+            guarded: Vec::new(),
+            unguarded: Vec::new(),
+        }
+    }
+
+    /// Compiles and adds a filter to the set, and returns its id, which is
+    /// the number of filters added before it.
+    pub fn add(&mut self, ast: FilterAst<'s>) -> Result<usize, SchemeMismatchError> {
+        if self.scheme != ast.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        let id = self.filters.len();
+
+        match find_guard(&ast) {
+            Some((field, value)) => {
+                let position = match self.guarded.iter().position(|entry| entry.0 == field) {
+                    Some(position) => position,
+                    None => {
+                        self.guarded.push((field, Guards::new(&value).unwrap()));
+                        self.guarded.len() - 1
+                    }
+                };
+                self.guarded[position].1.insert(&value, id);
+            }
+            None => self.unguarded.push(id),
+        }
+
+        self.filters.push(ast.compile());
+        Ok(id)
+    }
+
+    /// Returns the number of filters in the set.
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Checks whether there are no filters in the set.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Returns a filter by its id.
+    pub fn get(&self, id: usize) -> Option<&Filter<'s>> {
+        self.filters.get(id)
+    }
+
+    /// Returns ids of all filters that match the context, in ascending order.
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> Result<Vec<usize>, SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        let mut matched = Vec::new();
+
+        for &id in &self.unguarded {
+            if self.filters[id].execute(ctx)? {
+                matched.push(id);
+            }
+        }
+
+        for (field, guards) in &self.guarded {
+            if let Some(value) = ctx.get_field_value_unchecked(*field) {
+                for &id in guards.get(&value) {
+                    if self.filters[id].execute(ctx)? {
+                        matched.push(id);
+                    }
+                }
+            }
+        }
+
+        matched.sort_unstable();
+        Ok(matched)
+    }
+}
+
+#[test]
+fn test_filter_set() {
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+    let other_scheme = Scheme! { tcp.port: Int };
+
+    let mut set = FilterSet::new(&scheme);
+    for filter in &[
+        r#"http.host == "a.org" && tcp.port == 443"#,
+        "tcp.port > 1024",
+        r#"(ssl && http.host == "b.org")"#,
+        r#"http.host == "a.org""#,
+        "tcp.port == 443 || ip.src == 10.0.0.1",
+        "ip.src == 10.0.0.1 && not ssl",
+        r#"http.host == "A.org" nocase"#,
+    ] {
+        set.add(scheme.parse(filter).unwrap()).unwrap();
+    }
+    assert_eq!(set.len(), 7);
+    assert_eq!(set.unguarded, vec![1, 4, 6]);
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    assert_eq!(set.execute(&ctx), Ok(vec![]));
+
+    ctx.set_field_value("http.host", "a.org").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    assert_eq!(set.execute(&ctx), Ok(vec![0, 3, 4, 6]));
+
+    ctx.set_field_value("http.host", "b.org").unwrap();
+    ctx.set_field_value("tcp.port", 8080).unwrap();
+    ctx.set_field_value("ssl", true).unwrap();
+    assert_eq!(set.execute(&ctx), Ok(vec![1, 2]));
+
+    ctx.set_field_value("ip.src", IpAddr::from([10, 0, 0, 1]))
+        .unwrap();
+    ctx.set_field_value("ssl", false).unwrap();
+    assert_eq!(set.execute(&ctx), Ok(vec![1, 4, 5]));
+
+    assert_eq!(
+        set.add(other_scheme.parse("tcp.port == 1").unwrap()),
+        Err(SchemeMismatchError)
+    );
+    assert_eq!(
+        set.execute(&ExecutionContext::new(&other_scheme)),
+        Err(SchemeMismatchError)
+    );
+}
@@ -0,0 +1,48 @@
+use execution_context::{ExecutionContext, SetFieldValueError};
+use scheme::{FieldError, Scheme};
+
+/// A structure whose fields are fields of a scheme, so that the scheme and
+/// the code filling contexts are defined in one place.
+///
+/// With the `derive` feature, it can be implemented with
+/// `#[derive(Filterable)]`:
+///
+/// ```ignore
+/// #[derive(Filterable)]
+/// struct Request {
+///     #[filter(name = "ip.src")]
+///     src_ip: IpAddr,
+///     #[filter(name = "http.host")]
+///     host: String,
+///     #[filter(name = "tcp.port")]
+///     port: u16,
+///     #[filter(skip)]
+///     id: u64,
+/// }
+/// ```
+pub trait Filterable {
+    /// Registers fields of the structure in the scheme.
+    fn add_fields(scheme: &mut Scheme) -> Result<(), FieldError>;
+
+    /// Sets values of the fields in the context.
+    ///
+    /// The context has to be created from a scheme with the fields, or this
+    /// panics.
+    fn fill_context<'e>(&'e self, ctx: &mut ExecutionContext<'e>)
+        -> Result<(), SetFieldValueError>;
+
+    /// Creates a scheme with just the fields of the structure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`add_fields`](Filterable::add_fields) fails, e.g. if two
+    /// fields have the same name.
+    fn scheme() -> Scheme
+    where
+        Self: Sized,
+    {
+        let mut scheme = Scheme::new();
+        Self::add_fields(&mut scheme).unwrap();
+        scheme
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    cmp::min,
+    fmt::{self, Debug, Formatter},
+    i32,
+};
+use types::{GetType, LhsValue, Type};
+
+type FunctionImpl = dyn for<'a> Fn(&[LhsValue<'a>]) -> Option<FunctionValue<'a>> + Send + Sync;
+
+/// A result of a [`Function`] call.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FunctionValue<'a> {
+    /// A value that doesn't own any data, or borrows it from the arguments.
+    Value(LhsValue<'a>),
+    /// Bytes computed by the function, like a lowercased string.
+    Bytes(Vec<u8>),
+}
+
+impl<'a> FunctionValue<'a> {
+    pub(crate) fn as_lhs(&self) -> LhsValue<'_> {
+        match self {
+            FunctionValue::Value(value) => value.clone(),
+            FunctionValue::Bytes(bytes) => LhsValue::Bytes(bytes),
+        }
+    }
+}
+
+impl<'a> From<LhsValue<'a>> for FunctionValue<'a> {
+    fn from(value: LhsValue<'a>) -> Self {
+        FunctionValue::Value(value)
+    }
+}
+
+impl<'a> From<Vec<u8>> for FunctionValue<'a> {
+    fn from(bytes: Vec<u8>) -> Self {
+        FunctionValue::Bytes(bytes)
+    }
+}
+
+impl<'a> GetType for FunctionValue<'a> {
+    fn get_type(&self) -> Type {
+        match self {
+            FunctionValue::Value(value) => value.get_type(),
+            FunctionValue::Bytes(_) => Type::Bytes,
+        }
+    }
+}
+
+/// A function that can be called on the left-hand side of comparisons, like
+/// `lower(http.host) == "example.org"`, once registered with
+/// [`Scheme::add_function`](::Scheme::add_function).
+///
+/// Calls are type-checked against the parameters when parsing, and the
+/// implementation is invoked during execution with values of the arguments.
+/// If any of the arguments is an unset field, or the implementation returns
+/// `None`, the comparison doesn't match, just like for unset fields.
+pub struct Function {
+    params: Vec<Type>,
+    return_type: Type,
+    implementation: Box<FunctionImpl>,
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("params", &self.params)
+            .field("return_type", &self.return_type)
+            .finish()
+    }
+}
+
+impl Function {
+    /// Creates a function with the given parameter types, the type of the
+    /// result and an implementation.
+    ///
+    /// The implementation has to return values of the declared type, or the
+    /// execution panics.
+    pub fn new<F>(params: Vec<Type>, return_type: Type, implementation: F) -> Self
+    where
+        F: 'static + for<'a> Fn(&[LhsValue<'a>]) -> Option<FunctionValue<'a>> + Send + Sync,
+    {
+        Function {
+            params,
+            return_type,
+            implementation: Box::new(implementation),
+        }
+    }
+
+    /// `lower(bytes) -> bytes`, which converts ASCII letters to lowercase.
+    pub fn lower() -> Self {
+        Function::new(vec![Type::Bytes], Type::Bytes, |args| match args {
+            [LhsValue::Bytes(bytes)] => Some(bytes.to_ascii_lowercase().into()),
+            _ => unreachable!(),
+        })
+    }
+
+    /// `len(bytes) -> int`, which returns the length in bytes, saturated to
+    /// the range of `int`.
+    pub fn len() -> Self {
+        Function::new(vec![Type::Bytes], Type::Int, |args| match args {
+            [LhsValue::Bytes(bytes)] => {
+                let len = min(bytes.len(), i32::MAX as usize) as i32;
+                Some(LhsValue::Int(len).into())
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    /// `concat(bytes, bytes) -> bytes`, which joins two values together.
+    pub fn concat() -> Self {
+        Function::new(
+            vec![Type::Bytes, Type::Bytes],
+            Type::Bytes,
+            |args| match args {
+                [LhsValue::Bytes(first), LhsValue::Bytes(second)] => {
+                    Some([*first, *second].concat().into())
+                }
+                _ => unreachable!(),
+            },
+        )
+    }
+
+    pub(crate) fn params(&self) -> &[Type] {
+        &self.params
+    }
+
+    pub(crate) fn return_type(&self) -> Type {
+        self.return_type
+    }
+
+    pub(crate) fn call<'a>(&self, args: &[LhsValue<'a>]) -> Option<FunctionValue<'a>> {
+        let result = (self.implementation)(args)?;
+
+        // Results can't be checked upfront, so a wrong type is a bug in the
+        // implementation.
+        if result.get_type() != self.return_type {
+            panic!(
+                "the function should return {} type, but {} was returned",
+                self.return_type,
+                result.get_type()
+            );
+        }
+
+        Some(result)
+    }
+}
+
+#[test]
+fn test_builtins() {
+    let host = LhsValue::Bytes(b"Example.ORG");
+
+    assert_eq!(
+        Function::lower().call(&[host.clone()]),
+        Some(FunctionValue::Bytes(b"example.org".to_vec()))
+    );
+    assert_eq!(
+        Function::len().call(&[host.clone()]),
+        Some(FunctionValue::Value(LhsValue::Int(11)))
+    );
+    assert_eq!(
+        Function::concat().call(&[host, LhsValue::Bytes(b"/")]),
+        Some(FunctionValue::Bytes(b"Example.ORG/".to_vec()))
+    );
+}
+
+#[test]
+#[should_panic(expected = "the function should return int type, but bytes was returned")]
+fn test_wrong_return_type() {
+    let function = Function::new(vec![], Type::Int, |_| Some(b"a".to_vec().into()));
+    function.call(&[]);
+}
@@ -36,6 +36,11 @@ impl Drop for HeapSearcher {
     }
 }
 
+// The needle is owned and never mutated after construction, just like a
+// `Box<[u8]>` would be, so it's fine to move and share the searcher.
+unsafe impl Send for HeapSearcher {}
+unsafe impl Sync for HeapSearcher {}
+
 impl Searcher for HeapSearcher {
     fn search_in(&self, haystack: &[u8]) -> Option<usize> {
         self.searcher.search_in(haystack)
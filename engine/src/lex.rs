@@ -1,10 +1,43 @@
+use ast::field_expr::ComparisonOp;
 use cidr::NetworkParseError;
 use failure::Fail;
 use rhs_types::RegexError;
 use scheme::UnknownFieldError;
-use std::num::ParseIntError;
+use std::{
+    fmt::{self, Display, Formatter},
+    num::ParseIntError,
+    str::Utf8Error,
+};
 use types::Type;
 
+/// One of the limits in [`ParserOptions`](::ParserOptions) that a filter can
+/// exceed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The length of a filter in bytes.
+    FilterLength,
+
+    /// How deeply parentheses and `not` are nested.
+    Depth,
+
+    /// The number of items in a single `{ ... }` list.
+    ListLength,
+
+    /// The approximate size of a compiled regex in bytes.
+    RegexSize,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::FilterLength => "filter length",
+            Limit::Depth => "nesting depth",
+            Limit::ListLength => "list length",
+            Limit::RegexSize => "compiled regex size",
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Fail)]
 pub enum LexErrorKind {
     #[fail(display = "expected {}", _0)]
@@ -29,9 +62,15 @@ pub enum LexErrorKind {
     #[fail(display = "expected \", xHH or OOO after \\")]
     InvalidCharacterEscape,
 
+    #[fail(display = "invalid base64")]
+    InvalidBase64,
+
     #[fail(display = "could not find an ending quote")]
     MissingEndingQuote,
 
+    #[fail(display = "could not find the end of a comment")]
+    UnterminatedComment,
+
     #[fail(display = "expected {} {}s, but found {}", expected, name, actual)]
     CountMismatch {
         name: &'static str,
@@ -42,14 +81,56 @@ pub enum LexErrorKind {
     #[fail(display = "{}", _0)]
     UnknownField(#[cause] UnknownFieldError),
 
-    #[fail(display = "cannot use this operation type {:?}", field_type)]
-    UnsupportedOp { field_type: Type },
+    #[fail(
+        display = "cannot use operator {} on a field of type {}",
+        op, field_type
+    )]
+    UnsupportedOp { field_type: Type, op: ComparisonOp },
+
+    #[fail(display = "expected value of type {}, but got {}", expected, actual)]
+    TypeMismatch { expected: Type, actual: Type },
+
+    #[fail(display = "unknown constant")]
+    UnknownConstant,
+
+    #[fail(display = "unknown list")]
+    UnknownList,
+
+    #[fail(display = "unknown function")]
+    UnknownFunction,
+
+    #[fail(display = "unknown value, expected one of {}", expected)]
+    UnknownEnumValue { expected: String },
 
     #[fail(display = "incompatible range bounds")]
     IncompatibleRangeBounds,
 
-    #[fail(display = "unrecognised input")]
-    EOF,
+    #[fail(display = "shift amount should be between 0 and 31")]
+    InvalidShift,
+
+    #[fail(display = "invalid timestamp")]
+    InvalidTimestamp,
+
+    #[fail(display = "invalid MAC address")]
+    InvalidMac,
+
+    #[fail(display = "integer overflow")]
+    IntegerOverflow,
+
+    #[fail(display = "division by zero")]
+    DivisionByZero,
+
+    #[fail(display = "too many regular expressions, at most {} allowed", max)]
+    TooManyRegexes { max: usize },
+
+    #[fail(display = "{} exceeds the limit of {}", limit, max)]
+    LimitExceeded { limit: Limit, max: usize },
+
+    #[fail(display = "unrecognised input, expected end of filter")]
+    ExpectedEof,
+
+    #[fail(display = "{}", _0)]
+    InvalidUtf8(#[cause] Utf8Error),
 }
 
 pub type LexError<'i> = (LexErrorKind, &'i str);
@@ -78,23 +159,74 @@ pub fn expect<'i>(input: &'i str, s: &'static str) -> Result<&'i str, LexError<'
     }
 }
 
+/// Checks whether the input starts with a given literal without consuming it.
+pub fn peek(input: &str, s: &'static str) -> bool {
+    input.starts_with(s)
+}
+
+/// Returns the next character of the input without consuming it.
+pub fn peek_char(input: &str) -> Option<char> {
+    input.chars().next()
+}
+
 // Tabs are harder to format as part of the error message because they have
 // a different printable width than other characters, and so become a common
 // source of issues in different compilers.
 //
 // It's not impossible to work around that limitation, but let's not bother
 // for now until someone really needs them (tabs vs spaces all the way down...).
-const SPACE_CHARS: &[char] = &[' ', '\r', '\n'];
+pub(crate) const SPACE_CHARS: &[char] = &[' ', '\r', '\n'];
+
+// Lexes a comment, either from `#` to the end of the line or between `/*`
+// and `*/`, and returns it with the delimiters but without the line break.
+pub(crate) fn lex_comment(input: &str) -> Option<(&str, &str)> {
+    let end = if input.starts_with('#') {
+        input.find('\n').unwrap_or_else(|| input.len())
+    } else if input.starts_with("/*") {
+        input[2..].find("*/")? + 4
+    } else {
+        return None;
+    };
+    Some(input.split_at(end))
+}
 
-pub fn skip_space(input: &str) -> &str {
-    input.trim_start_matches(SPACE_CHARS)
+/// Skips whitespace and comments, which can go anywhere between tokens.
+///
+/// A `/*` without a matching `*/` is left in place, so that it's reported
+/// as [`LexErrorKind::UnterminatedComment`] by whatever comes next.
+pub fn skip_space(mut input: &str) -> &str {
+    loop {
+        input = input.trim_start_matches(SPACE_CHARS);
+        match lex_comment(input) {
+            Some((_, rest)) => input = rest,
+            None => return input,
+        }
+    }
 }
 
 /// This macro generates enum declaration + lexer implementation.
 ///
 /// It works by recursively processing variants one by one, while passing
 /// around intermediate state (partial declaration and lexer bodies).
+///
+/// Generated enums are `Copy`, `Eq` and `Hash` by default. Prefix the name with
+/// `?Copy` to only derive `Clone` and `PartialEq` for enums with variants
+/// holding data like `Vec<u8>` or `Regex`.
 macro_rules! lex_enum {
+    // Branch for handling attributes, like doc comments, on variants.
+    //
+    // Copies attribute into declaration as-is, so that it's applied to the
+    // variant that follows.
+    (@decl $preamble:tt $name:ident $input:ident { $($decl:tt)* } $expr:tt {
+        # $attr:tt
+        $($rest:tt)*
+    }) => {
+        lex_enum!(@decl $preamble $name $input {
+            $($decl)*
+            # $attr
+        } $expr { $($rest)* });
+    };
+
     // Branch for handling `SomeType => VariantName`.
     //
     // Creates a newtype variant `VariantName(SomeType)`.
@@ -143,7 +275,6 @@ macro_rules! lex_enum {
     // This is invoked when no more variants are left to process.
     // At this point declaration and lexer body are considered complete.
     (@decl { $($preamble:tt)* } $name:ident $input:ident $decl:tt { $($expr:stmt)* } {}) => {
-        #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
         $($preamble)*
         pub enum $name $decl
 
@@ -158,9 +289,134 @@ macro_rules! lex_enum {
         }
     };
 
+    // Branches collecting all the spellings accepted by the lexer, so that
+    // tests can check other lists of operators against them.
+    //
+    // Variants wrapping another type add all spellings of that type.
+    (@spellings $name:ident $list:ident { $($expr:tt)* } {
+        # $attr:tt
+        $($rest:tt)*
+    }) => {
+        lex_enum!(@spellings $name $list { $($expr)* } { $($rest)* });
+    };
+
+    (@spellings $name:ident $list:ident { $($expr:tt)* } {
+        $ty:ty => $item:ident,
+        $($rest:tt)*
+    }) => {
+        lex_enum!(@spellings $name $list {
+            $($expr)*
+            $list.extend(<$ty>::spellings());
+        } { $($rest)* });
+    };
+
+    (@spellings $name:ident $list:ident { $($expr:tt)* } {
+        $($s:tt)|+ => $item:ident $(= $value:expr)*,
+        $($rest:tt)*
+    }) => {
+        lex_enum!(@spellings $name $list {
+            $($expr)*
+            $list.extend_from_slice(&[$($s),+]);
+        } { $($rest)* });
+    };
+
+    (@spellings $name:ident $list:ident { $($expr:tt)* } {}) => {
+        #[cfg(test)]
+        impl $name {
+            #[allow(dead_code)]
+            pub(crate) fn spellings() -> Vec<&'static str> {
+                let mut $list = Vec::new();
+                $($expr)*
+                $list
+            }
+        }
+    };
+
+    // The public entry point to the macro for enums that can't be `Copy`.
+    ($(# $attrs:tt)* ?Copy $name:ident $items:tt) => {
+        lex_enum!(@decl {
+            #[derive(Debug, PartialEq, Clone, Serialize)]
+            $(# $attrs)*
+        } $name input {} {} $items);
+        lex_enum!(@spellings $name spellings {} $items);
+    };
+
     // The public entry point to the macro.
     ($(# $attrs:tt)* $name:ident $items:tt) => {
         lex_enum!(@decl {
+            #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize)]
+            $(# $attrs)*
+        } $name input {} {} $items);
+        lex_enum!(@spellings $name spellings {} $items);
+    };
+}
+
+/// This macro generates struct declaration + lexer implementation for
+/// literals made of several values with fixed separators in between, like
+/// `first:second`.
+///
+/// Fields and separators are listed in the order they appear in the input
+/// and are lexed one right after another, without skipping any space.
+///
+/// Works by recursively processing items one by one, just like `lex_enum!`.
+#[allow(unused_macros)]
+macro_rules! lex_struct {
+    // Branch for handling `"separator"`.
+    //
+    // Doesn't add anything to the declaration.
+    //
+    // On the parser side, expects the given string.
+    (@decl $preamble:tt $name:ident $input:ident $decl:tt { $($expr:tt)* } {
+        $sep:literal,
+        $($rest:tt)*
+    }) => {
+        lex_struct!(@decl $preamble $name $input $decl {
+            $($expr)*
+            let $input = $crate::lex::expect($input, $sep)?;
+        } { $($rest)* });
+    };
+
+    // Branch for handling `field_name: SomeType`.
+    //
+    // Creates a field `field_name: SomeType`.
+    //
+    // On the parser side, parses `SomeType` and stores it into the field.
+    (@decl $preamble:tt $name:ident $input:ident { $($decl:tt)* } { $($expr:tt)* } {
+        $field:ident: $ty:ty,
+        $($rest:tt)*
+    }) => {
+        lex_struct!(@decl $preamble $name $input {
+            $($decl)*
+            $field: $ty,
+        } {
+            $($expr)*
+            let ($field, $input) = <$ty as $crate::lex::Lex>::lex($input)?;
+        } { $($rest)* });
+    };
+
+    // Internal finish point for declaration + lexer generation.
+    //
+    // This is invoked when no more items are left to process.
+    (@decl { $($preamble:tt)* } $name:ident $input:ident { $($field:ident: $ty:ty,)* } {
+        $($expr:tt)*
+    } {}) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+        $($preamble)*
+        pub struct $name {
+            $(pub $field: $ty,)*
+        }
+
+        impl<'i> $crate::lex::Lex<'i> for $name {
+            fn lex($input: &'i str) -> $crate::lex::LexResult<'_, Self> {
+                $($expr)*
+                Ok(($name { $($field,)* }, $input))
+            }
+        }
+    };
+
+    // The public entry point to the macro.
+    ($(# $attrs:tt)* $name:ident $items:tt) => {
+        lex_struct!(@decl {
             $(# $attrs)*
         } $name input {} {} $items);
     };
@@ -191,13 +447,13 @@ pub fn take_while<'i, F: Fn(char) -> bool>(
     }
 }
 
-pub fn take(input: &str, expected: usize) -> LexResult<'_, &str> {
+pub fn take<'i>(input: &'i str, name: &'static str, expected: usize) -> LexResult<'i, &'i str> {
     let mut chars = input.chars();
     for i in 0..expected {
         chars.next().ok_or_else(|| {
             (
                 LexErrorKind::CountMismatch {
-                    name: "character",
+                    name,
                     actual: i,
                     expected,
                 },
@@ -214,7 +470,7 @@ pub fn complete<T>(res: LexResult<'_, T>) -> Result<T, LexError<'_>> {
     if input.is_empty() {
         Ok(res)
     } else {
-        Err((LexErrorKind::EOF, input))
+        Err((LexErrorKind::ExpectedEof, skip_space(input)))
     }
 }
 
@@ -247,3 +503,108 @@ macro_rules! assert_json {
         );
     };
 }
+
+#[test]
+fn test_skip_space() {
+    assert_eq!(skip_space("  \r\n x"), "x");
+    assert_eq!(skip_space("# a\n  # b\nx # c"), "x # c");
+    assert_eq!(skip_space("/* a\n * b */x"), "x");
+    assert_eq!(skip_space("/**/ # a"), "");
+    assert_eq!(skip_space(" /* a"), "/* a");
+    assert_eq!(lex_comment("# a\nx"), Some(("# a", "\nx")));
+    assert_eq!(lex_comment("/* a */ x"), Some(("/* a */", " x")));
+    assert_eq!(lex_comment("/*/"), None);
+}
+
+#[test]
+fn test_peek() {
+    assert!(peek("{ 1 }", "{"));
+    assert!(!peek(" { 1 }", "{"));
+    assert!(!peek("", "{"));
+
+    assert_eq!(peek_char("\u{e9}a"), Some('\u{e9}'));
+    assert_eq!(peek_char(""), None);
+}
+
+#[test]
+fn test_take_multibyte() {
+    assert_ok!(take("\u{e9}a", "char", 1), "\u{e9}", "a");
+    assert_ok!(take("a\u{1f600}b", "char", 2), "a\u{1f600}", "b");
+    assert_err!(
+        take("\u{20ac}", "char", 2),
+        LexErrorKind::CountMismatch {
+            name: "char",
+            actual: 1,
+            expected: 2,
+        },
+        "\u{20ac}"
+    );
+    assert_eq!(span("\u{e9}\u{e9}", &"\u{e9}\u{e9}"[2..]), "\u{e9}");
+}
+
+#[test]
+fn test_lex_enum_non_copy() {
+    use serde::Serialize;
+
+    #[derive(Debug, PartialEq, Clone, Serialize)]
+    pub struct Digits(Vec<u8>);
+
+    impl Digits {
+        fn spellings() -> Vec<&'static str> {
+            Vec::new()
+        }
+    }
+
+    impl<'i> Lex<'i> for Digits {
+        fn lex(input: &str) -> LexResult<'_, Self> {
+            let (digits, rest) = take_while(input, "digit", |c| c.is_ascii_digit())?;
+            Ok((Digits(digits.bytes().map(|b| b - b'0').collect()), rest))
+        }
+    }
+
+    lex_enum!(
+        /// Either a keyword or a list of digits.
+        ?Copy Value {
+            "none" => None,
+            Digits => Digits,
+        }
+    );
+
+    assert_ok!(Value::lex("none;"), Value::None, ";");
+    assert_ok!(
+        Value::lex("123;"),
+        Value::Digits(Digits(vec![1, 2, 3])),
+        ";"
+    );
+    assert_err!(Value::lex("x"), LexErrorKind::ExpectedName("Value"), "x");
+
+    assert_eq!(Value::spellings(), ["none"]);
+
+    let value = Value::lex("45").unwrap().0;
+    assert_eq!(value.clone(), value);
+}
+
+#[test]
+fn test_lex_struct() {
+    use serde::Serialize;
+
+    lex_struct!(
+        /// A pair of numbers, like `1:2`.
+        Pair {
+            first: i32,
+            ":",
+            second: i32,
+        }
+    );
+
+    assert_ok!(
+        Pair::lex("1:-2;"),
+        Pair {
+            first: 1,
+            second: -2
+        },
+        ";"
+    );
+    assert_err!(Pair::lex("1 2"), LexErrorKind::ExpectedLiteral(":"), " 2");
+    assert_err!(Pair::lex("1:"), LexErrorKind::ExpectedName("digit"), "");
+}
@@ -55,6 +55,7 @@
 //! ```
 #![warn(missing_docs)]
 
+extern crate aho_corasick;
 extern crate cfg_if;
 extern crate failure;
 extern crate serde;
@@ -65,7 +66,7 @@ extern crate indoc;
 #[cfg(test)]
 extern crate lazy_static;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "json"))]
 extern crate serde_json;
 
 extern crate cidr;
@@ -76,6 +77,11 @@ extern crate memmem;
 #[cfg(feature = "regex")]
 extern crate regex;
 
+#[cfg(feature = "derive")]
+#[allow(unused_imports)]
+#[macro_use]
+extern crate wirefilter_derive;
+
 #[macro_use]
 mod lex;
 
@@ -83,18 +89,58 @@ mod lex;
 mod scheme;
 
 mod ast;
+mod bpf;
 mod execution_context;
 mod filter;
+mod filter_set;
+mod filterable;
+mod functions;
 mod heap_searcher;
+mod parser;
+mod program;
 mod range_set;
 mod rhs_types;
+mod sha256;
 mod strict_partial_ord;
+mod tokens;
 mod types;
 
 pub use self::{
-    ast::FilterAst,
-    execution_context::{ExecutionContext, FieldValueTypeMismatchError},
-    filter::{Filter, SchemeMismatchError},
-    scheme::{FieldRedefinitionError, ParseError, Scheme, UnknownFieldError},
+    ast::{
+        field,
+        precedence::{
+            associativity, precedence, Associativity, OperatorPrecedence, PRECEDENCE_TABLE,
+        },
+        BpfCompileError, BpfInstruction, BuildError, BytesOp, ComparisonOp, ComparisonRhs,
+        FieldBuilder, FieldExpr, FilterAnalysis, FilterAst, FilterBuilder, IntOp, NodeProfile,
+        OrderingOp, ProfiledFilter, Residual, Ternary, Trace, TraceEntry, UnknownResultError,
+    },
+    bpf::{parse_bpf, translate_bpf, BpfError},
+    execution_context::{
+        ExecutionContext, FieldProvider, FieldValueTypeMismatchError, SetFieldValueError,
+    },
+    filter::{BatchError, Filter, OwnedFilter, SchemeMismatchError},
+    filter_set::FilterSet,
+    filterable::Filterable,
+    functions::{Function, FunctionValue},
+    parser::{Parser, ParserOptions, UnknownFieldPolicy, Warning},
+    program::Program,
+    rhs_types::{Endpoint, Mac, RegexCache, RegexError, Timestamp},
+    scheme::{
+        AliasError, ConstantError, EnumValuesError, Field, FieldError, FieldRedefinitionError,
+        FunctionError, ListError, ParseError, ReservedNameError, Scheme, UnknownFieldError,
+    },
+    tokens::{Token, Tokens},
     types::{GetType, LhsValue, Type},
 };
+
+#[cfg(feature = "generator")]
+pub use self::ast::FilterGenerator;
+#[cfg(feature = "json")]
+pub use self::{
+    ast::FilterJsonError,
+    execution_context::{JsonValueTypeMismatchError, SetJsonValuesError},
+};
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub use wirefilter_derive::*;
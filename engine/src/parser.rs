@@ -0,0 +1,972 @@
+use ast::FilterAst;
+#[cfg(feature = "json")]
+use ast::{filter_from_json, FilterJsonError};
+use lex::{complete, lex_comment, peek, skip_space, LexErrorKind, LexWith, Limit};
+use rhs_types::RegexCache;
+use scheme::{ParseError, Scheme};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokens::{lex_raw_string, Token, Tokens};
+
+/// What to do with fields that are not in the scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownFieldPolicy {
+    /// Fail to parse the filter.
+    Error,
+
+    /// Parse comparisons of such fields without checking their right-hand
+    /// side, and treat them like comparisons of unset fields, which never
+    /// match. They are displayed as they were written, so that the filter
+    /// means the same in a scheme that has the fields.
+    TreatAsMissing,
+}
+
+impl Default for UnknownFieldPolicy {
+    fn default() -> Self {
+        UnknownFieldPolicy::Error
+    }
+}
+
+/// A likely mistake in a filter that's not severe enough to reject it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A `matches` regex can match an empty string anywhere, like `"a?"`, so
+    /// the comparison is true for any value of the field.
+    ///
+    /// This is checked only when
+    /// [`warn_empty_matching_regexes`](ParserOptions::warn_empty_matching_regexes)
+    /// is enabled.
+    EmptyMatchingRegex {
+        /// Byte range of the comparison in the parsed input.
+        span: Range<usize>,
+    },
+}
+
+/// Options that change the syntax accepted when parsing a filter.
+///
+/// All of them are disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParserOptions {
+    /// Accept a doubled quote (`""`) as an escaped quote inside of string
+    /// literals, like in SQL, in addition to `\"`.
+    ///
+    /// This is opt-in because it changes the meaning of adjacent strings
+    /// without whitespace in between, e.g. `{ "a""b" }`.
+    pub doubled_quote_escapes: bool,
+
+    /// Limit the number of `matches` comparisons in a single filter, as a
+    /// guard against filters that are too expensive to compile and execute.
+    pub max_regexes: Option<usize>,
+
+    /// Limit the approximate size of each compiled `matches` regex in bytes,
+    /// so that patterns like `"a{1000}{1000}"` are rejected instead of
+    /// taking a lot of time and memory to compile.
+    ///
+    /// This is only checked when the engine is built with regex support.
+    pub max_regex_size: Option<usize>,
+
+    /// Limit how deeply parentheses and `not` can be nested, which bounds
+    /// the recursion when parsing, compiling and executing a filter.
+    pub max_depth: Option<usize>,
+
+    /// Limit the number of items in each `{ ... }` list of values.
+    pub max_list_len: Option<usize>,
+
+    /// Limit the length of a filter in bytes, not counting surrounding
+    /// whitespace. With [`Parser::parse_all`](Parser::parse_all), this applies
+    /// to each filter in the document separately.
+    pub max_filter_len: Option<usize>,
+
+    /// Reuse compiled `matches` regexes from a cache shared with other
+    /// parsers, instead of compiling each of them separately.
+    ///
+    /// This saves memory when many filters have the same patterns, e.g.
+    /// rules of different tenants.
+    pub regex_cache: Option<RegexCache>,
+
+    /// Whether fields that are not in the scheme are a parse error.
+    ///
+    /// Treating them as missing is useful when the same filter is used with
+    /// several schemes, only some of which have all the fields.
+    pub unknown_field_policy: UnknownFieldPolicy,
+
+    /// Report `matches` comparisons with regexes that can match an empty
+    /// string anywhere, and so are true for any value, in
+    /// [`FilterAst::warnings`](::FilterAst::warnings).
+    ///
+    /// These are usually unintended, like `"a?"` written instead of `"a+"`,
+    /// but are still valid filters, so they don't fail parsing.
+    pub warn_empty_matching_regexes: bool,
+
+    /// Keep `#` and `/* */` comments of the filter in
+    /// [`FilterAst::comments`](::FilterAst::comments), so that they can be
+    /// written back with the alternate format (`{:#}`) of the AST.
+    pub keep_comments: bool,
+}
+
+/// A reusable parser for filters over a given scheme.
+///
+/// This is what [`Scheme::parse`](::Scheme::parse) uses under the hood, but it
+/// allows to set up syntax options once and then parse any number of filters
+/// with them.
+///
+/// # Example
+///
+/// ```
+/// use wirefilter::{Parser, ParserOptions, Scheme};
+///
+/// let scheme = Scheme! { http.host: Bytes };
+///
+/// let parser = Parser::with_options(
+///     &scheme,
+///     ParserOptions {
+///         max_regexes: Some(1),
+///         ..ParserOptions::default()
+///     },
+/// );
+///
+/// assert!(parser.parse(r#"http.host matches "^a""#).is_ok());
+/// assert!(parser
+///     .parse(r#"http.host matches "^a" || http.host matches "^b""#)
+///     .is_err());
+/// ```
+pub struct Parser<'s> {
+    pub(crate) scheme: &'s Scheme,
+    pub(crate) options: ParserOptions,
+    regex_count: AtomicUsize,
+    depth: AtomicUsize,
+}
+
+impl<'s> Parser<'s> {
+    /// Creates a parser with default options.
+    pub fn new(scheme: &'s Scheme) -> Self {
+        Parser::with_options(scheme, ParserOptions::default())
+    }
+
+    /// Creates a parser with the given options.
+    pub fn with_options(scheme: &'s Scheme, options: ParserOptions) -> Self {
+        Parser {
+            scheme,
+            options,
+            regex_count: AtomicUsize::new(0),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the scheme filters are parsed against.
+    pub fn scheme(&self) -> &'s Scheme {
+        self.scheme
+    }
+
+    /// Returns the syntax options used by this parser.
+    pub fn options(&self) -> &ParserOptions {
+        &self.options
+    }
+
+    /// Counts another regex towards the `max_regexes` limit.
+    pub(crate) fn add_regex(&self) -> Result<(), LexErrorKind> {
+        let count = self.regex_count.fetch_add(1, Ordering::Relaxed) + 1;
+
+        match self.options.max_regexes {
+            Some(max) if count > max => Err(LexErrorKind::TooManyRegexes { max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Enters parentheses or `not`, checking the `max_depth` limit.
+    ///
+    /// Each successful call must be followed by [`exit`](Parser::exit), even
+    /// if lexing the nested expression fails.
+    pub(crate) fn enter(&self) -> Result<(), LexErrorKind> {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+
+        match self.options.max_depth {
+            Some(max) if depth > max => {
+                self.exit();
+                Err(LexErrorKind::LimitExceeded {
+                    limit: Limit::Depth,
+                    max,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Leaves parentheses or `not` entered with [`enter`](Parser::enter).
+    pub(crate) fn exit(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Parses a filter into an AST form.
+    pub fn parse<'i>(&self, input: &'i str) -> Result<FilterAst<'s>, ParseError<'i>> {
+        self.parse_segment(input, input)
+    }
+
+    /// Parses a document with several filters separated by `;`, returning
+    /// a result for each of them, so that one invalid filter doesn't prevent
+    /// using the rest.
+    ///
+    /// Only `;` outside of string literals, backtick-quoted names and
+    /// brackets separates filters. Segments with nothing but whitespace and
+    /// comments, like the one after a trailing `;`, are skipped. Positions in errors and
+    /// warnings are relative to the whole document.
+    pub fn parse_all<'i>(&self, input: &'i str) -> Vec<Result<FilterAst<'s>, ParseError<'i>>> {
+        split_filters(input)
+            .into_iter()
+            .filter(|segment| !skip_space(segment).is_empty())
+            .map(|segment| self.parse_segment(input, segment))
+            .collect()
+    }
+
+    /// Reads an AST from JSON, like
+    /// [`Scheme::filter_from_json`](::Scheme::filter_from_json), but with
+    /// comparisons of unknown fields read as missing if the
+    /// [`unknown_field_policy`](ParserOptions::unknown_field_policy) is
+    /// [`TreatAsMissing`](UnknownFieldPolicy::TreatAsMissing).
+    #[cfg(feature = "json")]
+    pub fn filter_from_json(&self, json: &str) -> Result<FilterAst<'s>, FilterJsonError> {
+        filter_from_json(self.scheme, json, self.options.unknown_field_policy)
+    }
+
+    /// Parses a filter like [`parse`](Parser::parse), but on failure reports
+    /// errors of each operand of the top-level `&&`, `||` and `^^`, so that
+    /// all of them can be shown at once, e.g. in an editor.
+    ///
+    /// Invalid operands that are wholly in parentheses, optionally after a
+    /// `not`, like `(a == "x" || b == 1)`, are split the same way, so errors
+    /// of each nested operand are reported too. If all operands are valid on
+    /// their own, like in `(a && b`, the error of the whole filter is
+    /// reported instead.
+    pub fn parse_all_errors<'i>(
+        &self,
+        input: &'i str,
+    ) -> Result<FilterAst<'s>, Vec<ParseError<'i>>> {
+        let err = match self.parse(input) {
+            Ok(ast) => return Ok(ast),
+            Err(err) => err,
+        };
+
+        let mut errors = Vec::new();
+        self.collect_errors(input, input, &mut errors);
+
+        Err(if errors.is_empty() { vec![err] } else { errors })
+    }
+
+    // Collects errors of each operand of `segment`, which is a part of
+    // `input`, going into the ones in parentheses.
+    fn collect_errors<'i>(
+        &self,
+        input: &'i str,
+        segment: &'i str,
+        errors: &mut Vec<ParseError<'i>>,
+    ) {
+        for operand in split_operands(segment) {
+            let err = match self.parse_segment(input, operand) {
+                Ok(_) => continue,
+                Err(err) => err,
+            };
+
+            let count = errors.len();
+            if let Some(inner) = parenthesized(operand) {
+                self.collect_errors(input, inner, errors);
+            }
+            if errors.len() == count {
+                errors.push(err);
+            }
+        }
+    }
+
+    // Parses a filter from `segment`, which is a part of `input`.
+    fn parse_segment<'i>(
+        &self,
+        input: &'i str,
+        segment: &'i str,
+    ) -> Result<FilterAst<'s>, ParseError<'i>> {
+        // Limits apply to each filter separately, so start with fresh counters
+        // instead of sharing them between (possibly concurrent) calls.
+        let parser = Parser::with_options(self.scheme, self.options.clone());
+
+        let filter = skip_space(segment.trim());
+        if let Some(max) = self.options.max_filter_len {
+            if filter.len() > max {
+                return Err(ParseError::new(
+                    input,
+                    (
+                        LexErrorKind::LimitExceeded {
+                            limit: Limit::FilterLength,
+                            max,
+                        },
+                        filter,
+                    ),
+                ));
+            }
+        }
+
+        let res = FilterAst::lex_with(filter, &parser).and_then(|(ast, rest)| {
+            let rest = skip_space(rest);
+            if peek(rest, "/*") {
+                Err((LexErrorKind::UnterminatedComment, rest))
+            } else {
+                Ok((ast, rest))
+            }
+        });
+        let mut ast = complete(res)
+            .map_err(|err| ParseError::new(input, err).with_suggestions(self.scheme))?;
+
+        // Spans are offsets from the end of the filter, so they are rebased
+        // to be relative to the end of the whole document instead.
+        let document = input.trim();
+        let filter_end = filter.as_ptr() as usize + filter.len();
+        ast.offset_spans(document.as_ptr() as usize + document.len() - filter_end);
+
+        if self.options.keep_comments {
+            ast.comments = Tokens::new(segment)
+                .filter_map(|res| match res {
+                    Ok((Token::Comment(comment), _)) => Some(comment.to_owned()),
+                    _ => None,
+                })
+                .collect();
+        }
+
+        if self.options.warn_empty_matching_regexes {
+            let warnings = ast
+                .comparisons()
+                .into_iter()
+                .filter(|comparison| comparison.matches_empty())
+                .filter_map(|comparison| comparison.source_range(input))
+                .map(|span| Warning::EmptyMatchingRegex { span })
+                .collect();
+            ast.warnings = warnings;
+        }
+
+        Ok(ast)
+    }
+}
+
+// Splits a filter at each logical operator that's not within brackets of any
+// kind. Tokens after an invalid one stay in the last operand, as there's no
+// telling where the invalid one ends.
+fn split_operands(input: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+
+    for res in Tokens::new(input) {
+        let (token, range) = match res {
+            Ok(token) => token,
+            Err(_) => break,
+        };
+
+        match token {
+            Token::Punct("(") | Token::Punct("[") | Token::Punct("{") => depth += 1,
+            Token::Punct(")") | Token::Punct("]") | Token::Punct("}") => {
+                depth = depth.saturating_sub(1)
+            }
+            Token::Punct("&&")
+            | Token::Punct("||")
+            | Token::Punct("^^")
+            | Token::Ident("and")
+            | Token::Ident("or")
+            | Token::Ident("xor")
+                if depth == 0 =>
+            {
+                operands.push(&input[start..range.start]);
+                start = range.end;
+            }
+            _ => {}
+        }
+    }
+
+    operands.push(&input[start..]);
+    operands
+}
+
+// Returns what's within the parentheses if the whole operand is in them,
+// optionally after `not` or `!`.
+fn parenthesized(operand: &str) -> Option<&str> {
+    let operand = operand.trim_end();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut end = None;
+
+    for res in Tokens::new(operand) {
+        let (token, range) = res.ok()?;
+
+        match (token, start) {
+            (Token::Eof, _) => break,
+            // Anything after the closing parenthesis, even a comment.
+            (_, _) if end.is_some() => return None,
+            (Token::Ident("not"), None) | (Token::Punct("!"), None) => {}
+            (Token::Comment(_), None) => {}
+            (Token::Punct("("), None) => {
+                start = Some(range.end);
+                depth = 1;
+            }
+            (_, None) => return None,
+            (Token::Punct("("), _) | (Token::Punct("["), _) | (Token::Punct("{"), _) => depth += 1,
+            (Token::Punct(")"), _) | (Token::Punct("]"), _) | (Token::Punct("}"), _) => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(range.start);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match (start, end) {
+        (Some(start), Some(end)) => Some(&operand[start..end]),
+        _ => None,
+    }
+}
+
+// Splits a document at each `;` that's not within a string, a quoted name or
+// brackets of any kind, which don't need to match as intervals like `[1, 2)`
+// mix them.
+fn split_filters(input: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut skip_to = 0;
+
+    for (i, c) in input.char_indices() {
+        if i < skip_to {
+            continue;
+        }
+
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' && q == '"' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '`' => quote = Some(c),
+            'r' => {
+                if let Ok((_, rest)) = lex_raw_string(&input[i..]) {
+                    skip_to = input.len() - rest.len();
+                }
+            }
+            '#' | '/' => {
+                if let Some((_, rest)) = lex_comment(&input[i..]) {
+                    skip_to = input.len() - rest.len();
+                }
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            ';' if depth == 0 => {
+                segments.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    segments.push(&input[start..]);
+    segments
+}
+
+#[test]
+fn test_reuse() {
+    let scheme = Scheme! { http.host: Bytes };
+
+    let parser = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            max_regexes: Some(1),
+            ..ParserOptions::default()
+        },
+    );
+
+    assert_eq!(parser.options().max_regexes, Some(1));
+
+    for _ in 0..3 {
+        assert_eq!(
+            parser.parse(r#"http.host matches "^a""#),
+            scheme.parse(r#"http.host matches "^a""#)
+        );
+    }
+
+    assert!(parser
+        .parse(r#"http.host matches "^a" && http.host matches "^b""#)
+        .is_err());
+}
+
+#[test]
+fn test_unknown_field_policy() {
+    use execution_context::ExecutionContext;
+
+    let scheme = Scheme! { http.host: Bytes };
+
+    let filter = r#"foo == 1 || http.host == "a""#;
+
+    assert_eq!(
+        Parser::new(&scheme).parse(filter),
+        Err(ParseError::new(
+            filter,
+            (
+                LexErrorKind::UnknownField(::scheme::UnknownFieldError),
+                &filter[..3]
+            )
+        ))
+    );
+
+    let parser = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            unknown_field_policy: UnknownFieldPolicy::TreatAsMissing,
+            ..ParserOptions::default()
+        },
+    );
+
+    let filter = parser.parse(filter).unwrap().compile();
+    let mut ctx = ExecutionContext::new(&scheme);
+
+    ctx.set_field_value("http.host", "a").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_field_value("http.host", "b").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    for filter in &[
+        "foo",
+        "foo.bar && foo.baz",
+        r#"foo in { 1 "}" 2..3 }"#,
+        "foo between 1 .. 2",
+        r#"foo == ip("1.2.3.4")"#,
+        "foo == $bar",
+        r#"foo contains any { "a" "b" }"#,
+        "foo == 0x[de ad]",
+        "foo in [1, 2)",
+        "foo & 1 == 0",
+        "foo >> 2 & 1",
+        r#"foo["k"] == "a""#,
+        r#"foo[*] == "a""#,
+        r#"foo["k"][*] contains "a""#,
+        "foo[0] == 1",
+        "any(foo == 1)",
+        r#"all(foo["k"][*] != "a")"#,
+        r#"foo == "A" nocase"#,
+    ] {
+        let ast = parser.parse(filter).unwrap();
+        assert_eq!(ast.to_string(), *filter);
+        assert_eq!(ast.compile().execute(&ctx), Ok(false), "{}", filter);
+    }
+
+    let filter = parser.parse("not foo in { 1 2 }").unwrap().compile();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    // Negated operators are `not` of the positive ones, like for known
+    // fields, so they match.
+    for &(filter, displayed) in &[
+        (r#"foo !~ "a""#, r#"not foo matches "a""#),
+        ("foo not between 1..2", "not foo between 1..2"),
+    ] {
+        let ast = parser.parse(filter).unwrap();
+        assert_eq!(ast.to_string(), displayed);
+        assert_eq!(ast.compile().execute(&ctx), Ok(true), "{}", filter);
+    }
+
+    // Filters keep their meaning in a scheme that has the field.
+    let other_scheme = Scheme! { http.host: Bytes, foo: Int };
+    let filter = r#"foo == 1 || http.host == "a""#;
+    let ast = parser.parse(filter).unwrap();
+    assert_eq!(ast.to_string(), filter);
+    assert_eq!(
+        other_scheme.parse(&ast.to_string()),
+        other_scheme.parse(filter)
+    );
+
+    assert!(parser.parse("foo in { 1 2").is_err());
+    assert!(parser.parse("foo == % && http.host").is_err());
+    assert!(parser.parse(r#"any(foo !~ "a")"#).is_err());
+    assert!(parser.parse(r#"foo["k" == "a""#).is_err());
+}
+
+#[test]
+fn test_limits() {
+    let scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+
+    let parser = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            max_depth: Some(2),
+            max_list_len: Some(2),
+            max_filter_len: Some(40),
+            ..ParserOptions::default()
+        },
+    );
+
+    let limit_error = |filter: &'static str, limit, max, span: &str| {
+        let start = filter.rfind(span).unwrap();
+        Err(ParseError::new(
+            filter,
+            (
+                LexErrorKind::LimitExceeded { limit, max },
+                &filter[start..start + span.len()],
+            ),
+        ))
+    };
+
+    assert!(parser.parse("not (tcp.port == 1)").is_ok());
+    assert!(parser
+        .parse("(tcp.port == 1) && (not tcp.port == 2)")
+        .is_ok());
+    let filter = "not (not tcp.port == 1)";
+    assert_eq!(
+        parser.parse(filter),
+        limit_error(filter, Limit::Depth, 2, "not")
+    );
+    let filter = "((( tcp.port == 1 )))";
+    assert_eq!(
+        parser.parse(filter),
+        limit_error(filter, Limit::Depth, 2, "(")
+    );
+
+    assert!(parser.parse("tcp.port in { 1 2..3 }").is_ok());
+    let filter = "tcp.port in { 1 2 3 }";
+    assert_eq!(
+        parser.parse(filter),
+        limit_error(filter, Limit::ListLength, 2, "3")
+    );
+    let filter = r#"http.host contains any { "a" "b" "c" }"#;
+    assert_eq!(
+        parser.parse(filter),
+        limit_error(filter, Limit::ListLength, 2, r#""c""#)
+    );
+
+    assert!(parser.parse(&format!("{:40}", " tcp.port == 1")).is_ok());
+    let filter = r#"http.host == "a" || http.host == "bcdefgh""#;
+    assert_eq!(
+        parser.parse(filter),
+        limit_error(filter, Limit::FilterLength, 40, filter)
+    );
+    let input = "tcp.port == 1; not (not tcp.port == 2); tcp.port == 3";
+    let results = parser.parse_all(input);
+    assert!(results[0].is_ok() && results[2].is_ok());
+    assert_eq!(results[1], limit_error(input, Limit::Depth, 2, "not"));
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_max_regex_size() {
+    let scheme = Scheme! { http.host: Bytes };
+
+    let parser = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            max_regex_size: Some(10_000),
+            ..ParserOptions::default()
+        },
+    );
+
+    assert!(parser.parse(r#"http.host matches "^a{10}$""#).is_ok());
+
+    let filter = r#"http.host matches "a{100}{100}""#;
+    assert_eq!(
+        parser.parse(filter),
+        Err(ParseError::new(
+            filter,
+            (
+                LexErrorKind::LimitExceeded {
+                    limit: Limit::RegexSize,
+                    max: 10_000,
+                },
+                &filter[19..30]
+            )
+        ))
+    );
+
+    // The default limit of the regex crate is much larger.
+    assert!(Parser::new(&scheme).parse(filter).is_ok());
+}
+
+#[test]
+fn test_parse_all_errors() {
+    let scheme = Scheme! { http.host: Bytes, tcp.port: Int, ssl: Bool };
+
+    assert_eq!(
+        scheme.parse_all_errors("ssl && tcp.port == 1"),
+        Ok(scheme.parse("ssl && tcp.port == 1").unwrap())
+    );
+
+    let input = r#"tcp.port == && (ssl || http.host == "a") or http.host in { 1 } and foo"#;
+    let errors = scheme.parse_all_errors(input).unwrap_err();
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0].start(), 11);
+    assert_eq!(errors[1].start(), input.find("1 }").unwrap());
+    assert_eq!(errors[2].span(), "foo");
+
+    // Operands within parentheses are split too.
+    let input = "(tcp.port == || ssl) && tcp.port == 1 && http.host ==";
+    let errors = scheme.parse_all_errors(input).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].start(), 12);
+    assert_eq!(errors[1].start(), input.len());
+
+    let input = r#"tcp.port == "x" && not (ssl || http.host == "y" || (tcp.port in { 1..2 } && tcp.port == 1.5))"#;
+    let errors = scheme.parse_all_errors(input).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].start(), input.find(r#""x""#).unwrap());
+    assert_eq!(errors[1].start(), input.find("1.5").unwrap() + 1);
+
+    // Intervals don't need to have matching brackets.
+    let input = "(tcp.port in [1, 2) || tcp.port == ssl) && ssl";
+    let errors = scheme.parse_all_errors(input).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span(), "ssl");
+
+    // Errors in operands followed by a comment are reported as a whole.
+    let input = "(tcp.port == 1 || ssl == 1) /* c */ && ssl";
+    let errors = scheme.parse_all_errors(input).unwrap_err();
+    assert_eq!(errors.len(), 1);
+
+    // An empty operand is an error too.
+    let errors = scheme.parse_all_errors("ssl && && ssl").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].start(), 6);
+
+    // All operands are valid, so only the error of the whole filter is left.
+    let input = "(ssl && ssl";
+    assert_eq!(
+        scheme.parse_all_errors(input),
+        Err(vec![scheme.parse(input).unwrap_err()])
+    );
+}
+
+#[test]
+fn test_regex_cache() {
+    let scheme = Scheme! { http.host: Bytes };
+
+    let cache = RegexCache::new();
+    let options = ParserOptions {
+        regex_cache: Some(cache.clone()),
+        ..ParserOptions::default()
+    };
+    let first = Parser::with_options(&scheme, options.clone());
+    let second = Parser::with_options(&scheme, options);
+    assert_eq!(first.options(), second.options());
+
+    let filter = r#"http.host matches "^a" || http.host matches "^b""#;
+    let ast = first.parse(filter).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(second.parse(filter), Ok(ast));
+    assert_eq!(cache.len(), 2);
+
+    assert!(second
+        .parse(r#"http.host matches "^a" && http.host ~ "c""#)
+        .is_ok());
+    assert_eq!(cache.len(), 3);
+
+    // A cache with a different size limit doesn't share the entries.
+    let limited = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            regex_cache: Some(cache.clone()),
+            max_regex_size: Some(1 << 20),
+            ..ParserOptions::default()
+        },
+    );
+    assert!(limited.parse(r#"http.host matches "^a""#).is_ok());
+    assert_eq!(cache.len(), 4);
+
+    cache.clear();
+    assert!(cache.is_empty());
+    assert_ne!(cache, RegexCache::new());
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_empty_matching_regexes() {
+    let scheme = Scheme! { http.path: Bytes };
+
+    let filter = r#" http.path matches "a?" || http.path matches "a+" || http.path !~ "^/|x*" "#;
+
+    assert_eq!(scheme.parse(filter).unwrap().warnings(), &[]);
+
+    let parser = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            warn_empty_matching_regexes: true,
+            ..ParserOptions::default()
+        },
+    );
+
+    let ast = parser.parse(filter).unwrap();
+
+    assert_eq!(
+        ast.warnings(),
+        &[
+            Warning::EmptyMatchingRegex { span: 1..23 },
+            Warning::EmptyMatchingRegex { span: 53..73 },
+        ]
+    );
+    assert_eq!(&filter[1..23], r#"http.path matches "a?""#);
+    assert_eq!(&filter[53..73], r#"http.path !~ "^/|x*""#);
+
+    // Anchored on both sides, an empty match is only possible for an empty
+    // value.
+    let ast = parser.parse(r#"http.path matches "^a*$""#).unwrap();
+    assert_eq!(ast.warnings(), &[]);
+}
+
+#[test]
+fn test_parse_all() {
+    let scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+
+    let input = r#"http.host == "a;b"; tcp.port in [1, 10); tcp.port in { 80 443 };"#;
+    assert_eq!(
+        scheme.parse_all(input),
+        vec![
+            scheme.parse(r#"http.host == "a;b""#),
+            scheme.parse("tcp.port in [1, 10)"),
+            scheme.parse("tcp.port in { 80 443 }"),
+        ]
+    );
+
+    assert_eq!(
+        scheme.parse_all(r#"http.host == "\";" ; ; tcp.port == 1"#),
+        vec![
+            scheme.parse(r#"http.host == "\";""#),
+            scheme.parse("tcp.port == 1"),
+        ]
+    );
+
+    // Raw strings only end at a quote followed by the same number of `#`.
+    assert_eq!(
+        scheme.parse_all(r##"http.host ~ r#"a";"b"#; tcp.port == 1"##),
+        vec![
+            scheme.parse(r##"http.host ~ r#"a";"b"#"##),
+            scheme.parse("tcp.port == 1"),
+        ]
+    );
+
+    // A quoted name is never split, even if it's not a valid field.
+    let results = scheme.parse_all("`tcp;port` == 1");
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+
+    // Errors are reported for their own filter and point into the document.
+    let input = "tcp.port == 1;\ntcp.port ==;\ntcp.port == 2";
+    let results = scheme.parse_all(input);
+    let error_pos = input.find("==;").unwrap() + 2;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0], scheme.parse("tcp.port == 1"));
+    assert_eq!(
+        results[1],
+        Err(ParseError::new(
+            input,
+            (
+                LexErrorKind::ExpectedName("digit"),
+                &input[error_pos..error_pos]
+            )
+        ))
+    );
+    assert_eq!(results[2], scheme.parse("tcp.port == 2"));
+
+    // Comparisons point into the document too.
+    let input = " tcp.port == 1 && http.host == \"a\"; http.host contains \"b\" || tcp.port > 2; ";
+    let sources = scheme
+        .parse_all(input)
+        .iter()
+        .map(|ast| {
+            ast.as_ref()
+                .unwrap()
+                .comparisons()
+                .iter()
+                .map(|comparison| comparison.source_text(input).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sources,
+        vec![
+            vec!["tcp.port == 1", r#"http.host == "a""#],
+            vec![r#"http.host contains "b""#, "tcp.port > 2"],
+        ]
+    );
+
+    assert!(scheme.parse_all(" ; ").is_empty());
+}
+
+#[test]
+fn test_comments() {
+    let scheme = Scheme! { http.host: Bytes, tcp.port: Int, ip.src: Ip };
+
+    let input = r#"
+        # Hosts of the main site.
+        http.host in { "a.org" /* old */ "b.org" } # and its API
+        && (
+            tcp.port == 443 # ; not 80
+            || tcp.port == 8000 /* + 43 */ + 443
+        )
+        /* Local networks:
+           ip.src in { 10.0.0.0/8 } */
+        # the end
+    "#;
+    let expected = scheme
+        .parse(r#"http.host in { "a.org" "b.org" } && (tcp.port == 443 || tcp.port == 8443)"#)
+        .unwrap();
+    assert_eq!(scheme.parse(input), Ok(expected.clone()));
+    assert_eq!(scheme.parse_all(input), vec![Ok(expected.clone())]);
+    assert_eq!(format!("{:#}", expected), expected.to_string());
+
+    assert_eq!(
+        scheme.parse_all("tcp.port == 1; # ; tcp.port == 2\n; /* ; */;"),
+        vec![scheme.parse("tcp.port == 1")]
+    );
+
+    let parser = Parser::with_options(
+        &scheme,
+        ParserOptions {
+            keep_comments: true,
+            ..ParserOptions::default()
+        },
+    );
+    let ast = parser.parse(input).unwrap();
+    assert_eq!(
+        ast.comments(),
+        [
+            "# Hosts of the main site.",
+            "/* old */",
+            "# and its API",
+            "# ; not 80",
+            "/* + 43 */",
+            "/* Local networks:\n           ip.src in { 10.0.0.0/8 } */",
+            "# the end",
+        ]
+    );
+    assert_ne!(ast, expected);
+
+    // Comments are written before the filter, and parse back the same.
+    let output = format!("{:#}", ast);
+    assert!(output.starts_with("# Hosts of the main site.\n/* old */\n# and its API\n"));
+    assert!(output.ends_with(&format!("# the end\n{}", expected)));
+    assert_eq!(parser.parse(&output), Ok(ast));
+
+    let input = "tcp.port == 1 /* a";
+    assert_eq!(
+        scheme.parse(input),
+        Err(ParseError::new(
+            input,
+            (LexErrorKind::UnterminatedComment, &input[14..])
+        ))
+    );
+    let input = "tcp.port == 1 && /* a";
+    assert_eq!(
+        scheme.parse(input),
+        Err(ParseError::new(
+            input,
+            (LexErrorKind::UnterminatedComment, &input[17..])
+        ))
+    );
+}
@@ -0,0 +1,249 @@
+use execution_context::ExecutionContext;
+use filter::{CompiledExpr, SchemeMismatchError};
+use scheme::Scheme;
+
+// A single step of a `Program`, operating on a boolean accumulator.
+//
+// Jumps are used for short-circuiting `and` and `or`: they look at the
+// accumulator and either jump past the rest of the chain, leaving the value
+// as its result, or fall through to the next item, which overwrites it.
+// Comparisons followed by a jump are fused into a single instruction.
+pub(crate) enum Instruction<'s> {
+    // Executes a comparison and stores its result.
+    Test(CompiledExpr<'s>),
+    // Executes a comparison, stores its result and jumps to a given
+    // instruction if it's `false`.
+    TestJumpIfFalse(CompiledExpr<'s>, usize),
+    // Executes a comparison, stores its result and jumps to a given
+    // instruction if it's `true`.
+    TestJumpIfTrue(CompiledExpr<'s>, usize),
+    // Negates the accumulator.
+    Not,
+    // Saves the accumulator before the next item of an `xor`.
+    Push,
+    // XORs the last saved value into the accumulator.
+    Xor,
+    // Jumps to a given instruction if the accumulator is `false`.
+    JumpIfFalse(usize),
+    // Jumps to a given instruction if the accumulator is `true`.
+    JumpIfTrue(usize),
+}
+
+/// A filter lowered into a flat list of instructions for a stack machine,
+/// as returned by [`FilterAst::compile_program`](::FilterAst::compile_program).
+///
+/// Unlike [`Filter`](::Filter), where each `and`, `or` and `not` is a
+/// closure calling the closures of its operands, only comparisons are
+/// closures here, and the logic between them is executed by a single loop
+/// over the instructions. Results are always the same as those of the
+/// corresponding [`Filter`](::Filter).
+///
+/// Intermediate results of `and`, `or` and `not` are kept in a single
+/// register, so executing filters without `xor` doesn't allocate.
+pub struct Program<'s> {
+    instructions: Box<[Instruction<'s>]>,
+    scheme: &'s Scheme,
+}
+
+impl<'s> Program<'s> {
+    pub(crate) fn new(instructions: Vec<Instruction<'s>>, scheme: &'s Scheme) -> Self {
+        Program {
+            instructions: instructions.into_boxed_slice(),
+            scheme,
+        }
+    }
+
+    /// Returns the number of instructions in the program.
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Checks whether the program has no instructions, which is never the
+    /// case for programs compiled from a filter.
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Executes the program against a provided context with values.
+    pub fn execute(&self, ctx: &ExecutionContext<'_>) -> Result<bool, SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError);
+        }
+
+        let mut acc = false;
+        let mut saved = Vec::new();
+        let mut pc = 0;
+
+        while let Some(instruction) = self.instructions.get(pc) {
+            pc += 1;
+
+            match instruction {
+                Instruction::Test(expr) => acc = expr.execute(ctx),
+                Instruction::TestJumpIfFalse(expr, target) => {
+                    acc = expr.execute(ctx);
+                    if !acc {
+                        pc = *target;
+                    }
+                }
+                Instruction::TestJumpIfTrue(expr, target) => {
+                    acc = expr.execute(ctx);
+                    if acc {
+                        pc = *target;
+                    }
+                }
+                Instruction::Not => acc = !acc,
+                Instruction::Push => saved.push(acc),
+                Instruction::Xor => acc ^= saved.pop().unwrap(),
+                Instruction::JumpIfFalse(target) => {
+                    if !acc {
+                        pc = *target;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if acc {
+                        pc = *target;
+                    }
+                }
+            }
+        }
+
+        Ok(acc)
+    }
+}
+
+#[test]
+fn test_program() {
+    let scheme = &Scheme! { a: Int, b: Int, ssl: Bool };
+    let program = |filter| scheme.parse(filter).unwrap().compile_program();
+
+    // `a == 1` with `JumpIfFalse`, `b == 2` with `JumpIfTrue`, `ssl`, `Not`.
+    assert_eq!(program("a == 1 && (b == 2 || not ssl)").len(), 4);
+    // `a == 1`, `Not`, `JumpIfTrue`, `b == 2`.
+    assert_eq!(program("not a == 1 || b == 2").len(), 4);
+    // `a == 1`, `Push`, `b == 2`, `Xor`.
+    assert_eq!(program("a == 1 ^^ b == 2").len(), 4);
+
+    let mut ctx = ExecutionContext::new(scheme);
+    ctx.set_field_value("a", 1).unwrap();
+    ctx.set_field_value("b", 3).unwrap();
+    ctx.set_field_value("ssl", true).unwrap();
+
+    assert_eq!(
+        program("a == 1 && (b == 2 || not ssl)").execute(&ctx),
+        Ok(false)
+    );
+    assert_eq!(
+        program("a == 1 && (b == 3 || not ssl)").execute(&ctx),
+        Ok(true)
+    );
+    assert_eq!(program("a == 1 ^^ b == 3 ^^ ssl").execute(&ctx), Ok(true));
+    assert_eq!(program("not (a == 2 || b == 2)").execute(&ctx), Ok(true));
+
+    let other_scheme = Scheme! { a: Int };
+    assert_eq!(
+        program("a == 1").execute(&ExecutionContext::new(&other_scheme)),
+        Err(SchemeMismatchError)
+    );
+}
+
+// Generates random filters and contexts over the same scheme and checks that
+// programs always agree with tree-walking filters.
+#[cfg(feature = "regex")]
+#[test]
+fn test_program_matches_filter() {
+    use parser::{Parser, ParserOptions, UnknownFieldPolicy};
+    use std::net::IpAddr;
+
+    // A tiny xorshift generator, to keep the test deterministic.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    const LEAVES: &[&str] = &[
+        "ssl",
+        "port == 80",
+        "port != 443",
+        "port > 1024",
+        "port in { 22 80..90 }",
+        "port & 1",
+        "ip == 10.0.0.1",
+        "ip in { 10.0.0.0/8 }",
+        r#"host == "example.org""#,
+        r#"host contains "ample""#,
+        r#"host contains "org""#,
+        r#"host matches "^ex""#,
+        "missing == 1",
+    ];
+
+    fn gen_filter(rng: &mut Rng, depth: usize) -> String {
+        if depth == 0 || rng.next(3) == 0 {
+            return LEAVES[rng.next(LEAVES.len())].to_owned();
+        }
+
+        match rng.next(4) {
+            0 => format!("not ({})", gen_filter(rng, depth - 1)),
+            op => {
+                let op = ["&&", "||", "^^"][op - 1];
+                let items = (0..2 + rng.next(2))
+                    .map(|_| format!("({})", gen_filter(rng, depth - 1)))
+                    .collect::<Vec<_>>();
+                items.join(&format!(" {} ", op))
+            }
+        }
+    }
+
+    let scheme = &Scheme! { ssl: Bool, port: Int, ip: Ip, host: Bytes };
+    let parser = Parser::with_options(
+        scheme,
+        ParserOptions {
+            unknown_field_policy: UnknownFieldPolicy::TreatAsMissing,
+            ..Default::default()
+        },
+    );
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+    let contexts = (0..16)
+        .map(|_| {
+            let mut ctx = ExecutionContext::new(scheme);
+            if rng.next(4) != 0 {
+                ctx.set_field_value("ssl", rng.next(2) == 0).unwrap();
+            }
+            if rng.next(4) != 0 {
+                let port = [22, 80, 85, 443, 1025, 8080][rng.next(6)];
+                ctx.set_field_value("port", port).unwrap();
+            }
+            if rng.next(4) != 0 {
+                let ip = [[10, 0, 0, 1], [10, 1, 2, 3], [192, 0, 2, 1]][rng.next(3)];
+                ctx.set_field_value("ip", IpAddr::from(ip)).unwrap();
+            }
+            if rng.next(4) != 0 {
+                let host = ["example.org", "example.com", "org"][rng.next(3)];
+                ctx.set_field_value("host", host).unwrap();
+            }
+            ctx
+        })
+        .collect::<Vec<_>>();
+
+    for _ in 0..200 {
+        let filter = gen_filter(&mut rng, 4);
+        let ast = parser.parse(&filter).unwrap();
+        let program = ast.clone().compile_program();
+        let compiled = ast.compile();
+
+        for ctx in &contexts {
+            assert_eq!(
+                program.execute(ctx),
+                compiled.execute(ctx),
+                "filter: {}",
+                filter
+            );
+        }
+    }
+}
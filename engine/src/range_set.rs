@@ -54,3 +54,78 @@ impl<T> RangeSet<T> {
             .is_ok()
     }
 }
+
+/// Removes items whose range is entirely covered by the range of another
+/// item, keeping the first one of equal ranges and the order of the rest.
+pub(crate) fn remove_covered<I, T: Ord>(
+    items: &mut Vec<I>,
+    range_of: impl Fn(&I) -> RangeInclusive<T>,
+) {
+    let ranges = items.iter().map(range_of).collect::<Vec<_>>();
+
+    // With ranges sorted by start, and the widest first among equal starts,
+    // any range that covers another comes before it.
+    let mut order = (0..ranges.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        ranges[a]
+            .start()
+            .cmp(ranges[b].start())
+            .then_with(|| ranges[b].end().cmp(ranges[a].end()))
+    });
+
+    let mut keep = vec![true; ranges.len()];
+    let mut max_end = None;
+
+    for &i in &order {
+        match max_end {
+            Some(end) if ranges[i].end() <= end => keep[i] = false,
+            _ => max_end = Some(ranges[i].end()),
+        }
+    }
+
+    let mut keep = keep.into_iter();
+    items.retain(|_| keep.next().unwrap());
+}
+
+#[test]
+fn test_remove_covered() {
+    let mut items = vec![5..=5, 1..=3, 0..=10, 12..=15, 13..=14, 12..=15, 20..=20];
+    remove_covered(&mut items, |range| range.clone());
+    assert_eq!(items, vec![0..=10, 12..=15, 20..=20]);
+}
+
+#[test]
+fn test_matches_linear_scan() {
+    // A tiny deterministic LCG, good enough to shuffle test data around.
+    let mut seed = 0x2545_F491u32;
+    let mut next = move |max: i32| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 8) as i32 % max
+    };
+
+    for _ in 0..100 {
+        let ranges = (0..next(20))
+            .map(|_| {
+                let start = next(200) - 100;
+                // mostly single values, with some duplicates and overlaps
+                let len = if next(4) == 0 { next(30) } else { 0 };
+                start..=start + len
+            })
+            .collect::<Vec<_>>();
+
+        let set = RangeSet::from(ranges.clone());
+
+        for _ in 0..100 {
+            let value = next(260) - 130;
+            assert_eq!(
+                set.contains(&value),
+                ranges
+                    .iter()
+                    .any(|range| *range.start() <= value && value <= *range.end()),
+                "{} in {:?}",
+                value,
+                ranges
+            );
+        }
+    }
+}
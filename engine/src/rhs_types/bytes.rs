@@ -1,8 +1,9 @@
-use lex::{expect, take, Lex, LexErrorKind, LexResult};
+use lex::{expect, peek, skip_space, take, Lex, LexErrorKind, LexResult, LexWith};
+use parser::ParserOptions;
 use serde::Serialize;
 use std::{
     borrow::Borrow,
-    fmt::{self, Debug, Formatter},
+    fmt::{self, Debug, Display, Formatter},
     hash::{Hash, Hasher},
     ops::Deref,
     str,
@@ -51,7 +52,7 @@ impl From<Bytes> for Box<[u8]> {
 impl Debug for Bytes {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Bytes::Str(s) => s.fmt(f),
+            Bytes::Str(s) => Debug::fmt(s, f),
             Bytes::Raw(b) => {
                 for (i, b) in b.iter().cloned().enumerate() {
                     if i != 0 {
@@ -65,6 +66,24 @@ impl Debug for Bytes {
     }
 }
 
+// Formats the bytes as a string literal that lexes back into the same bytes,
+// escaping everything but printable ASCII.
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for &b in self.iter() {
+            if b == b'"' || b == b'\\' {
+                write!(f, "\\{}", b as char)?;
+            } else if b' ' <= b && b <= b'~' {
+                write!(f, "{}", b as char)?;
+            } else {
+                write!(f, "\\x{:02X}", b)?;
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
 impl Deref for Bytes {
     type Target = [u8];
 
@@ -82,8 +101,13 @@ impl Borrow<[u8]> for Bytes {
     }
 }
 
-fn fixed_byte(input: &str, digits: usize, radix: u32) -> LexResult<'_, u8> {
-    let (digits, rest) = take(input, digits)?;
+fn fixed_byte<'i>(
+    input: &'i str,
+    name: &'static str,
+    digits: usize,
+    radix: u32,
+) -> LexResult<'i, u8> {
+    let (digits, rest) = take(input, name, digits)?;
     match u8::from_str_radix(digits, radix) {
         Ok(b) => Ok((b, rest)),
         Err(err) => Err((LexErrorKind::ParseInt { err, radix }, digits)),
@@ -91,11 +115,11 @@ fn fixed_byte(input: &str, digits: usize, radix: u32) -> LexResult<'_, u8> {
 }
 
 fn hex_byte(input: &str) -> LexResult<'_, u8> {
-    fixed_byte(input, 2, 16)
+    fixed_byte(input, "hex digit", 2, 16)
 }
 
 fn oct_byte(input: &str) -> LexResult<'_, u8> {
-    fixed_byte(input, 3, 8)
+    fixed_byte(input, "octal digit", 3, 8)
 }
 
 lex_enum!(ByteSeparator {
@@ -104,11 +128,118 @@ lex_enum!(ByteSeparator {
     "." => Dot,
 });
 
-impl<'i> Lex<'i> for Bytes {
-    fn lex(mut input: &str) -> LexResult<'_, Self> {
+impl Bytes {
+    /// Lexes hex digits, with or without separators between bytes, until the
+    /// end of input.
+    pub(crate) fn lex_hex(mut input: &str) -> LexResult<'_, Self> {
+        let mut res = Vec::new();
+        while !input.is_empty() {
+            let (b, rest) = hex_byte(input)?;
+            res.push(b);
+            input = match ByteSeparator::lex(rest) {
+                Ok((_, rest)) => rest,
+                Err(_) => rest,
+            };
+        }
+        Ok((res.into(), input))
+    }
+
+    /// Decodes base64 until the end of input.
+    ///
+    /// Both the standard (`+/`) and the URL-safe (`-_`) alphabets are
+    /// accepted. Padding is optional, but, if present, must be complete.
+    pub(crate) fn lex_base64(input: &str) -> LexResult<'_, Self> {
+        let data = input.trim_end_matches('=');
+        let padding = input.len() - data.len();
+
+        let mut res = Vec::with_capacity(data.len() * 3 / 4);
+        let mut acc = 0u32;
+        let mut bits = 0;
+
+        for (i, c) in data.char_indices() {
+            let value = match c {
+                'A'..='Z' => c as u32 - 'A' as u32,
+                'a'..='z' => c as u32 - 'a' as u32 + 26,
+                '0'..='9' => c as u32 - '0' as u32 + 52,
+                '+' | '-' => 62,
+                '/' | '_' => 63,
+                _ => {
+                    return Err((LexErrorKind::InvalidBase64, &data[i..i + c.len_utf8()]));
+                }
+            };
+
+            acc = (acc << 6) | value;
+            bits += 6;
+
+            if bits >= 8 {
+                bits -= 8;
+                res.push((acc >> bits) as u8);
+                acc &= (1 << bits) - 1;
+            }
+        }
+
+        // A single leftover character doesn't make up a full byte.
+        if data.len() % 4 == 1 || (padding != 0 && (padding > 2 || input.len() % 4 != 0)) {
+            return Err((LexErrorKind::InvalidBase64, input));
+        }
+
+        Ok((res.into(), &input[input.len()..]))
+    }
+
+    /// Lexes hex digits in brackets, like `0x[de ad be ef]`, where any
+    /// space between digits is ignored, but the total number of digits
+    /// must still be even.
+    fn lex_bracketed_hex(input: &str) -> LexResult<'_, Self> {
+        let mut input = expect(input, "0x[")?;
+        let mut res = Vec::new();
+        // A digit still waiting for its pair, if any.
+        let mut pending: Option<(u8, &str)> = None;
+
+        loop {
+            input = skip_space(input);
+
+            if let Ok(rest) = expect(input, "]") {
+                return match pending {
+                    Some((_, digit)) => Err((
+                        LexErrorKind::CountMismatch {
+                            name: "hex digit",
+                            actual: 1,
+                            expected: 2,
+                        },
+                        digit,
+                    )),
+                    None => Ok((res.into(), rest)),
+                };
+            }
+
+            if input.is_empty() {
+                return Err((LexErrorKind::ExpectedLiteral("]"), input));
+            }
+
+            let (digit, rest) = take(input, "hex digit", 1)?;
+            let value = u8::from_str_radix(digit, 16)
+                .map_err(|err| (LexErrorKind::ParseInt { err, radix: 16 }, digit))?;
+
+            pending = match pending {
+                Some((high, _)) => {
+                    res.push(high << 4 | value);
+                    None
+                }
+                None => Some((value, digit)),
+            };
+
+            input = rest;
+        }
+    }
+}
+
+impl<'i, 'o> LexWith<'i, &'o ParserOptions> for Bytes {
+    fn lex_with(mut input: &'i str, options: &'o ParserOptions) -> LexResult<'i, Self> {
         if let Ok(input) = expect(input, "\"") {
             let full_input = input;
-            let mut res = String::new();
+            // Escapes can produce arbitrary bytes, so the result is only
+            // checked to be a valid string at the end.
+            let mut res = Vec::new();
             let mut iter = input.chars();
             loop {
                 match iter
@@ -123,16 +254,16 @@ impl<'i> Lex<'i> for Bytes {
                             .ok_or_else(|| (LexErrorKind::MissingEndingQuote, full_input))?;
 
                         res.push(match c {
-                            '"' | '\\' => c,
+                            '"' | '\\' => c as u8,
                             'x' => {
                                 let (b, input) = hex_byte(iter.as_str())?;
                                 iter = input.chars();
-                                b as char
+                                b
                             }
                             '0'..='7' => {
                                 let (b, input) = oct_byte(input)?;
                                 iter = input.chars();
-                                b as char
+                                b
                             }
                             _ => {
                                 return Err((
@@ -142,10 +273,27 @@ impl<'i> Lex<'i> for Bytes {
                             }
                         });
                     }
-                    '"' => return Ok((res.into(), iter.as_str())),
-                    c => res.push(c),
+                    '"' => {
+                        let rest = iter.as_str();
+                        match expect(rest, "\"") {
+                            Ok(rest) if options.doubled_quote_escapes => {
+                                res.push(b'"');
+                                iter = rest.chars();
+                            }
+                            _ => {
+                                let res = match String::from_utf8(res) {
+                                    Ok(s) => s.into(),
+                                    Err(err) => err.into_bytes().into(),
+                                };
+                                return Ok((res, rest));
+                            }
+                        }
+                    }
+                    c => res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
                 };
             }
+        } else if peek(input, "0x[") {
+            Bytes::lex_bracketed_hex(input)
         } else {
             let mut res = Vec::new();
             loop {
@@ -167,18 +315,18 @@ impl StrictPartialOrd for [u8] {}
 #[test]
 fn test() {
     assert_ok!(
-        Bytes::lex("01:2e:f3-77.12;"),
+        Bytes::lex_with("01:2e:f3-77.12;", &ParserOptions::default()),
         Bytes::from(vec![0x01, 0x2E, 0xF3, 0x77, 0x12]),
         ";"
     );
 
     assert_ok!(
-        Bytes::lex(r#""s\\t\"r\x0A\000t""#),
+        Bytes::lex_with(r#""s\\t\"r\x0A\000t""#, &ParserOptions::default()),
         Bytes::from("s\\t\"r\n\0t".to_owned())
     );
 
     assert_err!(
-        Bytes::lex("01:4x;"),
+        Bytes::lex_with("01:4x;", &ParserOptions::default()),
         LexErrorKind::ParseInt {
             err: u8::from_str_radix("4x", 16).unwrap_err(),
             radix: 16,
@@ -186,26 +334,37 @@ fn test() {
         "4x"
     );
 
-    assert_ok!(Bytes::lex("01;"), Bytes::from(vec![0x01]), ";");
+    assert_ok!(
+        Bytes::lex_with("01;", &ParserOptions::default()),
+        Bytes::from(vec![0x01]),
+        ";"
+    );
 
-    assert_ok!(Bytes::lex("01:2f-34"), Bytes::from(vec![0x01, 0x2F, 0x34]));
+    assert_ok!(
+        Bytes::lex_with("01:2f-34", &ParserOptions::default()),
+        Bytes::from(vec![0x01, 0x2F, 0x34])
+    );
 
-    assert_err!(Bytes::lex("\"1"), LexErrorKind::MissingEndingQuote, "1");
+    assert_err!(
+        Bytes::lex_with("\"1", &ParserOptions::default()),
+        LexErrorKind::MissingEndingQuote,
+        "1"
+    );
 
     assert_err!(
-        Bytes::lex(r#""\n""#),
+        Bytes::lex_with(r#""\n""#, &ParserOptions::default()),
         LexErrorKind::InvalidCharacterEscape,
         "n"
     );
 
     assert_err!(
-        Bytes::lex(r#""abcd\"#),
+        Bytes::lex_with(r#""abcd\"#, &ParserOptions::default()),
         LexErrorKind::MissingEndingQuote,
         "abcd\\"
     );
 
     assert_err!(
-        Bytes::lex(r#""\01😢""#),
+        Bytes::lex_with(r#""\01😢""#, &ParserOptions::default()),
         LexErrorKind::ParseInt {
             err: u8::from_str_radix("01😢", 8).unwrap_err(),
             radix: 8,
@@ -214,7 +373,7 @@ fn test() {
     );
 
     assert_err!(
-        Bytes::lex(r#""\x3😢""#),
+        Bytes::lex_with(r#""\x3😢""#, &ParserOptions::default()),
         LexErrorKind::ParseInt {
             err: u8::from_str_radix("3😢", 16).unwrap_err(),
             radix: 16,
@@ -223,7 +382,7 @@ fn test() {
     );
 
     assert_err!(
-        Bytes::lex("12:3😢"),
+        Bytes::lex_with("12:3😢", &ParserOptions::default()),
         LexErrorKind::ParseInt {
             err: u8::from_str_radix("3😢", 16).unwrap_err(),
             radix: 16,
@@ -231,3 +390,188 @@ fn test() {
         "3😢"
     );
 }
+
+#[test]
+fn test_truncated_escapes() {
+    let err = Bytes::lex_with(r#""\x3"#, &ParserOptions::default()).unwrap_err();
+    assert_eq!(
+        err,
+        (
+            LexErrorKind::CountMismatch {
+                name: "hex digit",
+                actual: 1,
+                expected: 2,
+            },
+            "3"
+        )
+    );
+    assert_eq!(err.0.to_string(), "expected 2 hex digits, but found 1");
+
+    assert_err!(
+        Bytes::lex_with(r#""\01"#, &ParserOptions::default()),
+        LexErrorKind::CountMismatch {
+            name: "octal digit",
+            actual: 2,
+            expected: 3,
+        },
+        "01"
+    );
+}
+
+#[test]
+fn test_doubled_quote_escapes() {
+    let options = &ParserOptions {
+        doubled_quote_escapes: true,
+        ..ParserOptions::default()
+    };
+
+    assert_ok!(
+        Bytes::lex_with(r#""he said ""hi""""#, options),
+        Bytes::from(r#"he said "hi""#.to_owned())
+    );
+
+    assert_ok!(
+        Bytes::lex_with(r#""a\"b""c" rest"#, options),
+        Bytes::from(r#"a"b"c"#.to_owned()),
+        " rest"
+    );
+
+    assert_ok!(
+        Bytes::lex_with(r#""""""#, options),
+        Bytes::from(r#"""#.to_owned())
+    );
+
+    assert_err!(
+        Bytes::lex_with(r#""abc"""#, options),
+        LexErrorKind::MissingEndingQuote,
+        r#"abc"""#
+    );
+
+    // Without the option, doubled quote ends the string as usual.
+    assert_ok!(
+        Bytes::lex_with(r#""a""b""#, &ParserOptions::default()),
+        Bytes::from("a".to_owned()),
+        r#""b""#
+    );
+}
+
+#[test]
+fn test_base64() {
+    assert_ok!(
+        Bytes::lex_base64("3q2+7w=="),
+        Bytes::from(vec![0xDE, 0xAD, 0xBE, 0xEF])
+    );
+    assert_ok!(
+        Bytes::lex_base64("3q2-7w"),
+        Bytes::from(vec![0xDE, 0xAD, 0xBE, 0xEF])
+    );
+    assert_ok!(
+        Bytes::lex_base64("aGVsbG8="),
+        Bytes::from(b"hello".to_vec())
+    );
+    assert_ok!(Bytes::lex_base64(""), Bytes::from(vec![]));
+
+    assert_err!(
+        Bytes::lex_base64("3q2*7w=="),
+        LexErrorKind::InvalidBase64,
+        "*"
+    );
+    assert_err!(
+        Bytes::lex_base64("3q2+7"),
+        LexErrorKind::InvalidBase64,
+        "3q2+7"
+    );
+    assert_err!(
+        Bytes::lex_base64("3q2+7w="),
+        LexErrorKind::InvalidBase64,
+        "3q2+7w="
+    );
+}
+
+#[test]
+fn test_display_round_trip() {
+    let options = ParserOptions::default();
+
+    assert_ok!(
+        Bytes::lex_with(r#""\xFF\xfe\377""#, &options),
+        Bytes::from(vec![0xFF, 0xFE, 0xFF])
+    );
+    assert_ok!(
+        Bytes::lex_with(r#""\xE2\x9D\xA4 ❤""#, &options),
+        Bytes::from("❤ ❤".to_owned())
+    );
+
+    assert_eq!(
+        Bytes::from(b"a\"b\\c\n\xFF".to_vec()).to_string(),
+        r#""a\"b\\c\x0A\xFF""#
+    );
+    assert_eq!(
+        Bytes::from("❤".to_owned()).to_string(),
+        r#""\xE2\x9D\xA4""#
+    );
+
+    // A tiny deterministic LCG, good enough to generate random byte strings.
+    let mut seed = 0x2545_F491u32;
+    let mut next = move |max: u32| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 8) % max
+    };
+
+    for _ in 0..1000 {
+        let bytes = Bytes::from(
+            (0..next(20))
+                .map(|_| match next(4) {
+                    // bias towards characters that need escaping
+                    0 => b"\"\\x0"[next(4) as usize],
+                    _ => next(256) as u8,
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let literal = bytes.to_string();
+        let (lexed, rest) = Bytes::lex_with(&literal, &options).unwrap();
+
+        assert_eq!(rest, "");
+        assert_eq!(&*lexed, &*bytes, "{}", literal);
+    }
+}
+
+#[test]
+fn test_bracketed_hex() {
+    let options = &ParserOptions::default();
+    let expected = Bytes::from(vec![0xDE, 0xAD, 0xBE, 0xEF]);
+
+    assert_ok!(
+        Bytes::lex_with("0x[de ad be ef];", options),
+        expected.clone(),
+        ";"
+    );
+    assert_ok!(Bytes::lex_with("0x[deadbeef]", options), expected.clone());
+    assert_ok!(Bytes::lex_with("0x[ dead\nBEEF ]", options), expected);
+    assert_ok!(Bytes::lex_with("0x[]", options), Bytes::from(vec![]));
+
+    assert_err!(
+        Bytes::lex_with("0x[de ad b]", options),
+        LexErrorKind::CountMismatch {
+            name: "hex digit",
+            actual: 1,
+            expected: 2,
+        },
+        "b"
+    );
+
+    assert_err!(
+        Bytes::lex_with("0x[de ag]", options),
+        LexErrorKind::ParseInt {
+            err: u8::from_str_radix("g", 16).unwrap_err(),
+            radix: 16,
+        },
+        "g"
+    );
+
+    assert_err!(
+        Bytes::lex_with("0x[de ad", options),
+        LexErrorKind::ExpectedLiteral("]"),
+        ""
+    );
+}
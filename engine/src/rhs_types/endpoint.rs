@@ -0,0 +1,171 @@
+use cidr::NetworkParseError;
+use lex::{expect, span, take_while, Lex, LexErrorKind, LexResult};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+use strict_partial_ord::StrictPartialOrd;
+
+/// An IP address together with a port, written as `192.0.2.1:443` or, to
+/// tell the port apart from the address, `[2001:db8::1]:443` for IPv6.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Endpoint {
+    /// The address of the socket.
+    pub addr: IpAddr,
+    /// The port of the socket.
+    pub port: u16,
+}
+
+impl Endpoint {
+    /// Creates an endpoint from an address and a port.
+    pub fn new(addr: IpAddr, port: u16) -> Self {
+        Endpoint { addr, port }
+    }
+}
+
+fn lex_addr(input: &str) -> LexResult<'_, IpAddr> {
+    let (chunk, rest, addr) = if let Ok(input) = expect(input, "[") {
+        let (chunk, rest) = take_while(input, "IPv6 address character", |c| {
+            c.is_ascii_hexdigit() || c == ':' || c == '.'
+        })?;
+        let rest = expect(rest, "]")?;
+        (chunk, rest, Ipv6Addr::from_str(chunk).map(IpAddr::V6))
+    } else {
+        let (chunk, rest) = take_while(input, "IPv4 address character", |c| {
+            c.is_ascii_digit() || c == '.'
+        })?;
+        (chunk, rest, Ipv4Addr::from_str(chunk).map(IpAddr::V4))
+    };
+
+    match addr {
+        Ok(addr) => Ok((addr, rest)),
+        Err(err) => Err((
+            LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(err)),
+            chunk,
+        )),
+    }
+}
+
+impl<'i> Lex<'i> for Endpoint {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let (addr, input) = lex_addr(input)?;
+        let input = expect(input, ":")?;
+        let (digits, rest) = take_while(input, "port digit", |c| c.is_ascii_digit())?;
+        let port = u16::from_str(digits)
+            .map_err(|err| (LexErrorKind::ParseInt { err, radix: 10 }, span(input, rest)))?;
+        Ok((Endpoint::new(addr, port), rest))
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match Endpoint::lex(s) {
+            Ok((endpoint, "")) => Ok(endpoint),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            IpAddr::V4(addr) => write!(f, "{}:{}", addr, self.port),
+            IpAddr::V6(addr) => write!(f, "[{}]:{}", addr, self.port),
+        }
+    }
+}
+
+impl Serialize for Endpoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Endpoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|()| de::Error::invalid_value(de::Unexpected::Str(&s), &"an endpoint"))
+    }
+}
+
+// Endpoints of different families are incomparable, just like addresses.
+impl StrictPartialOrd for Endpoint {
+    fn strict_partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.addr
+            .strict_partial_cmp(&other.addr)
+            .map(|ord| ord.then(self.port.cmp(&other.port)))
+    }
+}
+
+impl PartialOrd for Endpoint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.strict_partial_cmp(other)
+    }
+}
+
+#[test]
+fn test_lex() {
+    assert_ok!(
+        Endpoint::lex("192.0.2.1:443;"),
+        Endpoint::new([192, 0, 2, 1].into(), 443),
+        ";"
+    );
+    assert_ok!(
+        Endpoint::lex("[2001:db8::1]:443 "),
+        Endpoint::new([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1].into(), 443),
+        " "
+    );
+    assert_err!(
+        Endpoint::lex("192.0.2.1:65536"),
+        LexErrorKind::ParseInt {
+            err: u16::from_str("65536").unwrap_err(),
+            radix: 10,
+        },
+        "65536"
+    );
+    assert_err!(
+        Endpoint::lex("192.0.2.1:-1"),
+        LexErrorKind::ExpectedName("port digit"),
+        "-1"
+    );
+    assert_err!(
+        Endpoint::lex("192.0.2.1"),
+        LexErrorKind::ExpectedLiteral(":"),
+        ""
+    );
+    assert_err!(
+        Endpoint::lex("[2001:db8::1:443"),
+        LexErrorKind::ExpectedLiteral("]"),
+        ""
+    );
+    assert_err!(
+        Endpoint::lex("192.0.2:443"),
+        LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+            Ipv4Addr::from_str("192.0.2").unwrap_err()
+        )),
+        "192.0.2"
+    );
+}
+
+#[test]
+fn test_display() {
+    for s in &["192.0.2.1:443", "[2001:db8::1]:0"] {
+        assert_eq!(s.parse::<Endpoint>().unwrap().to_string(), *s);
+    }
+}
+
+#[test]
+fn test_strict_partial_ord() {
+    let v4 = |port| Endpoint::new([10, 0, 0, 1].into(), port);
+    let v6 = Endpoint::new([0, 0, 0, 0, 0, 0, 0, 1].into(), 80);
+
+    assert_eq!(v4(80).strict_partial_cmp(&v4(443)), Some(Ordering::Less));
+    assert_eq!(v4(80).strict_partial_cmp(&v4(80)), Some(Ordering::Equal));
+    assert_eq!(v4(80).strict_partial_cmp(&v6), None);
+}
@@ -0,0 +1,155 @@
+use lex::{expect, span, take_while, Lex, LexErrorKind, LexResult};
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    fmt::{self, Debug, Display, Formatter},
+    ops::RangeInclusive,
+    str::FromStr,
+};
+use strict_partial_ord::StrictPartialOrd;
+
+/// A floating-point literal, like `0.85`, `-3` or `1.5e-3`.
+///
+/// Literals are never NaN, so unlike `f64` these are totally ordered.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Float(f64);
+
+impl Float {
+    /// Creates a literal from a value, unless it's NaN.
+    pub fn new(value: f64) -> Option<Self> {
+        if value.is_nan() {
+            None
+        } else {
+            Some(Float(value))
+        }
+    }
+
+    /// Returns the value of the literal.
+    pub fn value(self) -> f64 {
+        self.0
+    }
+}
+
+impl From<Float> for f64 {
+    fn from(value: Float) -> Self {
+        value.0
+    }
+}
+
+impl Debug for Float {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+// Formats the value in the literal syntax, writing infinities as the
+// smallest literals that overflow into them.
+impl Display for Float {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_infinite() {
+            f.write_str(if self.0 < 0.0 { "-1e309" } else { "1e309" })
+        } else {
+            Debug::fmt(&self.0, f)
+        }
+    }
+}
+
+impl Eq for Float {}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.partial_cmp(other).unwrap()
+    }
+}
+
+fn lex_digits(input: &str) -> LexResult<'_, &str> {
+    take_while(input, "digit", |c| c.is_ascii_digit())
+}
+
+impl<'i> Lex<'i> for Float {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let mut rest = expect(input, "-").unwrap_or(input);
+        rest = lex_digits(rest)?.1;
+
+        // A fraction needs a digit after the dot, so that `1..2` is still
+        // lexed as a range.
+        if let Ok(fraction) = expect(rest, ".") {
+            if fraction.starts_with(|c: char| c.is_ascii_digit()) {
+                rest = lex_digits(fraction)?.1;
+            }
+        }
+
+        if let Ok(exponent) = expect(rest, "e").or_else(|_| expect(rest, "E")) {
+            let exponent = expect(exponent, "-")
+                .or_else(|_| expect(exponent, "+"))
+                .unwrap_or(exponent);
+            rest = lex_digits(exponent)?.1;
+        }
+
+        // The syntax above is a subset of what `f64` accepts.
+        let value = f64::from_str(span(input, rest)).unwrap();
+        Ok((Float(value), rest))
+    }
+}
+
+impl<'i> Lex<'i> for RangeInclusive<Float> {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let initial_input = input;
+        let (first, input) = Float::lex(input)?;
+        let (last, input) = if let Ok(input) = expect(input, "..") {
+            Float::lex(input)?
+        } else {
+            (first, input)
+        };
+        if last < first {
+            return Err((
+                LexErrorKind::IncompatibleRangeBounds,
+                span(initial_input, input),
+            ));
+        }
+        Ok((first..=last, input))
+    }
+}
+
+impl StrictPartialOrd for Float {}
+
+// Field values are compared with literals directly. A NaN value is not
+// ordered relative to any literal, so only `!=` matches it.
+impl PartialEq<Float> for f64 {
+    fn eq(&self, other: &Float) -> bool {
+        *self == other.0
+    }
+}
+
+impl PartialOrd<Float> for f64 {
+    fn partial_cmp(&self, other: &Float) -> Option<Ordering> {
+        self.partial_cmp(&other.0)
+    }
+}
+
+impl StrictPartialOrd<Float> for f64 {}
+
+#[test]
+fn test_lex() {
+    assert_ok!(Float::lex("0.85 "), Float(0.85), " ");
+    assert_ok!(Float::lex("-3;"), Float(-3.0), ";");
+    assert_ok!(Float::lex("1.5e-3"), Float(0.0015), "");
+    assert_ok!(Float::lex("2E+2}"), Float(200.0), "}");
+    assert_ok!(Float::lex("1."), Float(1.0), ".");
+    assert_ok!(Float::lex("1..2"), Float(1.0), "..2");
+    assert_err!(Float::lex(".5"), LexErrorKind::ExpectedName("digit"), ".5");
+    assert_err!(Float::lex("1e"), LexErrorKind::ExpectedName("digit"), "");
+    assert_err!(Float::lex("-"), LexErrorKind::ExpectedName("digit"), "");
+
+    assert_ok!(
+        RangeInclusive::<Float>::lex("0.5..1e3 "),
+        Float(0.5)..=Float(1000.0),
+        " "
+    );
+    assert_err!(
+        RangeInclusive::<Float>::lex("1.5..0.5"),
+        LexErrorKind::IncompatibleRangeBounds,
+        "1.5..0.5"
+    );
+}
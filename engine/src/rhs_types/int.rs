@@ -1,4 +1,4 @@
-use lex::{expect, span, take_while, Lex, LexErrorKind, LexResult};
+use lex::{expect, peek, peek_char, skip_space, span, take_while, Lex, LexErrorKind, LexResult};
 use std::ops::RangeInclusive;
 use strict_partial_ord::StrictPartialOrd;
 
@@ -18,7 +18,7 @@ impl<'i> Lex<'i> for i32 {
     fn lex(input: &str) -> LexResult<'_, Self> {
         if let Ok(input) = expect(input, "0x") {
             parse_number(lex_digits(input)?, 16)
-        } else if input.starts_with('0') {
+        } else if peek_char(input) == Some('0') {
             // not using `expect` because we want to include `0` too
             parse_number(lex_digits(input)?, 8)
         } else {
@@ -53,6 +53,105 @@ impl<'i> Lex<'i> for RangeInclusive<i32> {
     }
 }
 
+/// Lexes an interval like `[1, 10)`, where each bracket controls whether
+/// its bound is included, into the equivalent inclusive range. Intervals
+/// without any numbers in them, like `(1, 2)`, are rejected.
+pub(crate) fn lex_interval(input: &str) -> LexResult<'_, RangeInclusive<i32>> {
+    let initial_input = input;
+
+    let (first_included, input) = match expect(input, "[") {
+        Ok(input) => (true, input),
+        Err(_) => (false, expect(input, "(")?),
+    };
+    let (first, input) = i32::lex(skip_space(input))?;
+    let input = expect(skip_space(input), ",")?;
+    let (last, input) = i32::lex(skip_space(input))?;
+    let input = skip_space(input);
+    let (last_included, input) = match expect(input, "]") {
+        Ok(input) => (true, input),
+        Err(_) => (false, expect(input, ")")?),
+    };
+
+    let first = if first_included {
+        Some(first)
+    } else {
+        first.checked_add(1)
+    };
+    let last = if last_included {
+        Some(last)
+    } else {
+        last.checked_sub(1)
+    };
+
+    match (first, last) {
+        (Some(first), Some(last)) if first <= last => Ok((first..=last, input)),
+        _ => Err((
+            LexErrorKind::IncompatibleRangeBounds,
+            span(initial_input, input),
+        )),
+    }
+}
+
+// Lexes a chain of operands joined by any of the given operators, which
+// all have the same precedence and are evaluated left to right.
+fn lex_binary<'i>(
+    input: &'i str,
+    ops: &[char],
+    operand: fn(&'i str) -> LexResult<'i, i32>,
+) -> LexResult<'i, i32> {
+    let (mut value, mut rest) = operand(input)?;
+
+    loop {
+        // Trailing space is left alone if no operator follows.
+        let op_input = skip_space(rest);
+        let op = match peek_char(op_input) {
+            // An unterminated comment, rather than a division.
+            Some('/') if peek(op_input, "/*") => return Ok((value, rest)),
+            Some(op) if ops.contains(&op) => op,
+            _ => return Ok((value, rest)),
+        };
+
+        let (rhs, operand_rest) = operand(skip_space(&op_input[1..]))?;
+        let result = match op {
+            '/' | '%' if rhs == 0 => {
+                return Err((LexErrorKind::DivisionByZero, span(input, operand_rest)));
+            }
+            '+' => value.checked_add(rhs),
+            '-' => value.checked_sub(rhs),
+            '*' => value.checked_mul(rhs),
+            '/' => value.checked_div(rhs),
+            '%' => value.checked_rem(rhs),
+            _ => unreachable!(),
+        };
+
+        value = result.ok_or_else(|| (LexErrorKind::IntegerOverflow, span(input, operand_rest)))?;
+        rest = operand_rest;
+    }
+}
+
+fn lex_factor(input: &str) -> LexResult<'_, i32> {
+    match expect(input, "(") {
+        Ok(rest) => {
+            let (value, rest) = lex_int_expr(skip_space(rest))?;
+            let rest = expect(skip_space(rest), ")")?;
+            Ok((value, rest))
+        }
+        Err(_) => i32::lex(input),
+    }
+}
+
+fn lex_term(input: &str) -> LexResult<'_, i32> {
+    lex_binary(input, &['*', '/', '%'], lex_factor)
+}
+
+/// Lexes constant arithmetic with `+`, `-`, `*`, `/`, `%` and brackets, like
+/// `8000 + 80` or `(1 + 1) * 1024`, and folds it into a single value.
+///
+/// Overflows and division by zero are errors rather than wrapping around.
+pub(crate) fn lex_int_expr(input: &str) -> LexResult<'_, i32> {
+    lex_binary(input, &['+', '-'], lex_term)
+}
+
 impl StrictPartialOrd for i32 {}
 
 #[test]
@@ -100,3 +199,29 @@ fn test() {
         "10..0"
     );
 }
+
+#[test]
+fn test_int_expr() {
+    assert_ok!(lex_int_expr("8000 + 80 "), 8080, " ");
+    assert_ok!(lex_int_expr("1024 * 1024"), 1_048_576);
+    assert_ok!(lex_int_expr("2 + 3 * 4 - 10 / 3"), 11);
+    assert_ok!(lex_int_expr("( 2 + 3 ) * 4 % 7)"), 6, ")");
+    assert_ok!(lex_int_expr("10 - -2 - 1"), 11);
+    assert_ok!(lex_int_expr("0x10+010"), 24);
+    assert_err!(lex_int_expr("1 +"), LexErrorKind::ExpectedName("digit"), "");
+    assert_err!(
+        lex_int_expr("(1 + 2"),
+        LexErrorKind::ExpectedLiteral(")"),
+        ""
+    );
+    assert_err!(
+        lex_int_expr("1 + 4 / (2 - 2)"),
+        LexErrorKind::DivisionByZero,
+        "4 / (2 - 2)"
+    );
+    assert_err!(
+        lex_int_expr("65536 * 65536"),
+        LexErrorKind::IntegerOverflow,
+        "65536 * 65536"
+    );
+}
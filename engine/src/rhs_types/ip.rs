@@ -1,8 +1,9 @@
 use cidr::{Cidr, IpCidr, Ipv4Cidr, Ipv6Cidr, NetworkParseError};
-use lex::{take_while, Lex, LexError, LexErrorKind, LexResult};
+use lex::{expect, skip_space, span, take_while, Lex, LexError, LexErrorKind, LexResult};
 use serde::Serialize;
 use std::{
     cmp::Ordering,
+    fmt::{self, Display, Formatter},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::RangeInclusive,
     str::FromStr,
@@ -32,6 +33,41 @@ impl<'i> Lex<'i> for IpAddr {
     }
 }
 
+/// Lexes an optional zone identifier of an IPv6 address, like `eth0` in
+/// `fe80::1%eth0`, where `input` starts with the address and `rest` follows
+/// right after it.
+///
+/// Zones are made of alphanumeric characters, `_`, `-` and `.`, and are not
+/// valid for IPv4 addresses.
+pub(crate) fn lex_zone<'i>(
+    input: &'i str,
+    addr: IpAddr,
+    rest: &'i str,
+) -> LexResult<'i, Option<(Ipv6Addr, &'i str)>> {
+    let zone_input = match expect(rest, "%") {
+        Ok(zone_input) => zone_input,
+        Err(_) => return Ok((None, rest)),
+    };
+
+    let len = zone_input
+        .find(|c: char| !c.is_ascii_alphanumeric() && !"_-.".contains(c))
+        .unwrap_or_else(|| zone_input.len());
+    let (zone, rest) = zone_input.split_at(len);
+
+    match addr {
+        IpAddr::V6(addr) if !zone.is_empty() => Ok((Some((addr, zone)), rest)),
+        _ => {
+            let chunk = span(input, rest);
+            Err((
+                LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+                    IpAddr::from_str(chunk).unwrap_err(),
+                )),
+                chunk,
+            ))
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Serialize, Debug)]
 #[serde(untagged)]
 pub enum ExplicitIpRange {
@@ -39,6 +75,44 @@ pub enum ExplicitIpRange {
     V6(RangeInclusive<Ipv6Addr>),
 }
 
+impl ExplicitIpRange {
+    fn new(first: IpAddr, last: IpAddr) -> Option<Self> {
+        match (first, last) {
+            (IpAddr::V4(first), IpAddr::V4(last)) if first <= last => {
+                Some(ExplicitIpRange::V4(first..=last))
+            }
+            (IpAddr::V6(first), IpAddr::V6(last)) if first <= last => {
+                Some(ExplicitIpRange::V6(first..=last))
+            }
+            _ => None,
+        }
+    }
+
+    /// Lexes a range written as `first .. last`, where spaces around `..`
+    /// are optional.
+    pub(crate) fn lex_spaced(input: &str) -> LexResult<'_, Self> {
+        // Addresses can contain single dots, so stop at `..` explicitly.
+        fn lex_bound(input: &str) -> LexResult<'_, IpAddr> {
+            let (chunk, _) = match_addr_or_cidr(input)?;
+            let chunk = &chunk[..chunk.find("..").unwrap_or_else(|| chunk.len())];
+            parse_addr(chunk).map(|addr| (addr, &input[chunk.len()..]))
+        }
+
+        let initial_input = input;
+        let (first, input) = lex_bound(input)?;
+        let input = expect(skip_space(input), "..")?;
+        let (last, input) = lex_bound(skip_space(input))?;
+
+        match ExplicitIpRange::new(first, last) {
+            Some(range) => Ok((range, input)),
+            None => Err((
+                LexErrorKind::IncompatibleRangeBounds,
+                span(initial_input, input),
+            )),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Serialize, Debug)]
 #[serde(untagged)]
 pub enum IpRange {
@@ -46,44 +120,107 @@ pub enum IpRange {
     Cidr(IpCidr),
 }
 
+impl Display for IpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IpRange::Explicit(ExplicitIpRange::V4(range)) => {
+                write!(f, "{}..{}", range.start(), range.end())
+            }
+            IpRange::Explicit(ExplicitIpRange::V6(range)) => {
+                write!(f, "{}..{}", range.start(), range.end())
+            }
+            IpRange::Cidr(cidr) => cidr.fmt(f),
+        }
+    }
+}
+
 impl<'i> Lex<'i> for IpRange {
     fn lex(input: &str) -> LexResult<'_, Self> {
         let (chunk, rest) = match_addr_or_cidr(input)?;
 
+        // Ranges can also be written as `first-last`, like in many
+        // blocklists, as addresses never contain dashes.
+        if let Ok(last_input) = expect(rest, "-") {
+            let first = parse_addr(chunk)?;
+            let (last_chunk, rest) = match_addr_or_cidr(last_input)?;
+            let last = parse_addr(last_chunk)?;
+
+            return match ExplicitIpRange::new(first, last) {
+                Some(range) => Ok((IpRange::Explicit(range), rest)),
+                None => Err((LexErrorKind::IncompatibleRangeBounds, span(input, rest))),
+            };
+        }
+
         // check for ".." before trying to lex an address
         let range = if let Some(split_pos) = chunk.find("..") {
             let first = parse_addr(&chunk[..split_pos])?;
             let last = parse_addr(&chunk[split_pos + "..".len()..])?;
 
-            IpRange::Explicit(match (first, last) {
-                (IpAddr::V4(first), IpAddr::V4(last)) if first <= last => {
-                    ExplicitIpRange::V4(first..=last)
-                }
-                (IpAddr::V6(first), IpAddr::V6(last)) if first <= last => {
-                    ExplicitIpRange::V6(first..=last)
-                }
-                _ => {
-                    return Err((LexErrorKind::IncompatibleRangeBounds, chunk));
-                }
-            })
+            IpRange::Explicit(
+                ExplicitIpRange::new(first, last)
+                    .ok_or_else(|| (LexErrorKind::IncompatibleRangeBounds, chunk))?,
+            )
         } else {
-            IpRange::Cidr(cidr::IpCidr::from_str(chunk).map_err(|err| {
-                let split_pos = chunk.find('/').unwrap_or_else(|| chunk.len());
-                let err_span = match err {
-                    NetworkParseError::AddrParseError(_) | NetworkParseError::InvalidHostPart => {
-                        &chunk[..split_pos]
-                    }
-                    NetworkParseError::NetworkLengthParseError(_) => &chunk[split_pos + 1..],
-                    NetworkParseError::NetworkLengthTooLongError(_) => chunk,
-                };
-                (LexErrorKind::ParseNetwork(err), err_span)
-            })?)
+            IpRange::Cidr(parse_cidr(chunk)?)
         };
 
         Ok((range, rest))
     }
 }
 
+fn parse_cidr(chunk: &str) -> Result<IpCidr, LexError<'_>> {
+    IpCidr::from_str(chunk).map_err(|err| {
+        let split_pos = chunk.find('/').unwrap_or_else(|| chunk.len());
+        let err_span = match err {
+            NetworkParseError::AddrParseError(_) | NetworkParseError::InvalidHostPart => {
+                &chunk[..split_pos]
+            }
+            NetworkParseError::NetworkLengthParseError(_) => &chunk[split_pos + 1..],
+            NetworkParseError::NetworkLengthTooLongError(_) => chunk,
+        };
+        (LexErrorKind::ParseNetwork(err), err_span)
+    })
+}
+
+impl<'i> Lex<'i> for IpCidr {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let (chunk, rest) = match_addr_or_cidr(input)?;
+        parse_cidr(chunk).map(|cidr| (cidr, rest))
+    }
+}
+
+// Networks of different families are incomparable, just like addresses.
+impl StrictPartialOrd for IpCidr {
+    fn strict_partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.is_ipv4() == other.is_ipv4() {
+            self.partial_cmp(other)
+        } else {
+            None
+        }
+    }
+}
+
+/// Either a single address or, if written with a network prefix, a CIDR.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum IpAddrOrCidr {
+    Addr(IpAddr),
+    Cidr(IpCidr),
+}
+
+impl<'i> Lex<'i> for IpAddrOrCidr {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let (chunk, rest) = match_addr_or_cidr(input)?;
+
+        let res = if chunk.contains('/') {
+            IpAddrOrCidr::Cidr(parse_cidr(chunk)?)
+        } else {
+            IpAddrOrCidr::Addr(parse_addr(chunk)?)
+        };
+
+        Ok((res, rest))
+    }
+}
+
 macro_rules! impl_ip_range_from {
     (@single $v:ident, |$input:ident: $ty:ty| $transform:expr) => {
         impl From<$ty> for ExplicitIpRange {
@@ -131,6 +268,39 @@ impl StrictPartialOrd for IpAddr {
     }
 }
 
+#[test]
+fn test_lex_zone() {
+    let v6 = Ipv6Addr::from([0xfe80, 0, 0, 0, 0, 0, 0, 1]);
+
+    let input = "fe80::1%eth0.1 ";
+    assert_ok!(
+        lex_zone(input, v6.into(), &input[7..]),
+        Some((v6, "eth0.1")),
+        " "
+    );
+
+    let input = "fe80::1 ";
+    assert_ok!(lex_zone(input, v6.into(), &input[7..]), None, " ");
+
+    let input = "fe80::1% ";
+    assert_err!(
+        lex_zone(input, v6.into(), &input[7..]),
+        LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+            IpAddr::from_str("fe80::1%").unwrap_err()
+        )),
+        "fe80::1%"
+    );
+
+    let input = "10.0.0.1%eth0";
+    assert_err!(
+        lex_zone(input, [10, 0, 0, 1].into(), &input[8..]),
+        LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+            IpAddr::from_str("10.0.0.1%eth0").unwrap_err()
+        )),
+        "10.0.0.1%eth0"
+    );
+}
+
 #[test]
 fn test_lex() {
     fn addr<A: Into<IpAddr>>(addr: A) -> IpRange {
@@ -183,6 +353,28 @@ fn test_lex() {
         range([10, 0, 0, 0]..=[127, 0, 0, 1]),
         " "
     );
+    assert_ok!(
+        IpRange::lex("192.0.2.10-192.0.2.50 "),
+        range([192, 0, 2, 10]..=[192, 0, 2, 50]),
+        " "
+    );
+    assert_ok!(
+        IpRange::lex("2001:db8::1-2001:db8::ff}"),
+        range([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]..=[0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xff]),
+        "}"
+    );
+    assert_err!(
+        IpRange::lex("192.0.2.50-192.0.2.10"),
+        LexErrorKind::IncompatibleRangeBounds,
+        "192.0.2.50-192.0.2.10"
+    );
+    assert_err!(
+        IpRange::lex("192.0.2.0/24-192.0.3.0"),
+        LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+            IpAddr::from_str("192.0.2.0/24").unwrap_err()
+        )),
+        "192.0.2.0/24"
+    );
     assert_ok!(
         IpRange::lex("::1..::2||"),
         range([0, 0, 0, 0, 0, 0, 0, 1]..=[0, 0, 0, 0, 0, 0, 0, 2]),
@@ -229,6 +421,15 @@ fn test_lex() {
         )),
         "10.0.0.0.0"
     );
+    assert_ok!(IpRange::lex("10.0/16;"), cidr([10, 0, 0, 0], 16), ";");
+    assert_ok!(IpRange::lex("10/8;"), cidr([10, 0, 0, 0], 8), ";");
+    assert_err!(
+        IpRange::lex("/8"),
+        LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(
+            IpAddr::from_str("").unwrap_err()
+        )),
+        ""
+    );
 }
 
 #[test]
@@ -0,0 +1,227 @@
+use lex::{complete, expect, span, take_while, Lex, LexErrorKind, LexResult};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::RangeInclusive,
+    str::FromStr,
+};
+use strict_partial_ord::StrictPartialOrd;
+
+/// A 48-bit MAC address, like the source of an Ethernet frame.
+///
+/// Literals are written as six hex octets separated by colons, like
+/// `aa:bb:cc:dd:ee:ff`. In `in` lists, prefixes like the OUI of a vendor
+/// can be written with their length in bits, like `aa:bb:cc/24`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Mac([u8; 6]);
+
+impl Mac {
+    /// Creates an address from its octets.
+    pub fn new(octets: [u8; 6]) -> Self {
+        Mac(octets)
+    }
+
+    /// Returns the octets of the address.
+    pub fn octets(self) -> [u8; 6] {
+        self.0
+    }
+
+    fn from_u64(value: u64) -> Self {
+        let mut octets = [0; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            *octet = (value >> (40 - i * 8)) as u8;
+        }
+        Mac(octets)
+    }
+
+    fn to_u64(self) -> u64 {
+        self.0
+            .iter()
+            .fold(0, |value, octet| value << 8 | u64::from(*octet))
+    }
+}
+
+fn lex_octet(input: &str) -> LexResult<'_, u8> {
+    let (digits, rest) = take_while(input, "hex digit", |c| c.is_ascii_hexdigit())?;
+    if digits.len() != 2 {
+        return Err((LexErrorKind::InvalidMac, digits));
+    }
+    Ok((u8::from_str_radix(digits, 16).unwrap(), rest))
+}
+
+// Lexes up to 6 octets separated by colons, returning them together with how
+// many of them there were.
+fn lex_octets(mut input: &str) -> LexResult<'_, ([u8; 6], usize)> {
+    let mut octets = [0; 6];
+    let mut len = 0;
+    loop {
+        let (octet, rest) = lex_octet(input)?;
+        octets[len] = octet;
+        len += 1;
+        input = rest;
+        if len == octets.len() {
+            break;
+        }
+        match expect(input, ":") {
+            Ok(rest) => input = rest,
+            Err(_) => break,
+        }
+    }
+    Ok(((octets, len), input))
+}
+
+impl<'i> Lex<'i> for Mac {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let ((octets, len), rest) = lex_octets(input)?;
+        if len != octets.len() {
+            return Err((LexErrorKind::ExpectedLiteral(":"), rest));
+        }
+        Ok((Mac(octets), rest))
+    }
+}
+
+/// Lexes a single address, a range like `aa:bb:cc:00:00:00..aa:bb:cc:7f:ff:ff`
+/// or a prefix like `aa:bb:cc/24`, which only needs the octets covering its
+/// length.
+impl<'i> Lex<'i> for RangeInclusive<Mac> {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let initial_input = input;
+        let ((octets, len), rest) = lex_octets(input)?;
+
+        if let Ok(rest) = expect(rest, "/") {
+            let (digits, rest) = take_while(rest, "digit", |c| c.is_ascii_digit())?;
+            let bits = match u32::from_str(digits) {
+                Ok(bits) if bits as usize <= len * 8 => bits,
+                _ => return Err((LexErrorKind::InvalidMac, digits)),
+            };
+            let first = Mac(octets).to_u64();
+            let host_mask = (1u64 << (48 - bits)) - 1;
+            if first & host_mask != 0 {
+                return Err((LexErrorKind::InvalidMac, span(initial_input, rest)));
+            }
+            return Ok((
+                Mac::from_u64(first)..=Mac::from_u64(first | host_mask),
+                rest,
+            ));
+        }
+
+        if len != octets.len() {
+            return Err((LexErrorKind::ExpectedLiteral(":"), rest));
+        }
+        let first = Mac(octets);
+        let (last, rest) = match expect(rest, "..") {
+            Ok(rest) => Mac::lex(rest)?,
+            Err(_) => (first, rest),
+        };
+        if last < first {
+            return Err((
+                LexErrorKind::IncompatibleRangeBounds,
+                span(initial_input, rest),
+            ));
+        }
+        Ok((first..=last, rest))
+    }
+}
+
+impl FromStr for Mac {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        complete(Mac::lex(s)).map_err(|_| ())
+    }
+}
+
+/// Formats the address as lowercase hex octets separated by colons.
+impl Display for Mac {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let o = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            o[0], o[1], o[2], o[3], o[4], o[5]
+        )
+    }
+}
+
+impl Serialize for Mac {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Mac {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|()| de::Error::invalid_value(de::Unexpected::Str(&s), &"a MAC address"))
+    }
+}
+
+impl StrictPartialOrd for Mac {}
+
+#[test]
+fn test_lex() {
+    let mac = |s: &str| -> Mac { s.parse().unwrap() };
+
+    assert_ok!(
+        Mac::lex("00:1A:2b:3c:4d:5e;"),
+        Mac::new([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e]),
+        ";"
+    );
+    assert_err!(
+        Mac::lex("00:1a:2b:3c:4d"),
+        LexErrorKind::ExpectedLiteral(":"),
+        ""
+    );
+    assert_err!(Mac::lex("00:1a:2:3c:4d:5e"), LexErrorKind::InvalidMac, "2");
+
+    assert_ok!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc/24 }"),
+        mac("aa:bb:cc:00:00:00")..=mac("aa:bb:cc:ff:ff:ff"),
+        " }"
+    );
+    assert_ok!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc:80/25"),
+        mac("aa:bb:cc:80:00:00")..=mac("aa:bb:cc:ff:ff:ff")
+    );
+    assert_ok!(
+        RangeInclusive::<Mac>::lex("00:00:00:00:00:00/0"),
+        mac("00:00:00:00:00:00")..=mac("ff:ff:ff:ff:ff:ff")
+    );
+    assert_ok!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc:dd:ee:ff/48"),
+        mac("aa:bb:cc:dd:ee:ff")..=mac("aa:bb:cc:dd:ee:ff")
+    );
+    assert_ok!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc:dd:ee:00..aa:bb:cc:dd:ee:0f"),
+        mac("aa:bb:cc:dd:ee:00")..=mac("aa:bb:cc:dd:ee:0f")
+    );
+    assert_err!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc/32"),
+        LexErrorKind::InvalidMac,
+        "32"
+    );
+    assert_err!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc/16"),
+        LexErrorKind::InvalidMac,
+        "aa:bb:cc/16"
+    );
+    assert_err!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc"),
+        LexErrorKind::ExpectedLiteral(":"),
+        ""
+    );
+    assert_err!(
+        RangeInclusive::<Mac>::lex("aa:bb:cc:dd:ee:ff..aa:bb:cc:dd:ee:00"),
+        LexErrorKind::IncompatibleRangeBounds,
+        "aa:bb:cc:dd:ee:ff..aa:bb:cc:dd:ee:00"
+    );
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(
+        Mac::new([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e]).to_string(),
+        "00:1a:2b:3c:4d:5e"
+    );
+}
@@ -1,12 +1,27 @@
 mod bool;
 mod bytes;
+mod endpoint;
+mod float;
 mod int;
 mod ip;
+mod mac;
 mod regex;
+mod timestamp;
+mod wildcard;
 
 pub use self::{
     bool::UninhabitedBool,
     bytes::Bytes,
-    ip::{ExplicitIpRange, IpRange},
-    regex::{Error as RegexError, Regex},
+    endpoint::Endpoint,
+    float::Float,
+    ip::{ExplicitIpRange, IpAddrOrCidr, IpRange},
+    mac::Mac,
+    regex::{Error as RegexError, Regex, RegexCache},
+    timestamp::Timestamp,
+    wildcard::Wildcard,
+};
+
+pub(crate) use self::{
+    int::{lex_int_expr, lex_interval},
+    ip::lex_zone,
 };
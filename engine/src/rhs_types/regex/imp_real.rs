@@ -9,18 +9,40 @@ impl FromStr for Regex {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Error> {
-        ::regex::bytes::RegexBuilder::new(s)
-            .unicode(false)
-            .build()
-            .map(Regex)
+        Regex::new(s, None)
+    }
+}
+
+/// Checks whether the regex failed to compile because of the size limit.
+pub fn is_too_big(err: &Error) -> bool {
+    match err {
+        Error::CompiledTooBig(_) => true,
+        _ => false,
     }
 }
 
 impl Regex {
+    /// Compiles a regex, optionally with a limit on its approximate size in
+    /// bytes instead of the default one.
+    pub fn new(s: &str, size_limit: Option<usize>) -> Result<Self, Error> {
+        let mut builder = ::regex::bytes::RegexBuilder::new(s);
+        builder.unicode(false);
+        if let Some(size_limit) = size_limit {
+            builder.size_limit(size_limit);
+        }
+        builder.build().map(Regex)
+    }
+
     pub fn is_match(&self, text: &[u8]) -> bool {
         self.0.is_match(text)
     }
 
+    /// Whether the regex is likely to match any input, because it can match
+    /// an empty string both on its own and within another string.
+    pub fn matches_empty(&self) -> bool {
+        self.0.is_match(b"") && self.0.is_match(b"\0")
+    }
+
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
@@ -1,10 +1,13 @@
 use cfg_if::cfg_if;
-use lex::{expect, span, Lex, LexErrorKind, LexResult};
+use lex::{expect, peek, span, LexErrorKind, LexResult, LexWith, Limit};
+use parser::ParserOptions;
 use serde::{Serialize, Serializer};
 use std::{
-    fmt::{self, Debug, Formatter},
-    str::FromStr,
+    collections::HashMap,
+    fmt::{self, Debug, Display, Formatter, Write},
+    sync::{Arc, Mutex},
 };
+use tokens::lex_raw_string;
 
 cfg_if! {
     if #[cfg(feature = "regex")] {
@@ -30,48 +33,168 @@ impl Debug for Regex {
     }
 }
 
-impl<'i> Lex<'i> for Regex {
-    fn lex(input: &str) -> LexResult<'_, Self> {
-        let input = expect(input, "\"")?;
-        let mut regex_buf = String::new();
+// Formats the regex as a string literal that lexes back into the same regex,
+// escaping quotes outside of character classes, just like they are lexed.
+impl Display for Regex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let mut in_char_class = false;
-        let (regex_str, input) = {
-            let mut iter = input.chars();
-            loop {
-                let before_char = iter.as_str();
-                match iter
-                    .next()
-                    .ok_or_else(|| (LexErrorKind::MissingEndingQuote, input))?
-                {
-                    '\\' => {
-                        if let Some(c) = iter.next() {
-                            if in_char_class || c != '"' {
-                                regex_buf.push('\\');
-                            }
-                            regex_buf.push(c);
-                        }
-                    }
-                    '"' if !in_char_class => {
-                        break (span(input, before_char), iter.as_str());
-                    }
-                    '[' if !in_char_class => {
-                        in_char_class = true;
-                        regex_buf.push('[');
-                    }
-                    ']' if in_char_class => {
-                        in_char_class = false;
-                        regex_buf.push(']');
+        let mut chars = self.as_str().chars();
+        f.write_char('"')?;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    f.write_char('\\')?;
+                    if let Some(c) = chars.next() {
+                        f.write_char(c)?;
                     }
-                    c => {
-                        regex_buf.push(c);
+                }
+                '"' if !in_char_class => f.write_str("\\\"")?,
+                '[' if !in_char_class => {
+                    in_char_class = true;
+                    f.write_char('[')?;
+                }
+                ']' if in_char_class => {
+                    in_char_class = false;
+                    f.write_char(']')?;
+                }
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+// Raw strings are taken as-is, so that backslashes and quotes don't need to
+// be escaped, like in `r"\d+\."` or `r#"say "hi""#`.
+fn lex_raw(input: &str) -> LexResult<'_, (String, &str)> {
+    let (regex_str, input) = lex_raw_string(input)?;
+    Ok(((regex_str.to_owned(), regex_str), input))
+}
+
+fn lex_quoted(input: &str) -> LexResult<'_, (String, &str)> {
+    let input = expect(input, "\"")?;
+    let mut regex_buf = String::new();
+    let mut in_char_class = false;
+    let mut iter = input.chars();
+    loop {
+        let before_char = iter.as_str();
+        match iter
+            .next()
+            .ok_or_else(|| (LexErrorKind::MissingEndingQuote, input))?
+        {
+            '\\' => {
+                if let Some(c) = iter.next() {
+                    if in_char_class || c != '"' {
+                        regex_buf.push('\\');
                     }
-                };
+                    regex_buf.push(c);
+                }
+            }
+            '"' if !in_char_class => {
+                return Ok(((regex_buf, span(input, before_char)), iter.as_str()));
+            }
+            '[' if !in_char_class => {
+                in_char_class = true;
+                regex_buf.push('[');
             }
+            ']' if in_char_class => {
+                in_char_class = false;
+                regex_buf.push(']');
+            }
+            c => {
+                regex_buf.push(c);
+            }
+        };
+    }
+}
+
+impl<'i, 'o> LexWith<'i, &'o ParserOptions> for Regex {
+    fn lex_with(input: &'i str, options: &'o ParserOptions) -> LexResult<'i, Self> {
+        let ((regex_buf, regex_str), input) = if peek(input, "r") {
+            lex_raw(input)?
+        } else {
+            lex_quoted(input)?
+        };
+        let res = match &options.regex_cache {
+            Some(cache) => cache.get_or_compile(regex_buf, options.max_regex_size),
+            None => Regex::new(&regex_buf, options.max_regex_size),
         };
-        match Regex::from_str(&regex_buf) {
+        match res {
             Ok(regex) => Ok((regex, input)),
-            Err(err) => Err((LexErrorKind::ParseRegex(err), regex_str)),
+            Err(err) => Err((
+                match options.max_regex_size {
+                    Some(max) if is_too_big(&err) => LexErrorKind::LimitExceeded {
+                        limit: Limit::RegexSize,
+                        max,
+                    },
+                    _ => LexErrorKind::ParseRegex(err),
+                },
+                regex_str,
+            )),
+        }
+    }
+}
+
+// Regexes by their pattern and size limit.
+type RegexMap = HashMap<(String, Option<usize>), Regex>;
+
+/// A cache of compiled regexes that can be shared between filters, so that
+/// the same pattern in many of them is compiled and stored only once.
+///
+/// Clones refer to the same cache. It's used when set as
+/// [`ParserOptions::regex_cache`](::ParserOptions::regex_cache), and keeps
+/// every pattern until [`clear`](RegexCache::clear)ed.
+#[derive(Clone, Default)]
+pub struct RegexCache(Arc<Mutex<RegexMap>>);
+
+impl RegexCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        RegexCache::default()
+    }
+
+    /// Returns the number of cached regexes.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Checks whether there are no cached regexes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all cached regexes. Filters that use them keep their own
+    /// references.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    // Invalid patterns are not cached, so they fail again each time.
+    fn get_or_compile(&self, pattern: String, size_limit: Option<usize>) -> Result<Regex, Error> {
+        let key = (pattern, size_limit);
+        if let Some(regex) = self.0.lock().unwrap().get(&key) {
+            return Ok(regex.clone());
         }
+        let regex = Regex::new(&key.0, size_limit)?;
+        self.0.lock().unwrap().insert(key, regex.clone());
+        Ok(regex)
+    }
+}
+
+/// Caches are equal if they are clones of each other.
+impl PartialEq for RegexCache {
+    fn eq(&self, other: &RegexCache) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RegexCache {}
+
+impl Debug for RegexCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegexCache")
+            .field("len", &self.len())
+            .finish()
     }
 }
 
@@ -83,8 +206,12 @@ impl Serialize for Regex {
 
 #[test]
 fn test() {
+    use std::str::FromStr;
+
+    let options = &ParserOptions::default();
+
     let expr = assert_ok!(
-        Regex::lex(r#""[a-z"\]]+\d{1,10}\"";"#),
+        Regex::lex_with(r#""[a-z"\]]+\d{1,10}\"";"#, options),
         Regex::from_str(r#"[a-z"\]]+\d{1,10}""#).unwrap(),
         ";"
     );
@@ -92,8 +219,25 @@ fn test() {
     assert_json!(expr, r#"[a-z"\]]+\d{1,10}""#);
 
     assert_err!(
-        Regex::lex(r#""abcd\"#),
+        Regex::lex_with(r#""abcd\"#, options),
         LexErrorKind::MissingEndingQuote,
         "abcd\\"
     );
+
+    assert_ok!(
+        Regex::lex_with(r#"r"foo\d+\." ;"#, options),
+        Regex::from_str(r"foo\d+\.").unwrap(),
+        " ;"
+    );
+
+    assert_ok!(
+        Regex::lex_with(r##"r#"say "hi"\b"#"##, options),
+        Regex::from_str(r#"say "hi"\b"#).unwrap()
+    );
+
+    assert_err!(
+        Regex::lex_with(r##"r#"abcd"##, options),
+        LexErrorKind::MissingEndingQuote,
+        r#"abcd"#
+    );
 }
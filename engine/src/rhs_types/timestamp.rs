@@ -0,0 +1,346 @@
+use lex::{expect, span, take_while, Lex, LexErrorKind, LexResult};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::RangeInclusive,
+    str::FromStr,
+};
+use strict_partial_ord::StrictPartialOrd;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+// `0000-01-01T00:00:00Z` and `9999-12-31T23:59:59Z`, the range of years that
+// can be written in RFC 3339.
+const MIN_SECS: i64 = -62_167_219_200;
+const MAX_SECS: i64 = 253_402_300_799;
+
+/// A point in time with nanosecond precision, like an activation date.
+///
+/// Literals are written either as quoted RFC 3339 strings, like
+/// `"2024-01-01T00:00:00Z"` or `"2024-01-01T02:00:00.5+02:00"`, or as
+/// seconds since the Unix epoch, like `1704067200`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Timestamp {
+    secs: i64,
+    nanos: u32,
+}
+
+impl Timestamp {
+    /// Creates a timestamp from seconds since the Unix epoch and
+    /// nanoseconds within the second.
+    ///
+    /// Returns `None` if there are a second or more of nanoseconds, or if
+    /// the timestamp is outside of the years 0 to 9999.
+    pub fn new(secs: i64, nanos: u32) -> Option<Self> {
+        if nanos < 1_000_000_000 && secs >= MIN_SECS && secs <= MAX_SECS {
+            Some(Timestamp { secs, nanos })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the seconds since the Unix epoch.
+    pub fn secs(self) -> i64 {
+        self.secs
+    }
+
+    /// Returns the nanoseconds within the second.
+    pub fn nanos(self) -> u32 {
+        self.nanos
+    }
+}
+
+// Days since the Unix epoch, from http://howardhinnant.github.io/date_algorithms.html.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// A cursor over the ASCII bytes of an RFC 3339 string.
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn digits(&mut self, len: usize) -> Option<i64> {
+        if self.0.len() < len || !self.0[..len].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let value = self.0[..len]
+            .iter()
+            .fold(0, |value, digit| value * 10 + i64::from(digit - b'0'));
+        self.0 = &self.0[len..];
+        Some(value)
+    }
+
+    fn byte(&mut self, choices: &[u8]) -> Option<u8> {
+        match self.0.first() {
+            Some(byte) if choices.contains(byte) => {
+                self.0 = &self.0[1..];
+                Some(*byte)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_rfc3339(input: &str) -> Option<Timestamp> {
+    let mut cursor = Cursor(input.as_bytes());
+
+    let year = cursor.digits(4)?;
+    cursor.byte(b"-")?;
+    let month = cursor.digits(2)?;
+    cursor.byte(b"-")?;
+    let day = cursor.digits(2)?;
+    cursor.byte(b"Tt")?;
+    let hour = cursor.digits(2)?;
+    cursor.byte(b":")?;
+    let minute = cursor.digits(2)?;
+    cursor.byte(b":")?;
+    // Leap seconds are allowed, and are the same as the next second.
+    let second = cursor.digits(2)?;
+
+    let mut nanos = 0;
+    if cursor.byte(b".").is_some() {
+        let mut len = 0;
+        while let Some(digit) = cursor.digits(1) {
+            len += 1;
+            if len > 9 {
+                return None;
+            }
+            nanos += digit as u32 * 10u32.pow(9 - len);
+        }
+        if len == 0 {
+            return None;
+        }
+    }
+
+    let offset = match cursor.byte(b"Zz+-")? {
+        b'Z' | b'z' => 0,
+        sign => {
+            let hours = cursor.digits(2)?;
+            cursor.byte(b":")?;
+            let minutes = cursor.digits(2)?;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+    };
+
+    if !cursor.0.is_empty()
+        || month < 1
+        || month > 12
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let secs =
+        days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60 + second
+            - offset;
+
+    Timestamp::new(secs, nanos)
+}
+
+impl<'i> Lex<'i> for Timestamp {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        if let Ok(content) = expect(input, "\"") {
+            let end = content
+                .find('"')
+                .ok_or_else(|| (LexErrorKind::MissingEndingQuote, content))?;
+            let (content, rest) = (&content[..end], &content[end + 1..]);
+            return match parse_rfc3339(content) {
+                Some(timestamp) => Ok((timestamp, rest)),
+                None => Err((LexErrorKind::InvalidTimestamp, content)),
+            };
+        }
+
+        let digits = expect(input, "-").unwrap_or(input);
+        let (_, rest) = take_while(digits, "digit", |c| c.is_ascii_digit())?;
+        let secs = span(input, rest);
+        match i64::from_str(secs) {
+            Ok(value) => match Timestamp::new(value, 0) {
+                Some(timestamp) => Ok((timestamp, rest)),
+                None => Err((LexErrorKind::InvalidTimestamp, secs)),
+            },
+            Err(err) => Err((LexErrorKind::ParseInt { err, radix: 10 }, secs)),
+        }
+    }
+}
+
+impl<'i> Lex<'i> for RangeInclusive<Timestamp> {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let initial_input = input;
+        let (first, input) = Timestamp::lex(input)?;
+        let (last, input) = if let Ok(input) = expect(input, "..") {
+            Timestamp::lex(input)?
+        } else {
+            (first, input)
+        };
+        if last < first {
+            return Err((
+                LexErrorKind::IncompatibleRangeBounds,
+                span(initial_input, input),
+            ));
+        }
+        Ok((first..=last, input))
+    }
+}
+
+/// Parses an RFC 3339 string, without quotes.
+impl FromStr for Timestamp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        parse_rfc3339(s).ok_or(())
+    }
+}
+
+/// Formats the timestamp in RFC 3339 in UTC, with as many digits of the
+/// fraction as needed.
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let days = if self.secs >= 0 {
+            self.secs / SECS_PER_DAY
+        } else {
+            (self.secs - SECS_PER_DAY + 1) / SECS_PER_DAY
+        };
+        let secs = self.secs - days * SECS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )?;
+
+        if self.nanos != 0 {
+            let fraction = format!("{:09}", self.nanos);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+
+        f.write_str("Z")
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|()| {
+            de::Error::invalid_value(de::Unexpected::Str(&s), &"an RFC 3339 timestamp")
+        })
+    }
+}
+
+impl StrictPartialOrd for Timestamp {}
+
+#[test]
+fn test_lex() {
+    let new = |secs, nanos| Timestamp::new(secs, nanos).unwrap();
+
+    assert_ok!(
+        Timestamp::lex(r#""2024-01-01T00:00:00Z";"#),
+        new(1_704_067_200, 0),
+        ";"
+    );
+    assert_ok!(
+        Timestamp::lex(r#""2024-01-01T02:00:00.25+02:00""#),
+        new(1_704_067_200, 250_000_000)
+    );
+    assert_ok!(
+        Timestamp::lex(r#""1969-12-31t23:59:59.000000001z""#),
+        new(-1, 1)
+    );
+    assert_ok!(Timestamp::lex("1704067200 "), new(1_704_067_200, 0), " ");
+    assert_ok!(Timestamp::lex("-1"), new(-1, 0));
+    assert_err!(
+        Timestamp::lex(r#""2023-02-29T00:00:00Z""#),
+        LexErrorKind::InvalidTimestamp,
+        "2023-02-29T00:00:00Z"
+    );
+    assert_err!(
+        Timestamp::lex(r#""2024-01-01 00:00:00Z""#),
+        LexErrorKind::InvalidTimestamp,
+        "2024-01-01 00:00:00Z"
+    );
+    assert_err!(
+        Timestamp::lex("253402300800"),
+        LexErrorKind::InvalidTimestamp,
+        "253402300800"
+    );
+    assert_err!(
+        Timestamp::lex(r#""2024"#),
+        LexErrorKind::MissingEndingQuote,
+        "2024"
+    );
+
+    assert_ok!(
+        RangeInclusive::<Timestamp>::lex(r#""2024-01-01T00:00:00Z"..1706745600}"#),
+        new(1_704_067_200, 0)..=new(1_706_745_600, 0),
+        "}"
+    );
+    assert_err!(
+        RangeInclusive::<Timestamp>::lex("2..1"),
+        LexErrorKind::IncompatibleRangeBounds,
+        "2..1"
+    );
+}
+
+#[test]
+fn test_display() {
+    for s in &[
+        "2024-01-01T00:00:00Z",
+        "1969-12-31T23:59:59.000000001Z",
+        "0000-01-01T00:00:00Z",
+        "9999-12-31T23:59:59.5Z",
+        "2000-02-29T12:34:56Z",
+    ] {
+        assert_eq!(s.parse::<Timestamp>().unwrap().to_string(), *s);
+    }
+}
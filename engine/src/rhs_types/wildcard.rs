@@ -0,0 +1,184 @@
+use lex::{LexResult, LexWith};
+use parser::ParserOptions;
+use rhs_types::Bytes;
+use serde::{Serialize, Serializer};
+use std::fmt::{self, Debug, Display, Formatter};
+use types::Literal;
+
+// A part of the pattern between two `*`, where `None` is a `?`.
+type Piece = Box<[Option<u8>]>;
+
+fn piece_matches(piece: &[Option<u8>], value: &[u8]) -> bool {
+    piece
+        .iter()
+        .zip(value)
+        .all(|(expected, actual)| expected.map_or(true, |expected| expected == *actual))
+}
+
+fn find_piece(piece: &[Option<u8>], value: &[u8]) -> Option<usize> {
+    if piece.is_empty() {
+        return Some(0);
+    }
+    value
+        .windows(piece.len())
+        .position(|window| piece_matches(piece, window))
+}
+
+/// A glob pattern of the `wildcard` operator, like `"*.example.com"`.
+///
+/// `*` matches any number of bytes and `?` exactly one of them, and a
+/// backslash makes the next character match only itself, like `\*`.
+///
+/// The pattern is split at each `*`, so that the part before the first one
+/// is only checked at the start of the value, the part after the last one at
+/// the end, and the rest are searched in between from left to right, without
+/// backtracking.
+#[derive(Clone)]
+pub struct Wildcard {
+    pattern: Bytes,
+    pieces: Box<[Piece]>,
+}
+
+impl Wildcard {
+    /// Compiles a pattern.
+    pub fn new(pattern: Bytes) -> Self {
+        let mut pieces = Vec::new();
+        let mut piece = Vec::new();
+        {
+            let mut bytes = pattern.iter().cloned();
+            while let Some(b) = bytes.next() {
+                match b {
+                    b'*' => pieces.push(::std::mem::replace(&mut piece, Vec::new()).into()),
+                    b'?' => piece.push(None),
+                    // A trailing backslash just matches itself.
+                    b'\\' => piece.push(Some(bytes.next().unwrap_or(b'\\'))),
+                    b => piece.push(Some(b)),
+                }
+            }
+        }
+        pieces.push(piece.into());
+
+        Wildcard {
+            pattern,
+            pieces: pieces.into(),
+        }
+    }
+
+    /// Returns the pattern as written.
+    pub fn pattern(&self) -> &[u8] {
+        &self.pattern
+    }
+
+    /// Checks whether the whole value matches the pattern.
+    pub fn is_match(&self, value: &[u8]) -> bool {
+        let (first, rest) = self.pieces.split_first().unwrap();
+
+        let (last, middle) = match rest.split_last() {
+            Some(pieces) => pieces,
+            None => return value.len() == first.len() && piece_matches(first, value),
+        };
+
+        if value.len() < first.len() + last.len()
+            || !piece_matches(first, value)
+            || !piece_matches(last, &value[value.len() - last.len()..])
+        {
+            return false;
+        }
+
+        let mut value = &value[first.len()..value.len() - last.len()];
+        for piece in middle {
+            match find_piece(piece, value) {
+                Some(pos) => value = &value[pos + piece.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl PartialEq for Wildcard {
+    fn eq(&self, other: &Wildcard) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for Wildcard {}
+
+impl Debug for Wildcard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.pattern, f)
+    }
+}
+
+/// Formats the pattern in the same form as it was lexed from.
+impl Display for Wildcard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.pattern.fmt_literal(f)
+    }
+}
+
+impl<'i, 'o> LexWith<'i, &'o ParserOptions> for Wildcard {
+    fn lex_with(input: &'i str, options: &'o ParserOptions) -> LexResult<'i, Self> {
+        let (pattern, rest) = Bytes::lex_with(input, options)?;
+        Ok((Wildcard::new(pattern), rest))
+    }
+}
+
+impl Serialize for Wildcard {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.pattern.serialize(ser)
+    }
+}
+
+#[test]
+fn test_is_match() {
+    let wildcard = |pattern: &str| Wildcard::new(pattern.to_owned().into());
+
+    let cases: &[(&str, &[&str], &[&str])] = &[
+        (
+            "example.com",
+            &["example.com"],
+            &["example.co", "www.example.com"],
+        ),
+        (
+            "*.example.com",
+            &["www.example.com", "a.b.example.com", ".example.com"],
+            &["example.com", "www.example.org", "www.example.com.evil"],
+        ),
+        (
+            "www.*",
+            &["www.", "www.example.com"],
+            &["ww", "api.www.com"],
+        ),
+        ("*", &["", "anything"], &[]),
+        ("**", &[""], &[]),
+        (
+            "a*b*c",
+            &["abc", "aXbYc", "abbbc", "acbc"],
+            &["ab", "bac", "aXc", "abcd"],
+        ),
+        ("*ab*ab*", &["abab", "xabyabz"], &["xaby", "aba"]),
+        ("?", &["a"], &["", "ab"]),
+        ("a?c*", &["abc", "axcd"], &["ac", "abd"]),
+        ("*.?", &["a.b", "..b"], &["a.bc", "ab"]),
+        ("\\*.com", &["*.com"], &["a.com"]),
+        (r"a\?\\", &[r"a?\"], &[r"ab\", "a?"]),
+        ("a\\", &[r"a\"], &["a"]),
+        ("aa*aa", &["aaaa", "aaxaa"], &["aaa"]),
+    ];
+
+    for (pattern, matching, not_matching) in cases {
+        let wildcard = wildcard(pattern);
+        for value in *matching {
+            assert!(wildcard.is_match(value.as_bytes()), "{} {}", pattern, value);
+        }
+        for value in *not_matching {
+            assert!(
+                !wildcard.is_match(value.as_bytes()),
+                "{} {}",
+                pattern,
+                value
+            );
+        }
+    }
+}
@@ -1,19 +1,31 @@
-use ast::FilterAst;
+#[cfg(feature = "json")]
+use ast::FilterJsonError;
+use ast::{field_expr::supported_ops, FilterAnalysis, FilterAst};
 use failure::Fail;
 use fnv::FnvBuildHasher;
+use functions::Function;
 use indexmap::map::{Entry, IndexMap};
-use lex::{complete, expect, span, take_while, LexErrorKind, LexResult, LexWith};
-use serde::{Deserialize, Serialize, Serializer};
+use lex::{expect, span, take_while, LexErrorKind, LexResult, LexWith};
+use parser::{Parser, ParserOptions};
+use serde::{
+    de::{self, value::MapAccessDeserializer, IntoDeserializer, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{
     cmp::{max, min},
+    collections::{HashMap, HashSet},
     error::Error,
     fmt::{self, Debug, Display, Formatter},
-    ptr,
+    ptr, str,
 };
-use types::{GetType, Type};
+use types::{GetType, LhsValue, Type};
 
+/// A handle of a field resolved with [`Scheme::get_field`], which is just an
+/// index into the scheme, so that values can be set without looking up the
+/// name every time.
 #[derive(PartialEq, Eq, Clone, Copy)]
-pub(crate) struct Field<'s> {
+pub struct Field<'s> {
     scheme: &'s Scheme,
     index: usize,
 }
@@ -34,16 +46,25 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for Field<'s> {
     fn lex_with(mut input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
         let initial_input = input;
 
-        loop {
-            input = take_while(input, "identifier character", |c| {
-                c.is_ascii_alphanumeric() || c == '_'
-            })?
-            .1;
+        // Quoted names are looked up together with the quotes, which is what
+        // allows to register fields named like keywords.
+        if let Ok(rest) = expect(input, "`") {
+            let end = rest
+                .find('`')
+                .ok_or_else(|| (LexErrorKind::MissingEndingQuote, rest))?;
+            input = &rest[end + 1..];
+        } else {
+            loop {
+                input = take_while(input, "identifier character", |c| {
+                    c.is_ascii_alphanumeric() || c == '_'
+                })?
+                .1;
 
-            match expect(input, ".") {
-                Ok(rest) => input = rest,
-                Err(_) => break,
-            };
+                match expect(input, ".") {
+                    Ok(rest) => input = rest,
+                    Err(_) => break,
+                };
+            }
         }
 
         let name = span(initial_input, input);
@@ -57,17 +78,32 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for Field<'s> {
 }
 
 impl<'s> Field<'s> {
+    /// Returns the name the field was registered with.
     pub fn name(&self) -> &'s str {
         self.scheme.fields.get_index(self.index).unwrap().0
     }
 
+    /// Returns the position of the field in the scheme.
     pub fn index(&self) -> usize {
         self.index
     }
 
+    /// Returns the scheme the field belongs to.
     pub fn scheme(&self) -> &'s Scheme {
         self.scheme
     }
+
+    /// Whether the field was registered with
+    /// [`Scheme::add_map_field`](Scheme::add_map_field).
+    pub fn is_map(&self) -> bool {
+        self.scheme.maps.contains(&self.index)
+    }
+
+    /// Whether the field was registered with
+    /// [`Scheme::add_array_field`](Scheme::add_array_field).
+    pub fn is_array(&self) -> bool {
+        self.scheme.arrays.contains(&self.index)
+    }
 }
 
 impl<'s> GetType for Field<'s> {
@@ -87,23 +123,120 @@ pub struct UnknownFieldError;
 #[fail(display = "attempt to redefine field {}", _0)]
 pub struct FieldRedefinitionError(String);
 
-/// An opaque filter parsing error associated with the original input.
+/// An error that occurs when a field name is a keyword of the filter syntax
+/// and would be ambiguous in filters.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(
+    display = "{} is a reserved word, quote it with backticks to use it as a field name",
+    _0
+)]
+pub struct ReservedNameError(String);
+
+/// An error that occurs when registering a field.
+#[derive(Debug, PartialEq, Fail)]
+pub enum FieldError {
+    /// The name is already taken by a field or an alias.
+    #[fail(display = "{}", _0)]
+    Redefinition(#[cause] FieldRedefinitionError),
+
+    /// The name is a reserved word.
+    #[fail(display = "{}", _0)]
+    ReservedName(#[cause] ReservedNameError),
+}
+
+/// An error that occurs when registering a field alias.
+#[derive(Debug, PartialEq, Fail)]
+pub enum AliasError {
+    /// The alias name is already taken by a field or another alias.
+    #[fail(display = "{}", _0)]
+    Redefinition(#[cause] FieldRedefinitionError),
+
+    /// The alias name is a reserved word.
+    #[fail(display = "{}", _0)]
+    ReservedName(#[cause] ReservedNameError),
+
+    /// The aliased field doesn't exist.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+}
+
+/// An error that occurs when registering enum values for a field.
+#[derive(Debug, PartialEq, Fail)]
+pub enum EnumValuesError {
+    /// The field doesn't exist.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// Enum values can be registered only for bytes fields.
+    #[fail(display = "expected a field of type bytes, but got {}", _0)]
+    UnsupportedType(Type),
+}
+
+/// An error that occurs when registering a constant.
+#[derive(Debug, PartialEq, Fail)]
+pub enum ConstantError {
+    /// The constant is already registered.
+    #[fail(display = "attempt to redefine constant {}", _0)]
+    Redefinition(String),
+
+    /// Constants can be only of types with ordering comparisons.
+    #[fail(
+        display = "expected a constant of type ip, bytes or int, but got {}",
+        _0
+    )]
+    UnsupportedType(Type),
+}
+
+/// An error that occurs when registering a list.
+#[derive(Debug, PartialEq, Fail)]
+pub enum ListError {
+    /// The list is already registered.
+    #[fail(display = "attempt to redefine list {}", _0)]
+    Redefinition(String),
+
+    /// Lists can be only of types with equality comparisons.
+    #[fail(display = "expected a list of type ip, bytes or int, but got {}", _0)]
+    UnsupportedType(Type),
+}
+
+/// An error that occurs when registering a function.
+#[derive(Debug, PartialEq, Fail)]
+pub enum FunctionError {
+    /// The function is already registered.
+    #[fail(display = "attempt to redefine function {}", _0)]
+    Redefinition(String),
+
+    /// The name is a reserved word.
+    #[fail(display = "{}", _0)]
+    ReservedName(#[cause] ReservedNameError),
+}
+
+/// A filter parsing error associated with the original input.
 ///
-/// For now, you can just print it in a debug or a human-readable fashion.
+/// Its `Display` implementation renders the offending line with carets under
+/// the problem, and the accessors give the same location in a structured
+/// form, e.g. for highlighting it in an editor.
 #[derive(Debug, PartialEq)]
 pub struct ParseError<'i> {
     kind: LexErrorKind,
+    // The whole offending span and its byte offset in the original input.
+    span: &'i str,
+    offset: usize,
+    // The line of the span, and the part of the span within that line.
     input: &'i str,
     line_number: usize,
     span_start: usize,
     span_len: usize,
+    // Names of fields similar to an unknown one.
+    suggestions: Vec<String>,
 }
 
 impl<'i> Error for ParseError<'i> {}
 
 impl<'i> ParseError<'i> {
     pub(crate) fn new(mut input: &'i str, (kind, span): (LexErrorKind, &'i str)) -> Self {
-        let mut span_start = span.as_ptr() as usize - input.as_ptr() as usize;
+        let offset = span.as_ptr() as usize - input.as_ptr() as usize;
+        let mut span_start = offset;
 
         let (line_number, line_start) = input[..span_start]
             .match_indices('\n')
@@ -127,11 +260,80 @@ impl<'i> ParseError<'i> {
 
         ParseError {
             kind,
+            span,
+            offset,
             input,
             line_number,
             span_start,
             span_len,
+            suggestions: Vec::new(),
+        }
+    }
+
+    // Adds names of fields similar to the unknown one, if that's the problem.
+    pub(crate) fn with_suggestions(mut self, scheme: &Scheme) -> Self {
+        if let LexErrorKind::UnknownField(_) = self.kind {
+            self.suggestions = scheme.similar_field_names(self.span);
         }
+        self
+    }
+
+    /// Returns the description of the problem, like `unknown field`.
+    pub fn message(&self) -> String {
+        self.kind.to_string()
+    }
+
+    /// Returns the byte offset in the original input where the offending
+    /// span starts.
+    pub fn start(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the byte offset in the original input right after the
+    /// offending span.
+    ///
+    /// It's the same as [`start`](ParseError::start) when the problem is a
+    /// missing token, e.g. at the end of the input.
+    pub fn end(&self) -> usize {
+        self.offset + self.span.len()
+    }
+
+    /// Returns the offending part of the original input, which may span
+    /// several lines.
+    pub fn span(&self) -> &'i str {
+        self.span
+    }
+
+    /// Returns the 1-based number of the line where the span starts.
+    pub fn line(&self) -> usize {
+        self.line_number + 1
+    }
+
+    /// Returns the 1-based column where the span starts, counted in
+    /// characters rather than bytes.
+    pub fn column(&self) -> usize {
+        self.input[..self.span_start].chars().count() + 1
+    }
+
+    /// Returns the whole line where the span starts, without the line
+    /// break.
+    pub fn source_line(&self) -> &'i str {
+        self.input
+    }
+
+    /// Returns the name written in the filter if the problem is an unknown
+    /// field.
+    pub fn unknown_field(&self) -> Option<&'i str> {
+        match self.kind {
+            LexErrorKind::UnknownField(_) => Some(self.span),
+            _ => None,
+        }
+    }
+
+    /// Returns fields of the scheme with names similar to the unknown one,
+    /// closest first, e.g. for "did you mean" hints.
+    pub fn suggestions(&self) -> &[String] {
+        &self.suggestions
     }
 }
 
@@ -140,24 +342,73 @@ impl<'i> Display for ParseError<'i> {
         writeln!(
             f,
             "Filter parsing error ({}:{}):",
-            self.line_number + 1,
-            self.span_start + 1
+            self.line(),
+            self.column()
         )?;
 
         writeln!(f, "{}", self.input)?;
 
-        for _ in 0..self.span_start {
+        // Offsets are in bytes, but carets should line up with characters.
+        let (before, rest) = self.input.split_at(self.span_start);
+
+        for _ in before.chars() {
             write!(f, " ")?;
         }
 
-        for _ in 0..max(1, self.span_len) {
+        for _ in 0..max(1, rest[..self.span_len].chars().count()) {
             write!(f, "^")?;
         }
 
-        writeln!(f, " {}", self.kind)?;
+        write!(f, " {}", self.kind)?;
 
-        Ok(())
+        if let Some(name) = self.unknown_field() {
+            write!(f, " `{}`", name)?;
+            if let Some((last, rest)) = self.suggestions.split_last() {
+                write!(f, "; did you mean ")?;
+                for (i, name) in rest.iter().enumerate() {
+                    write!(f, "{}`{}`", if i > 0 { ", " } else { "" }, name)?;
+                }
+                if !rest.is_empty() {
+                    write!(f, " or ")?;
+                }
+                write!(f, "`{}`?", last)?;
+            }
+        }
+
+        if let LexErrorKind::UnsupportedOp { field_type, .. } = self.kind {
+            if let Some((last, rest)) = supported_ops(field_type).split_last() {
+                write!(f, "; try ")?;
+                for (i, op) in rest.iter().enumerate() {
+                    write!(f, "{}`{}`", if i > 0 { ", " } else { "" }, op)?;
+                }
+                if !rest.is_empty() {
+                    write!(f, " or ")?;
+                }
+                write!(f, "`{}`", last)?;
+            }
+        }
+
+        writeln!(f)
+    }
+}
+
+// The number of characters to insert, remove or replace to turn one string
+// into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, a) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let replace = prev[j] + if a == b { 0 } else { 1 };
+            cur[j + 1] = min(replace, min(prev[j + 1], cur[j]) + 1);
+        }
+        ::std::mem::swap(&mut prev, &mut cur);
     }
+
+    prev[b.len()]
 }
 
 /// The main registry for fields and their associated types.
@@ -165,10 +416,165 @@ impl<'i> Display for ParseError<'i> {
 /// This is necessary to provide typechecking for runtime values provided
 /// to the [execution context](::ExecutionContext) and also to aid parser
 /// in ambiguous contexts.
-#[derive(Default, Deserialize)]
-#[serde(transparent)]
+///
+/// It can be (de)serialized as a map from field names to their types, which
+/// is handy for loading schemes from configuration files. Map, array and
+/// namespace fields are stored as an object with their kind and type
+/// instead, like `{"kind": "map", "type": "Bytes"}`. Aliases, enum values,
+/// constants and functions are not part of the serialized form. Deserialized
+/// names are checked just like with [`add_field`](Scheme::add_field).
+#[derive(Default)]
 pub struct Scheme {
     fields: IndexMap<String, Type, FnvBuildHasher>,
+
+    // Maps alias names directly to indices of canonical fields.
+    aliases: HashMap<String, usize, FnvBuildHasher>,
+
+    // Maps indices of fields to their allowed bare identifiers.
+    enum_values: HashMap<usize, Vec<String>, FnvBuildHasher>,
+
+    // Maps names of constants supplied at execution time to their types.
+    constants: HashMap<String, Type, FnvBuildHasher>,
+
+    // Maps names of lists supplied at execution time to types of their items.
+    lists: HashMap<String, Type, FnvBuildHasher>,
+
+    // Checks of values set for fields, by indices of the fields, so that
+    // setting a value doesn't need a lookup.
+    validators: Vec<Option<Box<FieldValidator>>>,
+
+    // Indices of fields whose values are looked up by key.
+    maps: HashSet<usize, FnvBuildHasher>,
+
+    // Indices of fields whose values are lists of elements.
+    arrays: HashSet<usize, FnvBuildHasher>,
+
+    // Indices of map fields whose keys can also be written as the rest of a
+    // dotted name.
+    namespaces: HashSet<usize, FnvBuildHasher>,
+
+    // Functions callable on the left-hand side of comparisons.
+    functions: IndexMap<String, Function, FnvBuildHasher>,
+}
+
+type FieldValidator = dyn Fn(&LhsValue<'_>) -> Result<(), String> + Send + Sync;
+
+// Words that can appear in a filter where a field name is expected, or right
+// after one, so they can't be field names on their own.
+const RESERVED_WORDS: &[&str] = &[
+    "any",
+    "all",
+    "nocase",
+    "and",
+    "or",
+    "xor",
+    "not",
+    "in",
+    "between",
+    "contains",
+    "matches",
+    "wildcard",
+    "like",
+    "bitwise_and",
+    "eq",
+    "ne",
+    "ge",
+    "le",
+    "gt",
+    "lt",
+];
+
+fn check_reserved(name: &str) -> Result<(), ReservedNameError> {
+    if RESERVED_WORDS.contains(&name) {
+        Err(ReservedNameError(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+// Kinds of fields that are serialized as an object rather than just a type.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldKind {
+    Map,
+    Array,
+    Namespace,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KindedField {
+    kind: FieldKind,
+    #[serde(rename = "type")]
+    ty: Type,
+}
+
+// A field in the serialized form, either `"Int"` or an object with a kind.
+struct SerializedField(Option<FieldKind>, Type);
+
+impl<'de> Deserialize<'de> for SerializedField {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct FieldVisitor;
+
+        impl<'de> Visitor<'de> for FieldVisitor {
+            type Value = SerializedField;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a type or an object with a kind and a type")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Type::deserialize(value.into_deserializer()).map(|ty| SerializedField(None, ty))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                KindedField::deserialize(MapAccessDeserializer::new(map))
+                    .map(|field| SerializedField(Some(field.kind), field.ty))
+            }
+        }
+
+        de.deserialize_any(FieldVisitor)
+    }
+}
+
+impl Serialize for Scheme {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        let mut map = ser.serialize_map(Some(self.fields.len()))?;
+        for (index, (name, &ty)) in self.fields.iter().enumerate() {
+            let kind = if self.namespaces.contains(&index) {
+                Some(FieldKind::Namespace)
+            } else if self.maps.contains(&index) {
+                Some(FieldKind::Map)
+            } else if self.arrays.contains(&index) {
+                Some(FieldKind::Array)
+            } else {
+                None
+            };
+
+            match kind {
+                Some(kind) => map.serialize_entry(name, &KindedField { kind, ty })?,
+                None => map.serialize_entry(name, &ty)?,
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Scheme {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let fields = IndexMap::<String, SerializedField, FnvBuildHasher>::deserialize(de)?;
+        let mut scheme = Scheme::with_capacity(fields.len());
+        for (name, SerializedField(kind, ty)) in fields {
+            match kind {
+                None => scheme.add_field(name, ty),
+                Some(FieldKind::Map) => scheme.add_map_field(name, ty),
+                Some(FieldKind::Array) => scheme.add_array_field(name, ty),
+                Some(FieldKind::Namespace) => scheme.add_namespace(name, ty),
+            }
+            .map_err(de::Error::custom)?;
+        }
+        Ok(scheme)
+    }
 }
 
 impl PartialEq for Scheme {
@@ -189,13 +595,34 @@ impl<'s> Scheme {
     pub fn with_capacity(n: usize) -> Self {
         Scheme {
             fields: IndexMap::with_capacity_and_hasher(n, FnvBuildHasher::default()),
+            aliases: HashMap::default(),
+            enum_values: HashMap::default(),
+            constants: HashMap::default(),
+            lists: HashMap::default(),
+            validators: Vec::new(),
+            maps: HashSet::default(),
+            arrays: HashSet::default(),
+            namespaces: HashSet::default(),
+            functions: IndexMap::default(),
         }
     }
 
     /// Registers a field and its corresponding type.
-    pub fn add_field(&mut self, name: String, ty: Type) -> Result<(), FieldRedefinitionError> {
+    ///
+    /// Names can't be keywords of the filter syntax, like `or` or `matches`,
+    /// unless they are quoted with backticks, e.g. `` `or` ``, in which case
+    /// filters have to refer to the field with the same quotes.
+    pub fn add_field(&mut self, name: String, ty: Type) -> Result<(), FieldError> {
+        check_reserved(&name).map_err(FieldError::ReservedName)?;
+
+        if self.aliases.contains_key(&name) {
+            return Err(FieldError::Redefinition(FieldRedefinitionError(name)));
+        }
+
         match self.fields.entry(name) {
-            Entry::Occupied(entry) => Err(FieldRedefinitionError(entry.key().to_string())),
+            Entry::Occupied(entry) => Err(FieldError::Redefinition(FieldRedefinitionError(
+                entry.key().to_string(),
+            ))),
             Entry::Vacant(entry) => {
                 entry.insert(ty);
                 Ok(())
@@ -203,10 +630,235 @@ impl<'s> Scheme {
         }
     }
 
+    /// Registers a field that maps string keys to values of the given type,
+    /// like HTTP headers.
+    ///
+    /// Filters have to pick a key with a subscript, like
+    /// `http.headers["x-forwarded-for"] == "192.0.2.1"`, and compare it as a
+    /// field of the value type. Values are set per key with
+    /// [`ExecutionContext::set_map_value`](::ExecutionContext::set_map_value),
+    /// and keys that were never set are treated like unset fields.
+    pub fn add_map_field(&mut self, name: String, value_type: Type) -> Result<(), FieldError> {
+        let index = self.fields.len();
+        self.add_field(name, value_type)?;
+        self.maps.insert(index);
+        Ok(())
+    }
+
+    /// Registers a namespace of fields of the given type, like `http.request`,
+    /// for protocols where names of fields are not known up front, like
+    /// headers or query arguments.
+    ///
+    /// Any name under the namespace that isn't registered as a field, like
+    /// `http.request.headers.x_custom`, refers to the value under the rest of
+    /// the name as a key, so it's the same as
+    /// `http.request["headers.x_custom"]`. The namespace itself is a map
+    /// field, and values are set with
+    /// [`ExecutionContext::set_map_value`](::ExecutionContext::set_map_value)
+    /// in the same way.
+    pub fn add_namespace(&mut self, name: String, ty: Type) -> Result<(), FieldError> {
+        let index = self.fields.len();
+        self.add_map_field(name, ty)?;
+        self.namespaces.insert(index);
+        Ok(())
+    }
+
+    /// Registers a field whose value is a list of elements of the given type,
+    /// like cookies of a request.
+    ///
+    /// Filters can compare a single element by its index, like
+    /// `http.cookies[0] == "a"`, or all of them with a quantifier, like
+    /// `any(http.cookies contains "session")` or `all(...)`. Indices past
+    /// the end are treated like unset fields. The whole array can only be
+    /// checked to be empty, like `http.cookies == []`, which as for other
+    /// comparisons is false for an unset array. Values are set with
+    /// [`ExecutionContext::set_array_values`](::ExecutionContext::set_array_values).
+    pub fn add_array_field(&mut self, name: String, element_type: Type) -> Result<(), FieldError> {
+        let index = self.fields.len();
+        self.add_field(name, element_type)?;
+        self.arrays.insert(index);
+        Ok(())
+    }
+
+    /// Registers a function that filters can call on the left-hand side of
+    /// comparisons, like `lower(http.host) == "example.org"`.
+    ///
+    /// Arguments are fields, with subscripts if they are maps or arrays, or
+    /// literals of the parameter types, but at least one of them has to be a
+    /// field. The comparison then works like one of a field of the return
+    /// type. Functions live in their own namespace, so they can share names
+    /// with fields, but not with reserved words like `any` and `all`.
+    pub fn add_function(&mut self, name: String, function: Function) -> Result<(), FunctionError> {
+        check_reserved(&name).map_err(FunctionError::ReservedName)?;
+
+        match self.functions.entry(name) {
+            Entry::Occupied(entry) => Err(FunctionError::Redefinition(entry.key().to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(function);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn get_function(&'s self, name: &str) -> Option<(&'s str, &'s Function)> {
+        self.functions
+            .get_full(name)
+            .map(|(_, name, function)| (&name[..], function))
+    }
+
+    /// Registers an alternative name for an existing field.
+    ///
+    /// The alias can be used anywhere the field name can, but the parsed
+    /// filter always refers to the canonical field, so e.g. serialized ASTs
+    /// contain only canonical names.
+    ///
+    /// Aliasing another alias resolves to the same canonical field, so alias
+    /// chains can't form cycles.
+    pub fn add_alias(&mut self, alias: String, name: &str) -> Result<(), AliasError> {
+        check_reserved(&alias).map_err(AliasError::ReservedName)?;
+
+        if self.fields.contains_key(&alias) || self.aliases.contains_key(&alias) {
+            return Err(AliasError::Redefinition(FieldRedefinitionError(alias)));
+        }
+
+        let index = self
+            .get_field_index(name)
+            .map_err(AliasError::UnknownField)?
+            .index();
+
+        self.aliases.insert(alias, index);
+        Ok(())
+    }
+
+    /// Registers names of known values of a bytes field, like `GET` and
+    /// `POST` for an HTTP method.
+    ///
+    /// Filters can then compare the field against these names with `==` and
+    /// `!=` without quotes, e.g. `http.method == GET`, and each name stands
+    /// for its own bytes. Any other bare identifier is reported as a parse
+    /// error listing the registered names. Quoted strings work as usual.
+    pub fn add_enum_values(
+        &mut self,
+        name: &str,
+        values: impl IntoIterator<Item = String>,
+    ) -> Result<(), EnumValuesError> {
+        let index = {
+            let field = self
+                .get_field_index(name)
+                .map_err(EnumValuesError::UnknownField)?;
+
+            match field.get_type() {
+                Type::Bytes => field.index(),
+                ty => return Err(EnumValuesError::UnsupportedType(ty)),
+            }
+        };
+
+        self.enum_values
+            .entry(index)
+            .or_insert_with(Vec::new)
+            .extend(values);
+        Ok(())
+    }
+
+    /// Registers a check for values of a field, which is run by
+    /// [`ExecutionContext::set_field_value`](::ExecutionContext::set_field_value)
+    /// and the other setters of the context, as well as by
+    /// [`Filter::execute_batch`](::Filter::execute_batch), to reject values
+    /// that are of the right type, but make no sense, like a port over 65535.
+    /// The check returns the reason for a rejection.
+    ///
+    /// Values computed during execution, by accessors, resolvers and field
+    /// providers, are checked when they're read, and a rejected one panics.
+    ///
+    /// Fields without a validator accept any value of their type. A new
+    /// validator replaces the previous one for the same field.
+    pub fn add_field_validator<F>(
+        &mut self,
+        name: &str,
+        validator: F,
+    ) -> Result<(), UnknownFieldError>
+    where
+        F: 'static + Fn(&LhsValue<'_>) -> Result<(), String> + Send + Sync,
+    {
+        let index = self.get_field_index(name)?.index();
+        if self.validators.len() <= index {
+            self.validators.resize_with(index + 1, || None);
+        }
+        self.validators[index] = Some(Box::new(validator));
+        Ok(())
+    }
+
+    pub(crate) fn validate_field_value(
+        &self,
+        field: Field<'_>,
+        value: &LhsValue<'_>,
+    ) -> Result<(), String> {
+        match self.validators.get(field.index()) {
+            Some(Some(validator)) => validator(value),
+            _ => Ok(()),
+        }
+    }
+
+    /// Registers a named scalar constant, whose value is supplied at
+    /// execution time via
+    /// [`ExecutionContext::set_constant`](::ExecutionContext::set_constant).
+    ///
+    /// Filters can then compare fields of the same type against it with
+    /// ordering operators, e.g. `tcp.port < $max_port`. Constants live in
+    /// their own namespace, so they can share names with fields.
+    pub fn add_constant(&mut self, name: String, ty: Type) -> Result<(), ConstantError> {
+        match ty {
+            Type::Ip | Type::Bytes | Type::Int => {}
+            ty => return Err(ConstantError::UnsupportedType(ty)),
+        }
+
+        if self.constants.contains_key(&name) {
+            return Err(ConstantError::Redefinition(name));
+        }
+
+        self.constants.insert(name, ty);
+        Ok(())
+    }
+
+    pub(crate) fn get_constant_type(&self, name: &str) -> Option<Type> {
+        self.constants.get(name).cloned()
+    }
+
+    /// Registers a named list of values supplied at execution time with
+    /// [`ExecutionContext::set_list`](::ExecutionContext::set_list).
+    ///
+    /// Filters can then check whether fields of the same type are in it,
+    /// e.g. `ip.src in $bad_actors`, and the list can be replaced between
+    /// executions without recompiling them. Lists live in their own
+    /// namespace, so they can share names with fields and constants.
+    pub fn add_list(&mut self, name: String, ty: Type) -> Result<(), ListError> {
+        match ty {
+            Type::Ip | Type::Bytes | Type::Int => {}
+            ty => return Err(ListError::UnsupportedType(ty)),
+        }
+
+        if self.lists.contains_key(&name) {
+            return Err(ListError::Redefinition(name));
+        }
+
+        self.lists.insert(name, ty);
+        Ok(())
+    }
+
+    pub(crate) fn get_list_type(&self, name: &str) -> Option<Type> {
+        self.lists.get(name).cloned()
+    }
+
+    pub(crate) fn get_enum_values(&self, field: Field<'_>) -> Option<&[String]> {
+        self.enum_values
+            .get(&field.index())
+            .map(|values| values.as_slice())
+    }
+
     /// Registers a series of fields from an iterable, reporting any conflicts.
     pub fn try_from_iter(
         iter: impl IntoIterator<Item = (String, Type)>,
-    ) -> Result<Self, FieldRedefinitionError> {
+    ) -> Result<Self, FieldError> {
         let iter = iter.into_iter();
         let (low, _) = iter.size_hint();
         let mut scheme = Scheme::with_capacity(low);
@@ -216,14 +868,65 @@ impl<'s> Scheme {
         Ok(scheme)
     }
 
+    /// Resolves a field or an alias by name into a handle, which can be used
+    /// to set values of the field without looking it up again.
+    pub fn get_field(&'s self, name: &str) -> Result<Field<'s>, UnknownFieldError> {
+        self.get_field_index(name)
+    }
+
     pub(crate) fn get_field_index(&'s self, name: &str) -> Result<Field<'s>, UnknownFieldError> {
-        match self.fields.get_full(name) {
-            Some((index, ..)) => Ok(Field {
-                scheme: self,
-                index,
-            }),
-            None => Err(UnknownFieldError),
-        }
+        let index = match self.fields.get_full(name) {
+            Some((index, ..)) => index,
+            None => *self.aliases.get(name).ok_or(UnknownFieldError)?,
+        };
+
+        Ok(Field {
+            scheme: self,
+            index,
+        })
+    }
+
+    /// Resolves a dotted name under the longest namespace it starts with into
+    /// the namespace and the rest of the name.
+    pub(crate) fn get_namespace_field<'n>(&'s self, name: &'n str) -> Option<(Field<'s>, &'n str)> {
+        name.rmatch_indices('.').find_map(|(dot, _)| {
+            let field = self.get_field_index(&name[..dot]).ok()?;
+            if self.namespaces.contains(&field.index) && dot + 1 < name.len() {
+                Some((field, &name[dot + 1..]))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns all fields of the scheme in the order they were added,
+    /// without aliases.
+    pub fn iter_fields(&'s self) -> impl Iterator<Item = Field<'s>> {
+        (0..self.fields.len()).map(move |index| Field {
+            scheme: self,
+            index,
+        })
+    }
+
+    // Returns up to 3 names of fields or aliases that are a few typos away
+    // from the given one, closest first.
+    fn similar_field_names(&self, name: &str) -> Vec<String> {
+        let max_distance = max(1, name.chars().count() / 3);
+
+        let mut names: Vec<_> = self
+            .fields
+            .keys()
+            .chain(self.aliases.keys())
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .collect();
+        names.sort();
+
+        names
+            .into_iter()
+            .take(3)
+            .map(|(_, candidate)| candidate.clone())
+            .collect()
     }
 
     pub(crate) fn get_field_count(&self) -> usize {
@@ -231,8 +934,76 @@ impl<'s> Scheme {
     }
 
     /// Parses a filter into an AST form.
+    ///
+    /// The AST owns all of its literals and only borrows the scheme, so it
+    /// can be kept around after the input is dropped.
     pub fn parse<'i>(&'s self, input: &'i str) -> Result<FilterAst<'s>, ParseError<'i>> {
-        complete(FilterAst::lex_with(input.trim(), self)).map_err(|err| ParseError::new(input, err))
+        Parser::new(self).parse(input)
+    }
+
+    /// Parses a document with several filters separated by `;`, see
+    /// [`Parser::parse_all`](::Parser::parse_all).
+    pub fn parse_all<'i>(&'s self, input: &'i str) -> Vec<Result<FilterAst<'s>, ParseError<'i>>> {
+        Parser::new(self).parse_all(input)
+    }
+
+    /// Parses a filter, reporting all errors instead of only the first one,
+    /// see [`Parser::parse_all_errors`](::Parser::parse_all_errors).
+    pub fn parse_all_errors<'i>(
+        &'s self,
+        input: &'i str,
+    ) -> Result<FilterAst<'s>, Vec<ParseError<'i>>> {
+        Parser::new(self).parse_all_errors(input)
+    }
+
+    /// Reads an AST from JSON, in the format it's serialized to.
+    ///
+    /// All comparisons are checked against the scheme again, just like when
+    /// parsing, so the JSON doesn't have to come from a trusted source.
+    /// Unknown fields are an error, see
+    /// [`Parser::filter_from_json`](::Parser::filter_from_json) for reading
+    /// filters with fields treated as missing.
+    #[cfg(feature = "json")]
+    pub fn filter_from_json(&'s self, json: &str) -> Result<FilterAst<'s>, FilterJsonError> {
+        Parser::new(self).filter_from_json(json)
+    }
+
+    /// Parses a filter and returns the fields and operators it uses,
+    /// without keeping the AST around.
+    ///
+    /// The filter is fully checked against the scheme, so this fails for
+    /// anything [`parse`](Scheme::parse) would fail for.
+    pub fn analyze<'i>(&'s self, input: &'i str) -> Result<FilterAnalysis<'s>, ParseError<'i>> {
+        self.parse(input).map(|ast| ast.analyze())
+    }
+
+    /// Parses a filter from raw bytes into an AST form.
+    ///
+    /// This is the same as [`parse`](Scheme::parse), except that input which
+    /// is not valid UTF-8 is reported as a parse error pointing at the first
+    /// invalid sequence instead of having to be validated by the caller.
+    pub fn parse_bytes<'i>(&'s self, input: &'i [u8]) -> Result<FilterAst<'s>, ParseError<'i>> {
+        match str::from_utf8(input) {
+            Ok(input) => self.parse(input),
+            Err(err) => {
+                // Everything before the error is guaranteed to be valid.
+                let valid = str::from_utf8(&input[..err.valid_up_to()]).unwrap_or_default();
+                let span = &valid[valid.len()..];
+                Err(ParseError::new(
+                    valid,
+                    (LexErrorKind::InvalidUtf8(err), span),
+                ))
+            }
+        }
+    }
+
+    /// Parses a filter into an AST form with non-default syntax options.
+    pub fn parse_with_options<'i>(
+        &'s self,
+        input: &'i str,
+        options: ParserOptions,
+    ) -> Result<FilterAst<'s>, ParseError<'i>> {
+        Parser::with_options(self, options).parse(input)
     }
 }
 
@@ -268,10 +1039,13 @@ fn test_parse_error() {
             err,
             ParseError {
                 kind: LexErrorKind::UnknownField(UnknownFieldError),
+                span: "xyz",
+                offset: 0,
                 input: "xyz",
                 line_number: 0,
                 span_start: 0,
-                span_len: 3
+                span_len: 3,
+                suggestions: Vec::new(),
             }
         );
         assert_eq!(
@@ -280,7 +1054,7 @@ fn test_parse_error() {
                 r#"
                 Filter parsing error (1:1):
                 xyz
-                ^^^ unknown field
+                ^^^ unknown field `xyz`
                 "#
             )
         );
@@ -292,10 +1066,13 @@ fn test_parse_error() {
             err,
             ParseError {
                 kind: LexErrorKind::UnknownField(UnknownFieldError),
+                span: "xyz",
+                offset: 0,
                 input: "xyz",
                 line_number: 0,
                 span_start: 0,
-                span_len: 3
+                span_len: 3,
+                suggestions: Vec::new(),
             }
         );
         assert_eq!(
@@ -304,7 +1081,7 @@ fn test_parse_error() {
                 r#"
                 Filter parsing error (1:1):
                 xyz
-                ^^^ unknown field
+                ^^^ unknown field `xyz`
                 "#
             )
         );
@@ -316,10 +1093,13 @@ fn test_parse_error() {
             err,
             ParseError {
                 kind: LexErrorKind::UnknownField(UnknownFieldError),
+                span: "xyz",
+                offset: 6,
                 input: "    xyz",
                 line_number: 2,
                 span_start: 4,
-                span_len: 3
+                span_len: 3,
+                suggestions: Vec::new(),
             }
         );
         assert_eq!(
@@ -328,7 +1108,7 @@ fn test_parse_error() {
                 r#"
                 Filter parsing error (3:5):
                     xyz
-                    ^^^ unknown field
+                    ^^^ unknown field `xyz`
                 "#
             )
         );
@@ -348,10 +1128,13 @@ fn test_parse_error() {
             err,
             ParseError {
                 kind: LexErrorKind::ExpectedName("digit"),
+                span: "true or\nnum == 20",
+                offset: 20,
                 input: "num == true or",
                 line_number: 1,
                 span_start: 7,
-                span_len: 7
+                span_len: 7,
+                suggestions: Vec::new(),
             }
         );
         assert_eq!(
@@ -365,6 +1148,108 @@ fn test_parse_error() {
             )
         );
     }
+
+    {
+        let err = scheme.parse(r#"num matches "^1""#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (1:1):
+                num matches "^1"
+                ^^^^^^^^^^^ cannot use operator matches on a field of type int; try `==`, `!=`, `<`, `<=`, `>`, `>=`, `in`, `between`, `&`, `>>` or `<<`
+                "#
+            )
+        );
+    }
+
+    {
+        let scheme = &Scheme! { ssl: Bool, prefix: Cidr };
+
+        let err = scheme.parse("prefix > 10.0.0.0/8").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (1:1):
+                prefix > 10.0.0.0/8
+                ^^^^^^^^ cannot use operator > on a field of type cidr; try `==`, `!=` or `in`
+                "#
+            )
+        );
+    }
+}
+
+#[test]
+fn test_parse_error_location() {
+    let scheme = &Scheme! { num: Int, str: Bytes };
+
+    let input = "num == 1 or\nstr == \"é\" or num == \"x\"\nor num == 2";
+    let err = scheme.parse(input).unwrap_err();
+
+    assert_eq!(err.message(), "expected digit");
+    assert_eq!(err.start(), 34);
+    assert_eq!(err.end(), input.len());
+    assert_eq!(err.span(), "\"x\"\nor num == 2");
+    assert_eq!(err.line(), 2);
+    // Columns are in characters, and `é` takes two bytes.
+    assert_eq!(err.column(), 22);
+    assert_eq!(err.source_line(), "str == \"é\" or num == \"x\"");
+    assert!(err
+        .to_string()
+        .starts_with("Filter parsing error (2:22):\n"));
+
+    let err = scheme.parse("num ==").unwrap_err();
+    assert_eq!((err.start(), err.end(), err.span()), (6, 6, ""));
+    assert_eq!((err.line(), err.column()), (1, 7));
+}
+
+#[test]
+fn test_parse_error_suggestions() {
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        http.hostname: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+    };
+    scheme.add_alias("ip.source".into(), "ip.src").unwrap();
+
+    let err = scheme.parse("http.hots == \"a\"").unwrap_err();
+    assert_eq!(err.unknown_field(), Some("http.hots"));
+    assert_eq!(err.suggestions(), ["http.host"]);
+    assert!(err
+        .to_string()
+        .ends_with("unknown field `http.hots`; did you mean `http.host`?\n"));
+
+    let err = scheme.parse("ip.sourc == 1.1.1.1").unwrap_err();
+    assert_eq!(err.suggestions(), ["ip.source", "ip.src"]);
+    assert!(err
+        .to_string()
+        .ends_with("did you mean `ip.source` or `ip.src`?\n"));
+
+    let err = scheme.parse("udp.port == 1 or tcp.prot == 2").unwrap_err();
+    assert_eq!(err.unknown_field(), Some("udp.port"));
+    assert_eq!(err.suggestions(), ["tcp.port"]);
+
+    let err = scheme.parse("dns.qname == \"a\"").unwrap_err();
+    assert!(err.suggestions().is_empty());
+    assert!(err.to_string().ends_with("unknown field `dns.qname`\n"));
+
+    let err = scheme.parse("tcp.port == \"a\"").unwrap_err();
+    assert_eq!(err.unknown_field(), None);
+    assert!(err.suggestions().is_empty());
+}
+
+#[test]
+fn test_iter_fields() {
+    let mut scheme = Scheme! { b: Int, a: Bytes };
+    scheme.add_alias("c".into(), "a").unwrap();
+
+    let fields: Vec<_> = scheme
+        .iter_fields()
+        .map(|field| (field.name(), field.get_type()))
+        .collect();
+    assert_eq!(fields, [("b", Type::Int), ("a", Type::Bytes)]);
 }
 
 #[test]
@@ -424,6 +1309,531 @@ fn test_field_type_override() {
 
     assert_eq!(
         scheme.add_field("foo".into(), Type::Bytes),
-        Err(FieldRedefinitionError("foo".into()))
+        Err(FieldError::Redefinition(FieldRedefinitionError(
+            "foo".into()
+        )))
     )
 }
+
+#[test]
+fn test_parse_with_options() {
+    let scheme = &Scheme! { http.host: Bytes };
+
+    let options = ParserOptions {
+        doubled_quote_escapes: true,
+        ..ParserOptions::default()
+    };
+
+    assert_eq!(
+        scheme
+            .parse_with_options(r#"http.host in { "a""b" "c" }"#, options)
+            .unwrap(),
+        scheme.parse(r#"http.host in { "a\"b" "c" }"#).unwrap()
+    );
+
+    assert_eq!(
+        scheme.parse(r#"http.host in { "a""b" "c" }"#).unwrap(),
+        scheme.parse(r#"http.host in { "a" "b" "c" }"#).unwrap()
+    );
+}
+
+#[test]
+fn test_max_regexes() {
+    let scheme = &Scheme! { http.host: Bytes, http.ua: Bytes };
+
+    let options = ParserOptions {
+        max_regexes: Some(2),
+        ..ParserOptions::default()
+    };
+
+    assert!(scheme
+        .parse_with_options(
+            r#"http.host matches "a" && (http.ua ~ "b" || http.host contains "c")"#,
+            options.clone()
+        )
+        .is_ok());
+
+    assert_eq!(
+        scheme
+            .parse_with_options(
+                r#"http.host matches "a" && not (http.ua ~ "b" or http.host !~ "c")"#,
+                options
+            )
+            .unwrap_err(),
+        ParseError {
+            kind: LexErrorKind::TooManyRegexes { max: 2 },
+            span: "http.host !~",
+            offset: 47,
+            input: r#"http.host matches "a" && not (http.ua ~ "b" or http.host !~ "c")"#,
+            line_number: 0,
+            span_start: 47,
+            span_len: 12,
+            suggestions: Vec::new(),
+        }
+    );
+
+    assert!(scheme
+        .parse(r#"http.host matches "a" && http.ua ~ "b" && http.host ~ "c""#)
+        .is_ok());
+}
+
+#[test]
+fn test_serde() {
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ip.addr: Ip,
+        ssl: Bool,
+        tcp.port: Int,
+    };
+
+    let json = serde_json::to_string(&scheme).unwrap();
+    assert_eq!(
+        json,
+        r#"{"http.host":"Bytes","ip.addr":"Ip","ssl":"Bool","tcp.port":"Int"}"#
+    );
+
+    let scheme: Scheme = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&scheme).unwrap(), json);
+    assert!(scheme.parse("tcp.port == 443 && ssl").is_ok());
+
+    let err = serde_json::from_str::<Scheme>(r#"{"tcp.port":"Int","ip.ttl":"Int8"}"#)
+        .err()
+        .unwrap();
+    assert!(
+        err.to_string().starts_with("unknown variant `Int8`"),
+        "{}",
+        err
+    );
+
+    let mut scheme = Scheme::new();
+    scheme.add_field("ssl".into(), Type::Bool).unwrap();
+    scheme
+        .add_map_field("http.headers".into(), Type::Bytes)
+        .unwrap();
+    scheme
+        .add_array_field("http.cookies".into(), Type::Bytes)
+        .unwrap();
+    scheme.add_namespace("http.args".into(), Type::Int).unwrap();
+
+    let json = serde_json::to_string(&scheme).unwrap();
+    assert_eq!(
+        json,
+        r#"{"ssl":"Bool","http.headers":{"kind":"map","type":"Bytes"},"http.cookies":{"kind":"array","type":"Bytes"},"http.args":{"kind":"namespace","type":"Int"}}"#
+    );
+
+    let scheme: Scheme = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&scheme).unwrap(), json);
+    assert!(scheme
+        .parse(r#"http.headers["host"] == "a" && any(http.cookies == "b") && http.args.id == 1"#)
+        .is_ok());
+
+    let err = serde_json::from_str::<Scheme>(r#"{"tcp.port":{"kind":"set","type":"Int"}}"#)
+        .err()
+        .unwrap();
+    assert!(
+        err.to_string().starts_with("unknown variant `set`"),
+        "{}",
+        err
+    );
+
+    let err = serde_json::from_str::<Scheme>(r#"{"tcp.port":"Int","or":"Bool"}"#)
+        .err()
+        .unwrap();
+    assert!(
+        err.to_string().starts_with("or is a reserved word"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_trailing_input() {
+    let scheme = &Scheme! { num: Int };
+
+    assert!(scheme.parse("num == 1").is_ok());
+    assert!(scheme.parse("  num == 1 \n ").is_ok());
+
+    assert_eq!(
+        scheme.parse("num == 1 num").unwrap_err(),
+        ParseError {
+            kind: LexErrorKind::ExpectedEof,
+            span: "num",
+            offset: 9,
+            input: "num == 1 num",
+            line_number: 0,
+            span_start: 9,
+            span_len: 3,
+            suggestions: Vec::new(),
+        }
+    );
+
+    assert_eq!(
+        scheme.parse("(num == 1))").unwrap_err(),
+        ParseError {
+            kind: LexErrorKind::ExpectedEof,
+            span: ")",
+            offset: 10,
+            input: "(num == 1))",
+            line_number: 0,
+            span_start: 10,
+            span_len: 1,
+            suggestions: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn test_parse_from_temporary() {
+    use execution_context::ExecutionContext;
+
+    let scheme = Scheme! { http.host: Bytes, tcp.port: Int };
+
+    let ast = {
+        let input = format!(
+            r#"http.host == "{}" && tcp.port in {{ 80 443 }}"#,
+            "example.org"
+        );
+        scheme.parse(&input).unwrap()
+    };
+
+    let filter = ast.compile();
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+}
+
+#[test]
+fn test_parse_bytes() {
+    let scheme = &Scheme! { str: Bytes };
+
+    assert_eq!(
+        scheme.parse_bytes(br#"str == "\xE2\x82\xAC""#),
+        scheme.parse(r#"str == "\xE2\x82\xAC""#)
+    );
+
+    let input = b"str == \"\xE2\x82\"";
+    assert_eq!(
+        scheme.parse_bytes(input).unwrap_err(),
+        ParseError {
+            kind: LexErrorKind::InvalidUtf8(str::from_utf8(input).unwrap_err()),
+            span: "",
+            offset: 8,
+            input: "str == \"",
+            line_number: 0,
+            span_start: 8,
+            span_len: 0,
+            suggestions: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn test_multibyte_input() {
+    let scheme = &Scheme! { str: Bytes, num: Int, ip: Ip };
+
+    // Multi-byte characters in every position of otherwise valid filters,
+    // including in the middle of escapes and fixed-width byte literals
+    // consumed via `take`, should never cause a panic.
+    for filter in &[
+        r#"str == "\x41\101""#,
+        r#"str in { "a" "b" } && num == 1"#,
+        "str == 01:02:03",
+        "ip in { 10.0.0.0/8 ::1 }",
+        "not (num > 1 || num & 2)",
+    ] {
+        for (i, _) in filter.char_indices().chain(Some((filter.len(), ' '))) {
+            for c in &["\u{e9}", "\u{20ac}", "\u{1f600}"] {
+                let filter = format!("{}{}{}", &filter[..i], c, &filter[i..]);
+                if let Err(err) = scheme.parse(&filter) {
+                    err.to_string();
+                }
+            }
+            if let Err(err) = scheme.parse(&filter[..i]) {
+                err.to_string();
+            }
+        }
+    }
+
+    let err = scheme.parse("str == \"\\x\u{e9}1\"").unwrap_err();
+    assert_eq!(err.span_start, 10);
+    assert_eq!(err.span_len, 3);
+    assert_eq!(
+        err.to_string(),
+        indoc::indoc!(
+            r#"
+            Filter parsing error (1:11):
+            str == "\xé1"
+                      ^^ invalid digit found in string while parsing with radix 16
+            "#
+        )
+    );
+}
+
+#[test]
+fn test_aliases() {
+    let mut scheme = Scheme! { ip.src: Ip, ip.dst: Ip };
+
+    scheme.add_alias("src_ip".into(), "ip.src").unwrap();
+    scheme.add_alias("source".into(), "src_ip").unwrap();
+
+    {
+        let ast = scheme
+            .parse("src_ip == 10.0.0.1 && source == 10.0.0.1 && ip.src == 10.0.0.1")
+            .unwrap();
+        assert_eq!(
+            ast.comparisons()
+                .iter()
+                .map(|expr| expr.field())
+                .collect::<Vec<_>>(),
+            vec!["ip.src", "ip.src", "ip.src"]
+        );
+        assert_eq!(ast.uses("src_ip"), Ok(true));
+        assert_eq!(ast.uses("ip.dst"), Ok(false));
+    }
+
+    assert_eq!(
+        scheme.add_alias("dst_ip".into(), "ip.destination"),
+        Err(AliasError::UnknownField(UnknownFieldError))
+    );
+    assert_eq!(
+        scheme.add_alias("loop".into(), "loop"),
+        Err(AliasError::UnknownField(UnknownFieldError))
+    );
+    assert_eq!(
+        scheme.add_alias("ip.dst".into(), "ip.src"),
+        Err(AliasError::Redefinition(FieldRedefinitionError(
+            "ip.dst".into()
+        )))
+    );
+    assert_eq!(
+        scheme.add_alias("src_ip".into(), "ip.dst"),
+        Err(AliasError::Redefinition(FieldRedefinitionError(
+            "src_ip".into()
+        )))
+    );
+    assert_eq!(
+        scheme.add_field("src_ip".into(), Type::Ip),
+        Err(FieldError::Redefinition(FieldRedefinitionError(
+            "src_ip".into()
+        )))
+    );
+}
+
+#[test]
+fn test_enum_values() {
+    use execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! { http.method: Bytes, tcp.port: Int };
+
+    let methods = || vec!["GET".to_owned(), "POST".to_owned()];
+
+    scheme.add_enum_values("http.method", methods()).unwrap();
+
+    assert_eq!(
+        scheme.add_enum_values("tcp.port", methods()),
+        Err(EnumValuesError::UnsupportedType(Type::Int))
+    );
+    assert_eq!(
+        scheme.add_enum_values("http.path", methods()),
+        Err(EnumValuesError::UnknownField(UnknownFieldError))
+    );
+
+    assert_eq!(
+        scheme.parse("http.method == GET"),
+        scheme.parse(r#"http.method == "GET""#)
+    );
+
+    let filter = scheme
+        .parse(r#"http.method != POST && http.method != "PUT""#)
+        .unwrap()
+        .compile();
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.method", "GET").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    ctx.set_field_value("http.method", "POST").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    // Hex bytes still work, even if they look like identifiers.
+    assert!(scheme.parse("http.method == AB:CD").is_ok());
+
+    let err = scheme.parse("http.method == FOO").unwrap_err();
+    assert_eq!(
+        err,
+        ParseError {
+            kind: LexErrorKind::UnknownEnumValue {
+                expected: "GET, POST".to_owned(),
+            },
+            span: "FOO",
+            offset: 15,
+            input: "http.method == FOO",
+            line_number: 0,
+            span_start: 15,
+            span_len: 3,
+            suggestions: Vec::new(),
+        }
+    );
+    assert!(err
+        .to_string()
+        .contains("unknown value, expected one of GET, POST"));
+}
+
+#[test]
+fn test_reserved_names() {
+    use execution_context::ExecutionContext;
+
+    let mut scheme = Scheme::new();
+
+    assert_eq!(
+        scheme.add_field("or".into(), Type::Int),
+        Err(FieldError::ReservedName(ReservedNameError("or".into())))
+    );
+    assert_eq!(
+        scheme.add_field("matches".into(), Type::Bytes),
+        Err(FieldError::ReservedName(ReservedNameError(
+            "matches".into()
+        )))
+    );
+
+    assert_eq!(
+        scheme.add_field("nocase".into(), Type::Bool),
+        Err(FieldError::ReservedName(ReservedNameError("nocase".into())))
+    );
+    assert_eq!(
+        scheme.add_alias("all".into(), "order"),
+        Err(AliasError::ReservedName(ReservedNameError("all".into())))
+    );
+
+    // only whole names are reserved
+    scheme.add_field("order".into(), Type::Int).unwrap();
+    scheme.add_field("http.in".into(), Type::Bytes).unwrap();
+
+    scheme.add_field("`or`".into(), Type::Int).unwrap();
+
+    assert_eq!(
+        scheme.add_alias("not".into(), "order"),
+        Err(AliasError::ReservedName(ReservedNameError("not".into())))
+    );
+
+    let filter = scheme
+        .parse("`or` == 1 or order == 2 or http.in == \"a\"")
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("`or`", 1).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_field_value("`or`", 2).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    assert!(scheme.parse("or == 1").is_err());
+
+    let filter = "`or == 1";
+    assert_eq!(
+        scheme.parse(filter),
+        Err(ParseError::new(
+            filter,
+            (LexErrorKind::MissingEndingQuote, &filter[1..])
+        ))
+    );
+}
+
+#[test]
+fn test_functions() {
+    use execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        len: Int,
+    };
+
+    // Functions and fields have separate namespaces.
+    scheme.add_function("len".into(), Function::len()).unwrap();
+    scheme
+        .add_function("lower".into(), Function::lower())
+        .unwrap();
+
+    assert_eq!(
+        scheme.add_function("len".into(), Function::len()),
+        Err(FunctionError::Redefinition("len".into()))
+    );
+    assert_eq!(
+        scheme.add_function("any".into(), Function::len()),
+        Err(FunctionError::ReservedName(ReservedNameError("any".into())))
+    );
+    assert_eq!(
+        scheme.add_function("not".into(), Function::len()),
+        Err(FunctionError::ReservedName(ReservedNameError("not".into())))
+    );
+
+    let ast = scheme
+        .parse(r#"len(http.host) > 10 && len == 1 && not lower(http.host) contains any { "admin" "test" }"#)
+        .unwrap();
+    assert_eq!(
+        ast.analyze().used_fields().iter().collect::<HashSet<_>>(),
+        ["http.host", "len"].iter().collect()
+    );
+    assert_eq!(ast.uses("http.host"), Ok(true));
+
+    let filter = ast.compile();
+    assert_eq!(filter.field_types(), &[Type::Bytes, Type::Int]);
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("len", 1).unwrap();
+    ctx.set_field_value("http.host", "www.example.org").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_field_value("http.host", "www.Test.example.org")
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+}
+
+#[test]
+fn test_namespaces() {
+    use execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! {
+        http.request.method: Bytes,
+    };
+    scheme
+        .add_namespace("http.request".into(), Type::Bytes)
+        .unwrap();
+    scheme
+        .add_namespace("http.request.args".into(), Type::Int)
+        .unwrap();
+    scheme.add_function("len".into(), Function::len()).unwrap();
+
+    // Registered fields take precedence, and the longest namespace wins.
+    let filter = scheme
+        .parse(
+            r#"http.request.method == "GET" && http.request.headers.x_custom == "1" && http.request.args.page > 1 && len(http.request.headers.host) == 11"#,
+        )
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.request.method", "GET").unwrap();
+    ctx.set_map_value("http.request", "headers.x_custom", "1")
+        .unwrap();
+    ctx.set_map_value("http.request.args", "page", 2).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_map_value("http.request", "headers.host", "example.org")
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        scheme.parse(r#"http.request.headers.x_custom == "1""#),
+        scheme.parse(r#"http.request["headers.x_custom"] == "1""#)
+    );
+
+    let filter = "http.response.status == 1";
+    assert_eq!(
+        scheme.parse(filter),
+        Err(ParseError::new(
+            filter,
+            (LexErrorKind::UnknownField(UnknownFieldError), &filter[..20])
+        ))
+    );
+}
@@ -0,0 +1,307 @@
+use lex::{expect, lex_comment, peek, span, take_while, LexErrorKind, LexResult, SPACE_CHARS};
+use scheme::ParseError;
+use std::ops::Range;
+
+/// A single token of the filter syntax, borrowed from the input.
+///
+/// Tokenization doesn't depend on a [`Scheme`](struct@::Scheme), so it only
+/// splits the input into pieces without checking that they make sense
+/// together, e.g. for syntax highlighting or quick inspection of huge inputs.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Token<'i> {
+    /// A field name or a word, like `http.host`, `and` or `contains`.
+    Ident(&'i str),
+    /// A literal that's not a string, like `443`, `10.0.0.0/8` or `AB:CD`.
+    Literal(&'i str),
+    /// A quoted string, including the quotes, with escapes left as-is, or a
+    /// raw string like `r"\d+"` or `r#"say "hi""#`.
+    String(&'i str),
+    /// An operator or a bracket, like `==`, `&&` or `{`.
+    Punct(&'i str),
+    /// A comment, like `# note` or `/* note */`, including the delimiters
+    /// but not the line break after a `#` comment.
+    Comment(&'i str),
+    /// The end of the input, always yielded as the last token.
+    Eof,
+}
+
+// Longer operators have to go first, so that `==` isn't lexed as `=`.
+const PUNCTS: &[&str] = &[
+    "==", "!=", "<>", ">=", "<=", "!~", "&&", "||", "^^", ">", "<", "~", "!", "&", "(", ")", "{",
+    "}", "[", "]", ",", "$",
+];
+
+pub(crate) fn lex_string(input: &str) -> LexResult<'_, ()> {
+    let full_input = input;
+    let mut iter = expect(input, "\"")?.chars();
+
+    loop {
+        match iter
+            .next()
+            .ok_or_else(|| (LexErrorKind::MissingEndingQuote, full_input))?
+        {
+            '"' => return Ok(((), iter.as_str())),
+            '\\' => {
+                iter.next();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Raw strings, like `r"\d+"` or `r#"say "hi""#`, have no escapes and end at
+// the first quote followed by as many `#` as there are after the `r`.
+pub(crate) fn lex_raw_string(input: &str) -> LexResult<'_, &str> {
+    let rest = expect(input, "r")?;
+    let hashes = &rest[..rest.len() - rest.trim_start_matches('#').len()];
+    let rest = expect(&rest[hashes.len()..], "\"")?;
+
+    rest.match_indices('"')
+        .map(|(end, _)| end)
+        .find(|&end| rest[end + 1..].starts_with(hashes))
+        .map(|end| (&rest[..end], &rest[end + 1 + hashes.len()..]))
+        .ok_or_else(|| (LexErrorKind::MissingEndingQuote, rest))
+}
+
+pub(crate) fn lex_token(input: &str) -> LexResult<'_, Token<'_>> {
+    if input.starts_with('"') {
+        let (_, rest) = lex_string(input)?;
+        return Ok((Token::String(span(input, rest)), rest));
+    }
+
+    if input.starts_with("r\"") || input.starts_with("r#") {
+        let (_, rest) = lex_raw_string(input)?;
+        return Ok((Token::String(span(input, rest)), rest));
+    }
+
+    // Field names quoted with backticks, like `` `or` ``.
+    if let Ok(rest) = expect(input, "`") {
+        let end = rest
+            .find('`')
+            .ok_or_else(|| (LexErrorKind::MissingEndingQuote, rest))?;
+        let rest = &rest[end + 1..];
+        return Ok((Token::Ident(span(input, rest)), rest));
+    }
+
+    // Bracketed hex bytes, like `0x[de ad]`, can contain spaces.
+    if let Ok(rest) = expect(input, "0x[") {
+        let end = rest
+            .find(']')
+            .ok_or_else(|| (LexErrorKind::ExpectedLiteral("]"), &rest[rest.len()..]))?;
+        let rest = &rest[end + 1..];
+        return Ok((Token::Literal(span(input, rest)), rest));
+    }
+
+    // Bracketed IPv6 endpoints, like `[2001:db8::1]:443`, as opposed to
+    // intervals like `[1, 10)`.
+    if let Ok(rest) = expect(input, "[") {
+        if let Some(end) = rest.find(|c: char| !c.is_ascii_hexdigit() && c != ':' && c != '.') {
+            if rest[end..].starts_with(']') {
+                let rest = &rest[end + 1..];
+                let rest = rest.trim_start_matches(|c: char| c == ':' || c.is_ascii_digit());
+                return Ok((Token::Literal(span(input, rest)), rest));
+            }
+        }
+    }
+
+    if let Some(punct) = PUNCTS.iter().find(|punct| input.starts_with(*punct)) {
+        return Ok((Token::Punct(punct), &input[punct.len()..]));
+    }
+
+    // `%` can only separate an IPv6 address from its zone, like in
+    // `fe80::1%eth0`, so it's not allowed at the start of a word.
+    if input.starts_with('%') {
+        return Err((LexErrorKind::ExpectedName("token"), input));
+    }
+
+    let (word, rest) = take_while(input, "token", |c| {
+        c.is_ascii_alphanumeric() || "_.:/-%+".contains(c)
+    })?;
+
+    // Addresses, networks and hex bytes can start with a letter too, but
+    // only those contain separators other than dots.
+    let is_ident = word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && !word.contains(|c| ":/-%+".contains(c));
+
+    Ok((
+        if is_ident {
+            Token::Ident(word)
+        } else {
+            Token::Literal(word)
+        },
+        rest,
+    ))
+}
+
+/// A lazy iterator over tokens of a filter together with their byte ranges.
+///
+/// Tokens are lexed one at a time, so that consumers can stop early without
+/// tokenizing the rest of the input. The last item is either
+/// [`Token::Eof`] or an error, after which the iterator is exhausted.
+pub struct Tokens<'i> {
+    input: &'i str,
+    rest: Option<&'i str>,
+}
+
+impl<'i> Tokens<'i> {
+    /// Creates an iterator over tokens of the given filter.
+    pub fn new(input: &'i str) -> Self {
+        Tokens {
+            input,
+            rest: Some(input),
+        }
+    }
+}
+
+impl<'i> Iterator for Tokens<'i> {
+    type Item = Result<(Token<'i>, Range<usize>), ParseError<'i>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.rest.take()?.trim_start_matches(SPACE_CHARS);
+        let start = self.input.len() - input.len();
+
+        if input.is_empty() {
+            return Some(Ok((Token::Eof, start..start)));
+        }
+
+        if let Some((comment, rest)) = lex_comment(input) {
+            self.rest = Some(rest);
+            return Some(Ok((Token::Comment(comment), start..start + comment.len())));
+        }
+
+        if peek(input, "/*") {
+            return Some(Err(ParseError::new(
+                self.input,
+                (LexErrorKind::UnterminatedComment, input),
+            )));
+        }
+
+        Some(match lex_token(input) {
+            Ok((token, rest)) => {
+                self.rest = Some(rest);
+                Ok((token, start..self.input.len() - rest.len()))
+            }
+            Err(err) => Err(ParseError::new(self.input, err)),
+        })
+    }
+}
+
+#[test]
+fn test_tokens() {
+    let input =
+        r#"http.host matches "a\"b" && not (ip.src in { 10.0.0.0/8 ::1 }) || tcp.port >= 1024"#;
+
+    let tokens = Tokens::new(input)
+        .map(|res| res.map(|(token, range)| (token, &input[range])))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(
+        tokens,
+        vec![
+            (Token::Ident("http.host"), "http.host"),
+            (Token::Ident("matches"), "matches"),
+            (Token::String(r#""a\"b""#), r#""a\"b""#),
+            (Token::Punct("&&"), "&&"),
+            (Token::Ident("not"), "not"),
+            (Token::Punct("("), "("),
+            (Token::Ident("ip.src"), "ip.src"),
+            (Token::Ident("in"), "in"),
+            (Token::Punct("{"), "{"),
+            (Token::Literal("10.0.0.0/8"), "10.0.0.0/8"),
+            (Token::Literal("::1"), "::1"),
+            (Token::Punct("}"), "}"),
+            (Token::Punct(")"), ")"),
+            (Token::Punct("||"), "||"),
+            (Token::Ident("tcp.port"), "tcp.port"),
+            (Token::Punct(">="), ">="),
+            (Token::Literal("1024"), "1024"),
+            (Token::Eof, ""),
+        ]
+    );
+
+    let mut tokens = Tokens::new("0x[de ad] }");
+    assert_eq!(tokens.next(), Some(Ok((Token::Literal("0x[de ad]"), 0..9))));
+
+    let mut tokens = Tokens::new("[2001:db8::1]:443 }");
+    assert_eq!(
+        tokens.next(),
+        Some(Ok((Token::Literal("[2001:db8::1]:443"), 0..17)))
+    );
+
+    let mut tokens = Tokens::new("fe80::1%eth0 }");
+    assert_eq!(
+        tokens.next(),
+        Some(Ok((Token::Literal("fe80::1%eth0"), 0..12)))
+    );
+
+    let mut tokens = Tokens::new("1.5e+3 }");
+    assert_eq!(tokens.next(), Some(Ok((Token::Literal("1.5e+3"), 0..6))));
+
+    let tokens = Tokens::new("x in [1, 10)")
+        .map(|res| res.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("x"),
+            Token::Ident("in"),
+            Token::Punct("["),
+            Token::Literal("1"),
+            Token::Punct(","),
+            Token::Literal("10"),
+            Token::Punct(")"),
+            Token::Eof,
+        ]
+    );
+
+    let mut tokens = Tokens::new(r##"r"\d+\" r#"a"b"# "##);
+    assert_eq!(tokens.next(), Some(Ok((Token::String(r#"r"\d+\""#), 0..7))));
+    assert_eq!(
+        tokens.next(),
+        Some(Ok((Token::String(r##"r#"a"b"#"##), 8..16)))
+    );
+
+    let mut tokens = Tokens::new("`or` == 1");
+    assert_eq!(tokens.next(), Some(Ok((Token::Ident("`or`"), 0..4))));
+
+    let mut tokens = Tokens::new("  ");
+    assert_eq!(tokens.next(), Some(Ok((Token::Eof, 2..2))));
+    assert_eq!(tokens.next(), None);
+
+    let input = r#"http.host == "abc"#;
+    let mut tokens = Tokens::new(input);
+    assert_eq!(tokens.next(), Some(Ok((Token::Ident("http.host"), 0..9))));
+    assert_eq!(tokens.next(), Some(Ok((Token::Punct("=="), 10..12))));
+    assert_eq!(
+        tokens.next(),
+        Some(Err(ParseError::new(
+            input,
+            (LexErrorKind::MissingEndingQuote, &input[13..])
+        )))
+    );
+    assert_eq!(tokens.next(), None);
+
+    let input = "a # b /* c\n/* d\n */ e /* f";
+    let mut tokens = Tokens::new(input);
+    assert_eq!(tokens.next(), Some(Ok((Token::Ident("a"), 0..1))));
+    assert_eq!(tokens.next(), Some(Ok((Token::Comment("# b /* c"), 2..10))));
+    assert_eq!(
+        tokens.next(),
+        Some(Ok((Token::Comment("/* d\n */"), 11..19)))
+    );
+    assert_eq!(tokens.next(), Some(Ok((Token::Ident("e"), 20..21))));
+    assert_eq!(
+        tokens.next(),
+        Some(Err(ParseError::new(
+            input,
+            (LexErrorKind::UnterminatedComment, &input[22..])
+        )))
+    );
+    assert_eq!(tokens.next(), None);
+
+    let mut tokens = Tokens::new("a % b");
+    assert_eq!(tokens.next(), Some(Ok((Token::Ident("a"), 0..1))));
+    assert!(tokens.next().unwrap().is_err());
+    assert_eq!(tokens.next(), None);
+}
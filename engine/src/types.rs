@@ -1,24 +1,216 @@
-use lex::{expect, skip_space, Lex, LexResult, LexWith};
-use rhs_types::{Bytes, IpRange, UninhabitedBool};
+use cidr::{Cidr, IpCidr};
+use lex::{
+    complete, expect, skip_space, span, take_while, Lex, LexError, LexErrorKind, LexResult,
+    LexWith, Limit,
+};
+use parser::ParserOptions;
+use range_set::remove_covered;
+use rhs_types::{
+    Bytes, Endpoint, ExplicitIpRange, Float, IpRange, Mac, Timestamp, UninhabitedBool,
+};
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
-    fmt::{self, Debug, Formatter},
+    fmt::{self, Debug, Display, Formatter},
     net::IpAddr,
     ops::RangeInclusive,
 };
 use strict_partial_ord::StrictPartialOrd;
+use tokens::lex_string;
+
+// Rough upper bound for the number of items in a list, used to pre-allocate
+// storage for huge lists (e.g. tens of thousands of IPs) in one go instead of
+// repeatedly growing it. It might be an underestimate if some items contain
+// `}` or an overestimate if they contain spaces, but that's fine for a hint.
+fn estimate_list_len(input: &str) -> usize {
+    let end = input.find('}').unwrap_or_else(|| input.len());
+    input[..end].split_whitespace().count()
+}
+
+// Lexes an explicitly typed literal like `int("0x10")` or `bytes("DEADBEEF")`.
+//
+// The string inside is taken as-is, without escapes, and must contain a
+// value in the syntax of the given type (for bytes, hex digits with optional
+// separators). `b64("...")` is a bytes literal decoded from base64.
+// Returns `None` if the input doesn't look like a cast at all.
+pub(crate) fn lex_cast(input: &str, ty: Type) -> Option<LexResult<'_, RhsValue>> {
+    let (name, cast_ty, rest) = lex_cast_type(input)?;
+    Some(lex_cast_body(rest, name, ty, cast_ty))
+}
+
+// Lexes the name of a typed literal up to and including the opening bracket.
+fn lex_cast_type(input: &str) -> Option<(&str, Type, &str)> {
+    let (name, rest) = take_while(input, "type name", |c| c.is_ascii_alphanumeric()).ok()?;
+    let rest = expect(skip_space(rest), "(").ok()?;
+
+    let cast_ty = match name {
+        "ip" => Type::Ip,
+        "bytes" | "b64" => Type::Bytes,
+        "int" => Type::Int,
+        _ => return None,
+    };
+
+    Some((name, cast_ty, rest))
+}
+
+fn lex_cast_body<'i>(
+    input: &'i str,
+    name: &'i str,
+    ty: Type,
+    cast_ty: Type,
+) -> LexResult<'i, RhsValue> {
+    if !cast_ty.can_coerce_to(ty) {
+        return Err((
+            LexErrorKind::TypeMismatch {
+                expected: ty,
+                actual: cast_ty,
+            },
+            name,
+        ));
+    }
+
+    let input = expect(skip_space(input), "\"")?;
+    let end = input
+        .find('"')
+        .ok_or_else(|| (LexErrorKind::MissingEndingQuote, input))?;
+    let (content, rest) = (&input[..end], &input[end + 1..]);
+    let rest = expect(skip_space(rest), ")")?;
+
+    let value = match cast_ty {
+        Type::Ip => {
+            let addr = complete(IpAddr::lex(content))?;
+            if ty == Type::Cidr {
+                RhsValue::Cidr(IpCidr::new_host(addr))
+            } else {
+                RhsValue::Ip(addr)
+            }
+        }
+        Type::Bytes if name == "b64" => RhsValue::Bytes(complete(Bytes::lex_base64(content))?),
+        Type::Bytes => RhsValue::Bytes(complete(Bytes::lex_hex(content))?),
+        Type::Int => RhsValue::Int(complete(i32::lex(content))?),
+        Type::Bool | Type::Cidr | Type::Endpoint | Type::Float | Type::Timestamp | Type::Mac => {
+            unreachable!()
+        }
+    };
 
-fn lex_rhs_values<'i, T: Lex<'i>>(input: &'i str) -> LexResult<'i, Vec<T>> {
+    Ok((value, rest))
+}
+
+// Items of lists are lexed by their own type, so a string or a typed literal
+// of another type would only fail with a confusing syntax error further
+// inside of it, e.g. `"GET"` in a list of ints. Report those up front.
+fn check_item_type(input: &str, ty: Type) -> Result<(), LexError<'_>> {
+    let (actual, span) = if input.starts_with('"') && ty != Type::Timestamp {
+        let (_, rest) = lex_string(input)?;
+        (Type::Bytes, span(input, rest))
+    } else if let Some((name, actual, _)) = lex_cast_type(input) {
+        (actual, name)
+    } else {
+        return Ok(());
+    };
+
+    if actual.can_coerce_to(ty) {
+        Ok(())
+    } else {
+        Err((
+            LexErrorKind::TypeMismatch {
+                expected: ty,
+                actual,
+            },
+            span,
+        ))
+    }
+}
+
+// Formats values in the literal syntax of their type, so that filters can be
+// written back as source.
+pub(crate) trait Literal {
+    fn fmt_literal(&self, f: &mut Formatter<'_>) -> fmt::Result;
+}
+
+macro_rules! display_literals {
+    ($($ty:ty),*) => {
+        $(impl Literal for $ty {
+            fn fmt_literal(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                Display::fmt(self, f)
+            }
+        })*
+    };
+}
+
+display_literals!(IpAddr, IpRange, i32, IpCidr, Endpoint, Float, Mac);
+
+// Bytes are written in the same form as they were lexed from, so that they
+// lex back into the same variant.
+impl Literal for Bytes {
+    fn fmt_literal(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Bytes::Raw(bytes) if !bytes.is_empty() => Debug::fmt(self, f),
+            _ => Display::fmt(self, f),
+        }
+    }
+}
+
+impl Literal for UninhabitedBool {
+    fn fmt_literal(&self, _: &mut Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl Literal for Timestamp {
+    fn fmt_literal(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+// Ranges of a single value are written as just the value.
+impl<T: Literal + PartialEq> Literal for RangeInclusive<T> {
+    fn fmt_literal(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.start().fmt_literal(f)?;
+        if self.start() != self.end() {
+            f.write_str("..")?;
+            self.end().fmt_literal(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt_literals<T: Literal>(values: &[T], f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str("{")?;
+    for value in values {
+        f.write_str(" ")?;
+        value.fmt_literal(f)?;
+    }
+    f.write_str(" }")
+}
+
+fn lex_rhs_values<'i, 'o, T: LexWith<'i, &'o ParserOptions>>(
+    input: &'i str,
+    ty: Type,
+    options: &'o ParserOptions,
+) -> LexResult<'i, Vec<T>> {
     let mut input = expect(input, "{")?;
-    let mut res = Vec::new();
+    let len = estimate_list_len(input);
+    let mut res = Vec::with_capacity(options.max_list_len.map_or(len, |max| len.min(max)));
     loop {
         input = skip_space(input);
         if let Ok(rest) = expect(input, "}") {
             input = rest;
             return Ok((res, input));
         } else {
-            let (item, rest) = T::lex(input)?;
+            check_item_type(input, ty)?;
+            let (item, rest) = T::lex_with(input, options)?;
+            if let Some(max) = options.max_list_len {
+                if res.len() == max {
+                    return Err((
+                        LexErrorKind::LimitExceeded {
+                            limit: Limit::ListLength,
+                            max,
+                        },
+                        span(input, rest),
+                    ));
+                }
+            }
             res.push(item);
             input = rest;
         }
@@ -52,7 +244,7 @@ macro_rules! declare_types {
 
     ($($(# $attrs:tt)* $name:ident ( $lhs_ty:ty | $rhs_ty:ty | $multi_rhs_ty:ty ) , )*) => {
         /// Enumeration of supported types for field values.
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
         #[repr(u8)]
         pub enum Type {
             $($(# $attrs)* $name,)*
@@ -76,7 +268,7 @@ macro_rules! declare_types {
             /// These are passed to the [execution context](::ExecutionContext)
             /// and are used by [filters](::Filter)
             /// for execution and comparisons.
-            #[derive(PartialEq, Eq, Clone, Deserialize)]
+            #[derive(PartialEq, Clone, Deserialize)]
             #[serde(untagged)]
             enum LhsValue<'a> {
                 $($(# $attrs)* $name($lhs_ty),)*
@@ -98,17 +290,30 @@ macro_rules! declare_types {
             }
         }
 
-        impl<'i> LexWith<'i, Type> for RhsValue {
-            fn lex_with(input: &str, ty: Type) -> LexResult<'_, Self> {
+        impl<'i, 'o> LexWith<'i, (Type, &'o ParserOptions)> for RhsValue {
+            fn lex_with(input: &'i str, (ty, options): (Type, &'o ParserOptions)) -> LexResult<'i, Self> {
+                if let Some(res) = lex_cast(input, ty) {
+                    return res;
+                }
+
                 Ok(match ty {
                     $(Type::$name => {
-                        let (value, input) = <$rhs_ty>::lex(input)?;
+                        let (value, input) = <$rhs_ty>::lex_with(input, options)?;
                         (RhsValue::$name(value), input)
                     })*
                 })
             }
         }
 
+        /// Formats the value as a literal of its type.
+        impl Display for RhsValue {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                match self {
+                    $(RhsValue::$name(value) => value.fmt_literal(f),)*
+                }
+            }
+        }
+
         impl<'a> PartialOrd<RhsValue> for LhsValue<'a> {
             fn partial_cmp(&self, other: &RhsValue) -> Option<Ordering> {
                 match (self, other) {
@@ -140,11 +345,37 @@ macro_rules! declare_types {
             }
         }
 
-        impl<'i> LexWith<'i, Type> for RhsValues {
-            fn lex_with(input: &str, ty: Type) -> LexResult<'_, Self> {
+        impl RhsValues {
+            pub(crate) fn len(&self) -> usize {
+                match self {
+                    $(RhsValues::$name(values) => values.len(),)*
+                }
+            }
+
+            /// Sorts the values by their debug representation.
+            pub(crate) fn sort(&mut self) {
+                match self {
+                    $(RhsValues::$name(values) => {
+                        values.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)))
+                    })*
+                }
+            }
+        }
+
+        /// Formats the values as a list for `in`, like `{ 1 2..5 }`.
+        impl Display for RhsValues {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                match self {
+                    $(RhsValues::$name(values) => fmt_literals(values, f),)*
+                }
+            }
+        }
+
+        impl<'i, 'o> LexWith<'i, (Type, &'o ParserOptions)> for RhsValues {
+            fn lex_with(input: &'i str, (ty, options): (Type, &'o ParserOptions)) -> LexResult<'i, Self> {
                 Ok(match ty {
                     $(Type::$name => {
-                        let (value, input) = lex_rhs_values(input)?;
+                        let (value, input) = lex_rhs_values(input, ty, options)?;
                         (RhsValues::$name(value), input)
                     })*
                 })
@@ -177,8 +408,136 @@ declare_types!(
 
     /// A boolean.
     Bool(bool | UninhabitedBool | UninhabitedBool),
+
+    /// An IPv4 or IPv6 network, like a routing prefix.
+    ///
+    /// `in` checks whether the network is a subnet of any network on the
+    /// right, including equal ones.
+    Cidr(IpCidr | IpCidr | IpCidr),
+
+    /// An IP address with a port, like a socket address.
+    ///
+    /// Endpoints of different address families are never equal.
+    Endpoint(Endpoint | Endpoint | Endpoint),
+
+    /// A 64-bit floating-point number.
+    ///
+    /// Literals can be written with a fraction and an exponent, like
+    /// `0.85` or `1.5e-3`. NaN values never compare equal to anything, so
+    /// only `!=` matches them.
+    Float(f64 | Float | RangeInclusive<Float>),
+
+    /// A point in time, like the time of a request.
+    ///
+    /// Literals are either quoted RFC 3339 strings, like
+    /// `"2024-01-01T00:00:00Z"`, or seconds since the Unix epoch.
+    Timestamp(Timestamp | Timestamp | RangeInclusive<Timestamp>),
+
+    /// A MAC address, like the source of an Ethernet frame.
+    ///
+    /// `in` lists can contain prefixes, like the `aa:bb:cc/24` OUI of a
+    /// vendor.
+    Mac(Mac | Mac | RangeInclusive<Mac>),
 );
 
+impl RhsValues {
+    /// Removes items of `in` lists that are covered by other items, like
+    /// `10.1.0.0/16` next to `10.0.0.0/8`, as they never change the result.
+    pub(crate) fn remove_covered(&mut self) {
+        match self {
+            RhsValues::Ip(ranges) => {
+                remove_covered(ranges, |range| match ExplicitIpRange::from(range.clone()) {
+                    ExplicitIpRange::V4(range) => {
+                        IpAddr::V4(*range.start())..=IpAddr::V4(*range.end())
+                    }
+                    ExplicitIpRange::V6(range) => {
+                        IpAddr::V6(*range.start())..=IpAddr::V6(*range.end())
+                    }
+                })
+            }
+            RhsValues::Int(ranges) => remove_covered(ranges, |range| range.clone()),
+            // A network is a subnet of a covered network only if it's also a
+            // subnet of the one covering it.
+            RhsValues::Cidr(networks) => remove_covered(networks, |network| {
+                network.first_address()..=network.last_address()
+            }),
+            RhsValues::Float(ranges) => remove_covered(ranges, |range| range.clone()),
+            RhsValues::Timestamp(ranges) => remove_covered(ranges, |range| range.clone()),
+            RhsValues::Mac(ranges) => remove_covered(ranges, |range| range.clone()),
+            RhsValues::Bytes(_) | RhsValues::Bool(_) | RhsValues::Endpoint(_) => {}
+        }
+    }
+}
+
+impl<'a> From<&'a RhsValue> for LhsValue<'a> {
+    fn from(value: &'a RhsValue) -> Self {
+        match value {
+            RhsValue::Ip(addr) => LhsValue::Ip(*addr),
+            RhsValue::Bytes(bytes) => LhsValue::Bytes(bytes),
+            RhsValue::Int(value) => LhsValue::Int(*value),
+            RhsValue::Bool(value) => match *value {},
+            RhsValue::Cidr(cidr) => LhsValue::Cidr(cidr.clone()),
+            RhsValue::Endpoint(endpoint) => LhsValue::Endpoint(*endpoint),
+            RhsValue::Float(value) => LhsValue::Float(value.value()),
+            RhsValue::Timestamp(timestamp) => LhsValue::Timestamp(*timestamp),
+            RhsValue::Mac(mac) => LhsValue::Mac(*mac),
+        }
+    }
+}
+
+impl Type {
+    /// Checks whether a value of this type can be used where a value of the
+    /// `other` type is expected without an explicit conversion.
+    ///
+    /// Every type coerces to itself, and additionally an IP address coerces
+    /// to a network of just that address (`/32` or `/128`). There are no
+    /// other coercions, so the lattice is flat apart from `ip <= cidr`:
+    ///
+    /// ```text
+    ///   cidr
+    ///    |
+    ///    ip    bytes    int    bool    endpoint    float    timestamp
+    /// ```
+    pub fn can_coerce_to(self, other: Type) -> bool {
+        match (self, other) {
+            (Type::Ip, Type::Cidr) => true,
+            _ => self == other,
+        }
+    }
+}
+
+/// Orders types by [`Type::can_coerce_to`], so that `a <= b` means that `a`
+/// can be used where `b` is expected.
+impl PartialOrd for Type {
+    fn partial_cmp(&self, other: &Type) -> Option<Ordering> {
+        if self == other {
+            Some(Ordering::Equal)
+        } else if self.can_coerce_to(*other) {
+            Some(Ordering::Less)
+        } else if other.can_coerce_to(*self) {
+            Some(Ordering::Greater)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Type::Ip => "ip",
+            Type::Bytes => "bytes",
+            Type::Int => "int",
+            Type::Bool => "bool",
+            Type::Cidr => "cidr",
+            Type::Endpoint => "endpoint",
+            Type::Float => "float",
+            Type::Timestamp => "timestamp",
+            Type::Mac => "mac",
+        })
+    }
+}
+
 #[test]
 fn test_lhs_value_deserialize() {
     use std::str::FromStr;
@@ -214,3 +573,145 @@ fn test_lhs_value_deserialize() {
     let b: LhsValue<'_> = serde_json::from_str("false").unwrap();
     assert_eq!(b, LhsValue::Bool(false));
 }
+
+#[test]
+fn test_type_display() {
+    assert_eq!(Type::Ip.to_string(), "ip");
+    assert_eq!(Type::Bytes.to_string(), "bytes");
+    assert_eq!(Type::Int.to_string(), "int");
+    assert_eq!(Type::Bool.to_string(), "bool");
+    assert_eq!(Type::Cidr.to_string(), "cidr");
+    assert_eq!(Type::Endpoint.to_string(), "endpoint");
+    assert_eq!(Type::Float.to_string(), "float");
+    assert_eq!(Type::Timestamp.to_string(), "timestamp");
+    assert_eq!(Type::Mac.to_string(), "mac");
+}
+
+#[test]
+fn test_type_coercion() {
+    let types = [
+        Type::Ip,
+        Type::Bytes,
+        Type::Int,
+        Type::Bool,
+        Type::Cidr,
+        Type::Endpoint,
+        Type::Float,
+        Type::Timestamp,
+        Type::Mac,
+    ];
+
+    for &from in &types {
+        for &to in &types {
+            let expected = from == to || (from == Type::Ip && to == Type::Cidr);
+            assert_eq!(from.can_coerce_to(to), expected, "{} -> {}", from, to);
+            assert_eq!(from <= to, expected, "{} <= {}", from, to);
+        }
+    }
+
+    assert_eq!(Type::Ip.partial_cmp(&Type::Cidr), Some(Ordering::Less));
+    assert_eq!(Type::Cidr.partial_cmp(&Type::Ip), Some(Ordering::Greater));
+    assert_eq!(Type::Int.partial_cmp(&Type::Int), Some(Ordering::Equal));
+    assert_eq!(Type::Int.partial_cmp(&Type::Bytes), None);
+    assert_eq!(Type::Bool.partial_cmp(&Type::Int), None);
+}
+
+#[test]
+fn test_lex_large_rhs_values() {
+    use std::net::Ipv4Addr;
+
+    let input = format!(
+        "{{ {} }}",
+        (0..50_000u32)
+            .map(|i| Ipv4Addr::from(0x0A00_0000 + i).to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    // For a plain space-separated list the estimate is exact...
+    assert_eq!(estimate_list_len(&input[1..]), 50_000);
+
+    let (values, rest) =
+        lex_rhs_values::<IpRange>(&input, Type::Ip, &ParserOptions::default()).unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(values.len(), 50_000);
+    // ...so storage is allocated once upfront and never grows during lexing,
+    // which would leave it at the next power of two instead.
+    assert_eq!(values.capacity(), 50_000);
+}
+
+#[test]
+fn test_lex_cast() {
+    let options = &ParserOptions::default();
+
+    assert_ok!(
+        RhsValue::lex_with(r#"bytes("DEADBEEF")"#, (Type::Bytes, options)),
+        RhsValue::Bytes(vec![0xDE, 0xAD, 0xBE, 0xEF].into())
+    );
+
+    assert_ok!(
+        RhsValue::lex_with(r#"bytes ( "DE:AD:BE:EF" ) ;"#, (Type::Bytes, options)),
+        RhsValue::Bytes(vec![0xDE, 0xAD, 0xBE, 0xEF].into()),
+        " ;"
+    );
+
+    assert_ok!(
+        RhsValue::lex_with(r#"int("0x10")"#, (Type::Int, options)),
+        RhsValue::Int(16)
+    );
+
+    assert_ok!(
+        RhsValue::lex_with(r#"ip("10.0.0.1")"#, (Type::Ip, options)),
+        RhsValue::Ip(IpAddr::from([10, 0, 0, 1]))
+    );
+
+    assert_ok!(
+        RhsValue::lex_with(r#"ip("10.0.0.1")"#, (Type::Cidr, options)),
+        RhsValue::Cidr("10.0.0.1/32".parse().unwrap())
+    );
+
+    assert_err!(
+        RhsValue::lex_with(r#"int("0x10")"#, (Type::Bytes, options)),
+        LexErrorKind::TypeMismatch {
+            expected: Type::Bytes,
+            actual: Type::Int,
+        },
+        "int"
+    );
+
+    assert_err!(
+        RhsValue::lex_with(r#"int("10 ")"#, (Type::Int, options)),
+        LexErrorKind::ExpectedEof,
+        ""
+    );
+
+    assert_err!(
+        RhsValue::lex_with(r#"bytes("DEADBEE")"#, (Type::Bytes, options)),
+        LexErrorKind::CountMismatch {
+            name: "hex digit",
+            actual: 1,
+            expected: 2,
+        },
+        "E"
+    );
+
+    assert_ok!(
+        RhsValue::lex_with(r#"b64("3q2+7w==")"#, (Type::Bytes, options)),
+        RhsValue::Bytes(vec![0xDE, 0xAD, 0xBE, 0xEF].into())
+    );
+
+    assert_err!(
+        RhsValue::lex_with(r#"b64("3q2+7w==")"#, (Type::Int, options)),
+        LexErrorKind::TypeMismatch {
+            expected: Type::Int,
+            actual: Type::Bytes,
+        },
+        "b64"
+    );
+
+    // anything else is still lexed as a regular literal
+    assert_ok!(
+        RhsValue::lex_with("de:ad", (Type::Bytes, options)),
+        RhsValue::Bytes(vec![0xDE, 0xAD].into())
+    );
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "derive")]
+
+extern crate wirefilter;
+
+use std::net::IpAddr;
+use wirefilter::{Endpoint, ExecutionContext, Filterable, GetType, Timestamp, Type};
+
+#[derive(Filterable)]
+struct Request<'a> {
+    #[filter(name = "ip.src")]
+    src_ip: IpAddr,
+    #[filter(name = "http.host")]
+    host: String,
+    #[filter(name = "http.method")]
+    method: &'a str,
+    #[filter(name = "http.body")]
+    body: Vec<u8>,
+    #[filter(name = "tcp.port")]
+    port: u16,
+    ssl: bool,
+    score: f32,
+    peer: Endpoint,
+    time: Option<Timestamp>,
+    #[filter(skip)]
+    #[allow(dead_code)]
+    id: u64,
+}
+
+#[test]
+fn test_derive() {
+    let scheme = Request::scheme();
+    assert_eq!(
+        scheme.get_field("ip.src").map(|field| field.get_type()),
+        Ok(Type::Ip)
+    );
+    assert!(scheme.get_field("id").is_err());
+    assert!(Request::add_fields(&mut Request::scheme()).is_err());
+
+    let filter = scheme
+        .parse(
+            r#"ip.src == 10.0.0.1 && http.host == "example.org" && http.method == "GET"
+                && http.body contains "ok" && tcp.port == 443 && ssl && score > 0.5
+                && peer == 10.0.0.1:443 && not time > 0"#,
+        )
+        .unwrap()
+        .compile();
+
+    let mut request = Request {
+        src_ip: IpAddr::from([10, 0, 0, 1]),
+        host: "example.org".to_owned(),
+        method: "GET",
+        body: b"ok".to_vec(),
+        port: 443,
+        ssl: true,
+        score: 0.75,
+        peer: "10.0.0.1:443".parse().unwrap(),
+        time: None,
+        id: 1,
+    };
+
+    {
+        let mut ctx = ExecutionContext::new(&scheme);
+        request.fill_context(&mut ctx).unwrap();
+        assert_eq!(filter.execute(&ctx), Ok(true));
+    }
+
+    request.time = Some(Timestamp::new(1, 0).unwrap());
+    let mut ctx = ExecutionContext::new(&scheme);
+    request.fill_context(&mut ctx).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+}
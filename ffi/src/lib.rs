@@ -16,12 +16,14 @@ use std::{
     hash::Hasher,
     io::{self, Write},
     net::IpAddr,
+    panic::{catch_unwind, AssertUnwindSafe},
+    process,
 };
 use transfer_types::{
     ExternallyAllocatedByteArr, ExternallyAllocatedStr, RustAllocatedString, RustBox,
     StaticRustAllocatedString,
 };
-use wirefilter::{ExecutionContext, Filter, FilterAst, ParseError, Scheme, Type};
+use wirefilter::{ExecutionContext, Filter, FilterAst, LhsValue, ParseError, Scheme, Type};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -52,6 +54,73 @@ impl<'s> ParsingResult<'s> {
     }
 }
 
+/// A parsing error with its location, for highlighting it in editors.
+///
+/// Offsets are in bytes from the start of the input, while line and column
+/// numbers start at 1 and columns are counted in characters.
+#[repr(C)]
+pub struct ParsingError {
+    pub message: RustAllocatedString,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl<'a> From<ParseError<'a>> for ParsingError {
+    fn from(err: ParseError<'a>) -> Self {
+        ParsingError {
+            message: RustAllocatedString::from(err.message()),
+            start: err.start(),
+            end: err.end(),
+            line: err.line(),
+            column: err.column(),
+        }
+    }
+}
+
+#[repr(u8)]
+pub enum ValidationResult {
+    Ok,
+    Err(ParsingError),
+}
+
+#[repr(u8)]
+pub enum MatchingResult {
+    Err(RustAllocatedString),
+    Ok(bool),
+}
+
+impl MatchingResult {
+    pub fn unwrap(self) -> bool {
+        match self {
+            MatchingResult::Err(err) => panic!("{}", &err as &str),
+            MatchingResult::Ok(matched) => matched,
+        }
+    }
+}
+
+#[repr(u8)]
+pub enum UsingResult {
+    Err(RustAllocatedString),
+    Ok(bool),
+}
+
+impl UsingResult {
+    pub fn unwrap(self) -> bool {
+        match self {
+            UsingResult::Err(err) => panic!("{}", &err as &str),
+            UsingResult::Ok(used) => used,
+        }
+    }
+}
+
+// Unwinding into C is undefined behaviour, so panics, which are bugs on
+// either side of the boundary, abort the process instead.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| process::abort())
+}
+
 #[no_mangle]
 pub extern "C" fn wirefilter_create_scheme() -> RustBox<Scheme> {
     Default::default()
@@ -67,8 +136,8 @@ pub extern "C" fn wirefilter_add_type_field_to_scheme(
     scheme: &mut Scheme,
     name: ExternallyAllocatedStr<'_>,
     ty: Type,
-) {
-    scheme.add_field(name.into_ref().to_owned(), ty).unwrap();
+) -> bool {
+    catch_panic(|| scheme.add_field(name.into_ref().to_owned(), ty).is_ok())
 }
 
 #[no_mangle]
@@ -86,10 +155,27 @@ pub extern "C" fn wirefilter_parse_filter<'s, 'i>(
     scheme: &'s Scheme,
     input: ExternallyAllocatedStr<'i>,
 ) -> ParsingResult<'s> {
-    match scheme.parse(input.into_ref()) {
+    catch_panic(|| match scheme.parse(input.into_ref()) {
         Ok(filter) => ParsingResult::from(filter),
         Err(err) => ParsingResult::from(err),
-    }
+    })
+}
+
+/// Checks whether a filter parses, without building it.
+#[no_mangle]
+pub extern "C" fn wirefilter_validate_filter(
+    scheme: &Scheme,
+    input: ExternallyAllocatedStr<'_>,
+) -> ValidationResult {
+    catch_panic(|| match scheme.parse(input.into_ref()) {
+        Ok(_) => ValidationResult::Ok,
+        Err(err) => ValidationResult::Err(ParsingError::from(err)),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_free_parsing_error(err: ParsingError) {
+    drop(err);
 }
 
 /// Wrapper for Hasher that allows using Write API (e.g. with serializer).
@@ -149,15 +235,24 @@ pub extern "C" fn wirefilter_free_execution_context(exec_context: RustBox<Execut
     drop(exec_context);
 }
 
+// Setting a value of an unknown field panics, but here it's only a mistake
+// of the caller, so it's reported the same way as a value of a wrong type.
+fn set_field_value<'e, V: Into<LhsValue<'e>>>(
+    exec_context: &mut ExecutionContext<'e>,
+    name: &str,
+    value: V,
+) -> bool {
+    exec_context.scheme().get_field(name).is_ok()
+        && exec_context.set_field_value(name, value).is_ok()
+}
+
 #[no_mangle]
 pub extern "C" fn wirefilter_add_int_value_to_execution_context<'a>(
     exec_context: &mut ExecutionContext<'a>,
     name: ExternallyAllocatedStr<'_>,
     value: i32,
-) {
-    exec_context
-        .set_field_value(name.into_ref(), value)
-        .unwrap();
+) -> bool {
+    catch_panic(|| set_field_value(exec_context, name.into_ref(), value))
 }
 
 #[no_mangle]
@@ -165,11 +260,9 @@ pub extern "C" fn wirefilter_add_bytes_value_to_execution_context<'a>(
     exec_context: &mut ExecutionContext<'a>,
     name: ExternallyAllocatedStr<'_>,
     value: ExternallyAllocatedByteArr<'a>,
-) {
+) -> bool {
     let slice: &[u8] = value.into_ref();
-    exec_context
-        .set_field_value(name.into_ref(), slice)
-        .unwrap();
+    catch_panic(|| set_field_value(exec_context, name.into_ref(), slice))
 }
 
 #[no_mangle]
@@ -177,10 +270,8 @@ pub extern "C" fn wirefilter_add_ipv6_value_to_execution_context(
     exec_context: &mut ExecutionContext<'_>,
     name: ExternallyAllocatedStr<'_>,
     value: &[u8; 16],
-) {
-    exec_context
-        .set_field_value(name.into_ref(), IpAddr::from(*value))
-        .unwrap();
+) -> bool {
+    catch_panic(|| set_field_value(exec_context, name.into_ref(), IpAddr::from(*value)))
 }
 
 #[no_mangle]
@@ -188,10 +279,8 @@ pub extern "C" fn wirefilter_add_ipv4_value_to_execution_context(
     exec_context: &mut ExecutionContext<'_>,
     name: ExternallyAllocatedStr<'_>,
     value: &[u8; 4],
-) {
-    exec_context
-        .set_field_value(name.into_ref(), IpAddr::from(*value))
-        .unwrap();
+) -> bool {
+    catch_panic(|| set_field_value(exec_context, name.into_ref(), IpAddr::from(*value)))
 }
 
 #[no_mangle]
@@ -199,10 +288,8 @@ pub extern "C" fn wirefilter_add_bool_value_to_execution_context(
     exec_context: &mut ExecutionContext<'_>,
     name: ExternallyAllocatedStr<'_>,
     value: bool,
-) {
-    exec_context
-        .set_field_value(name.into_ref(), value)
-        .unwrap();
+) -> bool {
+    catch_panic(|| set_field_value(exec_context, name.into_ref(), value))
 }
 
 #[no_mangle]
@@ -210,15 +297,29 @@ pub extern "C" fn wirefilter_compile_filter<'s>(
     filter_ast: RustBox<FilterAst<'s>>,
 ) -> RustBox<Filter<'s>> {
     let filter_ast = filter_ast.into_real_box();
-    filter_ast.compile().into()
+    catch_panic(|| filter_ast.compile().into())
 }
 
 #[no_mangle]
-pub extern "C" fn wirefilter_match<'s>(
-    filter: &Filter<'s>,
-    exec_context: &ExecutionContext<'s>,
+pub extern "C" fn wirefilter_match(
+    filter: &Filter<'_>,
+    exec_context: &ExecutionContext<'_>,
 ) -> bool {
-    filter.execute(exec_context).unwrap()
+    catch_panic(|| filter.execute(exec_context).unwrap())
+}
+
+/// Like `wirefilter_match`, but reports a context of another scheme as an
+/// error, which has to be freed with `wirefilter_free_string`, instead of
+/// aborting.
+#[no_mangle]
+pub extern "C" fn wirefilter_match_checked(
+    filter: &Filter<'_>,
+    exec_context: &ExecutionContext<'_>,
+) -> MatchingResult {
+    catch_panic(|| match filter.execute(exec_context) {
+        Ok(matched) => MatchingResult::Ok(matched),
+        Err(err) => MatchingResult::Err(RustAllocatedString::from(err.to_string())),
+    })
 }
 
 #[no_mangle]
@@ -231,7 +332,20 @@ pub extern "C" fn wirefilter_filter_uses(
     filter_ast: &FilterAst<'_>,
     field_name: ExternallyAllocatedStr<'_>,
 ) -> bool {
-    filter_ast.uses(field_name.into_ref()).unwrap()
+    catch_panic(|| filter_ast.uses(field_name.into_ref()).unwrap())
+}
+
+/// Like `wirefilter_filter_uses`, but reports an unknown field as an error,
+/// which has to be freed with `wirefilter_free_string`, instead of aborting.
+#[no_mangle]
+pub extern "C" fn wirefilter_filter_uses_checked(
+    filter_ast: &FilterAst<'_>,
+    field_name: ExternallyAllocatedStr<'_>,
+) -> UsingResult {
+    catch_panic(|| match filter_ast.uses(field_name.into_ref()) {
+        Ok(used) => UsingResult::Ok(used),
+        Err(err) => UsingResult::Err(RustAllocatedString::from(err.to_string())),
+    })
 }
 
 #[no_mangle]
@@ -384,6 +498,70 @@ mod ffi_test {
         wirefilter_free_scheme(scheme);
     }
 
+    #[test]
+    fn validate() {
+        let scheme = create_scheme();
+
+        match wirefilter_validate_filter(&scheme, ExternallyAllocatedStr::from("num1 == 42")) {
+            ValidationResult::Ok => {}
+            ValidationResult::Err(err) => panic!("{}", &err.message as &str),
+        }
+
+        let src = "num1 == 42 or\n  num1 == \"a\"";
+
+        match wirefilter_validate_filter(&scheme, ExternallyAllocatedStr::from(src)) {
+            ValidationResult::Ok => panic!("Error expected"),
+            ValidationResult::Err(err) => {
+                assert_eq!(&err.message as &str, "expected digit");
+                assert_eq!((err.start, err.end), (24, 27));
+                assert_eq!((err.line, err.column), (2, 11));
+                wirefilter_free_parsing_error(err);
+            }
+        }
+
+        wirefilter_free_scheme(scheme);
+    }
+
+    #[test]
+    fn setters() {
+        let mut scheme = create_scheme();
+
+        assert!(!wirefilter_add_type_field_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("num1"),
+            Type::Bytes,
+        ));
+
+        {
+            let mut exec_context = create_execution_context(&scheme);
+
+            assert!(wirefilter_add_int_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("num1"),
+                1,
+            ));
+            assert!(!wirefilter_add_bool_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("num1"),
+                true,
+            ));
+            assert!(!wirefilter_add_int_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("num3"),
+                1,
+            ));
+            assert!(!wirefilter_add_ipv4_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("ip3"),
+                &[127, 0, 0, 1],
+            ));
+
+            wirefilter_free_execution_context(exec_context);
+        }
+
+        wirefilter_free_scheme(scheme);
+    }
+
     #[test]
     fn filter_parsing() {
         let scheme = create_scheme();
@@ -393,7 +571,10 @@ mod ffi_test {
 
             let json = wirefilter_serialize_filter_to_json(&filter);
 
-            assert_eq!(&json as &str, r#"{"op":"And","items":[{"field":"num1","op":"GreaterThan","rhs":3},{"field":"str2","op":"Equal","rhs":"abc"}]}"#);
+            assert_eq!(
+                &json as &str,
+                r#"{"op":"And","items":[{"field":"num1","op":"GreaterThan","rhs":3},{"field":"str2","op":"Equal","rhs":"abc"}]}"#
+            );
 
             wirefilter_free_string(json);
 
@@ -428,6 +609,33 @@ mod ffi_test {
                 &exec_context
             ));
 
+            // A context of another scheme is an error rather than a crash.
+            let other_scheme = create_scheme();
+
+            {
+                let filter = parse_filter(&other_scheme, "num1 == 42").unwrap();
+                let filter = wirefilter_compile_filter(filter);
+
+                let other_context = create_execution_context(&other_scheme);
+                assert!(wirefilter_match_checked(&filter, &other_context).unwrap());
+                wirefilter_free_execution_context(other_context);
+
+                match wirefilter_match_checked(&filter, &exec_context) {
+                    MatchingResult::Ok(_) => panic!("Error expected"),
+                    MatchingResult::Err(err) => {
+                        assert_eq!(
+                            &err as &str,
+                            "execution context doesn't match the scheme with which filter was parsed"
+                        );
+                        wirefilter_free_string(err);
+                    }
+                }
+
+                wirefilter_free_compiled_filter(filter);
+            }
+
+            wirefilter_free_scheme(other_scheme);
+
             wirefilter_free_execution_context(exec_context);
         }
 
@@ -512,6 +720,19 @@ mod ffi_test {
                 ExternallyAllocatedStr::from("ip2")
             ));
 
+            assert!(
+                wirefilter_filter_uses_checked(&filter, ExternallyAllocatedStr::from("num1"))
+                    .unwrap()
+            );
+
+            match wirefilter_filter_uses_checked(&filter, ExternallyAllocatedStr::from("ip3")) {
+                UsingResult::Ok(_) => panic!("Error expected"),
+                UsingResult::Err(err) => {
+                    assert_eq!(&err as &str, "unknown field");
+                    wirefilter_free_string(err);
+                }
+            }
+
             wirefilter_free_parsed_filter(filter);
         }
 
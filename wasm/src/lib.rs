@@ -2,7 +2,9 @@ extern crate js_sys;
 extern crate wasm_bindgen;
 extern crate wirefilter;
 
+use js_sys::{Array, Object, Reflect};
 use wasm_bindgen::prelude::*;
+use wirefilter::{ParseError, Token, Tokens};
 
 #[wasm_bindgen]
 pub struct Scheme(wirefilter::Scheme);
@@ -12,6 +14,33 @@ fn into_js_error(err: impl std::error::Error) -> JsValue {
     js_sys::Error::new(&err.to_string()).into()
 }
 
+// JavaScript strings are indexed by UTF-16 code units rather than bytes.
+fn js_offset(input: &str, offset: usize) -> JsValue {
+    JsValue::from_f64(input[..offset].encode_utf16().count() as f64)
+}
+
+fn set(target: &JsValue, key: &str, value: &JsValue) {
+    Reflect::set(target, &JsValue::from_str(key), value).unwrap();
+}
+
+// Errors are still `Error`s with the rendered message, but also carry the
+// location, e.g. for underlining it in an editor.
+#[allow(clippy::needless_pass_by_value)]
+fn into_js_parse_error(input: &str, err: ParseError<'_>) -> JsValue {
+    let js_err = js_sys::Error::new(&err.to_string()).into();
+    set(&js_err, "reason", &JsValue::from_str(&err.message()));
+    set(&js_err, "start", &js_offset(input, err.start()));
+    set(&js_err, "end", &js_offset(input, err.end()));
+    set(&js_err, "line", &JsValue::from_f64(err.line() as f64));
+    set(&js_err, "column", &JsValue::from_f64(err.column() as f64));
+    let suggestions = Array::new();
+    for name in err.suggestions() {
+        suggestions.push(&JsValue::from_str(name));
+    }
+    set(&js_err, "suggestions", &suggestions);
+    js_err
+}
+
 #[wasm_bindgen]
 impl Scheme {
     #[wasm_bindgen(constructor)]
@@ -20,7 +49,61 @@ impl Scheme {
     }
 
     pub fn parse(&self, s: &str) -> Result<JsValue, JsValue> {
-        let filter = self.0.parse(s).map_err(into_js_error)?;
+        let filter = self.0.parse(s).map_err(|err| into_js_parse_error(s, err))?;
         JsValue::from_serde(&filter).map_err(into_js_error)
     }
+
+    /// Returns `null` if the filter is valid, or the same error that
+    /// `parse` would throw otherwise.
+    pub fn validate(&self, s: &str) -> JsValue {
+        match self.0.parse(s) {
+            Ok(_) => JsValue::NULL,
+            Err(err) => into_js_parse_error(s, err),
+        }
+    }
+
+    /// Returns an array of errors like the one `validate` returns, one for
+    /// each invalid operand of `&&`, `||` and `^^`, including ones in
+    /// parentheses, or an empty array if the filter is valid.
+    pub fn validate_all(&self, s: &str) -> JsValue {
+        let errors = Array::new();
+        if let Err(errs) = self.0.parse_all_errors(s) {
+            for err in errs {
+                errors.push(&into_js_parse_error(s, err));
+            }
+        }
+        errors.into()
+    }
+}
+
+/// Splits a filter into tokens for syntax highlighting, without checking
+/// them against a scheme.
+///
+/// Returns an array of `{ kind, start, end }` objects, where `kind` is one
+/// of `ident`, `literal`, `string`, `punct` or `comment`, or throws the same error as
+/// `Scheme.parse` if the input can't be tokenized.
+#[wasm_bindgen]
+pub fn tokenize(s: &str) -> Result<JsValue, JsValue> {
+    let tokens = Array::new();
+
+    for res in Tokens::new(s) {
+        let (token, range) = res.map_err(|err| into_js_parse_error(s, err))?;
+
+        let kind = match token {
+            Token::Ident(_) => "ident",
+            Token::Literal(_) => "literal",
+            Token::String(_) => "string",
+            Token::Punct(_) => "punct",
+            Token::Comment(_) => "comment",
+            Token::Eof => break,
+        };
+
+        let js_token = Object::new().into();
+        set(&js_token, "kind", &JsValue::from_str(kind));
+        set(&js_token, "start", &js_offset(s, range.start));
+        set(&js_token, "end", &js_offset(s, range.end));
+        tokens.push(&js_token);
+    }
+
+    Ok(tokens.into())
 }